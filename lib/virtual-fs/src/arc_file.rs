@@ -3,6 +3,7 @@
 
 use crate::{ClonableVirtualFile, VirtualFile};
 use derivative::Derivative;
+use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::{
@@ -126,6 +127,10 @@ where
         let mut inner = self.inner.lock().unwrap();
         inner.set_len(new_size)
     }
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> crate::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.set_times(atime, mtime)
+    }
     fn unlink(&mut self) -> crate::Result<()> {
         let mut inner = self.inner.lock().unwrap();
         inner.unlink()
@@ -138,6 +143,42 @@ where
         let inner = self.inner.lock().unwrap();
         inner.get_special_fd()
     }
+    fn shrink_to_fit(&mut self) -> crate::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.shrink_to_fit()
+    }
+    fn sync(&mut self) -> crate::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.sync()
+    }
+    fn sync_data(&mut self) -> crate::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.sync_data()
+    }
+    fn read_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = io::Result<usize>> + 'a>> {
+        // The lock can't be held across an await, so every poll starts the
+        // read again, which is fine because it doesn't touch the cursor
+        Box::pin(std::future::poll_fn(move |cx| {
+            let inner = self.inner.lock().unwrap();
+            let mut read = inner.read_at(offset, buf);
+            read.as_mut().poll(cx)
+        }))
+    }
+    fn write_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = io::Result<usize>> + 'a>> {
+        Box::pin(std::future::poll_fn(move |cx| {
+            let inner = self.inner.lock().unwrap();
+            let mut write = inner.write_at(offset, buf);
+            write.as_mut().poll(cx)
+        }))
+    }
     fn poll_read_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
         let mut inner = self.inner.lock().unwrap();
         let inner = Pin::new(inner.as_mut());
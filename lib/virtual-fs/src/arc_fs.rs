@@ -2,7 +2,7 @@
 //! can pass clonable file systems with a `Box<dyn FileSystem>` to other
 //! interfaces
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 #[allow(unused_imports, dead_code)]
 use tracing::{debug, error, info, trace, warn};
@@ -45,10 +45,18 @@ impl FileSystem for ArcFileSystem {
         self.fs.symlink_metadata(path)
     }
 
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.fs.read_link(path)
+    }
+
     fn remove_file(&self, path: &Path) -> Result<()> {
         self.fs.remove_file(path)
     }
 
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.fs.write_file(path, contents)
+    }
+
     fn new_open_options(&self) -> OpenOptions {
         self.fs.new_open_options()
     }
@@ -87,6 +87,10 @@ impl VirtualFile for BufferFile {
     fn unlink(&mut self) -> crate::Result<()> {
         Ok(())
     }
+    fn shrink_to_fit(&mut self) -> crate::Result<()> {
+        self.data.get_mut().shrink_to_fit();
+        Ok(())
+    }
     fn poll_read_ready(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
         let cur = self.data.stream_position().unwrap_or_default();
         let len = self.data.seek(SeekFrom::End(0)).unwrap_or_default();
@@ -0,0 +1,332 @@
+//! Wraps a [`VirtualFile`] and reads ahead of the current position, so a
+//! program reading a file sequentially in small chunks only needs a few
+//! large reads from the underlying file.
+
+use std::task::ready;
+
+use derivative::Derivative;
+
+use super::*;
+
+use crate::VirtualFile;
+
+/// How much is read ahead by default.
+pub const DEFAULT_READ_AHEAD: usize = 64 * 1024;
+
+/// Wraps a [`VirtualFile`] and fills an internal buffer with large reads,
+/// which small reads are then served from.
+///
+/// Seeking doesn't touch the underlying file until it is read from or
+/// written to again, so the buffer survives seeks within it. Writing or
+/// changing the length of the file throws the buffer away, so the wrapper
+/// never returns stale data for changes made through it.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct BufferedReadFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    #[derivative(Debug = "ignore")]
+    buf: Vec<u8>,
+    capacity: usize,
+    /// The position in the file that `buf[0]` was read from.
+    buf_start: u64,
+    /// The position the next read or write happens at.
+    pos: u64,
+    /// The position of the inner file, which lags behind `pos` after seeks.
+    /// It isn't known until the inner file has been moved to `pos` once, as
+    /// the file may have been read from before it was wrapped.
+    inner_pos: Option<u64>,
+    /// The inner file is being moved to `pos`.
+    syncing: bool,
+    /// A seek from the end was passed on to the inner file.
+    seeking_from_end: bool,
+}
+
+impl BufferedReadFile {
+    pub fn new(inner: Box<dyn VirtualFile + Send + Sync + 'static>) -> Self {
+        Self::with_capacity(DEFAULT_READ_AHEAD, inner)
+    }
+
+    pub fn with_capacity(
+        capacity: usize,
+        inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    ) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            capacity,
+            buf_start: 0,
+            pos: 0,
+            inner_pos: None,
+            syncing: false,
+            seeking_from_end: false,
+        }
+    }
+
+    pub fn into_inner(self) -> Box<dyn VirtualFile + Send + Sync + 'static> {
+        self.inner
+    }
+
+    /// Throws the buffered data away, once it may no longer match the file.
+    fn invalidate(&mut self) {
+        self.buf.clear();
+    }
+
+    /// The buffered data from the current position onwards.
+    fn buffered(&self) -> &[u8] {
+        let buf_end = self.buf_start + self.buf.len() as u64;
+        if self.pos < self.buf_start || self.pos >= buf_end {
+            return &[];
+        }
+        &self.buf[(self.pos - self.buf_start) as usize..]
+    }
+
+    /// Moves the inner file to the current position, before it is read
+    /// from or written to.
+    fn poll_sync_inner(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.inner_pos == Some(self.pos) {
+            return Poll::Ready(Ok(()));
+        }
+        if !self.syncing {
+            Pin::new(&mut self.inner).start_seek(io::SeekFrom::Start(self.pos))?;
+            self.syncing = true;
+        }
+        let result = ready!(Pin::new(&mut self.inner).poll_complete(cx));
+        self.syncing = false;
+        self.inner_pos = result.as_ref().ok().copied();
+        result?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl VirtualFile for BufferedReadFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> crate::Result<()> {
+        self.invalidate();
+        self.inner.set_len(new_size)
+    }
+
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> crate::Result<()> {
+        self.inner.set_times(atime, mtime)
+    }
+
+    fn unlink(&mut self) -> crate::Result<()> {
+        self.inner.unlink()
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn get_special_fd(&self) -> Option<u32> {
+        self.inner.get_special_fd()
+    }
+
+    fn shrink_to_fit(&mut self) -> crate::Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn sync(&mut self) -> crate::Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> crate::Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let buffered = self.buffered().len();
+        if buffered > 0 {
+            return Poll::Ready(Ok(buffered));
+        }
+        Pin::new(self.inner.as_mut()).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_write_ready(cx)
+    }
+}
+
+impl AsyncRead for BufferedReadFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if this.buffered().is_empty() {
+            ready!(this.poll_sync_inner(cx))?;
+
+            // Big reads don't need to go through the buffer
+            if buf.remaining() >= this.capacity {
+                let before = buf.filled().len();
+                let result = ready!(Pin::new(&mut this.inner).poll_read(cx, buf));
+                if result.is_err() {
+                    this.inner_pos = None;
+                }
+                result?;
+                this.pos += (buf.filled().len() - before) as u64;
+                this.inner_pos = Some(this.pos);
+                return Poll::Ready(Ok(()));
+            }
+
+            this.buf.resize(this.capacity, 0);
+            let mut fill = ReadBuf::new(&mut this.buf);
+            let result = Pin::new(&mut this.inner).poll_read(cx, &mut fill);
+            let filled = fill.filled().len();
+            this.buf.truncate(filled);
+            this.buf_start = this.pos;
+            this.inner_pos = match &result {
+                Poll::Ready(Err(_)) => None,
+                _ => Some(this.pos + filled as u64),
+            };
+            ready!(result)?;
+        }
+
+        let buffered = this.buffered();
+        let amt = buffered.len().min(buf.remaining());
+        buf.put_slice(&buffered[..amt]);
+        this.pos += amt as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for BufferedReadFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // Even a write that fails may have changed part of the file
+        self.invalidate();
+        ready!(self.poll_sync_inner(cx))?;
+        let result = ready!(Pin::new(&mut self.inner).poll_write(cx, buf));
+        let amt = match result {
+            Ok(amt) => amt,
+            Err(err) => {
+                self.inner_pos = None;
+                return Poll::Ready(Err(err));
+            }
+        };
+        self.pos += amt as u64;
+        self.inner_pos = Some(self.pos);
+        Poll::Ready(Ok(amt))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for BufferedReadFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        match position {
+            io::SeekFrom::Start(offset) => self.pos = offset,
+            io::SeekFrom::Current(offset) => {
+                self.pos = self.pos.checked_add_signed(offset).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "invalid seek to a negative or overflowing position",
+                    )
+                })?;
+            }
+            // Only the inner file knows where its end is
+            io::SeekFrom::End(_) => {
+                self.syncing = false;
+                self.inner_pos = None;
+                Pin::new(&mut self.inner).start_seek(position)?;
+                self.seeking_from_end = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        if self.seeking_from_end {
+            let result = ready!(Pin::new(&mut self.inner).poll_complete(cx));
+            self.seeking_from_end = false;
+            self.pos = result?;
+            self.inner_pos = Some(self.pos);
+        }
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::BufferFile;
+
+    #[tokio::test]
+    async fn writes_and_seeks_are_seen_by_later_reads() {
+        let file = BufferFile {
+            data: io::Cursor::new(b"0123456789".to_vec()),
+        };
+        let mut file = BufferedReadFile::with_capacity(4, Box::new(file));
+
+        let mut buf = [0u8; 2];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"01");
+
+        // Overwrite the part that was read ahead already
+        file.write_all(b"ab").await.unwrap();
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"45");
+
+        file.seek(io::SeekFrom::Current(-4)).await.unwrap();
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "ab456789");
+    }
+
+    #[tokio::test]
+    async fn a_file_that_was_read_from_before_it_was_wrapped_is_read_from_the_start() {
+        let mut file = BufferFile {
+            data: io::Cursor::new(b"0123456789".to_vec()),
+        };
+        file.seek(io::SeekFrom::Start(6)).await.unwrap();
+        let mut file = BufferedReadFile::with_capacity(4, Box::new(file));
+
+        let mut buf = [0u8; 2];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"01");
+    }
+
+    #[tokio::test]
+    async fn writes_throw_the_read_ahead_away() {
+        let file = BufferFile {
+            data: io::Cursor::new(b"0123456789".to_vec()),
+        };
+        let mut file = BufferedReadFile::with_capacity(8, Box::new(file));
+
+        let mut buf = [0u8; 2];
+        file.read_exact(&mut buf).await.unwrap();
+        file.seek(io::SeekFrom::Start(4)).await.unwrap();
+        file.write_all(b"ab").await.unwrap();
+
+        file.seek(io::SeekFrom::Start(2)).await.unwrap();
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "23ab6789");
+    }
+}
@@ -3,7 +3,6 @@ use crate::{FileSystem, VirtualFile};
 use std::path::{Path, PathBuf};
 use tracing::*;
 
-use super::ZeroFile;
 use super::{DeviceFile, NullFile};
 use crate::tmp_fs::TmpFileSystem;
 
@@ -76,15 +75,9 @@ impl RootFileSystemBuilder {
                 .insert_device_file(PathBuf::from("/bin/wasmer"), Box::<NullFile>::default());
         }
         if self.default_dev_files {
-            let _ = tmp
-                .new_open_options_ext()
-                .insert_device_file(PathBuf::from("/dev/null"), Box::<NullFile>::default());
-            let _ = tmp
-                .new_open_options_ext()
-                .insert_device_file(PathBuf::from("/dev/zero"), Box::<ZeroFile>::default());
-            let _ = tmp
-                .new_open_options_ext()
-                .insert_device_file(PathBuf::from("/dev/urandom"), Box::<RandomFile>::default());
+            if let Err(err) = tmp.mount_standard_dev(RandomFile::default()) {
+                debug!("failed to mount the standard devices - {}", err);
+            }
             let _ = tmp.new_open_options_ext().insert_device_file(
                 PathBuf::from("/dev/stdin"),
                 self.stdin
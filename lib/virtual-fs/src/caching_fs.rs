@@ -0,0 +1,379 @@
+//! A [`FileSystem`] decorator that remembers the result of `metadata()`
+//! calls, for guests that keep checking the same files over and over.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::{
+    FileOpener, FileSystem, Metadata, OpenOptions, OpenOptionsConfig, ReadDir, Result, VirtualFile,
+};
+
+#[derive(Debug, Default)]
+struct MetadataCache {
+    entries: Mutex<HashMap<PathBuf, Metadata>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl MetadataCache {
+    /// Forgets everything about `path`, the files below it (in case it is
+    /// a directory) and its parent, whose timestamps change when entries
+    /// are added or removed.
+    fn invalidate(&self, path: &Path) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|cached, _| !cached.starts_with(path));
+        if let Some(parent) = path.parent() {
+            entries.remove(parent);
+        }
+    }
+}
+
+/// A [`FileSystem`] wrapper that caches the [`Metadata`] of the paths that
+/// are looked up, so repeated `stat`s of unchanged files don't have to go
+/// to the inner file system.
+///
+/// A cached entry is dropped as soon as the path is changed through this
+/// wrapper, including writes to files opened through it. Changes made to
+/// the inner file system behind the wrapper's back are not noticed.
+#[derive(Debug, Clone)]
+pub struct CachingFileSystem<F> {
+    inner: F,
+    cache: Arc<MetadataCache>,
+}
+
+impl<F> CachingFileSystem<F> {
+    pub fn new(inner: F) -> Self {
+        CachingFileSystem {
+            inner,
+            cache: Default::default(),
+        }
+    }
+
+    /// How many `metadata()` calls were answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.cache.hits.load(Ordering::Relaxed)
+    }
+
+    /// How many `metadata()` calls had to go to the inner file system.
+    pub fn misses(&self) -> u64 {
+        self.cache.misses.load(Ordering::Relaxed)
+    }
+
+    /// Forgets every cached entry.
+    pub fn clear(&self) {
+        self.cache.entries.lock().unwrap().clear();
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F> FileSystem for CachingFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let result = self.inner.create_dir(path);
+        self.cache.invalidate(path);
+        result
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        let result = self.inner.remove_dir(path);
+        self.cache.invalidate(path);
+        result
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let result = self.inner.rename(from, to);
+        self.cache.invalidate(from);
+        self.cache.invalidate(to);
+        result
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        if let Some(metadata) = self.cache.entries.lock().unwrap().get(path) {
+            self.cache.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(metadata.clone());
+        }
+
+        self.cache.misses.fetch_add(1, Ordering::Relaxed);
+        let metadata = self.inner.metadata(path)?;
+        self.cache
+            .entries
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), metadata.clone());
+        Ok(metadata)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.inner.read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        self.inner.canonicalize(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let result = self.inner.remove_file(path);
+        self.cache.invalidate(path);
+        result
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let result = self.inner.write_file(path, contents);
+        self.cache.invalidate(path);
+        result
+    }
+
+    fn new_open_options(&self) -> OpenOptions<'_> {
+        OpenOptions::new(self)
+    }
+}
+
+impl<F> FileOpener for CachingFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let inner = self
+            .inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(path);
+        if !conf.would_mutate() {
+            return inner;
+        }
+        // Opening a file can create or truncate it, and whoever looked the
+        // path up in the meantime may have cached the old metadata
+        self.cache.invalidate(path);
+        let inner = inner?;
+
+        Ok(Box::new(CachingFile {
+            inner,
+            path: path.to_path_buf(),
+            cache: self.cache.clone(),
+        }))
+    }
+}
+
+/// A file opened for writing, which invalidates the cached metadata of its
+/// path whenever it is changed.
+#[derive(Debug)]
+struct CachingFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    path: PathBuf,
+    cache: Arc<MetadataCache>,
+}
+
+impl VirtualFile for CachingFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        let result = self.inner.set_len(new_size);
+        self.cache.invalidate(&self.path);
+        result
+    }
+
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        let result = self.inner.set_times(atime, mtime);
+        self.cache.invalidate(&self.path);
+        result
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        let result = self.inner.unlink();
+        self.cache.invalidate(&self.path);
+        result
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn get_special_fd(&self) -> Option<u32> {
+        self.inner.get_special_fd()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_write_ready(cx)
+    }
+}
+
+impl AsyncRead for CachingFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CachingFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = &result {
+            if *len > 0 {
+                self.cache.invalidate(&self.path);
+            }
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for CachingFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut *self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut *self.inner).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::mem_fs;
+
+    async fn create(fs: &impl FileSystem, path: &str, contents: &[u8]) {
+        let mut f = fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open(path)
+            .unwrap();
+        f.write_all(contents).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn repeated_stats_hit_the_cache_until_the_file_changes() {
+        let fs = CachingFileSystem::new(mem_fs::FileSystem::default());
+        let path = Path::new("/config.toml");
+        create(&fs, "/config.toml", b"a = 1").await;
+
+        assert_eq!(fs.metadata(path).unwrap().len(), 5);
+        assert_eq!(fs.metadata(path).unwrap().len(), 5);
+        assert_eq!(fs.metadata(path).unwrap().len(), 5);
+        assert_eq!((fs.hits(), fs.misses()), (2, 1));
+
+        let mut f = fs
+            .new_open_options()
+            .write(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        // Just opening the file for writing doesn't change it...
+        assert_eq!(fs.metadata(path).unwrap().len(), 5);
+        assert_eq!((fs.hits(), fs.misses()), (2, 2));
+        // ... but writing to it does
+        f.write_all(b"\nb = 2").await.unwrap();
+        assert_eq!(fs.metadata(path).unwrap().len(), 11);
+        assert_eq!((fs.hits(), fs.misses()), (2, 3));
+        assert_eq!(fs.metadata(path).unwrap().len(), 11);
+        assert_eq!((fs.hits(), fs.misses()), (3, 3));
+    }
+
+    #[tokio::test]
+    async fn renaming_a_directory_forgets_everything_below_it() {
+        let fs = CachingFileSystem::new(mem_fs::FileSystem::default());
+        fs.create_dir(Path::new("/data")).unwrap();
+        create(&fs, "/data/a.txt", b"a").await;
+        fs.metadata(Path::new("/data/a.txt")).unwrap();
+
+        fs.rename(Path::new("/data"), Path::new("/moved")).unwrap();
+
+        assert_eq!(
+            fs.metadata(Path::new("/data/a.txt")),
+            Err(crate::FsError::EntryNotFound)
+        );
+        assert!(fs.metadata(Path::new("/moved/a.txt")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn write_file_goes_to_the_inner_file_system() {
+        let fs = CachingFileSystem::new(mem_fs::FileSystem::default());
+        let path = Path::new("/config.toml");
+        create(&fs, "/config.toml", b"a = 1").await;
+        assert_eq!(fs.metadata(path).unwrap().len(), 5);
+
+        fs.write_file(path, b"a = 100").unwrap();
+
+        assert_eq!(fs.inner().metadata(path).unwrap().len(), 7);
+        assert_eq!(fs.metadata(path).unwrap().len(), 7);
+    }
+}
@@ -45,6 +45,10 @@ impl VirtualFile for CombineFile {
         self.tx.unlink()
     }
 
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.tx.shrink_to_fit()
+    }
+
     fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
         Pin::new(self.rx.as_mut()).poll_read_ready(cx)
     }
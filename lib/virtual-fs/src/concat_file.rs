@@ -0,0 +1,226 @@
+//! Presents several files end-to-end as a single, read-only file, which is
+//! handy for serving something that was assembled from parts (e.g. a
+//! multipart upload).
+
+use std::{
+    io::{self, SeekFrom},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::{FsError, VirtualFile};
+
+#[derive(Debug)]
+pub struct ConcatFile {
+    files: Vec<Box<dyn VirtualFile + Send + Sync + 'static>>,
+    /// The position within the concatenated file.
+    position: u64,
+    /// The file whose cursor is known to be at `position`.
+    synced: Option<usize>,
+    /// The file that is being moved to `position`.
+    seeking: Option<usize>,
+}
+
+impl ConcatFile {
+    pub fn new(files: Vec<Box<dyn VirtualFile + Send + Sync + 'static>>) -> Self {
+        Self {
+            files,
+            position: 0,
+            synced: None,
+            seeking: None,
+        }
+    }
+
+    /// Finds the file that holds the byte at `position`, and the offset of
+    /// that byte within the file.
+    fn locate(&self, position: u64) -> Option<(usize, u64)> {
+        let mut start = 0;
+        for (index, file) in self.files.iter().enumerate() {
+            let end = start + file.size();
+            if position < end {
+                return Some((index, position - start));
+            }
+            start = end;
+        }
+        None
+    }
+
+    /// The position at which the file at `index` starts.
+    fn start_of(&self, index: usize) -> u64 {
+        self.files[..index].iter().map(|file| file.size()).sum()
+    }
+}
+
+impl VirtualFile for ConcatFile {
+    fn last_accessed(&self) -> u64 {
+        self.files
+            .iter()
+            .map(|file| file.last_accessed())
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.files
+            .iter()
+            .map(|file| file.last_modified())
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.files
+            .iter()
+            .map(|file| file.created_time())
+            .min()
+            .unwrap_or_default()
+    }
+
+    fn size(&self) -> u64 {
+        self.files.iter().map(|file| file.size()).sum()
+    }
+
+    fn set_len(&mut self, _new_size: u64) -> crate::Result<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn unlink(&mut self) -> crate::Result<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn poll_read_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let remaining = self.size().saturating_sub(self.position);
+        Poll::Ready(Ok(remaining as usize))
+    }
+
+    fn poll_write_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+    }
+}
+
+impl AsyncRead for ConcatFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let (index, offset) = match self.locate(self.position) {
+                Some(found) => found,
+                // Past the end of the last file
+                None => return Poll::Ready(Ok(())),
+            };
+
+            // Move the cursor of the file to where we are
+            if self.synced != Some(index) {
+                if self.seeking != Some(index) {
+                    Pin::new(&mut self.files[index]).start_seek(SeekFrom::Start(offset))?;
+                    self.seeking = Some(index);
+                }
+                ready!(Pin::new(&mut self.files[index]).poll_complete(cx))?;
+                self.seeking = None;
+                self.synced = Some(index);
+            }
+
+            let before = buf.filled().len();
+            ready!(Pin::new(&mut self.files[index]).poll_read(cx, buf))?;
+            let read = (buf.filled().len() - before) as u64;
+
+            if read == 0 && buf.remaining() > 0 {
+                // The file ended sooner than its size said, carry on with
+                // the next one
+                self.position = self.start_of(index + 1);
+                self.synced = None;
+                continue;
+            }
+
+            self.position += read;
+            return Poll::Ready(Ok(()));
+        }
+    }
+}
+
+impl AsyncWrite for ConcatFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for ConcatFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let position = match position {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(delta) => self.size().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        let position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        if position != self.position {
+            self.position = position;
+            self.synced = None;
+        }
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::BufferFile;
+
+    fn part(data: &str) -> Box<dyn VirtualFile + Send + Sync + 'static> {
+        Box::new(BufferFile {
+            data: Cursor::new(data.as_bytes().to_vec()),
+        })
+    }
+
+    #[tokio::test]
+    async fn reads_cross_file_boundaries() {
+        let mut file = ConcatFile::new(vec![part("hello"), part(""), part(" wor"), part("ld")]);
+        assert_eq!(file.size(), 11);
+
+        let mut buf = [0; 7];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello w");
+
+        // Seek back into the middle of the first file
+        file.seek(SeekFrom::Start(3)).await.unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "lo world");
+
+        file.seek(SeekFrom::End(-4)).await.unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "orld");
+
+        assert!(file.write_all(b"nope").await.is_err());
+    }
+}
@@ -208,6 +208,12 @@ impl VirtualFile for CopyOnWriteFile {
         self.buf.set_len(0)?;
         Ok(())
     }
+    fn shrink_to_fit(&mut self) -> crate::Result<()> {
+        match self.state.as_mut() {
+            Some(inner) => inner.shrink_to_fit(),
+            None => self.buf.shrink_to_fit(),
+        }
+    }
     fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
         match self.poll_copy_progress(cx) {
             Poll::Pending => return Poll::Pending,
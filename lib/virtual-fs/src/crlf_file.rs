@@ -0,0 +1,194 @@
+//! Wraps a [`VirtualFile`] and expands every lone `\n` that is written to
+//! it into `\r\n`, which is what most terminals expect to see.
+
+use std::task::ready;
+
+use derivative::Derivative;
+
+use super::*;
+
+use crate::VirtualFile;
+
+/// Wraps a [`VirtualFile`] and translates `\n` into `\r\n` on writes.
+///
+/// Line endings that already are `\r\n` are left alone, even when the `\r`
+/// and the `\n` arrive in separate writes. Reads are passed through as is.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct CrlfFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    /// Translated bytes that the inner file hasn't accepted yet.
+    pending: Vec<u8>,
+    /// Whether the last byte that was written is a `\r`.
+    last_was_cr: bool,
+}
+
+impl CrlfFile {
+    pub fn new(inner: Box<dyn VirtualFile + Send + Sync + 'static>) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+            last_was_cr: false,
+        }
+    }
+
+    pub fn into_inner(self) -> Box<dyn VirtualFile + Send + Sync + 'static> {
+        self.inner
+    }
+
+    /// Writes out the translated bytes that are still pending.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let amt = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if amt == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.drain(..amt);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl VirtualFile for CrlfFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        self.inner.set_len(new_size)
+    }
+
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        self.inner.set_times(atime, mtime)
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        self.inner.unlink()
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn get_special_fd(&self) -> Option<u32> {
+        self.inner.get_special_fd()
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(self.inner.as_mut()).poll_write_ready(cx)
+    }
+}
+
+impl AsyncWrite for CrlfFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_drain(cx))?;
+
+        let mut last_was_cr = self.last_was_cr;
+        for &byte in buf {
+            if byte == b'\n' && !last_was_cr {
+                self.pending.push(b'\r');
+            }
+            self.pending.push(byte);
+            last_was_cr = byte == b'\r';
+        }
+        self.last_was_cr = last_was_cr;
+
+        // Whatever the inner file doesn't take now goes out with the next
+        // write or flush
+        if let Poll::Ready(Err(err)) = self.poll_drain(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncRead for CrlfFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for CrlfFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::Pipe;
+
+    #[tokio::test]
+    async fn lone_newlines_become_crlf() {
+        let (local, mut remote) = Pipe::channel();
+        let mut file = CrlfFile::new(Box::new(local));
+
+        file.write_all(b"a\nb\n").await.unwrap();
+        // Existing line endings are kept, even when they are split up
+        file.write_all(b"c\r\nd\r").await.unwrap();
+        file.write_all(b"\n").await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+
+        let mut output = String::new();
+        remote.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "a\r\nb\r\nc\r\nd\r\n");
+    }
+}
@@ -0,0 +1,228 @@
+//! Wraps a [`VirtualFile`] whose reader may go away at any time (e.g. a
+//! client that disconnects from a terminal session) so that writers don't
+//! get a broken pipe error thrown at them.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use derivative::Derivative;
+
+use super::*;
+
+use crate::VirtualFile;
+
+/// Wraps a [`VirtualFile`] and silently discards everything written to it
+/// once the other end has been closed.
+///
+/// The first write that fails because the other end is gone raises the
+/// shared `disconnected` flag. That write, and every write after it,
+/// reports success without doing anything.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct DisconnectTolerantFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    disconnected: Arc<AtomicBool>,
+}
+
+impl DisconnectTolerantFile {
+    pub fn new(
+        inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+        disconnected: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            inner,
+            disconnected,
+        }
+    }
+
+    /// Whether the other end has been closed.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Acquire)
+    }
+
+    pub fn into_inner(self) -> Box<dyn VirtualFile + Send + Sync + 'static> {
+        self.inner
+    }
+
+    /// Turns errors caused by the other end going away into a successful
+    /// (but discarded) write of `amt` bytes.
+    fn swallow<T>(&self, res: Poll<io::Result<T>>, amt: T) -> Poll<io::Result<T>> {
+        match res {
+            Poll::Ready(Err(e)) if is_disconnect(&e) => {
+                self.disconnected.store(true, Ordering::Release);
+                Poll::Ready(Ok(amt))
+            }
+            other => other,
+        }
+    }
+}
+
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::NotConnected
+    )
+}
+
+impl VirtualFile for DisconnectTolerantFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        self.inner.set_len(new_size)
+    }
+
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        self.inner.set_times(atime, mtime)
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        self.inner.unlink()
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn get_special_fd(&self) -> Option<u32> {
+        self.inner.get_special_fd()
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        if self.is_disconnected() {
+            return Poll::Ready(Ok(8192));
+        }
+        let res = Pin::new(self.inner.as_mut()).poll_write_ready(cx);
+        self.swallow(res, 8192)
+    }
+}
+
+impl AsyncWrite for DisconnectTolerantFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.is_disconnected() {
+            return Poll::Ready(Ok(buf.len()));
+        }
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.swallow(res, buf.len())
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        if self.is_disconnected() {
+            return Poll::Ready(Ok(len));
+        }
+        let res = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        self.swallow(res, len)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if self.is_disconnected() {
+            return Poll::Ready(Ok(()));
+        }
+        let res = Pin::new(&mut self.inner).poll_flush(cx);
+        self.swallow(res, ())
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if self.is_disconnected() {
+            return Poll::Ready(Ok(()));
+        }
+        let res = Pin::new(&mut self.inner).poll_shutdown(cx);
+        self.swallow(res, ())
+    }
+}
+
+impl AsyncRead for DisconnectTolerantFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for DisconnectTolerantFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::Pipe;
+
+    #[tokio::test]
+    async fn writes_after_the_reader_goes_away_are_discarded() {
+        let (local, mut remote) = Pipe::channel();
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let mut file = DisconnectTolerantFile::new(Box::new(local), disconnected.clone());
+
+        file.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        remote.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        assert!(!file.is_disconnected());
+
+        drop(remote);
+        file.write_all(b"anyone there?").await.unwrap();
+        file.flush().await.unwrap();
+
+        assert!(file.is_disconnected());
+        assert!(disconnected.load(Ordering::Acquire));
+    }
+}
@@ -49,10 +49,26 @@ impl VirtualFile for DualWriteFile {
         self.inner.set_len(new_size)
     }
 
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        self.inner.set_times(atime, mtime)
+    }
+
     fn unlink(&mut self) -> Result<()> {
         self.inner.unlink()
     }
 
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+
     fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
         Pin::new(self.inner.as_mut()).poll_read_ready(cx)
     }
@@ -0,0 +1,16 @@
+//! Swapping two entries of a file system in one step.
+
+use std::path::Path;
+
+use crate::Result;
+
+/// File systems that can swap two of their entries atomically, like
+/// `renameat2()` with `RENAME_EXCHANGE` does on Linux.
+pub trait ExchangeFileSystem {
+    /// Swaps the files or directories at `a` and `b`, so each path ends up
+    /// with what the other one had.
+    ///
+    /// Both paths must exist. Nobody can observe a state in which either of
+    /// them is missing, or in which both refer to the same entry.
+    fn exchange(&self, a: &Path, b: &Path) -> Result<()>;
+}
@@ -0,0 +1,136 @@
+//! Hashing of files without loading them fully into memory.
+
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::{FileSystem, Result};
+
+/// Size of the chunks that are read from the file and fed into the digest.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The digest algorithm used by [`hash_file()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgo {
+    /// SHA-256, producing a 32 byte digest.
+    Sha256,
+    /// BLAKE3, producing a 32 byte digest.
+    Blake3,
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgo::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Asynchronously compute the digest of a file's contents.
+///
+/// The file is streamed through the digest in fixed size chunks so it never
+/// needs to be held in memory in its entirety.
+pub async fn hash_file(fs: &dyn FileSystem, path: &Path, algo: HashAlgo) -> Result<Vec<u8>> {
+    let mut f = fs.new_open_options().read(true).open(path)?;
+    let mut hasher = Hasher::new(algo);
+    let mut buffer = vec![0; CHUNK_SIZE];
+
+    loop {
+        let read = f.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::mem_fs::FileSystem as MemFS;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[tokio::test]
+    async fn hash_known_content() {
+        let fs = MemFS::default();
+        let mut f = fs
+            .new_open_options()
+            .create(true)
+            .write(true)
+            .open("/file.txt")
+            .unwrap();
+        // Larger than a single chunk so the streaming path is exercised
+        for _ in 0..(CHUNK_SIZE / 16 + 1) {
+            f.write_all(b"0123456789abcdef").await.unwrap();
+        }
+        fs.new_open_options()
+            .create(true)
+            .write(true)
+            .open("/abc.txt")
+            .unwrap()
+            .write_all(b"abc")
+            .await
+            .unwrap();
+
+        let sha256 = hash_file(&fs, Path::new("/abc.txt"), HashAlgo::Sha256)
+            .await
+            .unwrap();
+        assert_eq!(
+            hex(&sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let blake3 = hash_file(&fs, Path::new("/abc.txt"), HashAlgo::Blake3)
+            .await
+            .unwrap();
+        assert_eq!(
+            hex(&blake3),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+
+        let contents = crate::ops::read(&fs, "/file.txt").await.unwrap();
+        let streamed = hash_file(&fs, Path::new("/file.txt"), HashAlgo::Sha256)
+            .await
+            .unwrap();
+        assert_eq!(streamed, Sha256::digest(&contents).to_vec());
+    }
+
+    #[tokio::test]
+    async fn hash_missing_file() {
+        let fs = MemFS::default();
+        let err = hash_file(&fs, Path::new("/missing"), HashAlgo::Blake3)
+            .await
+            .unwrap_err();
+        assert_eq!(err, crate::FsError::EntryNotFound);
+    }
+}
@@ -6,10 +6,12 @@ use bytes::{Buf, Bytes};
 #[cfg(feature = "enable-serde")]
 use serde::{de, Deserialize, Serialize};
 use std::convert::TryInto;
+use std::ffi::OsString;
 use std::fs;
 use std::io::{self, Seek};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -119,6 +121,40 @@ impl crate::FileSystem for FileSystem {
         fs::remove_file(path).map_err(Into::into)
     }
 
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        use std::io::Write;
+
+        static NEXT_TEMP_FILE: AtomicU64 = AtomicU64::new(0);
+
+        let parent = path.parent().ok_or(FsError::BaseNotDirectory)?;
+        let name = path.file_name().ok_or(FsError::InvalidInput)?;
+
+        // The contents go into a temporary file next to the target first,
+        // which is then renamed over it in one go
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        let temp_path = parent.join(temp_name);
+
+        let result = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(contents)?;
+                file.sync_data()
+            })
+            .and_then(|()| fs::rename(&temp_path, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result.map_err(Into::into)
+    }
+
     fn new_open_options(&self) -> OpenOptions {
         OpenOptions::new(self)
     }
@@ -128,6 +164,16 @@ impl crate::FileSystem for FileSystem {
             .and_then(TryInto::try_into)
             .map_err(Into::into)
     }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        fs::symlink_metadata(path)
+            .and_then(TryInto::try_into)
+            .map_err(Into::into)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        fs::read_link(path).map_err(Into::into)
+    }
 }
 
 impl TryInto<Metadata> for std::fs::Metadata {
@@ -389,10 +435,34 @@ impl VirtualFile for File {
         fs::File::set_len(&self.inner_std, new_size).map_err(Into::into)
     }
 
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        use filetime::{set_file_handle_times, FileTime};
+        let to_file_time = |nanos: u64| {
+            FileTime::from_unix_time(
+                (nanos / 1_000_000_000) as i64,
+                (nanos % 1_000_000_000) as u32,
+            )
+        };
+        set_file_handle_times(
+            &self.inner_std,
+            atime.map(to_file_time),
+            mtime.map(to_file_time),
+        )
+        .map_err(Into::into)
+    }
+
     fn unlink(&mut self) -> Result<()> {
         fs::remove_file(&self.host_path).map_err(Into::into)
     }
 
+    fn sync(&mut self) -> Result<()> {
+        self.inner_std.sync_all().map_err(Into::into)
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner_std.sync_data().map_err(Into::into)
+    }
+
     fn get_special_fd(&self) -> Option<u32> {
         None
     }
@@ -1366,4 +1436,116 @@ mod tests {
             "canonicalizing a crazily stupid path name",
         );
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_canonicalize_follows_symlinks() {
+        let temp = TempDir::new().unwrap();
+        let root_dir = temp.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root_dir.join("real/dir")).unwrap();
+        std::fs::write(root_dir.join("real/dir/file.txt"), b"").unwrap();
+        std::os::unix::fs::symlink("real/dir", root_dir.join("link")).unwrap();
+        std::os::unix::fs::symlink(root_dir.join("loop-b"), root_dir.join("loop-a")).unwrap();
+        std::os::unix::fs::symlink(root_dir.join("loop-a"), root_dir.join("loop-b")).unwrap();
+
+        let fs = FileSystem::default();
+
+        assert_eq!(
+            FileSystemTrait::canonicalize(&fs, &root_dir.join("link/file.txt")),
+            Ok(root_dir.join("real/dir/file.txt")),
+            "resolving a symlinked component",
+        );
+        assert_eq!(
+            FileSystemTrait::canonicalize(&fs, &root_dir.join("link/../dir")),
+            Ok(root_dir.join("real/dir")),
+            "`..` applies to the symlink target",
+        );
+        assert_eq!(
+            FileSystemTrait::canonicalize(&fs, &root_dir.join("link/missing.txt")),
+            Err(FsError::EntryNotFound),
+            "resolving a missing file behind a symlink",
+        );
+        assert_eq!(
+            FileSystemTrait::canonicalize(&fs, &root_dir.join("loop-a")),
+            Err(FsError::TooManySymlinks),
+            "detecting a symlink loop",
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_dir_reports_special_file_types() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp = TempDir::new().unwrap();
+        std::fs::write(temp.path().join("file.txt"), b"").unwrap();
+        std::os::unix::fs::symlink("file.txt", temp.path().join("link")).unwrap();
+        let fifo = CString::new(temp.path().join("pipe").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        let fs = FileSystem::default();
+        let file_types = fs
+            .read_dir(temp.path())
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let name = entry.file_name().to_str().unwrap().to_string();
+                (name, entry.file_type().unwrap())
+            })
+            .collect::<Vec<_>>();
+
+        let (name, ft) = &file_types[0];
+        assert_eq!(name, "file.txt");
+        assert!(ft.is_file() && !ft.is_symlink(), "{ft:?}");
+
+        // Symlinks are reported as such rather than as what they point to
+        let (name, ft) = &file_types[1];
+        assert_eq!(name, "link");
+        assert!(ft.is_symlink() && !ft.is_file(), "{ft:?}");
+
+        let (name, ft) = &file_types[2];
+        assert_eq!(name, "pipe");
+        assert!(ft.is_fifo() && !ft.is_file(), "{ft:?}");
+    }
+
+    #[test]
+    fn test_write_file_replaces_the_file() {
+        let temp = TempDir::new().unwrap();
+        let fs = FileSystem::default();
+        let path = temp.path().join("config.toml");
+
+        fs.write_file(&path, b"old").unwrap();
+        fs.write_file(&path, b"new contents").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new contents");
+        // The temporary file is gone after being renamed
+        assert_eq!(read_dir_names(&fs, temp.path()), ["config.toml"]);
+        assert_eq!(
+            fs.write_file(&temp.path().join("missing/config.toml"), b""),
+            Err(FsError::EntryNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_writes_through_to_the_host() {
+        use tokio::io::AsyncWriteExt;
+
+        let temp = TempDir::new().unwrap();
+        let fs = FileSystem::default();
+        let path = temp.path().join("synced.txt");
+
+        let mut file = fs
+            .new_open_options()
+            .create(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"durable").await.unwrap();
+        file.flush().await.unwrap();
+        file.sync_data().unwrap();
+        file.sync().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"durable");
+    }
 }
@@ -18,22 +18,38 @@ pub mod arc_box_file;
 pub mod arc_file;
 pub mod arc_fs;
 pub mod buffer_file;
+pub mod buffered_read_file;
 pub mod builder;
+mod caching_fs;
 pub mod combine_file;
+pub mod concat_file;
 pub mod cow_file;
+pub mod crlf_file;
+pub mod disconnect_tolerant_file;
 pub mod dual_write_file;
 pub mod empty_fs;
+mod hash;
 #[cfg(feature = "host-fs")]
 pub mod host_fs;
 pub mod mem_fs;
+pub mod metered_file;
 pub mod null_file;
 pub mod passthru_fs;
+pub mod prefixed_file;
 pub mod random_file;
+mod read_only_fs;
+pub mod recording_fs;
+pub mod remote_fs;
 pub mod special_file;
+mod static_file;
 pub mod tmp_fs;
+#[cfg(feature = "host-fs")]
+mod timeout_fs;
 pub mod union_fs;
+mod watch;
 pub mod zero_file;
 // tty_file -> see wasmer_wasi::tty_file
+mod exchange;
 mod filesystems;
 pub(crate) mod ops;
 mod overlay_fs;
@@ -52,20 +68,36 @@ pub use arc_box_file::*;
 pub use arc_file::*;
 pub use arc_fs::*;
 pub use buffer_file::*;
+pub use buffered_read_file::*;
 pub use builder::*;
+pub use caching_fs::CachingFileSystem;
 pub use combine_file::*;
+pub use concat_file::*;
 pub use cow_file::*;
+pub use crlf_file::*;
+pub use disconnect_tolerant_file::*;
 pub use dual_write_file::*;
 pub use empty_fs::*;
+pub use exchange::ExchangeFileSystem;
 pub use filesystems::FileSystems;
+pub use hash::{hash_file, HashAlgo};
+pub use metered_file::*;
 pub use null_file::*;
 pub use overlay_fs::OverlayFileSystem;
 pub use passthru_fs::*;
 pub use pipe::*;
+pub use prefixed_file::*;
+pub use read_only_fs::ReadOnlyFileSystem;
+pub use recording_fs::{FsOperation, FsOperationRecord, RecordingFileSystem};
+pub use remote_fs::{ChannelTransport, RemoteFileSystem, RemoteFileSystemServer, RemoteTransport};
 pub use special_file::*;
+pub use static_file::StaticFile;
 pub use tmp_fs::*;
+#[cfg(feature = "host-fs")]
+pub use timeout_fs::{BlockingExecutor, TimeoutFileSystem, WorkerPool};
 pub use trace_fs::TraceFileSystem;
 pub use union_fs::*;
+pub use watch::{FsEvent, FsWatcher, WatchFileSystem};
 #[cfg(feature = "webc-fs")]
 pub use webc_volume_fs::WebcVolumeFileSystem;
 pub use zero_file::*;
@@ -92,7 +124,43 @@ pub trait FileSystem: fmt::Debug + Send + Sync + 'static + Upcastable {
     fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
         self.metadata(path)
     }
+    /// Reads the target of a symbolic link.
+    ///
+    /// File systems without symlink support return [`FsError::InvalidInput`],
+    /// the same as reading a path that isn't a symlink.
+    fn read_link(&self, _path: &Path) -> Result<PathBuf> {
+        Err(FsError::InvalidInput)
+    }
+    /// Returns the absolute form of a path with all `.` and `..` components
+    /// collapsed and all symlinks resolved.
+    ///
+    /// Unlike [`TmpFileSystem::canonicalize_unchecked`], this fails with
+    /// [`FsError::EntryNotFound`] when the path doesn't exist.
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        ops::canonicalize(self, path)
+    }
     fn remove_file(&self, path: &Path) -> Result<()>;
+    /// Creates or replaces the file at `path` with `contents` atomically, so
+    /// anyone reading the file sees either the old or the new contents in
+    /// full, never a partially written file.
+    ///
+    /// The parent directory must already exist. File systems that can't
+    /// replace a file atomically return [`FsError::InvalidInput`].
+    fn write_file(&self, _path: &Path, _contents: &[u8]) -> Result<()> {
+        Err(FsError::InvalidInput)
+    }
+    /// Like [`FileSystem::write_file()`], but when `create_parents` is set
+    /// the missing directories leading up to `path` are created first.
+    ///
+    /// Only the file itself is written atomically.
+    fn write_file_ext(&self, path: &Path, contents: &[u8], create_parents: bool) -> Result<()> {
+        if create_parents {
+            if let Some(parent) = path.parent() {
+                ops::create_dir_all(self, parent)?;
+            }
+        }
+        self.write_file(path, contents)
+    }
 
     fn new_open_options(&self) -> OpenOptions;
 }
@@ -133,10 +201,26 @@ where
         (**self).metadata(path)
     }
 
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        (**self).symlink_metadata(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        (**self).read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        (**self).canonicalize(path)
+    }
+
     fn remove_file(&self, path: &Path) -> Result<()> {
         (**self).remove_file(path)
     }
 
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        (**self).write_file(path, contents)
+    }
+
     fn new_open_options(&self) -> OpenOptions {
         (**self).new_open_options()
     }
@@ -150,7 +234,7 @@ pub trait FileOpener {
     ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>>;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct OpenOptionsConfig {
     pub read: bool,
     pub write: bool,
@@ -158,6 +242,7 @@ pub struct OpenOptionsConfig {
     pub create: bool,
     pub append: bool,
     pub truncate: bool,
+    pub tmpfile: bool,
 }
 
 impl OpenOptionsConfig {
@@ -170,6 +255,7 @@ impl OpenOptionsConfig {
             create: parent_rights.create && self.create,
             append: parent_rights.append && self.append,
             truncate: parent_rights.truncate && self.truncate,
+            tmpfile: parent_rights.tmpfile && self.tmpfile,
         }
     }
 
@@ -197,6 +283,10 @@ impl OpenOptionsConfig {
         self.truncate
     }
 
+    pub const fn tmpfile(&self) -> bool {
+        self.tmpfile
+    }
+
     /// Would a file opened with this [`OpenOptionsConfig`] change files on the
     /// filesystem.
     pub const fn would_mutate(&self) -> bool {
@@ -207,8 +297,9 @@ impl OpenOptionsConfig {
             create,
             append,
             truncate,
+            tmpfile,
         } = *self;
-        append || write || create || create_new || truncate
+        append || write || create || create_new || truncate || tmpfile
     }
 }
 
@@ -234,6 +325,7 @@ impl<'a> OpenOptions<'a> {
                 create: false,
                 append: false,
                 truncate: false,
+                tmpfile: false,
             },
         }
     }
@@ -303,6 +395,16 @@ impl<'a> OpenOptions<'a> {
         self
     }
 
+    /// Sets the option to create an anonymous file in the directory at the
+    /// path that gets opened, like `O_TMPFILE`.
+    ///
+    /// The file has no name in the directory, and goes away once the last
+    /// handle to it is closed. It must be opened with write access.
+    pub fn tmpfile(&mut self, tmpfile: bool) -> &mut Self {
+        self.conf.tmpfile = tmpfile;
+        self
+    }
+
     pub fn open<P: AsRef<Path>>(
         &mut self,
         path: P,
@@ -335,6 +437,16 @@ pub trait VirtualFile:
     /// Request deletion of the file
     fn unlink(&mut self) -> Result<()>;
 
+    /// Changes the last accessed and last modified times of the file, in
+    /// nanoseconds as a UNIX timestamp. A timestamp that is `None` is left
+    /// unchanged.
+    ///
+    /// Defaults to ignoring the request.
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        let _ = (atime, mtime);
+        Ok(())
+    }
+
     /// Indicates if the file is opened or closed. This function must not block
     /// Defaults to a status of being constantly open
     fn is_open(&self) -> bool {
@@ -348,6 +460,30 @@ pub trait VirtualFile:
         None
     }
 
+    /// Releases any memory that isn't needed to hold the contents of the file.
+    ///
+    /// This is called when the guest advises that it won't access the data
+    /// in the near future (`POSIX_FADV_DONTNEED`). The contents of the file
+    /// must not change.
+    fn shrink_to_fit(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    /// Makes sure the contents and the metadata of the file have reached
+    /// durable storage, like `fsync()`. Any buffered writes must have been
+    /// flushed before calling this.
+    ///
+    /// Files that only live in memory have nothing to do.
+    fn sync(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    /// Like [`VirtualFile::sync`] but only the contents of the file need to
+    /// reach durable storage, like `fdatasync()`.
+    fn sync_data(&mut self) -> crate::Result<()> {
+        self.sync()
+    }
+
     /// This method will copy a file from a source to this destination where
     /// the default is to do a straight byte copy however file system implementors
     /// may optimize this to do a zero copy
@@ -362,11 +498,59 @@ pub trait VirtualFile:
         })
     }
 
-    /// Polls the file for when there is data to be read
-    fn poll_read_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>>;
+    /// Reads from the file at `offset` without moving the cursor, like
+    /// `pread()`, and returns how many bytes were read.
+    ///
+    /// It only needs a shared reference, so several reads (and writes) can
+    /// happen at the same time. That rules out a default that seeks and
+    /// reads, so files that don't support it return an
+    /// [`io::ErrorKind::Unsupported`] error instead. Callers then have to
+    /// fall back to seeking and reading themselves, like `fd_pread` does.
+    ///
+    /// Wrappers that simply pass operations through (like [`MeteredFile`]
+    /// and [`ArcBoxFile`]) forward this to the file they wrap.
+    fn read_at<'a>(
+        &'a self,
+        _offset: u64,
+        _buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = std::io::Result<usize>> + 'a>> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+
+    /// Writes to the file at `offset` without moving the cursor, like
+    /// `pwrite()`, and returns how many bytes were written.
+    ///
+    /// Like [`VirtualFile::read_at`], it only needs a shared reference and
+    /// defaults to an [`io::ErrorKind::Unsupported`] error.
+    fn write_at<'a>(
+        &'a self,
+        _offset: u64,
+        _buf: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = std::io::Result<usize>> + 'a>> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+
+    /// Polls the file for when there is data to be read, returning how many
+    /// bytes can be read without blocking. This is what `poll_oneoff` waits
+    /// on for `FdRead` subscriptions.
+    ///
+    /// Files that aren't always readable return [`Poll::Pending`] and wake
+    /// the waker in `cx` once they are. `Ok(0)` means the end of the file
+    /// was reached (a hang up).
+    ///
+    /// Defaults to always being ready.
+    fn poll_read_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(8192))
+    }
 
-    /// Polls the file for when it is available for writing
-    fn poll_write_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>>;
+    /// Polls the file for when it is available for writing, returning how
+    /// many bytes can be written without blocking. This is what
+    /// `poll_oneoff` waits on for `FdWrite` subscriptions.
+    ///
+    /// Defaults to always being ready.
+    fn poll_write_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(8192))
+    }
 }
 
 // Implementation of `Upcastable` taken from https://users.rust-lang.org/t/why-does-downcasting-not-work-for-subtraits/33286/7 .
@@ -481,8 +665,20 @@ pub enum FsError {
     /// Directory not Empty
     #[error("directory not empty")]
     DirectoryNotEmpty,
+    /// Too many symbolic links were encountered while resolving a path
+    #[error("too many levels of symbolic links")]
+    TooManySymlinks,
     #[error("storage full")]
     StorageFull,
+    /// The file system does not allow changes to be made
+    #[error("read-only file system")]
+    ReadOnly,
+    /// The entry can't be moved to a different file system
+    #[error("cross-device link")]
+    CrossDevice,
+    /// Expected something other than a directory, but found a directory
+    #[error("is a directory")]
+    IsADirectory,
     /// Some other unhandled error. If you see this, it's probably a bug.
     #[error("unknown error found")]
     UnknownError,
@@ -544,8 +740,12 @@ impl From<FsError> for io::Error {
             FsError::Lock => io::ErrorKind::Other,
             FsError::NoDevice => io::ErrorKind::Other,
             FsError::DirectoryNotEmpty => io::ErrorKind::Other,
+            FsError::TooManySymlinks => io::ErrorKind::Other,
             FsError::UnknownError => io::ErrorKind::Other,
             FsError::StorageFull => io::ErrorKind::Other,
+            FsError::ReadOnly => io::ErrorKind::PermissionDenied,
+            FsError::CrossDevice => io::ErrorKind::Other,
+            FsError::IsADirectory => io::ErrorKind::Other,
             // NOTE: Add this once the "io_error_more" Rust feature is stabilized
             // FsError::StorageFull => io::ErrorKind::StorageFull,
         };
@@ -581,10 +781,21 @@ impl DirEntry {
         self.path.clone()
     }
 
+    /// The metadata of the entry, as it was when the directory was listed.
+    ///
+    /// This avoids looking every entry up again after listing a directory,
+    /// at the cost of not reflecting changes made since then. Use
+    /// [`DirEntry::refresh_metadata()`] when an up-to-date copy is needed.
     pub fn metadata(&self) -> Result<Metadata> {
         self.metadata.clone()
     }
 
+    /// Looks the entry up again in `fs`, updating the cached metadata.
+    pub fn refresh_metadata(&mut self, fs: &dyn FileSystem) -> Result<Metadata> {
+        self.metadata = fs.metadata(&self.path);
+        self.metadata.clone()
+    }
+
     pub fn file_type(&self) -> Result<FileType> {
         let metadata = self.metadata.clone()?;
         Ok(metadata.file_type())
@@ -49,6 +49,10 @@ mod tracked_vec {
             })
         }
 
+        pub fn capacity(&self) -> usize {
+            self.data.capacity()
+        }
+
         pub fn clear(&mut self) {
             self.data.clear();
         }
@@ -110,6 +114,14 @@ mod tracked_vec {
             }
             Ok(())
         }
+
+        pub fn shrink_to_fit(&mut self) {
+            let old_capacity = self.data.capacity();
+            self.data.shrink_to_fit();
+            if let Some(limiter) = &self.limiter {
+                limiter.on_shrink(old_capacity - self.data.capacity());
+            }
+        }
     }
 
     impl Drop for TrackedVec {
@@ -164,6 +176,10 @@ mod tracked_vec {
             })
         }
 
+        pub fn capacity(&self) -> usize {
+            self.data.capacity()
+        }
+
         pub fn clear(&mut self) {
             self.data.clear();
         }
@@ -192,6 +208,10 @@ mod tracked_vec {
             self.data.reserve_exact(additional);
             Ok(())
         }
+
+        pub fn shrink_to_fit(&mut self) {
+            self.data.shrink_to_fit();
+        }
     }
 
     impl std::ops::Deref for TrackedVec {
@@ -0,0 +1,367 @@
+//! The storage behind the contents of a [`File`][super::File].
+
+use std::cmp;
+
+use crate::limiter::{DynFsMemoryLimiter, TrackedVec};
+use crate::{FsError, Result};
+
+/// The most bytes that are stored in a single chunk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+enum Chunk {
+    /// Bytes that were written to the file.
+    Data(TrackedVec),
+    /// A run of zeros that doesn't take up any memory, e.g. after seeking
+    /// past the end of the file and writing there.
+    Hole(u64),
+}
+
+impl Chunk {
+    fn len(&self) -> u64 {
+        match self {
+            Chunk::Data(data) => data.len() as u64,
+            Chunk::Hole(len) => *len,
+        }
+    }
+}
+
+/// A buffer of bytes that is split into chunks of at most [`CHUNK_SIZE`]
+/// bytes.
+///
+/// Growing the buffer never moves more than one chunk around, so files can
+/// get very large without ever needing one huge allocation, and runs of
+/// zeros are kept as holes so sparse files stay cheap.
+#[derive(Debug)]
+pub(super) struct ChunkedBuffer {
+    chunks: Vec<Chunk>,
+    /// The offset at which each of the chunks starts.
+    starts: Vec<u64>,
+    len: u64,
+    limiter: Option<DynFsMemoryLimiter>,
+}
+
+impl ChunkedBuffer {
+    pub(super) fn new(limiter: Option<DynFsMemoryLimiter>) -> Self {
+        Self {
+            chunks: Vec::new(),
+            starts: Vec::new(),
+            len: 0,
+            limiter,
+        }
+    }
+
+    pub(super) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// The memory that is reserved for the contents.
+    #[cfg(test)]
+    pub(super) fn capacity(&self) -> usize {
+        self.chunks
+            .iter()
+            .map(|chunk| match chunk {
+                Chunk::Data(data) => data.capacity(),
+                Chunk::Hole(_) => 0,
+            })
+            .sum()
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.chunks.clear();
+        self.starts.clear();
+        self.len = 0;
+    }
+
+    /// Gives back the memory that the chunks reserved beyond their length.
+    pub(super) fn shrink_to_fit(&mut self) {
+        for chunk in &mut self.chunks {
+            if let Chunk::Data(data) = chunk {
+                data.shrink_to_fit();
+            }
+        }
+        self.chunks.shrink_to_fit();
+        self.starts.shrink_to_fit();
+    }
+
+    /// Copies the contents into a new buffer that is accounted to `limiter`.
+    pub(super) fn duplicate(&self, limiter: Option<DynFsMemoryLimiter>) -> Result<Self> {
+        let mut chunks = Vec::with_capacity(self.chunks.len());
+        for chunk in &self.chunks {
+            chunks.push(match chunk {
+                Chunk::Data(data) => {
+                    let mut copy = TrackedVec::with_capacity(data.len(), limiter.clone())?;
+                    copy.extend_from_slice(data)?;
+                    Chunk::Data(copy)
+                }
+                Chunk::Hole(len) => Chunk::Hole(*len),
+            });
+        }
+
+        Ok(Self {
+            chunks,
+            starts: self.starts.clone(),
+            len: self.len,
+            limiter,
+        })
+    }
+
+    /// Copies the bytes starting at `offset` into `buf`, and returns how
+    /// many there were.
+    pub(super) fn read_at(&self, offset: u64, buf: &mut [u8]) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+
+        let mut index = self.chunk_at(offset);
+        let mut skip = offset - self.starts[index];
+        let mut read = 0;
+        while read < buf.len() && index < self.chunks.len() {
+            let chunk = &self.chunks[index];
+            let amount = cmp::min(chunk.len() - skip, (buf.len() - read) as u64) as usize;
+            let out = &mut buf[read..read + amount];
+            match chunk {
+                Chunk::Data(data) => out.copy_from_slice(&data[skip as usize..][..amount]),
+                Chunk::Hole(_) => out.fill(0),
+            }
+
+            read += amount;
+            skip = 0;
+            index += 1;
+        }
+        read
+    }
+
+    /// Inserts `data` at `offset`, moving everything after it further
+    /// back. Writing past the end leaves a hole between the old end and
+    /// `offset`.
+    pub(super) fn insert(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        if offset >= self.len {
+            self.extend_with_zeros(offset - self.len);
+            return self.extend_from_slice(data);
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let index = self.split_at(offset)?;
+
+        // Fill up the chunk in front of the insertion point if there's room
+        // left, so writing in small pieces doesn't leave lots of tiny chunks
+        let mut data = data;
+        if let Some(Chunk::Data(previous)) = index.checked_sub(1).map(|i| &mut self.chunks[i]) {
+            let amount = cmp::min(CHUNK_SIZE.saturating_sub(previous.len()), data.len());
+            extend_chunk(previous, &data[..amount])?;
+            data = &data[amount..];
+        }
+
+        let mut new_chunks = Vec::new();
+        for piece in data.chunks(CHUNK_SIZE) {
+            new_chunks.push(self.new_chunk(piece)?);
+        }
+        self.chunks.splice(index..index, new_chunks);
+        self.recompute_starts(index.saturating_sub(1));
+        Ok(())
+    }
+
+    /// Shrinks or grows the buffer to `new_len` bytes, padding it with
+    /// zeros when it grows.
+    pub(super) fn resize(&mut self, new_len: u64) -> Result<()> {
+        if new_len >= self.len {
+            self.extend_with_zeros(new_len - self.len);
+            return Ok(());
+        }
+
+        let index = self.split_at(new_len)?;
+        self.chunks.truncate(index);
+        self.starts.truncate(index);
+        self.len = new_len;
+        Ok(())
+    }
+
+    /// Appends `data` at the end of the buffer.
+    fn extend_from_slice(&mut self, mut data: &[u8]) -> Result<()> {
+        if let Some(Chunk::Data(last)) = self.chunks.last_mut() {
+            let amount = cmp::min(CHUNK_SIZE.saturating_sub(last.len()), data.len());
+            extend_chunk(last, &data[..amount])?;
+            self.len += amount as u64;
+            data = &data[amount..];
+        }
+
+        for piece in data.chunks(CHUNK_SIZE) {
+            let chunk = self.new_chunk(piece)?;
+            self.push(chunk);
+        }
+        Ok(())
+    }
+
+    /// Appends `len` zeros at the end of the buffer.
+    fn extend_with_zeros(&mut self, len: u64) {
+        if len == 0 {
+            return;
+        }
+        if let Some(Chunk::Hole(last)) = self.chunks.last_mut() {
+            *last += len;
+            self.len += len;
+        } else {
+            self.push(Chunk::Hole(len));
+        }
+    }
+
+    fn new_chunk(&self, data: &[u8]) -> Result<Chunk> {
+        let mut chunk = TrackedVec::with_capacity(data.len(), self.limiter.clone())?;
+        chunk.extend_from_slice(data)?;
+        Ok(Chunk::Data(chunk))
+    }
+
+    fn push(&mut self, chunk: Chunk) {
+        self.starts.push(self.len);
+        self.len += chunk.len();
+        self.chunks.push(chunk);
+    }
+
+    /// The index of the chunk that holds the byte at `offset`, which must be
+    /// inside the buffer.
+    fn chunk_at(&self, offset: u64) -> usize {
+        self.starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// Makes sure a chunk starts at `offset`, and returns its index (which
+    /// is the number of chunks when `offset` is the end of the buffer).
+    fn split_at(&mut self, offset: u64) -> Result<usize> {
+        if offset >= self.len {
+            return Ok(self.chunks.len());
+        }
+
+        let index = self.chunk_at(offset);
+        let at = offset - self.starts[index];
+        if at == 0 {
+            return Ok(index);
+        }
+
+        let tail = match &mut self.chunks[index] {
+            Chunk::Data(data) => {
+                let at = usize::try_from(at).map_err(|_| FsError::InvalidInput)?;
+                Chunk::Data(data.split_off(at)?)
+            }
+            Chunk::Hole(len) => {
+                let tail = *len - at;
+                *len = at;
+                Chunk::Hole(tail)
+            }
+        };
+        self.chunks.insert(index + 1, tail);
+        self.starts.insert(index + 1, offset);
+        Ok(index + 1)
+    }
+
+    /// Updates the start of every chunk from `index` onwards.
+    fn recompute_starts(&mut self, index: usize) {
+        self.starts.truncate(index);
+        let mut start = match index.checked_sub(1) {
+            Some(previous) => self.starts[previous] + self.chunks[previous].len(),
+            None => 0,
+        };
+        for chunk in &self.chunks[index..] {
+            self.starts.push(start);
+            start += chunk.len();
+        }
+        self.len = start;
+    }
+}
+
+/// Appends `data` to a chunk, growing it geometrically like a `Vec` would
+/// but never beyond [`CHUNK_SIZE`].
+fn extend_chunk(chunk: &mut TrackedVec, data: &[u8]) -> Result<()> {
+    let needed = chunk.len() + data.len();
+    if needed > chunk.capacity() {
+        let target = cmp::max(needed, cmp::min(chunk.capacity() * 2, CHUNK_SIZE));
+        chunk.reserve_exact(target - chunk.len())?;
+    }
+    chunk.extend_from_slice(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(buffer: &ChunkedBuffer) -> Vec<u8> {
+        let mut buf = vec![0; buffer.len() as usize];
+        assert_eq!(buffer.read_at(0, &mut buf), buf.len());
+        buf
+    }
+
+    #[test]
+    fn appending_many_times_keeps_chunks_bounded() {
+        let mut buffer = ChunkedBuffer::new(None);
+        let piece: Vec<u8> = (0..=99).collect();
+        for _ in 0..100_000 {
+            buffer.insert(buffer.len(), &piece).unwrap();
+        }
+
+        // Growing never reallocates more than one chunk's worth of data
+        assert_eq!(buffer.len(), 10_000_000);
+        assert_eq!(
+            buffer.chunks.len(),
+            (10_000_000 + CHUNK_SIZE - 1) / CHUNK_SIZE
+        );
+        assert!(buffer.chunks.iter().all(|chunk| match chunk {
+            Chunk::Data(data) => data.capacity() <= CHUNK_SIZE,
+            Chunk::Hole(_) => false,
+        }));
+
+        let mut buf = [0; 150];
+        assert_eq!(buffer.read_at(CHUNK_SIZE as u64 * 3 - 10, &mut buf), 150);
+        let offset = CHUNK_SIZE * 3 - 10;
+        let expected: Vec<u8> = (offset..offset + 150).map(|i| (i % 100) as u8).collect();
+        assert_eq!(buf[..], expected[..]);
+    }
+
+    #[test]
+    fn sparse_writes_at_far_offsets() {
+        let mut buffer = ChunkedBuffer::new(None);
+        buffer.insert(0, b"start").unwrap();
+        let far = 1 << 40;
+        buffer.insert(far, b"end").unwrap();
+
+        // The gap doesn't take up any memory
+        assert_eq!(buffer.len(), far + 3);
+        assert!(buffer.capacity() < CHUNK_SIZE);
+
+        let mut buf = [1; 8];
+        assert_eq!(buffer.read_at(far - 5, &mut buf), 8);
+        assert_eq!(&buf, b"\0\0\0\0\0end");
+
+        // Writing into the hole splits it
+        buffer.insert(far / 2, b"middle").unwrap();
+        assert_eq!(buffer.len(), far + 9);
+        let mut buf = [1; 8];
+        assert_eq!(buffer.read_at(far / 2 - 1, &mut buf), 8);
+        assert_eq!(&buf, b"\0middle\0");
+
+        buffer.resize(7).unwrap();
+        assert_eq!(contents(&buffer), b"start\0\0");
+    }
+
+    #[test]
+    fn inserting_and_truncating_across_chunks() {
+        let mut buffer = ChunkedBuffer::new(None);
+        let data = vec![7; CHUNK_SIZE * 2 + 10];
+        buffer.insert(0, &data).unwrap();
+        buffer.insert(CHUNK_SIZE as u64 - 2, b"abcd").unwrap();
+        buffer.insert(0, b"xy").unwrap();
+
+        let mut expected = data.clone();
+        expected.splice(CHUNK_SIZE - 2..CHUNK_SIZE - 2, b"abcd".iter().copied());
+        expected.splice(0..0, b"xy".iter().copied());
+        assert_eq!(contents(&buffer), expected);
+
+        buffer.resize(CHUNK_SIZE as u64 + 1).unwrap();
+        expected.truncate(CHUNK_SIZE + 1);
+        assert_eq!(contents(&buffer), expected);
+
+        buffer.resize(CHUNK_SIZE as u64 + 4).unwrap();
+        expected.extend_from_slice(&[0, 0, 0]);
+        assert_eq!(contents(&buffer), expected);
+    }
+}
@@ -5,8 +5,8 @@
 use tokio::io::AsyncRead;
 use tokio::io::{AsyncSeek, AsyncWrite};
 
+use super::buffer::ChunkedBuffer;
 use super::*;
-use crate::limiter::TrackedVec;
 use crate::{CopyOnWriteFile, FsError, Result, VirtualFile};
 use std::borrow::Cow;
 use std::cmp;
@@ -31,6 +31,7 @@ pub(super) struct FileHandle {
     append_mode: bool,
     cursor: u64,
     arc_file: Option<Result<Box<dyn VirtualFile + Send + Sync + 'static>>>,
+    anonymous: Option<Arc<AnonymousFile>>,
 }
 
 impl Clone for FileHandle {
@@ -43,6 +44,7 @@ impl Clone for FileHandle {
             append_mode: self.append_mode,
             cursor: self.cursor,
             arc_file: None,
+            anonymous: self.anonymous.clone(),
         }
     }
 }
@@ -64,6 +66,24 @@ impl FileHandle {
             append_mode,
             cursor,
             arc_file: None,
+            anonymous: None,
+        }
+    }
+
+    /// Ties the lifetime of the file to this handle (and its clones), for
+    /// files that have no directory entry.
+    pub(super) fn anonymous(mut self) -> Self {
+        self.anonymous = Some(Arc::new(AnonymousFile {
+            inode: self.inode,
+            filesystem: self.filesystem.clone(),
+        }));
+        self
+    }
+
+    fn notify_modified(&self) {
+        // Anonymous files can't be watched, they have no path
+        if self.anonymous.is_none() {
+            self.filesystem.notify_modified(self.inode);
         }
     }
 
@@ -149,7 +169,7 @@ impl VirtualFile for FileHandle {
 
         let inode = fs.storage.get(self.inode);
         match inode {
-            Some(Node::File(node)) => node.file.len().try_into().unwrap_or(0),
+            Some(Node::File(node)) => node.file.len(),
             Some(Node::ReadOnlyFile(node)) => node.file.len().try_into().unwrap_or(0),
             Some(Node::CustomFile(node)) => {
                 let file = node.file.lock().unwrap();
@@ -172,29 +192,53 @@ impl VirtualFile for FileHandle {
     }
 
     fn set_len(&mut self, new_size: u64) -> Result<()> {
-        let mut fs = self.filesystem.inner.write().map_err(|_| FsError::Lock)?;
+        // Like `ftruncate(2)`, the file has to be open for writing
+        if !self.writable {
+            return Err(FsError::PermissionDenied);
+        }
 
-        let inode = fs.storage.get_mut(self.inode);
-        match inode {
-            Some(Node::File(FileNode { file, metadata, .. })) => {
-                file.buffer
-                    .resize(new_size.try_into().map_err(|_| FsError::UnknownError)?, 0)?;
-                metadata.len = new_size;
-            }
-            Some(Node::CustomFile(node)) => {
-                let mut file = node.file.lock().unwrap();
-                file.set_len(new_size)?;
-                node.metadata.len = new_size;
-            }
-            Some(Node::ReadOnlyFile { .. }) => return Err(FsError::PermissionDenied),
-            Some(Node::ArcFile { .. }) => {
-                drop(fs);
-                self.lazy_load_arc_file_mut()
-                    .map(|file| file.set_len(new_size))??;
+        {
+            let mut fs = self.filesystem.inner.write().map_err(|_| FsError::Lock)?;
+
+            let inode = fs.storage.get_mut(self.inode);
+            match inode {
+                Some(Node::File(FileNode { file, metadata, .. })) => {
+                    file.set_len(new_size)?;
+                    metadata.len = new_size;
+                }
+                Some(Node::CustomFile(node)) => {
+                    let mut file = node.file.lock().unwrap();
+                    file.set_len(new_size)?;
+                    node.metadata.len = new_size;
+                }
+                Some(Node::ReadOnlyFile { .. }) => return Err(FsError::PermissionDenied),
+                Some(Node::ArcFile { .. }) => {
+                    drop(fs);
+                    self.lazy_load_arc_file_mut()
+                        .map(|file| file.set_len(new_size))??;
+                }
+                _ => return Err(FsError::NotAFile),
             }
-            _ => return Err(FsError::NotAFile),
         }
 
+        self.notify_modified();
+        Ok(())
+    }
+
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        let mut fs = self.filesystem.inner.write().map_err(|_| FsError::Lock)?;
+
+        let node = fs
+            .storage
+            .get_mut(self.inode)
+            .ok_or(FsError::EntryNotFound)?;
+        let metadata = node.metadata_mut();
+        if let Some(atime) = atime {
+            metadata.accessed = atime;
+        }
+        if let Some(mtime) = mtime {
+            metadata.modified = mtime;
+        }
         Ok(())
     }
 
@@ -243,6 +287,113 @@ impl VirtualFile for FileHandle {
         Ok(())
     }
 
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        let mut fs = self.filesystem.inner.write().map_err(|_| FsError::Lock)?;
+
+        match fs.storage.get_mut(self.inode) {
+            Some(Node::File(FileNode { file, .. })) => {
+                file.shrink_to_fit();
+                Ok(())
+            }
+            Some(Node::CustomFile(node)) => node.file.lock().unwrap().shrink_to_fit(),
+            // Read-only and referenced files don't own a buffer
+            _ => Ok(()),
+        }
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let fs = self.filesystem.inner.read().map_err(|_| FsError::Lock)?;
+
+        match fs.storage.get(self.inode) {
+            Some(Node::CustomFile(node)) => node.file.lock().unwrap().sync(),
+            // Everything else only lives in memory
+            _ => Ok(()),
+        }
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        let fs = self.filesystem.inner.read().map_err(|_| FsError::Lock)?;
+
+        match fs.storage.get(self.inode) {
+            Some(Node::CustomFile(node)) => node.file.lock().unwrap().sync_data(),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a mut [u8],
+    ) -> Pin<Box<dyn std::future::Future<Output = io::Result<usize>> + 'a>> {
+        Box::pin(async move {
+            if !self.readable {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "the file (inode `{}) doesn't have the `read` permission",
+                        self.inode
+                    ),
+                ));
+            }
+
+            // Reading straight from the buffer only needs a read lock, so
+            // handles to the same file don't have to wait for each other
+            let fs = self.filesystem.inner.read().map_err(|_| FsError::Lock)?;
+            let mut cursor = offset;
+            match fs.storage.get(self.inode) {
+                Some(Node::File(node)) => node.file.read(buf, &mut cursor),
+                Some(Node::ReadOnlyFile(node)) => node.file.read(buf, &mut cursor),
+                _ => Err(io::ErrorKind::Unsupported.into()),
+            }
+        })
+    }
+
+    fn write_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a [u8],
+    ) -> Pin<Box<dyn std::future::Future<Output = io::Result<usize>> + 'a>> {
+        Box::pin(async move {
+            if !self.writable {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "the file (inode `{}) doesn't have the `write` permission",
+                        self.inode
+                    ),
+                ));
+            }
+
+            let written = {
+                let mut fs = self.filesystem.inner.write().map_err(|_| FsError::Lock)?;
+                match fs.storage.get_mut(self.inode) {
+                    Some(Node::File(node)) => {
+                        // Like `pwrite()` on Linux, the offset is ignored
+                        // when appending
+                        let mut cursor = if self.append_mode {
+                            node.file.len()
+                        } else {
+                            offset
+                        };
+                        let written = node.file.write(buf, &mut cursor)?;
+                        node.metadata.len = node.file.len();
+                        Some(written)
+                    }
+                    _ => None,
+                }
+            };
+            match written {
+                Some(written) => {
+                    if written > 0 {
+                        self.notify_modified();
+                    }
+                    Ok(written)
+                }
+                None => Err(io::ErrorKind::Unsupported.into()),
+            }
+        })
+    }
+
     fn get_special_fd(&self) -> Option<u32> {
         let fs = match self.filesystem.inner.read() {
             Ok(a) => a,
@@ -318,11 +469,11 @@ impl VirtualFile for FileHandle {
         let inode = fs.storage.get_mut(self.inode);
         match inode {
             Some(Node::File(node)) => {
-                let remaining = node.file.buffer.len() - (self.cursor as usize);
-                Poll::Ready(Ok(remaining))
+                let remaining = node.file.len().saturating_sub(self.cursor);
+                Poll::Ready(Ok(remaining.try_into().unwrap_or(usize::MAX)))
             }
             Some(Node::ReadOnlyFile(node)) => {
-                let remaining = node.file.buffer.len() - (self.cursor as usize);
+                let remaining = node.file.buffer.len().saturating_sub(self.cursor as usize);
                 Poll::Ready(Ok(remaining))
             }
             Some(Node::CustomFile(node)) => {
@@ -451,6 +602,33 @@ mod test_virtual_file {
         assert!(file.last_modified() > 0, "last modified time is not zero");
     }
 
+    #[test]
+    fn test_set_times() {
+        let fs = FileSystem::default();
+
+        let mut file = fs
+            .new_open_options()
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+        let last_accessed_time = file.last_accessed();
+
+        file.set_times(None, Some(42)).unwrap();
+
+        assert_eq!(file.last_modified(), 42, "the last modified time is set");
+        assert_eq!(
+            file.last_accessed(),
+            last_accessed_time,
+            "the last accessed time is left alone"
+        );
+
+        file.set_times(Some(7), None).unwrap();
+
+        assert_eq!(file.last_accessed(), 7);
+        assert_eq!(file.last_modified(), 42);
+    }
+
     #[test]
     fn test_created_time() {
         let fs = FileSystem::default();
@@ -588,6 +766,11 @@ impl AsyncRead for FileHandle {
                 ),
             )));
         }
+        // Like `read(2)`, asking for nothing is a no-op, even for files
+        // that would otherwise wait for data
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
 
         let mut cursor = self.cursor;
         let ret = {
@@ -775,8 +958,13 @@ impl AsyncWrite for FileHandle {
                 ),
             )));
         }
+        // Writing nothing doesn't move the cursor, not even when appending
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
 
         let mut cursor = self.cursor;
+        let append_mode = self.append_mode;
         let bytes_written = {
             let mut fs = self.filesystem.inner.write().map_err(|_| {
                 io::Error::new(io::ErrorKind::Other, "failed to acquire a write lock")
@@ -785,8 +973,14 @@ impl AsyncWrite for FileHandle {
             let inode = fs.storage.get_mut(self.inode);
             match inode {
                 Some(Node::File(node)) => {
+                    // Moving to the end happens under the same lock as the
+                    // write, so appends from other handles can't get in
+                    // between (like `O_APPEND`)
+                    if append_mode {
+                        cursor = node.file.len();
+                    }
                     let bytes_written = node.file.write(buf, &mut cursor)?;
-                    node.metadata.len = node.file.len().try_into().unwrap();
+                    node.metadata.len = node.file.len();
                     bytes_written
                 }
                 Some(Node::ReadOnlyFile(node)) => {
@@ -839,6 +1033,9 @@ impl AsyncWrite for FileHandle {
             }
         };
         self.cursor = cursor;
+        if bytes_written > 0 {
+            self.notify_modified();
+        }
         Poll::Ready(Ok(bytes_written))
     }
 
@@ -847,7 +1044,12 @@ impl AsyncWrite for FileHandle {
         cx: &mut Context<'_>,
         bufs: &[io::IoSlice<'_>],
     ) -> Poll<io::Result<usize>> {
+        if bufs.iter().all(|buf| buf.is_empty()) {
+            return self.poll_write(cx, &[]);
+        }
+
         let mut cursor = self.cursor;
+        let append_mode = self.append_mode;
         let ret = {
             let mut fs = self.filesystem.inner.write().map_err(|_| {
                 io::Error::new(io::ErrorKind::Other, "failed to acquire a write lock")
@@ -856,12 +1058,16 @@ impl AsyncWrite for FileHandle {
             let inode = fs.storage.get_mut(self.inode);
             match inode {
                 Some(Node::File(node)) => {
-                    let buf = bufs
-                        .iter()
-                        .find(|b| !b.is_empty())
-                        .map_or(&[][..], |b| &**b);
-                    let bytes_written = node.file.write(buf, &mut cursor)?;
-                    node.metadata.len = node.file.buffer.len() as u64;
+                    // All the buffers are written in one go so they end up
+                    // next to each other, even when appending
+                    if append_mode {
+                        cursor = node.file.len();
+                    }
+                    let mut bytes_written = 0;
+                    for buf in bufs {
+                        bytes_written += node.file.write(buf, &mut cursor)?;
+                    }
+                    node.metadata.len = node.file.len();
                     Poll::Ready(Ok(bytes_written))
                 }
                 Some(Node::ReadOnlyFile(node)) => {
@@ -870,7 +1076,7 @@ impl AsyncWrite for FileHandle {
                         .find(|b| !b.is_empty())
                         .map_or(&[][..], |b| &**b);
                     let bytes_written = node.file.write(buf, &mut cursor)?;
-                    node.metadata.len = node.file.buffer.len() as u64;
+                    node.metadata.len = node.file.len() as u64;
                     Poll::Ready(Ok(bytes_written))
                 }
                 Some(Node::CustomFile(node)) => {
@@ -898,6 +1104,11 @@ impl AsyncWrite for FileHandle {
             }
         };
         self.cursor = cursor;
+        if let Poll::Ready(Ok(bytes_written)) = ret {
+            if bytes_written > 0 {
+                self.notify_modified();
+            }
+        }
         ret
     }
 
@@ -1001,7 +1212,7 @@ impl AsyncWrite for FileHandle {
 mod test_read_write_seek {
     use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
-    use crate::{mem_fs::*, FileSystem as FS};
+    use crate::{mem_fs::*, FileSystem as FS, FsError};
     use std::io;
 
     macro_rules! path {
@@ -1010,6 +1221,162 @@ mod test_read_write_seek {
         };
     }
 
+    /// Keeps count of the bytes a [`FileSystem`] has reserved.
+    #[cfg(feature = "tracking")]
+    #[derive(Debug, Default)]
+    struct CountingLimiter(std::sync::atomic::AtomicUsize);
+
+    #[cfg(feature = "tracking")]
+    impl CountingLimiter {
+        fn used(&self) -> usize {
+            self.0.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[cfg(feature = "tracking")]
+    impl crate::limiter::FsMemoryLimiter for CountingLimiter {
+        fn on_grow(&self, grown_bytes: usize) -> std::result::Result<(), FsError> {
+            self.0
+                .fetch_add(grown_bytes, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn on_shrink(&self, shrunk_bytes: usize) {
+            self.0
+                .fetch_sub(shrunk_bytes, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[cfg(feature = "tracking")]
+    #[tokio::test]
+    async fn test_shrink_to_fit_releases_spare_capacity() {
+        use crate::VirtualFile;
+
+        let fs = FileSystem::default();
+        let limiter = std::sync::Arc::new(CountingLimiter::default());
+        fs.set_memory_limiter(limiter.clone());
+
+        let file = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+        // Go through a wrapper to make sure the call is forwarded
+        let mut file = crate::ArcBoxFile::new(file);
+
+        file.write_all(&[1u8; 64 * 1024]).await.unwrap();
+        file.set_len(16).unwrap();
+        let before = limiter.used();
+        assert!(before >= 64 * 1024);
+
+        file.shrink_to_fit().unwrap();
+
+        assert!(limiter.used() < before);
+        assert!(limiter.used() < 64 * 1024);
+        let mut contents = Vec::new();
+        file.rewind().await.unwrap();
+        file.read_to_end(&mut contents).await.unwrap();
+        assert_eq!(contents, vec![1u8; 16]);
+    }
+
+    #[tokio::test]
+    async fn test_appends_go_to_the_current_end() {
+        let fs = FileSystem::default();
+        let open = || {
+            fs.new_open_options()
+                .append(true)
+                .create(true)
+                .open(path!("/log.txt"))
+                .unwrap()
+        };
+        let mut first = open();
+        let mut second = open();
+
+        first.write_all(b"a").await.unwrap();
+        second.write_all(b"b").await.unwrap();
+        first.write_all(b"c").await.unwrap();
+        let bufs = [io::IoSlice::new(b"d"), io::IoSlice::new(b"e")];
+        assert_eq!(second.write_vectored(&bufs).await.unwrap(), 2);
+
+        let mut contents = String::new();
+        fs.new_open_options()
+            .read(true)
+            .open(path!("/log.txt"))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .await
+            .unwrap();
+        assert_eq!(contents, "abcde");
+    }
+
+    #[test]
+    fn test_concurrent_appends_are_not_interleaved() {
+        const THREADS: usize = 8;
+        const RECORDS: usize = 200;
+        const RECORD_LEN: usize = 64;
+
+        let fs = FileSystem::default();
+        fs.new_open_options()
+            .write(true)
+            .create_new(true)
+            .open(path!("/log.txt"))
+            .unwrap();
+
+        let writers: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let fs = fs.clone();
+                std::thread::spawn(move || {
+                    let rt = tokio::runtime::Builder::new_current_thread()
+                        .build()
+                        .unwrap();
+                    // Every thread has its own handle, like separate file
+                    // descriptors opened with `O_APPEND`
+                    let mut file = fs
+                        .new_open_options()
+                        .append(true)
+                        .open(path!("/log.txt"))
+                        .unwrap();
+                    for record in 0..RECORDS {
+                        let line = format!("{thread:02}-{record:04}-");
+                        let line = format!("{line:x<width$}\n", width = RECORD_LEN - 1);
+                        let written = rt.block_on(file.write(line.as_bytes())).unwrap();
+                        assert_eq!(written, RECORD_LEN);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let mut contents = Vec::new();
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .open(path!("/log.txt"))
+            .unwrap();
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(file.read_to_end(&mut contents))
+            .unwrap();
+        assert_eq!(contents.len(), THREADS * RECORDS * RECORD_LEN);
+
+        // Every record is intact, and each thread's records are in order
+        let mut next = [0; THREADS];
+        for record in contents.chunks(RECORD_LEN) {
+            let record = std::str::from_utf8(record).unwrap();
+            let thread: usize = record[..2].parse().unwrap();
+            let expected = format!("{thread:02}-{:04}-", next[thread]);
+            let expected = format!("{expected:x<width$}\n", width = RECORD_LEN - 1);
+            assert_eq!(record, expected);
+            next[thread] += 1;
+        }
+        assert_eq!(next, [RECORDS; THREADS]);
+    }
+
     #[tokio::test]
     async fn test_writing_at_various_positions() {
         let fs = FileSystem::default();
@@ -1088,6 +1455,80 @@ mod test_read_write_seek {
         assert_eq!(string, "");
     }
 
+    #[tokio::test]
+    async fn test_seeking_out_of_bounds() {
+        let fs = FileSystem::default();
+
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+
+        assert!(
+            matches!(file.write(b"foobarbazqux").await, Ok(12)),
+            "writing `foobarbazqux`",
+        );
+
+        assert!(
+            matches!(
+                file.seek(io::SeekFrom::Current(-13)).await,
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput
+            ),
+            "seeking before the byte 0",
+        );
+        assert!(
+            matches!(
+                file.seek(io::SeekFrom::End(-13)).await,
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput
+            ),
+            "seeking before the byte 0 from the end",
+        );
+        assert!(
+            matches!(file.seek(io::SeekFrom::Start(u64::MAX)).await, Ok(u64::MAX)),
+            "seeking to the last position",
+        );
+        assert!(
+            matches!(
+                file.seek(io::SeekFrom::Current(1)).await,
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput
+            ),
+            "seeking past `u64::MAX`",
+        );
+
+        assert!(
+            matches!(file.seek(io::SeekFrom::Start(16)).await, Ok(16)),
+            "seeking past the end",
+        );
+        let mut buffer = [0; 4];
+        assert!(
+            matches!(file.read(&mut buffer[..]).await, Ok(0)),
+            "reading past the end",
+        );
+        assert!(matches!(file.write(b"!").await, Ok(1)), "writing `!`");
+        assert!(
+            matches!(fs.metadata(path!("/foo.txt")), Ok(Metadata { len: 17, .. })),
+            "checking the `metadata.len` is 17",
+        );
+
+        assert!(
+            matches!(file.seek(io::SeekFrom::Start(0)).await, Ok(0)),
+            "seeking to 0",
+        );
+        let mut buffer = Vec::new();
+        assert!(
+            matches!(file.read_to_end(&mut buffer).await, Ok(17)),
+            "reading all bytes",
+        );
+        assert_eq!(
+            buffer,
+            b"foobarbazqux\0\0\0\0!"[..],
+            "the gap is filled with zeros"
+        );
+    }
+
     #[tokio::test]
     async fn test_reading() {
         let fs = FileSystem::default();
@@ -1138,6 +1579,41 @@ mod test_read_write_seek {
         );
     }
 
+    #[tokio::test]
+    async fn test_reading_after_the_file_shrank() {
+        let fs = FileSystem::default();
+
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+
+        assert!(
+            matches!(file.write(b"foobarbazqux").await, Ok(12)),
+            "writing `foobarbazqux`",
+        );
+
+        let mut other = fs
+            .new_open_options()
+            .write(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to open the file again");
+        other.set_len(4).expect("failed to shrink the file");
+
+        let mut buffer = [0; 16];
+        assert!(
+            matches!(file.read(&mut buffer[..]).await, Ok(0)),
+            "reading past the new end",
+        );
+        assert!(
+            matches!(file.seek(io::SeekFrom::Current(0)).await, Ok(12)),
+            "the cursor didn't move",
+        );
+    }
+
     #[tokio::test]
     async fn test_reading_to_the_end() {
         let fs = FileSystem::default();
@@ -1237,6 +1713,161 @@ mod test_read_write_seek {
             "failing to read an exact buffer",
         );
     }
+
+    #[tokio::test]
+    async fn test_read_at_from_many_threads() {
+        let fs = FileSystem::default();
+
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path!("/blocks.bin"))
+            .expect("failed to create a new file");
+        // 16 blocks of 256 bytes, each filled with its own number
+        for block in 0..16u8 {
+            assert_eq!(
+                file.write_at(block as u64 * 256, &[block; 256])
+                    .await
+                    .unwrap(),
+                256
+            );
+        }
+        assert_eq!(
+            file.stream_position().await.unwrap(),
+            0,
+            "cursor didn't move"
+        );
+
+        // Every thread reads through the same handle
+        let file = std::sync::Arc::new(file);
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let file = file.clone();
+                std::thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .build()
+                        .unwrap();
+                    runtime.block_on(async {
+                        for i in 0..100u64 {
+                            let block = (thread + i) % 16;
+                            let mut buffer = [0; 256];
+                            let read = file.read_at(block * 256, &mut buffer).await.unwrap();
+                            assert_eq!(read, 256);
+                            assert!(buffer.iter().all(|b| *b as u64 == block));
+                        }
+                    });
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut file = std::sync::Arc::try_unwrap(file).unwrap();
+        assert_eq!(file.stream_position().await.unwrap(), 0);
+        let mut buffer = [0; 8];
+        assert_eq!(file.read_at(16 * 256 - 4, &mut buffer).await.unwrap(), 4);
+        assert_eq!(&buffer[..4], &[15; 4]);
+    }
+
+    async fn read_all(file: &mut Box<dyn crate::VirtualFile + Send + Sync>) -> Vec<u8> {
+        file.seek(io::SeekFrom::Start(0)).await.unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).await.unwrap();
+        contents
+    }
+
+    #[tokio::test]
+    async fn test_set_len_grows_and_shrinks() {
+        let fs = FileSystem::default();
+        let metadata_len = || fs.metadata(path!("/foo.txt")).unwrap().len();
+
+        let mut file = fs
+            .new_open_options()
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+        file.write_all(b"hello world").await.unwrap();
+        let mut reader = fs
+            .new_open_options()
+            .read(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to open the file");
+
+        // Shrinking throws away everything past the new end...
+        file.set_len(5).unwrap();
+        assert_eq!(metadata_len(), 5);
+        assert_eq!(read_all(&mut reader).await, b"hello");
+
+        // ... so growing again brings back zeros rather than the old data
+        file.set_len(8).unwrap();
+        assert_eq!(metadata_len(), 8);
+        assert_eq!(read_all(&mut reader).await, b"hello\0\0\0");
+
+        assert_eq!(
+            file.stream_position().await.unwrap(),
+            11,
+            "cursor didn't move"
+        );
+        assert_eq!(
+            reader.set_len(0).unwrap_err(),
+            FsError::PermissionDenied,
+            "the file isn't open for writing",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_zero_length_reads_and_writes() {
+        let fs = FileSystem::default();
+
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+        file.write_all(b"hello").await.unwrap();
+
+        // Past the end, so a write that did anything would leave a hole
+        file.seek(io::SeekFrom::Start(10)).await.unwrap();
+        assert_eq!(file.write(&[]).await.unwrap(), 0);
+        assert_eq!(
+            file.write_vectored(&[io::IoSlice::new(&[])]).await.unwrap(),
+            0
+        );
+        assert_eq!(file.write_at(20, &[]).await.unwrap(), 0);
+        assert_eq!(file.size(), 5, "the file didn't grow");
+        assert_eq!(
+            file.stream_position().await.unwrap(),
+            10,
+            "cursor didn't move"
+        );
+
+        file.seek(io::SeekFrom::Start(1)).await.unwrap();
+        assert_eq!(file.read(&mut []).await.unwrap(), 0);
+        assert_eq!(
+            file.stream_position().await.unwrap(),
+            1,
+            "cursor didn't move"
+        );
+
+        let mut appending = fs
+            .new_open_options()
+            .append(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to open the file");
+        assert_eq!(appending.write(&[]).await.unwrap(), 0);
+        assert_eq!(
+            appending.stream_position().await.unwrap(),
+            0,
+            "cursor didn't move"
+        );
+        assert_eq!(file.size(), 5);
+    }
 }
 
 impl fmt::Debug for FileHandle {
@@ -1248,17 +1879,35 @@ impl fmt::Debug for FileHandle {
     }
 }
 
+/// Removes an anonymous file from the storage once the last handle to it
+/// is closed.
+#[derive(Debug)]
+pub(super) struct AnonymousFile {
+    inode: Inode,
+    filesystem: FileSystem,
+}
+
+impl Drop for AnonymousFile {
+    fn drop(&mut self) {
+        if let Ok(mut fs) = self.filesystem.inner.write() {
+            fs.storage.try_remove(self.inode);
+        }
+    }
+}
+
 /// The real file! It is simply a buffer of bytes with a cursor that
 /// represents a read/write position in the buffer.
+///
+/// The bytes are stored in chunks, see [`ChunkedBuffer`].
 #[derive(Debug)]
 pub(super) struct File {
-    buffer: TrackedVec,
+    buffer: ChunkedBuffer,
 }
 
 impl File {
     pub(super) fn new(limiter: Option<crate::limiter::DynFsMemoryLimiter>) -> Self {
         Self {
-            buffer: TrackedVec::new(limiter),
+            buffer: ChunkedBuffer::new(limiter),
         }
     }
 
@@ -1266,98 +1915,78 @@ impl File {
         self.buffer.clear();
     }
 
-    pub(super) fn len(&self) -> usize {
+    pub(super) fn len(&self) -> u64 {
         self.buffer.len()
     }
+
+    /// Gives back the memory that the buffer reserved beyond its length.
+    pub(super) fn shrink_to_fit(&mut self) {
+        self.buffer.shrink_to_fit();
+    }
+
+    /// Grows or shrinks the file to `new_size` bytes, padding it with zeros
+    /// when it grows.
+    pub(super) fn set_len(&mut self, new_size: u64) -> Result<()> {
+        self.buffer.resize(new_size)
+    }
+
+    /// Copies the contents into a new file that is accounted to `limiter`.
+    pub(super) fn duplicate(
+        &self,
+        limiter: Option<crate::limiter::DynFsMemoryLimiter>,
+    ) -> Result<Self> {
+        Ok(Self {
+            buffer: self.buffer.duplicate(limiter)?,
+        })
+    }
 }
 
 impl File {
     pub fn read(&self, buf: &mut [u8], cursor: &mut u64) -> io::Result<usize> {
-        let cur_pos = *cursor as usize;
-        let max_to_read = cmp::min(self.buffer.len() - cur_pos, buf.len());
-        let data_to_copy = &self.buffer[cur_pos..][..max_to_read];
-
-        // SAFETY: `buf[..max_to_read]` and `data_to_copy` have the same size, due to
-        // how `max_to_read` is computed.
-        buf[..max_to_read].copy_from_slice(data_to_copy);
+        // The cursor may be past the end (e.g. after a seek, or because the
+        // file shrank), in which case there's simply nothing left to read.
+        let read = self.buffer.read_at(*cursor, buf);
 
-        *cursor += max_to_read as u64;
+        *cursor += read as u64;
 
-        Ok(max_to_read)
+        Ok(read)
     }
 }
 
 impl File {
+    /// Moves the cursor.
+    ///
+    /// It's an error to seek before the byte 0 or past `u64::MAX`, but the
+    /// cursor may be moved past the end of the file. Reading from there
+    /// gives nothing, and writing fills the gap with zeros.
     pub fn seek(&self, position: io::SeekFrom, cursor: &mut u64) -> io::Result<u64> {
-        let to_err = |_| io::ErrorKind::InvalidInput;
-
         // Calculate the next cursor.
-        let next_cursor: i64 = match position {
+        let next_cursor = match position {
             // Calculate from the beginning, so `0 + offset`.
-            io::SeekFrom::Start(offset) => offset.try_into().map_err(to_err)?,
+            io::SeekFrom::Start(offset) => Some(offset),
 
             // Calculate from the end, so `buffer.len() + offset`.
-            io::SeekFrom::End(offset) => {
-                TryInto::<i64>::try_into(self.buffer.len()).map_err(to_err)? + offset
-            }
+            io::SeekFrom::End(offset) => self.buffer.len().checked_add_signed(offset),
 
             // Calculate from the current cursor, so `cursor + offset`.
-            io::SeekFrom::Current(offset) => {
-                TryInto::<i64>::try_into(*cursor).map_err(to_err)? + offset
-            }
+            io::SeekFrom::Current(offset) => cursor.checked_add_signed(offset),
         };
 
-        // It's an error to seek before byte 0.
-        if next_cursor < 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "seeking before the byte 0",
-            ));
-        }
-
-        // In this implementation, it's an error to seek beyond the
-        // end of the buffer.
-        let next_cursor = next_cursor.try_into().map_err(to_err)?;
-        *cursor = cmp::min(self.buffer.len() as u64, next_cursor);
+        *cursor = next_cursor.ok_or(FsError::InvalidInput)?;
 
         let cursor = *cursor;
         Ok(cursor)
     }
 }
-
 impl File {
+    /// Inserts `buf` at the cursor. Writing past the end of the file fills
+    /// the gap with zeros.
     pub fn write(&mut self, buf: &[u8], cursor: &mut u64) -> io::Result<usize> {
-        match *cursor {
-            // The cursor is at the end of the buffer: happy path!
-            position if position == self.buffer.len() as u64 => {
-                self.buffer.extend_from_slice(buf)?;
-            }
-
-            // The cursor is at the beginning of the buffer (and the
-            // buffer is not empty, otherwise it would have been
-            // caught by the previous arm): almost a happy path!
-            0 => {
-                // FIXME(perf,theduke): make this faster, it's horrible!
-                let mut new_buffer = TrackedVec::with_capacity(
-                    self.buffer.len() + buf.len(),
-                    self.buffer.limiter().cloned(),
-                )?;
-                new_buffer.extend_from_slice(buf)?;
-                new_buffer.append(&mut self.buffer)?;
-
-                self.buffer = new_buffer;
-            }
-
-            // The cursor is somewhere in the buffer: not the happy path.
-            position => {
-                self.buffer.reserve_exact(buf.len())?;
-
-                // FIXME(perf,theduke): make this faster, it's horrible!
-                let mut remainder = self.buffer.split_off(position as usize)?;
-                self.buffer.extend_from_slice(buf)?;
-                self.buffer.append(&mut remainder)?;
-            }
+        // Nothing is written, so a cursor past the end mustn't grow the file
+        if buf.is_empty() {
+            return Ok(0);
         }
+        self.buffer.insert(*cursor, buf)?;
 
         *cursor += buf.len() as u64;
 
@@ -1370,7 +1999,7 @@ impl File {
 }
 
 /// Read only file that uses copy-on-write
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(super) struct ReadOnlyFile {
     buffer: Cow<'static, [u8]>,
 }
@@ -1387,7 +2016,9 @@ impl ReadOnlyFile {
 
 impl ReadOnlyFile {
     pub fn read(&self, buf: &mut [u8], cursor: &mut u64) -> io::Result<usize> {
-        let cur_pos = *cursor as usize;
+        // The cursor may be past the end (e.g. after a seek, or because the
+        // file shrank), in which case there's simply nothing left to read.
+        let cur_pos = cmp::min(*cursor as usize, self.buffer.len());
         let max_to_read = cmp::min(self.buffer.len() - cur_pos, buf.len());
         let data_to_copy = &self.buffer[cur_pos..][..max_to_read];
 
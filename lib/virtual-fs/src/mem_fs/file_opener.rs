@@ -1,10 +1,19 @@
 use super::filesystem::InodeResolution;
 use super::*;
+use crate::watch::FsEvent;
 use crate::{FileType, FsError, Metadata, OpenOptionsConfig, Result, VirtualFile};
 use std::borrow::Cow;
 use std::path::Path;
 use tracing::*;
 
+/// Reads the whole file a copy-on-write node refers to.
+fn read_shared_file(fs: &(dyn crate::FileSystem + Send + Sync), path: &Path) -> Result<Vec<u8>> {
+    let mut src = fs.new_open_options().read(true).open(path)?;
+    let mut data = Vec::new();
+    crate::remote_fs::block_on(tokio::io::AsyncReadExt::read_to_end(&mut src, &mut data))?;
+    Ok(data)
+}
+
 impl FileSystem {
     /// Inserts a readonly file into the file system that uses copy-on-write
     /// (this is required for zero-copy creation of the same file)
@@ -27,6 +36,7 @@ impl FileSystem {
             None => {
                 // Write lock.
                 let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+                fs.check_inode_budget()?;
 
                 let file = ReadOnlyFile::new(contents);
                 let file_len = file.len() as u64;
@@ -74,6 +84,28 @@ impl FileSystem {
         target_path: PathBuf,
         fs: Arc<dyn crate::FileSystem + Send + Sync>,
         source_path: PathBuf,
+    ) -> Result<()> {
+        self.insert_arc_file_ext(target_path, fs, source_path, false)
+    }
+
+    /// Like [`FileSystem::insert_arc_file_at()`], but the other file system
+    /// is never written to. Instead, the file is copied into this file system
+    /// the first time it is opened for writing.
+    pub fn insert_cow_arc_file_at(
+        &self,
+        target_path: PathBuf,
+        fs: Arc<dyn crate::FileSystem + Send + Sync>,
+        source_path: PathBuf,
+    ) -> Result<()> {
+        self.insert_arc_file_ext(target_path, fs, source_path, true)
+    }
+
+    fn insert_arc_file_ext(
+        &self,
+        target_path: PathBuf,
+        fs: Arc<dyn crate::FileSystem + Send + Sync>,
+        source_path: PathBuf,
+        copy_on_write: bool,
     ) -> Result<()> {
         let _ = crate::FileSystem::remove_file(self, target_path.as_path());
         let (inode_of_parent, maybe_inode_of_file, name_of_file) =
@@ -94,9 +126,10 @@ impl FileSystem {
             None => {
                 // Write lock.
                 let mut fs_lock = self.inner.write().map_err(|_| FsError::Lock)?;
+                fs_lock.check_inode_budget()?;
 
                 // Read the metadata or generate a dummy one
-                let meta = match fs.metadata(&target_path) {
+                let meta = match fs.metadata(&source_path) {
                     Ok(meta) => meta,
                     _ => {
                         let time = time();
@@ -121,6 +154,7 @@ impl FileSystem {
                     fs,
                     path: source_path,
                     metadata: meta,
+                    copy_on_write,
                 }));
 
                 assert_eq!(
@@ -174,6 +208,7 @@ impl FileSystem {
             None => {
                 // Write lock.
                 let mut fs_lock = self.inner.write().map_err(|_| FsError::Lock)?;
+                fs_lock.check_inode_budget()?;
 
                 // Creating the file in the storage.
                 let inode_of_file = fs_lock.storage.vacant_entry().key();
@@ -228,6 +263,40 @@ impl FileSystem {
         &self,
         path: PathBuf,
         file: Box<dyn crate::VirtualFile + Send + Sync>,
+    ) -> Result<()> {
+        self.insert_custom_file(
+            path,
+            file,
+            FileType {
+                file: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Like [`Self::insert_device_file()`], but the file reports
+    /// itself as a character device (like `/dev/null`) instead of a
+    /// regular file.
+    pub fn insert_char_device(
+        &self,
+        path: PathBuf,
+        file: Box<dyn crate::VirtualFile + Send + Sync>,
+    ) -> Result<()> {
+        self.insert_custom_file(
+            path,
+            file,
+            FileType {
+                char_device: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn insert_custom_file(
+        &self,
+        path: PathBuf,
+        file: Box<dyn crate::VirtualFile + Send + Sync>,
+        ft: FileType,
     ) -> Result<()> {
         let _ = crate::FileSystem::remove_file(self, path.as_path());
         let (inode_of_parent, maybe_inode_of_file, name_of_file) =
@@ -247,6 +316,7 @@ impl FileSystem {
         }
         // Write lock.
         let mut fs_lock = self.inner.write().map_err(|_| FsError::Lock)?;
+        fs_lock.check_inode_budget()?;
 
         // Creating the file in the storage.
         let inode_of_file = fs_lock.storage.vacant_entry().key();
@@ -257,10 +327,7 @@ impl FileSystem {
             metadata: {
                 let time = time();
                 Metadata {
-                    ft: FileType {
-                        file: true,
-                        ..Default::default()
-                    },
+                    ft,
                     accessed: time,
                     created: time,
                     modified: time,
@@ -319,6 +386,72 @@ impl FileSystem {
             name_of_file,
         ))
     }
+
+    /// Creates a file with no directory entry in the directory at `path`.
+    fn open_anonymous(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        // Nothing could ever be read from the file otherwise
+        if !conf.write() && !conf.append() {
+            return Err(FsError::InvalidInput);
+        }
+
+        let inode_of_file = {
+            // Write lock.
+            let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+
+            let inode_of_dir = match fs.inode_of_parent(path)? {
+                InodeResolution::Found(a) => a,
+                InodeResolution::Redirect(other, path) => {
+                    drop(fs);
+                    return other.new_open_options().options(conf.clone()).open(path);
+                }
+            };
+
+            fs.check_inode_budget()?;
+            let file = File::new(fs.limiter.clone());
+            let inode_of_file = fs.storage.vacant_entry().key();
+            let real_inode_of_file = fs.storage.insert(Node::File(FileNode {
+                inode: inode_of_file,
+                name: OsString::new(),
+                file,
+                metadata: {
+                    let time = time();
+
+                    Metadata {
+                        ft: FileType {
+                            file: true,
+                            ..Default::default()
+                        },
+                        accessed: time,
+                        created: time,
+                        modified: time,
+                        len: 0,
+                    }
+                },
+            }));
+
+            assert_eq!(
+                inode_of_file, real_inode_of_file,
+                "new file inode should have been correctly calculated",
+            );
+            debug!("open: anonymous file {inode_of_file} in directory {inode_of_dir}");
+
+            inode_of_file
+        };
+
+        let handle = FileHandle::new(
+            inode_of_file,
+            self.clone(),
+            conf.read(),
+            true,
+            conf.append(),
+            0,
+        );
+        Ok(Box::new(handle.anonymous()))
+    }
 }
 
 impl crate::FileOpener for FileSystem {
@@ -336,6 +469,10 @@ impl crate::FileOpener for FileSystem {
         let mut create = conf.create();
         let create_new = conf.create_new();
 
+        if conf.tmpfile() {
+            return self.open_anonymous(path, conf);
+        }
+
         // If `create_new` is used, `create` and `truncate ` are ignored.
         if create_new {
             create = false;
@@ -366,6 +503,23 @@ impl crate::FileOpener for FileSystem {
             }
         };
 
+        // A directory is in the way of the file.
+        if maybe_inode_of_file.is_none() && (create_new || create || write || append || truncate) {
+            let fs = self.inner.read().map_err(|_| FsError::Lock)?;
+            if fs
+                .as_parent_get_position_and_inode(inode_of_parent, &name_of_file)?
+                .is_some()
+            {
+                return Err(if create_new {
+                    FsError::AlreadyExists
+                } else {
+                    FsError::IsADirectory
+                });
+            }
+        }
+
+        let path = self.canonicalize_unchecked(path)?;
+        let mut event = None;
         let mut cursor = 0u64;
         let inode_of_file = match maybe_inode_of_file {
             // The file already exists, and a _new_ one _must_ be
@@ -381,9 +535,31 @@ impl crate::FileOpener for FileSystem {
                     }
                 };
 
+                // Files shared with another file system are copied before
+                // they are written to. Reading them may block, so it is done
+                // before taking the write lock.
+                let copy = if write || append || truncate {
+                    let source = self
+                        .inner
+                        .read()
+                        .map_err(|_| FsError::Lock)?
+                        .copy_on_write_source(inode_of_file);
+                    match source {
+                        Some(_) if truncate => Some(Vec::new()),
+                        Some((fs, path)) => Some(read_shared_file(&*fs, &path)?),
+                        None => None,
+                    }
+                } else {
+                    None
+                };
+
                 // Write lock.
                 let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
 
+                if let Some(data) = copy {
+                    fs.copy_up(inode_of_file, &data)?;
+                }
+
                 let inode = fs.storage.get_mut(inode_of_file);
                 match inode {
                     Some(Node::File(FileNode { metadata, file, .. })) => {
@@ -394,11 +570,12 @@ impl crate::FileOpener for FileSystem {
                         if truncate {
                             file.truncate();
                             metadata.len = 0;
+                            event = Some(FsEvent::Modified(path.clone()));
                         }
 
                         // Move the cursor to the end if needed.
                         if append {
-                            cursor = file.len() as u64;
+                            cursor = file.len();
                         }
                     }
 
@@ -421,6 +598,7 @@ impl crate::FileOpener for FileSystem {
                         if truncate {
                             file.set_len(0)?;
                             node.metadata.len = 0;
+                            event = Some(FsEvent::Modified(path.clone()));
                         }
 
                         // Move the cursor to the end if needed.
@@ -448,6 +626,7 @@ impl crate::FileOpener for FileSystem {
                         if truncate {
                             file.set_len(0)?;
                             node.metadata.len = 0;
+                            event = Some(FsEvent::Modified(path.clone()));
                         }
 
                         // Move the cursor to the end if needed.
@@ -469,6 +648,7 @@ impl crate::FileOpener for FileSystem {
             None if (create_new || create) && (write || append) => {
                 // Write lock.
                 let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+                fs.check_inode_budget()?;
 
                 let file = File::new(fs.limiter.clone());
 
@@ -501,6 +681,7 @@ impl crate::FileOpener for FileSystem {
 
                 // Adding the new directory to its parent.
                 fs.add_child_to_node(inode_of_parent, inode_of_file)?;
+                event = Some(FsEvent::Created(path.clone()));
 
                 inode_of_file
             }
@@ -510,6 +691,10 @@ impl crate::FileOpener for FileSystem {
             None => return Err(FsError::EntryNotFound),
         };
 
+        if let Some(event) = event {
+            self.notify(event);
+        }
+
         Ok(Box::new(FileHandle::new(
             inode_of_file,
             self.clone(),
@@ -613,6 +798,44 @@ mod test_file_opener {
         );
     }
 
+    #[test]
+    fn test_create_a_file_where_a_directory_is() {
+        let fs = FileSystem::default();
+        fs.create_dir(path!("/foo")).unwrap();
+
+        assert_eq!(
+            fs.new_open_options()
+                .write(true)
+                .create_new(true)
+                .open(path!("/foo"))
+                .map(|_| ()),
+            Err(FsError::AlreadyExists),
+            "exclusively creating a file over a directory",
+        );
+        assert_eq!(
+            fs.new_open_options()
+                .write(true)
+                .create(true)
+                .open(path!("/foo"))
+                .map(|_| ()),
+            Err(FsError::IsADirectory),
+            "creating a file over a directory",
+        );
+        assert_eq!(
+            fs.new_open_options()
+                .write(true)
+                .truncate(true)
+                .open(path!("/foo"))
+                .map(|_| ()),
+            Err(FsError::IsADirectory),
+            "truncating a directory",
+        );
+
+        // The directory is left alone
+        assert!(fs.metadata(path!("/foo")).unwrap().is_dir());
+        assert_eq!(fs.read_dir(path!("/")).unwrap().count(), 1);
+    }
+
     #[test]
     fn test_truncate_a_read_only_file() {
         let fs = FileSystem::default();
@@ -767,4 +990,51 @@ mod test_file_opener {
             "opening a file that already exists",
         );
     }
+
+    #[tokio::test]
+    async fn test_anonymous_tmpfile() {
+        let fs = FileSystem::default();
+        fs.create_dir(path!("/tmp")).unwrap();
+        let storage_len = || fs.inner.read().unwrap().storage.len();
+        let before = storage_len();
+
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .tmpfile(true)
+            .open(path!("/tmp"))
+            .expect("failed to create an anonymous file");
+
+        file.write_all(b"scratch").await.unwrap();
+        file.seek(io::SeekFrom::Start(0)).await.unwrap();
+        let mut string = String::new();
+        file.read_to_string(&mut string).await.unwrap();
+        assert_eq!(string, "scratch");
+
+        // The file isn't visible in the directory
+        assert_eq!(fs.read_dir(path!("/tmp")).unwrap().count(), 0);
+        assert_eq!(storage_len(), before + 1);
+
+        drop(file);
+        assert_eq!(storage_len(), before, "the file is gone once closed");
+
+        assert_eq!(
+            fs.new_open_options()
+                .read(true)
+                .tmpfile(true)
+                .open(path!("/tmp"))
+                .map(|_| ()),
+            Err(FsError::InvalidInput),
+            "anonymous files must be writable",
+        );
+        assert_eq!(
+            fs.new_open_options()
+                .write(true)
+                .tmpfile(true)
+                .open(path!("/missing"))
+                .map(|_| ()),
+            Err(FsError::EntryNotFound),
+        );
+    }
 }
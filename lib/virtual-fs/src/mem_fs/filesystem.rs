@@ -1,6 +1,8 @@
 //! This module contains the [`FileSystem`] type itself.
 
 use super::*;
+use crate::watch::{FsEvent, FsWatcher, WatchFileSystem, Watchers};
+use crate::ExchangeFileSystem;
 use crate::{DirEntry, FileSystem as _, FileType, FsError, Metadata, OpenOptions, ReadDir, Result};
 use slab::Slab;
 use std::collections::VecDeque;
@@ -24,16 +26,117 @@ impl FileSystem {
         self.inner.write().unwrap().limiter = Some(limiter);
     }
 
+    /// Limits how many inodes (files, directories and anything else,
+    /// including `/`) the file system can hold. Creating more fails with
+    /// [`FsError::StorageFull`], and removing entries frees up the budget.
+    ///
+    /// `None` removes the limit.
+    pub fn set_max_inodes(&self, max_inodes: Option<usize>) {
+        self.inner.write().unwrap().max_inodes = max_inodes;
+    }
+
+    /// How many inodes the file system holds right now, including `/`.
+    pub fn total_inodes(&self) -> usize {
+        self.inner.read().unwrap().storage.len()
+    }
+
     pub fn new_open_options_ext(&self) -> &FileSystem {
         self
     }
 
+    /// Registers a callback that is invoked after every successful
+    /// [`rename()`][crate::FileSystem::rename], with the canonical paths
+    /// the entry was moved from and to.
+    ///
+    /// Renames are atomic, so this can be used to reload a file once an
+    /// editor has finished writing it to a temporary file and moving it
+    /// into place.
+    pub fn on_rename(&self, hook: impl Fn(&Path, &Path) + Send + Sync + 'static) {
+        self.inner
+            .write()
+            .unwrap()
+            .rename_hooks
+            .push(Arc::new(hook));
+    }
+
+    /// Lets everyone watching the file system know about a change.
+    ///
+    /// This must not be called while holding a lock on the file system.
+    pub(super) fn notify(&self, event: FsEvent) {
+        if let Ok(fs) = self.inner.read() {
+            fs.watchers.notify(event);
+        }
+    }
+
+    /// Lets everyone watching the file system know that the contents of
+    /// a file changed. The path is looked up when the event is sent, so
+    /// it is still right after the file was renamed.
+    ///
+    /// This must not be called while holding a lock on the file system.
+    pub(super) fn notify_modified(&self, inode: Inode) {
+        if let Ok(fs) = self.inner.read() {
+            if fs.watchers.is_empty() {
+                return;
+            }
+            if let Some(path) = fs.path_of(inode) {
+                fs.watchers.notify(FsEvent::Modified(path));
+            }
+        }
+    }
+
+    /// Runs the rename hooks and lets the watchers know that `from` was
+    /// renamed to `to`.
+    fn renamed(&self, rename_hooks: Vec<RenameHook>, from: PathBuf, to: PathBuf) {
+        for hook in rename_hooks {
+            hook(&from, &to);
+        }
+        self.notify(FsEvent::Renamed { from, to });
+    }
+
     /// Canonicalize a path without validating that it actually exists.
     pub fn canonicalize_unchecked(&self, path: &Path) -> Result<PathBuf> {
         let lock = self.inner.read().map_err(|_| FsError::Lock)?;
         lock.canonicalize_without_inode(path)
     }
 
+    /// Deep-copies the directory at `root` and everything below it into a
+    /// new, independent file system in which `root` becomes `/`.
+    ///
+    /// The contents and metadata of files and directories are copied, so
+    /// changes to either file system aren't visible in the other one. Files
+    /// and directories that refer to another file system (e.g. after a
+    /// [`mount()`][Self::mount] or [`union()`][Self::union]) keep referring
+    /// to it, and custom files such as devices are left out because they
+    /// can't be duplicated.
+    ///
+    /// This file system has no symbolic links, so paths inside the copy
+    /// that point outside of `root` are simply not found.
+    pub fn clone_subtree(&self, root: &Path) -> Result<FileSystem> {
+        let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+
+        let root = match guard.canonicalize(root)? {
+            (_, InodeResolution::Found(inode)) => inode,
+            (_, InodeResolution::Redirect(..)) => return Err(FsError::InvalidInput),
+        };
+        let metadata = match guard.storage.get(root) {
+            Some(Node::Directory(DirectoryNode { metadata, .. })) => metadata.clone(),
+            _ => return Err(FsError::BaseNotDirectory),
+        };
+
+        let fs = FileSystem::default();
+        {
+            let mut target = fs.inner.write().map_err(|_| FsError::Lock)?;
+            guard.copy_children_into(root, &mut target, ROOT_INODE)?;
+            if let Some(Node::Directory(DirectoryNode { metadata: m, .. })) =
+                target.storage.get_mut(ROOT_INODE)
+            {
+                *m = metadata;
+            }
+        }
+
+        Ok(fs)
+    }
+
     /// Merge all items from a given source path (directory) of a different file
     /// system into this file system.
     ///
@@ -101,25 +204,79 @@ impl FileSystem {
         Ok(())
     }
 
+    /// Merges `other` into this file system, with `other` taking precedence
+    /// on overlapping paths. See [`FileSystem::union_with_priority`].
     pub fn union(&self, other: &Arc<dyn crate::FileSystem + Send + Sync>) {
+        self.union_with_priority(other, crate::Priority::Higher)
+    }
+
+    /// Like [`FileSystem::union()`], but `other` is never written to.
+    ///
+    /// The files of `other` are shared by everyone who merged it in, until
+    /// one of them opens a file for writing. That file is then copied into
+    /// this file system first, so nobody else sees the change.
+    pub fn union_copy_on_write(&self, other: &Arc<dyn crate::FileSystem + Send + Sync>) {
+        self.union_ext(other, crate::Priority::Higher, true)
+    }
+
+    /// Merges `other` into this file system by creating references back to
+    /// all of its files (the data is not copied).
+    ///
+    /// The `priority` is that of `other` relative to the files that are
+    /// already in this file system:
+    ///
+    /// - [`Priority::Higher`](crate::Priority::Higher) replaces files that
+    ///   exist in both, and whiteout files (`.wh.<name>`) in `other` remove
+    ///   `<name>` from this file system.
+    /// - [`Priority::Lower`](crate::Priority::Lower) only adds the files
+    ///   that don't exist yet and ignores whiteouts.
+    ///
+    /// Directories that exist in both are merged. Reads and writes on a
+    /// merged file go to the file system that won, while new files are
+    /// always created in this file system.
+    pub fn union_with_priority(
+        &self,
+        other: &Arc<dyn crate::FileSystem + Send + Sync>,
+        priority: crate::Priority,
+    ) {
+        self.union_ext(other, priority, false)
+    }
+
+    fn union_ext(
+        &self,
+        other: &Arc<dyn crate::FileSystem + Send + Sync>,
+        priority: crate::Priority,
+        copy_on_write: bool,
+    ) {
+        let overrides = priority == crate::Priority::Higher;
+
         // Iterate all the directories and files in the other filesystem
         // and create references back to them in this filesystem
         let mut remaining = VecDeque::new();
         remaining.push_back(PathBuf::from("/"));
         while let Some(next) = remaining.pop_back() {
-            if next
-                .file_name()
-                .map(|n| n.to_string_lossy().starts_with(".wh."))
-                .unwrap_or(false)
-            {
-                let rm = next.to_string_lossy();
-                let rm = &rm[".wh.".len()..];
-                let rm = PathBuf::from(rm);
-                let _ = crate::FileSystem::remove_dir(self, rm.as_path());
-                let _ = crate::FileSystem::remove_file(self, rm.as_path());
+            if let Some(rm) = whiteout_target(next.as_path()) {
+                if overrides {
+                    let _ = crate::FileSystem::remove_dir(self, rm.as_path());
+                    let _ = crate::FileSystem::remove_file(self, rm.as_path());
+                }
                 continue;
             }
-            let _ = crate::FileSystem::create_dir(self, next.as_path());
+
+            match crate::FileSystem::metadata(self, next.as_path()) {
+                Ok(meta) if meta.is_dir() => {}
+                Ok(_) if !overrides => {
+                    // A file in this file system shadows the directory
+                    continue;
+                }
+                Ok(_) => {
+                    let _ = crate::FileSystem::remove_file(self, next.as_path());
+                    let _ = crate::FileSystem::create_dir(self, next.as_path());
+                }
+                Err(_) => {
+                    let _ = crate::FileSystem::create_dir(self, next.as_path());
+                }
+            }
 
             let dir = match other.read_dir(next.as_path()) {
                 Ok(dir) => dir,
@@ -143,17 +300,29 @@ impl FileSystem {
                         remaining.push_back(sub_dir.path());
                     }
                     Ok(t) if t.is_file() => {
-                        if sub_dir.file_name().to_string_lossy().starts_with(".wh.") {
-                            let rm = next.to_string_lossy();
-                            let rm = &rm[".wh.".len()..];
-                            let rm = PathBuf::from(rm);
-                            let _ = crate::FileSystem::remove_dir(self, rm.as_path());
-                            let _ = crate::FileSystem::remove_file(self, rm.as_path());
+                        if let Some(rm) = whiteout_target(sub_dir.path.as_path()) {
+                            if overrides {
+                                let _ = crate::FileSystem::remove_dir(self, rm.as_path());
+                                let _ = crate::FileSystem::remove_file(self, rm.as_path());
+                            }
+                            continue;
+                        }
+                        if !overrides
+                            && crate::FileSystem::metadata(self, sub_dir.path.as_path()).is_ok()
+                        {
                             continue;
                         }
-                        let _ = self
-                            .new_open_options_ext()
-                            .insert_arc_file(sub_dir.path(), other.clone());
+                        let path = sub_dir.path();
+                        let _ = if copy_on_write {
+                            self.new_open_options_ext().insert_cow_arc_file_at(
+                                path.clone(),
+                                other.clone(),
+                                path,
+                            )
+                        } else {
+                            self.new_open_options_ext()
+                                .insert_arc_file(path, other.clone())
+                        };
                     }
                     _ => {}
                 }
@@ -202,6 +371,7 @@ impl FileSystem {
         {
             // Write lock.
             let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+            fs.check_inode_budget()?;
 
             // Creating the directory in the storage.
             let inode_of_directory = fs.storage.vacant_entry().key();
@@ -248,30 +418,45 @@ impl crate::FileSystem for FileSystem {
         let (path, inode_of_directory) = guard.canonicalize(path)?;
         let inode_of_directory = match inode_of_directory {
             InodeResolution::Found(a) => a,
-            InodeResolution::Redirect(fs, path) => {
-                return fs.read_dir(path.as_path());
+            InodeResolution::Redirect(fs, mounted_path) => {
+                drop(guard);
+                return rebase_entries(fs.read_dir(mounted_path.as_path())?, &path);
             }
         };
 
         // Check it's a directory and fetch the immediate children as `DirEntry`.
         let inode = guard.storage.get(inode_of_directory);
         let children = match inode {
-            Some(Node::Directory(DirectoryNode { children, .. })) => children
-                .iter()
-                .filter_map(|inode| guard.storage.get(*inode))
-                .map(|node| DirEntry {
-                    path: {
-                        let mut entry_path = path.to_path_buf();
-                        entry_path.push(node.name());
+            Some(Node::Directory(DirectoryNode { children, .. })) => {
+                let mut children: Vec<_> = children
+                    .iter()
+                    .filter_map(|inode| guard.storage.get(*inode))
+                    .map(|node| DirEntry {
+                        path: {
+                            let mut entry_path = path.to_path_buf();
+                            entry_path.push(node.name());
 
-                        entry_path
-                    },
-                    metadata: Ok(node.metadata().clone()),
-                })
-                .collect(),
+                            entry_path
+                        },
+                        metadata: Ok(node.metadata().clone()),
+                    })
+                    .collect();
+
+                // Entries are listed by name so that listings don't depend on
+                // the order in which files were created or renamed.
+                children.sort_by(|a, b| a.path.cmp(&b.path));
+                children
+            }
 
-            Some(Node::ArcDirectory(ArcDirectoryNode { fs, path, .. })) => {
-                return fs.read_dir(path.as_path());
+            Some(Node::ArcDirectory(ArcDirectoryNode {
+                fs,
+                path: mounted_path,
+                ..
+            })) => {
+                let fs = fs.clone();
+                let mounted_path = mounted_path.clone();
+                drop(guard);
+                return rebase_entries(fs.read_dir(mounted_path.as_path())?, &path);
             }
 
             _ => return Err(FsError::InvalidInput),
@@ -285,7 +470,7 @@ impl crate::FileSystem for FileSystem {
             return Err(FsError::AlreadyExists);
         }
 
-        let (inode_of_parent, name_of_directory) = {
+        let (inode_of_parent, name_of_directory, canonical_path) = {
             // Read lock.
             let guard = self.inner.read().map_err(|_| FsError::Lock)?;
 
@@ -312,7 +497,7 @@ impl crate::FileSystem for FileSystem {
                 }
             };
 
-            (inode_of_parent, name_of_directory)
+            (inode_of_parent, name_of_directory, path)
         };
 
         if self.read_dir(path).is_ok() {
@@ -323,6 +508,16 @@ impl crate::FileSystem for FileSystem {
             // Write lock.
             let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
 
+            // Something other than a directory is already there.
+            if fs
+                .as_parent_get_position_and_inode(inode_of_parent, &name_of_directory)?
+                .is_some()
+            {
+                return Err(FsError::AlreadyExists);
+            }
+
+            fs.check_inode_budget()?;
+
             // Creating the directory in the storage.
             let inode_of_directory = fs.storage.vacant_entry().key();
             let real_inode_of_directory = fs.storage.insert(Node::Directory(DirectoryNode {
@@ -354,11 +549,13 @@ impl crate::FileSystem for FileSystem {
             fs.add_child_to_node(inode_of_parent, inode_of_directory)?;
         }
 
+        self.notify(FsEvent::Created(canonical_path));
+
         Ok(())
     }
 
     fn remove_dir(&self, path: &Path) -> Result<()> {
-        let (inode_of_parent, position, inode_of_directory) = {
+        let (inode_of_parent, position, inode_of_directory, canonical_path) = {
             // Read lock.
             let guard = self.inner.read().map_err(|_| FsError::Lock)?;
 
@@ -393,7 +590,7 @@ impl crate::FileSystem for FileSystem {
                     DirectoryMustBeEmpty::Yes,
                 )?;
 
-            (inode_of_parent, position, inode_of_directory)
+            (inode_of_parent, position, inode_of_directory, path)
         };
 
         let inode_of_directory = match inode_of_directory {
@@ -414,33 +611,49 @@ impl crate::FileSystem for FileSystem {
             fs.remove_child_from_node(inode_of_parent, position)?;
         }
 
+        self.notify(FsEvent::Removed(canonical_path));
+
         Ok(())
     }
 
     fn rename(&self, from: &Path, to: &Path) -> Result<()> {
         let name_of_to;
 
+        // The whole rename happens under a single write lock, so nobody
+        // can observe the file under neither (or both) of its names.
+        let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+
+        // A rename inside a mounted file system is left to that file system
+        let canonical_from = fs.canonicalize_without_inode(from)?;
+        let canonical_to = fs.canonicalize_without_inode(to)?;
+        if let Some((mounted_fs, mounted_from, mounted_to)) =
+            fs.as_mounted_rename(&canonical_from, &canonical_to)?
+        {
+            let rename_hooks = fs.rename_hooks.clone();
+            drop(fs);
+            mounted_fs.rename(&mounted_from, &mounted_to)?;
+            self.renamed(rename_hooks, canonical_from, canonical_to);
+            return Ok(());
+        }
+
         let (
             (position_of_from, inode, inode_of_from_parent),
             (inode_of_to_parent, name_of_to),
             inode_dest,
         ) = {
-            // Read lock.
-            let fs = self.inner.read().map_err(|_| FsError::Lock)?;
-
-            let from = fs.canonicalize_without_inode(from)?;
-            let to = fs.canonicalize_without_inode(to)?;
-
             // Check the paths have parents.
-            let parent_of_from = from.parent().ok_or(FsError::BaseNotDirectory)?;
-            let parent_of_to = to.parent().ok_or(FsError::BaseNotDirectory)?;
+            let parent_of_from = canonical_from.parent().ok_or(FsError::BaseNotDirectory)?;
+            let parent_of_to = canonical_to.parent().ok_or(FsError::BaseNotDirectory)?;
 
             // Check the names.
-            let name_of_from = from
+            let name_of_from = canonical_from
+                .file_name()
+                .ok_or(FsError::InvalidInput)?
+                .to_os_string();
+            name_of_to = canonical_to
                 .file_name()
                 .ok_or(FsError::InvalidInput)?
                 .to_os_string();
-            name_of_to = to.file_name().ok_or(FsError::InvalidInput)?.to_os_string();
 
             // Find the parent inodes.
             let inode_of_from_parent = match fs.inode_of_parent(parent_of_from)? {
@@ -456,9 +669,10 @@ impl crate::FileSystem for FileSystem {
                 }
             };
 
-            // Find the inode of the dest file if it exists
+            // Find the inode of the destination if it exists, whether it's
+            // a file or a directory
             let maybe_position_and_inode_of_file =
-                fs.as_parent_get_position_and_inode_of_file(inode_of_to_parent, &name_of_to)?;
+                fs.as_parent_get_position_and_inode(inode_of_to_parent, &name_of_to)?;
 
             // Get the child indexes to update in the parent nodes, in
             // addition to the inode of the directory to update.
@@ -480,9 +694,38 @@ impl crate::FileSystem for FileSystem {
             }
         };
 
+        // A directory can't be moved inside of itself
+        if canonical_to != canonical_from && canonical_to.starts_with(&canonical_from) {
+            return Err(FsError::InvalidInput);
+        }
+
         {
-            // Write lock.
-            let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+            if let Some((_, InodeResolution::Found(inode_of_file))) = &inode_dest {
+                // Renaming a file onto itself does nothing
+                if *inode_of_file == inode {
+                    return Ok(());
+                }
+
+                // Only an empty directory can be replaced, and only by
+                // another directory. Otherwise its children would be lost.
+                let from_is_dir = matches!(
+                    fs.storage.get(inode),
+                    Some(Node::Directory(_) | Node::ArcDirectory(_))
+                );
+                match fs.storage.get(*inode_of_file) {
+                    Some(Node::Directory(DirectoryNode { children, .. })) => {
+                        if !children.is_empty() {
+                            return Err(FsError::DirectoryNotEmpty);
+                        }
+                        if !from_is_dir {
+                            return Err(FsError::NotAFile);
+                        }
+                    }
+                    Some(Node::ArcDirectory(_)) => return Err(FsError::DirectoryNotEmpty),
+                    _ if from_is_dir => return Err(FsError::BaseNotDirectory),
+                    _ => {}
+                }
+            }
 
             if let Some((position, inode_of_file)) = inode_dest {
                 // Remove the file from the storage.
@@ -522,6 +765,10 @@ impl crate::FileSystem for FileSystem {
             }
         }
 
+        let rename_hooks = fs.rename_hooks.clone();
+        drop(fs);
+        self.renamed(rename_hooks, canonical_from, canonical_to);
+
         Ok(())
     }
 
@@ -542,8 +789,37 @@ impl crate::FileSystem for FileSystem {
         }
     }
 
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        // This file system has no symlinks of its own, but the ones it
+        // mounts might
+        let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+        match guard.inode_of(path)? {
+            InodeResolution::Found(inode) => Ok(guard
+                .storage
+                .get(inode)
+                .ok_or(FsError::UnknownError)?
+                .metadata()
+                .clone()),
+            InodeResolution::Redirect(fs, path) => {
+                drop(guard);
+                fs.symlink_metadata(path.as_path())
+            }
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+        match guard.inode_of(path)? {
+            InodeResolution::Found(_) => Err(FsError::InvalidInput),
+            InodeResolution::Redirect(fs, path) => {
+                drop(guard);
+                fs.read_link(path.as_path())
+            }
+        }
+    }
+
     fn remove_file(&self, path: &Path) -> Result<()> {
-        let (inode_of_parent, position, inode_of_file) = {
+        let (inode_of_parent, position, inode_of_file, canonical_path) = {
             // Read lock.
             let guard = self.inner.read().map_err(|_| FsError::Lock)?;
 
@@ -573,7 +849,7 @@ impl crate::FileSystem for FileSystem {
                 guard.as_parent_get_position_and_inode_of_file(inode_of_parent, &name_of_file)?;
 
             match maybe_position_and_inode_of_file {
-                Some((position, inode_of_file)) => (inode_of_parent, position, inode_of_file),
+                Some((position, inode_of_file)) => (inode_of_parent, position, inode_of_file, path),
                 None => return Err(FsError::EntryNotFound),
             }
         };
@@ -596,6 +872,92 @@ impl crate::FileSystem for FileSystem {
             fs.remove_child_from_node(inode_of_parent, position)?;
         }
 
+        self.notify(FsEvent::Removed(canonical_path));
+
+        Ok(())
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        // The new contents are written to a file that isn't linked anywhere
+        // yet, which then takes the place of the old one in a single step.
+        let limiter = self
+            .inner
+            .read()
+            .map_err(|_| FsError::Lock)?
+            .limiter
+            .clone();
+        let mut file = File::new(limiter);
+        file.write(contents, &mut 0)?;
+
+        let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+
+        let path = fs.canonicalize_without_inode(path)?;
+        let parent_of_path = path.parent().ok_or(FsError::BaseNotDirectory)?;
+        let name_of_file = path
+            .file_name()
+            .ok_or(FsError::InvalidInput)?
+            .to_os_string();
+
+        let inode_of_parent = match fs.inode_of_parent(parent_of_path)? {
+            InodeResolution::Found(inode) => inode,
+            InodeResolution::Redirect(other, mut parent_path) => {
+                drop(fs);
+                parent_path.push(name_of_file);
+                return other.write_file(&parent_path, contents);
+            }
+        };
+        let existing = match fs.as_parent_get_position_and_inode(inode_of_parent, &name_of_file)? {
+            Some((_, InodeResolution::Redirect(other, path))) => {
+                drop(fs);
+                return other.write_file(&path, contents);
+            }
+            Some((position, InodeResolution::Found(inode))) => match fs.storage.get(inode) {
+                Some(Node::Directory(_)) | Some(Node::ArcDirectory(_)) => {
+                    return Err(FsError::NotAFile)
+                }
+                _ => Some((position, inode)),
+            },
+            None => None,
+        };
+
+        if existing.is_none() {
+            fs.check_inode_budget()?;
+        }
+
+        let time = time();
+        let len = file.len();
+        let inode_of_file = fs.storage.vacant_entry().key();
+        fs.storage.insert(Node::File(FileNode {
+            inode: inode_of_file,
+            name: name_of_file,
+            file,
+            metadata: Metadata {
+                ft: FileType {
+                    file: true,
+                    ..Default::default()
+                },
+                accessed: time,
+                created: time,
+                modified: time,
+                len,
+            },
+        }));
+
+        let event = match existing {
+            Some((position, old_inode)) => {
+                fs.replace_child_of_node(inode_of_parent, position, inode_of_file)?;
+                fs.storage.remove(old_inode);
+                FsEvent::Modified(path)
+            }
+            None => {
+                fs.add_child_to_node(inode_of_parent, inode_of_file)?;
+                FsEvent::Created(path)
+            }
+        };
+
+        drop(fs);
+        self.notify(event);
+
         Ok(())
     }
 
@@ -604,6 +966,72 @@ impl crate::FileSystem for FileSystem {
     }
 }
 
+impl WatchFileSystem for FileSystem {
+    fn watch(&self, path: &Path) -> Result<FsWatcher> {
+        let fs = self.inner.read().map_err(|_| FsError::Lock)?;
+        let (path, _) = fs.canonicalize(path)?;
+        Ok(fs.watchers.watch(path))
+    }
+}
+
+impl ExchangeFileSystem for FileSystem {
+    fn exchange(&self, a: &Path, b: &Path) -> Result<()> {
+        // Everything happens under a single write lock, so the swap can't
+        // be observed half-way.
+        let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+
+        let canonical_a = fs.canonicalize_without_inode(a)?;
+        let canonical_b = fs.canonicalize_without_inode(b)?;
+
+        let locate = |path: &Path| -> Result<(Inode, usize, Inode, OsString)> {
+            let parent = path.parent().ok_or(FsError::BaseNotDirectory)?;
+            let name = path
+                .file_name()
+                .ok_or(FsError::InvalidInput)?
+                .to_os_string();
+
+            // Entries of mounted file systems can't be swapped from here
+            let inode_of_parent = match fs.inode_of_parent(parent)? {
+                InodeResolution::Found(inode) => inode,
+                InodeResolution::Redirect(..) => return Err(FsError::InvalidInput),
+            };
+            match fs.as_parent_get_position_and_inode(inode_of_parent, &name)? {
+                Some((position, InodeResolution::Found(inode))) => {
+                    Ok((inode_of_parent, position, inode, name))
+                }
+                Some((_, InodeResolution::Redirect(..))) => Err(FsError::InvalidInput),
+                None => Err(FsError::EntryNotFound),
+            }
+        };
+        let (parent_of_a, position_of_a, inode_of_a, name_of_a) = locate(&canonical_a)?;
+        let (parent_of_b, position_of_b, inode_of_b, name_of_b) = locate(&canonical_b)?;
+
+        if inode_of_a == inode_of_b {
+            return Ok(());
+        }
+        // A directory can't be swapped with something inside of it
+        if canonical_a.starts_with(&canonical_b) || canonical_b.starts_with(&canonical_a) {
+            return Err(FsError::InvalidInput);
+        }
+
+        fs.update_node_name(inode_of_a, name_of_b)?;
+        fs.update_node_name(inode_of_b, name_of_a)?;
+        fs.replace_child_of_node(parent_of_a, position_of_a, inode_of_b)?;
+        fs.replace_child_of_node(parent_of_b, position_of_b, inode_of_a)?;
+
+        let rename_hooks = fs.rename_hooks.clone();
+        drop(fs);
+        self.renamed(
+            rename_hooks.clone(),
+            canonical_a.clone(),
+            canonical_b.clone(),
+        );
+        self.renamed(rename_hooks, canonical_b, canonical_a);
+
+        Ok(())
+    }
+}
+
 impl fmt::Debug for FileSystem {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         let fs: &FileSystemInner = &self.inner.read().unwrap();
@@ -617,8 +1045,14 @@ impl fmt::Debug for FileSystem {
 pub(super) struct FileSystemInner {
     pub(super) storage: Slab<Node>,
     pub(super) limiter: Option<crate::limiter::DynFsMemoryLimiter>,
+    pub(super) max_inodes: Option<usize>,
+    pub(super) watchers: Watchers,
+    pub(super) rename_hooks: Vec<RenameHook>,
 }
 
+/// A callback registered with [`FileSystem::on_rename()`].
+pub(super) type RenameHook = Arc<dyn Fn(&Path, &Path) + Send + Sync>;
+
 #[derive(Debug)]
 pub(super) enum InodeResolution {
     Found(Inode),
@@ -638,6 +1072,15 @@ impl InodeResolution {
 }
 
 impl FileSystemInner {
+    /// Fails if there's no room left for another inode, see
+    /// [`FileSystem::set_max_inodes()`].
+    pub(super) fn check_inode_budget(&self) -> Result<()> {
+        match self.max_inodes {
+            Some(max_inodes) if self.storage.len() >= max_inodes => Err(FsError::StorageFull),
+            _ => Ok(()),
+        }
+    }
+
     /// Get the inode associated to a path if it exists.
     pub(super) fn inode_of(&self, path: &Path) -> Result<InodeResolution> {
         // SAFETY: The root node always exists, so it's safe to unwrap here.
@@ -778,7 +1221,7 @@ impl FileSystemInner {
     /// From the inode of a parent node (so, a directory), returns the
     /// child index of `name_of` along with its inode, whatever the
     /// type of inode is (directory or file).
-    fn as_parent_get_position_and_inode(
+    pub(super) fn as_parent_get_position_and_inode(
         &self,
         inode_of_parent: Inode,
         name_of: &OsString,
@@ -815,6 +1258,41 @@ impl FileSystemInner {
         }
     }
 
+    /// Translates both sides of a rename into paths of a mounted file
+    /// system, when they both live inside the same one.
+    ///
+    /// Returns `None` when neither side is inside a mounted file system.
+    #[allow(clippy::type_complexity)]
+    fn as_mounted_rename(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<
+        Option<(
+            Arc<dyn crate::FileSystem + Send + Sync + 'static>,
+            PathBuf,
+            PathBuf,
+        )>,
+    > {
+        let mounted = |path: &Path| -> Result<_> {
+            let parent = path.parent().ok_or(FsError::BaseNotDirectory)?;
+            let name = path.file_name().ok_or(FsError::InvalidInput)?;
+            Ok(match self.inode_of_parent(parent)? {
+                InodeResolution::Found(_) => None,
+                InodeResolution::Redirect(fs, path) => Some((fs, path.join(name))),
+            })
+        };
+
+        match (mounted(from)?, mounted(to)?) {
+            (None, None) => Ok(None),
+            (Some((from_fs, from)), Some((to_fs, to))) if Arc::ptr_eq(&from_fs, &to_fs) => {
+                Ok(Some((from_fs, from, to)))
+            }
+            // Entries can't be moved between file systems
+            _ => Err(FsError::CrossDevice),
+        }
+    }
+
     /// Set a new name for the node represented by `inode`.
     pub(super) fn update_node_name(&mut self, inode: Inode, new_name: OsString) -> Result<()> {
         let node = self.storage.get_mut(inode).ok_or(FsError::UnknownError)?;
@@ -848,6 +1326,175 @@ impl FileSystemInner {
         }
     }
 
+    /// Replaces the child at `position` of the directory node represented
+    /// by `inode` with `new_child`.
+    ///
+    /// This function also updates the modified time of the directory.
+    pub(super) fn replace_child_of_node(
+        &mut self,
+        inode: Inode,
+        position: usize,
+        new_child: Inode,
+    ) -> Result<()> {
+        match self.storage.get_mut(inode) {
+            Some(Node::Directory(DirectoryNode {
+                children,
+                metadata: Metadata { modified, .. },
+                ..
+            })) => {
+                *children.get_mut(position).ok_or(FsError::UnknownError)? = new_child;
+                *modified = time();
+
+                Ok(())
+            }
+            _ => Err(FsError::UnknownError),
+        }
+    }
+
+    /// Where the file behind a copy-on-write [`ArcFileNode`] lives, or
+    /// [`None`] for any other node.
+    pub(super) fn copy_on_write_source(
+        &self,
+        inode: Inode,
+    ) -> Option<(Arc<dyn crate::FileSystem + Send + Sync>, PathBuf)> {
+        match self.storage.get(inode) {
+            Some(Node::ArcFile(node)) if node.copy_on_write => {
+                Some((node.fs.clone(), node.path.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Replaces a copy-on-write [`ArcFileNode`] with a private copy holding
+    /// `data`, so it can be written to without touching the file system it
+    /// came from. Any other node is left alone.
+    ///
+    /// `data` has to be read from [`FileSystemInner::copy_on_write_source()`]
+    /// beforehand, as reading it may block.
+    pub(super) fn copy_up(&mut self, inode: Inode, data: &[u8]) -> Result<()> {
+        let node = match self.storage.get(inode) {
+            Some(Node::ArcFile(node)) if node.copy_on_write => node,
+            _ => return Ok(()),
+        };
+
+        let mut file = File::new(self.limiter.clone());
+        file.write(data, &mut 0)?;
+
+        let copy = Node::File(FileNode {
+            inode,
+            name: node.name.clone(),
+            metadata: Metadata {
+                len: file.len(),
+                ..node.metadata.clone()
+            },
+            file,
+        });
+        self.storage[inode] = copy;
+
+        Ok(())
+    }
+
+    /// Copies the children of the directory `from` into the directory `to`
+    /// of `target`, recursively. See [`FileSystem::clone_subtree()`].
+    fn copy_children_into(
+        &self,
+        from: Inode,
+        target: &mut FileSystemInner,
+        to: Inode,
+    ) -> Result<()> {
+        let children = match self.storage.get(from) {
+            Some(Node::Directory(DirectoryNode { children, .. })) => children,
+            _ => return Err(FsError::BaseNotDirectory),
+        };
+
+        for child in children {
+            let node = match self.storage.get(*child) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            let inode = target.storage.vacant_entry().key();
+            let copy = match node {
+                Node::File(FileNode {
+                    name,
+                    file,
+                    metadata,
+                    ..
+                }) => Node::File(FileNode {
+                    inode,
+                    name: name.clone(),
+                    file: file.duplicate(target.limiter.clone())?,
+                    metadata: metadata.clone(),
+                }),
+                Node::ReadOnlyFile(ReadOnlyFileNode {
+                    name,
+                    file,
+                    metadata,
+                    ..
+                }) => Node::ReadOnlyFile(ReadOnlyFileNode {
+                    inode,
+                    name: name.clone(),
+                    file: file.clone(),
+                    metadata: metadata.clone(),
+                }),
+                Node::ArcFile(ArcFileNode {
+                    name,
+                    fs,
+                    path,
+                    metadata,
+                    copy_on_write,
+                    ..
+                }) => Node::ArcFile(ArcFileNode {
+                    inode,
+                    name: name.clone(),
+                    fs: fs.clone(),
+                    path: path.clone(),
+                    metadata: metadata.clone(),
+                    copy_on_write: *copy_on_write,
+                }),
+                Node::ArcDirectory(ArcDirectoryNode {
+                    name,
+                    fs,
+                    path,
+                    metadata,
+                    ..
+                }) => Node::ArcDirectory(ArcDirectoryNode {
+                    inode,
+                    name: name.clone(),
+                    fs: fs.clone(),
+                    path: path.clone(),
+                    metadata: metadata.clone(),
+                }),
+                Node::Directory(DirectoryNode { name, metadata, .. }) => {
+                    Node::Directory(DirectoryNode {
+                        inode,
+                        name: name.clone(),
+                        children: Vec::new(),
+                        metadata: metadata.clone(),
+                    })
+                }
+                // There is no way to duplicate whatever is behind a custom
+                // file (e.g. a device)
+                Node::CustomFile(_) => continue,
+            };
+            target.storage.insert(copy);
+
+            // Not using `add_child_to_node()` because that would bump the
+            // modification time of the copied directory
+            if let Some(Node::Directory(DirectoryNode { children, .. })) =
+                target.storage.get_mut(to)
+            {
+                children.push(inode);
+            }
+
+            if let Node::Directory(_) = node {
+                self.copy_children_into(*child, target, inode)?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Remove the child at position `position` of a directory node
     /// represented by `inode`.
     ///
@@ -887,6 +1534,26 @@ impl FileSystemInner {
         Ok((new_path, inode))
     }
 
+    /// Finds the path of `inode` by walking the tree from the root, or
+    /// `None` if it is no longer linked into the file system.
+    pub(super) fn path_of(&self, inode: Inode) -> Option<PathBuf> {
+        let mut to_visit = VecDeque::from([(ROOT_INODE, PathBuf::from("/"))]);
+        while let Some((current, path)) = to_visit.pop_front() {
+            if current == inode {
+                return Some(path);
+            }
+            if let Some(Node::Directory(DirectoryNode { children, .. })) = self.storage.get(current)
+            {
+                for child in children {
+                    if let Some(node) = self.storage.get(*child) {
+                        to_visit.push_back((*child, path.join(node.name())));
+                    }
+                }
+            }
+        }
+        None
+    }
+
     /// Like `Self::canonicalize` but without returning the inode of
     /// the path, which means that there is no guarantee that the path
     /// exists in the file system.
@@ -1013,6 +1680,9 @@ impl Default for FileSystemInner {
         Self {
             storage: slab,
             limiter: None,
+            max_inodes: None,
+            watchers: Watchers::default(),
+            rename_hooks: Vec::new(),
         }
     }
 }
@@ -1033,11 +1703,35 @@ impl DirectoryMustBeEmpty {
     }
 }
 
+/// Returns the path that a whiteout entry (`.wh.<name>`) hides, if `path`
+/// is one.
+fn whiteout_target(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let hidden = name.strip_prefix(".wh.")?;
+    Some(path.with_file_name(hidden))
+}
+
+/// Lists the entries of a mounted directory under `path`, the path the
+/// directory is mounted at, rather than under its path in the mounted file
+/// system.
+fn rebase_entries(entries: ReadDir, path: &Path) -> Result<ReadDir> {
+    let entries = entries
+        .map(|entry| {
+            let mut entry = entry?;
+            if let Some(name) = entry.path.file_name() {
+                entry.path = path.join(name);
+            }
+            Ok(entry)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(ReadDir::new(entries))
+}
+
 #[cfg(test)]
 mod test_filesystem {
     use std::{borrow::Cow, path::Path};
 
-    use tokio::io::AsyncReadExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     use crate::{mem_fs::*, ops, DirEntry, FileOpener, FileSystem as FS, FileType, FsError};
 
@@ -1168,6 +1862,31 @@ mod test_filesystem {
         }
     }
 
+    #[test]
+    fn test_create_dir_where_a_file_is() {
+        let fs = FileSystem::default();
+        fs.new_open_options()
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo"))
+            .unwrap();
+
+        assert_eq!(
+            fs.create_dir(path!("/foo")),
+            Err(FsError::AlreadyExists),
+            "creating a directory over a file",
+        );
+        assert_eq!(
+            fs.create_dir(path!("/foo/bar")),
+            Err(FsError::BaseNotDirectory),
+            "creating a directory inside a file",
+        );
+
+        // The file is left alone
+        assert!(fs.metadata(path!("/foo")).unwrap().is_file());
+        assert_eq!(fs.read_dir(path!("/")).unwrap().count(), 1);
+    }
+
     #[test]
     fn test_remove_dir() {
         let fs = FileSystem::default();
@@ -1449,6 +2168,152 @@ mod test_filesystem {
         }
     }
 
+    #[tokio::test]
+    async fn test_rename_moves_the_whole_directory() {
+        let fs = FileSystem::default();
+        ops::create_dir_all(&fs, "/foo/bar/baz").unwrap();
+        fs.write_file(path!("/foo/bar/baz/hello.txt"), b"Hello, World!")
+            .unwrap();
+        fs.create_dir(path!("/qux")).unwrap();
+
+        fs.rename(path!("/foo/bar"), path!("/qux/renamed")).unwrap();
+
+        assert_eq!(fs.metadata(path!("/foo/bar")), Err(FsError::EntryNotFound));
+        assert!(fs.metadata(path!("/qux/renamed/baz")).unwrap().is_dir());
+        let mut contents = String::new();
+        fs.new_open_options()
+            .read(true)
+            .open(path!("/qux/renamed/baz/hello.txt"))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .await
+            .unwrap();
+        assert_eq!(contents, "Hello, World!");
+
+        // An empty directory can be replaced by a non-empty one
+        fs.create_dir(path!("/empty")).unwrap();
+        fs.rename(path!("/qux/renamed"), path!("/empty")).unwrap();
+        assert!(fs
+            .metadata(path!("/empty/baz/hello.txt"))
+            .unwrap()
+            .is_file());
+
+        assert_eq!(
+            fs.rename(path!("/empty"), path!("/empty/baz/inside")),
+            Err(FsError::InvalidInput),
+            "moving a directory inside of itself",
+        );
+    }
+
+    #[test]
+    fn test_rename_onto_a_non_empty_directory() {
+        let fs = FileSystem::default();
+        ops::create_dir_all(&fs, "/foo/child").unwrap();
+        ops::create_dir_all(&fs, "/bar/child").unwrap();
+        fs.write_file(path!("/file.txt"), b"").unwrap();
+
+        assert_eq!(
+            fs.rename(path!("/foo"), path!("/bar")),
+            Err(FsError::DirectoryNotEmpty),
+        );
+        assert_eq!(
+            fs.rename(path!("/file.txt"), path!("/bar")),
+            Err(FsError::DirectoryNotEmpty),
+        );
+        assert_eq!(
+            fs.rename(path!("/foo"), path!("/file.txt")),
+            Err(FsError::BaseNotDirectory),
+        );
+
+        // Nothing was moved or lost
+        assert!(fs.metadata(path!("/foo/child")).unwrap().is_dir());
+        assert!(fs.metadata(path!("/bar/child")).unwrap().is_dir());
+        assert!(fs.metadata(path!("/file.txt")).unwrap().is_file());
+    }
+
+    #[test]
+    fn test_rename_across_mounts() {
+        let fs = FileSystem::default();
+        let other: Arc<dyn crate::FileSystem + Send + Sync> = Arc::new(FileSystem::default());
+        fs.mount("/mnt".into(), &other, "/".into()).unwrap();
+        fs.create_dir(path!("/foo")).unwrap();
+
+        assert_eq!(
+            fs.rename(path!("/foo"), path!("/mnt/foo")),
+            Err(FsError::CrossDevice),
+        );
+        assert!(fs.metadata(path!("/foo")).unwrap().is_dir());
+    }
+
+    #[test]
+    fn test_rename_is_atomic() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let fs = FileSystem::default();
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open(path!("/config.toml"))
+            .unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let fs = fs.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    assert!(
+                        fs.metadata(path!("/config.toml")).is_ok(),
+                        "the file is missing half way through a rename",
+                    );
+                }
+            })
+        };
+
+        // Like an editor that writes to a temporary file and moves it into place
+        for _ in 0..1000 {
+            fs.new_open_options()
+                .write(true)
+                .create(true)
+                .open(path!("/config.toml.tmp"))
+                .unwrap();
+            fs.rename(path!("/config.toml.tmp"), path!("/config.toml"))
+                .unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+
+        assert_eq!(
+            fs.metadata(path!("/config.toml.tmp")),
+            Err(FsError::EntryNotFound)
+        );
+    }
+
+    #[test]
+    fn test_on_rename() {
+        let fs = FileSystem::default();
+        fs.create_dir(path!("/foo")).unwrap();
+
+        let renames = Arc::new(std::sync::Mutex::new(Vec::new()));
+        fs.on_rename({
+            let renames = renames.clone();
+            move |from, to| {
+                renames
+                    .lock()
+                    .unwrap()
+                    .push((from.to_path_buf(), to.to_path_buf()))
+            }
+        });
+
+        fs.rename(path!("/foo"), path!("/bar")).unwrap();
+        assert!(fs.rename(path!("/foo"), path!("/baz")).is_err());
+
+        assert_eq!(
+            *renames.lock().unwrap(),
+            [(path!(buf "/foo"), path!(buf "/bar"))]
+        );
+    }
+
     #[test]
     fn test_metadata() {
         use std::thread::sleep;
@@ -1640,7 +2505,7 @@ mod test_filesystem {
                     path,
                     metadata: Ok(Metadata { ft, .. }),
                 }))
-                    if path == path!(buf "/foo") && ft.is_dir()
+                    if path == path!(buf "/a.txt") && ft.is_file()
             ),
             "checking entry #1",
         );
@@ -1651,7 +2516,7 @@ mod test_filesystem {
                     path,
                     metadata: Ok(Metadata { ft, .. }),
                 }))
-                    if path == path!(buf "/bar") && ft.is_dir()
+                    if path == path!(buf "/b.txt") && ft.is_file()
             ),
             "checking entry #2",
         );
@@ -1662,7 +2527,7 @@ mod test_filesystem {
                     path,
                     metadata: Ok(Metadata { ft, .. }),
                 }))
-                    if path == path!(buf "/baz") && ft.is_dir()
+                    if path == path!(buf "/bar") && ft.is_dir()
             ),
             "checking entry #3",
         );
@@ -1673,7 +2538,7 @@ mod test_filesystem {
                     path,
                     metadata: Ok(Metadata { ft, .. }),
                 }))
-                    if path == path!(buf "/a.txt") && ft.is_file()
+                    if path == path!(buf "/baz") && ft.is_dir()
             ),
             "checking entry #4",
         );
@@ -1684,13 +2549,78 @@ mod test_filesystem {
                     path,
                     metadata: Ok(Metadata { ft, .. }),
                 }))
-                    if path == path!(buf "/b.txt") && ft.is_file()
+                    if path == path!(buf "/foo") && ft.is_dir()
             ),
             "checking entry #5",
         );
         assert!(matches!(readdir.next(), None), "no more entries");
     }
 
+    #[test]
+    fn test_readdir_is_sorted() {
+        let fs = FileSystem::default();
+
+        for name in ["/delta", "/alpha", "/charlie", "/bravo"] {
+            fs.new_open_options()
+                .write(true)
+                .create_new(true)
+                .open(name)
+                .unwrap();
+        }
+        fs.create_dir(path!("/aardvark")).unwrap();
+
+        let names: Vec<_> = fs
+            .read_dir(path!("/"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                path!(buf "/aardvark"),
+                path!(buf "/alpha"),
+                path!(buf "/bravo"),
+                path!(buf "/charlie"),
+                path!(buf "/delta"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readdir_caches_metadata() {
+        let fs = FileSystem::default();
+
+        let mut f = fs
+            .new_open_options()
+            .write(true)
+            .create_new(true)
+            .open(path!("/a.txt"))
+            .unwrap();
+        f.write_all(b"hello").await.unwrap();
+
+        let mut entry = fs.read_dir(path!("/")).unwrap().next().unwrap().unwrap();
+        let metadata = entry.metadata().unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 5);
+        assert_ne!(metadata.created(), 0);
+        assert_ne!(metadata.modified(), 0);
+
+        // The cached metadata doesn't need the file system at all...
+        f.write_all(b", world").await.unwrap();
+        assert_eq!(entry.metadata().unwrap().len(), 5);
+
+        // ...until it's explicitly refreshed
+        assert_eq!(entry.refresh_metadata(&fs).unwrap().len(), 12);
+        assert_eq!(entry.metadata().unwrap().len(), 12);
+
+        fs.remove_file(path!("/a.txt")).unwrap();
+        assert_eq!(entry.metadata().unwrap().len(), 12);
+        assert_eq!(
+            entry.refresh_metadata(&fs).unwrap_err(),
+            FsError::EntryNotFound
+        );
+    }
+
     #[test]
     fn test_canonicalize() {
         let fs = FileSystem::default();
@@ -1,3 +1,4 @@
+mod buffer;
 mod file;
 mod file_opener;
 mod filesystem;
@@ -40,6 +41,9 @@ struct ArcFileNode {
     fs: Arc<dyn crate::FileSystem + Send + Sync>,
     path: PathBuf,
     metadata: Metadata,
+    /// Whether the file is copied into this file system when it is opened
+    /// for writing, instead of writing to `fs`.
+    copy_on_write: bool,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,292 @@
+//! Wraps a [`VirtualFile`] and keeps a running count of the bytes that
+//! flow through it, which is useful for per-session accounting of stdio.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use derivative::Derivative;
+
+use super::*;
+
+use crate::VirtualFile;
+
+/// Cumulative byte counters shared between a [`MeteredFile`] and anyone
+/// holding on to a [`MeteredFileStats`] handle.
+#[derive(Debug, Default)]
+struct MeteredFileCounters {
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+/// A cheap, cloneable handle to the counters of a [`MeteredFile`].
+///
+/// This stays valid after the file itself has been boxed up and handed
+/// over to the runtime.
+#[derive(Debug, Clone, Default)]
+pub struct MeteredFileStats {
+    counters: Arc<MeteredFileCounters>,
+}
+
+impl MeteredFileStats {
+    /// Total number of bytes read from the file so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.counters.read.load(Ordering::Relaxed)
+    }
+
+    /// Total number of bytes written to the file so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.counters.written.load(Ordering::Relaxed)
+    }
+}
+
+/// Wraps a [`VirtualFile`] and counts every byte read from or written to it.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct MeteredFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    stats: MeteredFileStats,
+}
+
+impl MeteredFile {
+    pub fn new(inner: Box<dyn VirtualFile + Send + Sync + 'static>) -> Self {
+        Self {
+            inner,
+            stats: MeteredFileStats::default(),
+        }
+    }
+
+    /// Total number of bytes read from the file so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.stats.bytes_read()
+    }
+
+    /// Total number of bytes written to the file so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.stats.bytes_written()
+    }
+
+    /// Returns a handle to the counters that outlives this file.
+    pub fn stats(&self) -> MeteredFileStats {
+        self.stats.clone()
+    }
+
+    pub fn into_inner(self) -> Box<dyn VirtualFile + Send + Sync + 'static> {
+        self.inner
+    }
+}
+
+impl VirtualFile for MeteredFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        self.inner.set_len(new_size)
+    }
+
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        self.inner.set_times(atime, mtime)
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        self.inner.unlink()
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn get_special_fd(&self) -> Option<u32> {
+        self.inner.get_special_fd()
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn read_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = io::Result<usize>> + 'a>> {
+        Box::pin(async move {
+            let amt = self.inner.read_at(offset, buf).await?;
+            self.stats
+                .counters
+                .read
+                .fetch_add(amt as u64, Ordering::Relaxed);
+            Ok(amt)
+        })
+    }
+
+    fn write_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = io::Result<usize>> + 'a>> {
+        Box::pin(async move {
+            let amt = self.inner.write_at(offset, buf).await?;
+            self.stats
+                .counters
+                .written
+                .fetch_add(amt as u64, Ordering::Relaxed);
+            Ok(amt)
+        })
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_write_ready(cx)
+    }
+}
+
+impl AsyncWrite for MeteredFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(amt)) = &res {
+            self.stats
+                .counters
+                .written
+                .fetch_add(*amt as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(amt)) = &res {
+            self.stats
+                .counters
+                .written
+                .fetch_add(*amt as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncRead for MeteredFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &res {
+            let amt = buf.filled().len().saturating_sub(before);
+            self.stats
+                .counters
+                .read
+                .fetch_add(amt as u64, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
+impl AsyncSeek for MeteredFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::Pipe;
+
+    #[tokio::test]
+    async fn counts_bytes_through_a_pipe() {
+        let (local, mut remote) = Pipe::channel();
+        let mut file = MeteredFile::new(Box::new(local));
+        let stats = file.stats();
+
+        file.write_all(b"hello world").await.unwrap();
+        let mut buf = [0u8; 11];
+        remote.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello world");
+
+        remote.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        assert_eq!(file.bytes_written(), 11);
+        assert_eq!(file.bytes_read(), 4);
+        assert_eq!(stats.bytes_written(), 11);
+        assert_eq!(stats.bytes_read(), 4);
+    }
+
+    #[tokio::test]
+    async fn forwards_positional_io() {
+        let fs = crate::mem_fs::FileSystem::default();
+        let inner = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open("/file.txt")
+            .unwrap();
+        let mut file = MeteredFile::new(inner);
+
+        file.write_all(b"0123456789").await.unwrap();
+        file.write_at(2, b"ab").await.unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(file.read_at(0, &mut buf).await.unwrap(), 4);
+        assert_eq!(&buf, b"01ab");
+
+        assert_eq!(file.bytes_written(), 12);
+        assert_eq!(file.bytes_read(), 4);
+    }
+}
@@ -1,12 +1,20 @@
 //! Common [`FileSystem`] operations.
 #![allow(dead_code)] // Most of these helpers are used during testing
 
-use std::{collections::VecDeque, path::Path};
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+};
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use crate::{DirEntry, FileSystem, FsError};
 
+/// The maximum number of symlinks that will be followed while resolving a
+/// single path, mirroring Linux's `MAXSYMLINKS`.
+pub const MAX_SYMLINK_FOLLOWS: usize = 40;
+
 /// Does this item exists?
 pub fn exists<F>(fs: &F, path: impl AsRef<Path>) -> bool
 where
@@ -127,6 +135,102 @@ where
     Ok(())
 }
 
+/// Resolve a path to its canonical, absolute form, following symlinks and
+/// checking that every component exists.
+///
+/// This is analogous to [`std::fs::canonicalize()`].
+pub fn canonicalize<F>(fs: &F, path: &Path) -> Result<PathBuf, FsError>
+where
+    F: FileSystem + ?Sized,
+{
+    canonicalize_with_limit(fs, path, MAX_SYMLINK_FOLLOWS)
+}
+
+/// Like [`canonicalize()`], but gives up with [`FsError::TooManySymlinks`]
+/// after following `max_symlink_follows` symlinks instead of
+/// [`MAX_SYMLINK_FOLLOWS`].
+pub fn canonicalize_with_limit<F>(
+    fs: &F,
+    path: &Path,
+    max_symlink_follows: usize,
+) -> Result<PathBuf, FsError>
+where
+    F: FileSystem + ?Sized,
+{
+    let mut resolved = PathBuf::new();
+    // Components that still need to be resolved, in reverse order
+    let mut remaining = Vec::new();
+    push_components(&mut resolved, &mut remaining, path)?;
+
+    let mut follows = 0;
+    while let Some(name) = remaining.pop() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+
+        let candidate = resolved.join(&name);
+        let metadata = fs.symlink_metadata(&candidate)?;
+        if !metadata.file_type().is_symlink() {
+            resolved = candidate;
+            continue;
+        }
+
+        follows += 1;
+        if follows > max_symlink_follows {
+            return Err(FsError::TooManySymlinks);
+        }
+        let target = fs.read_link(&candidate)?;
+        if target.is_absolute() {
+            resolved = PathBuf::new();
+            push_components(&mut resolved, &mut remaining, &target)?;
+        } else {
+            push_relative_components(&mut remaining, &target)?;
+        }
+    }
+
+    // Make sure the root itself exists (e.g. for a plain `/`)
+    fs.metadata(&resolved)?;
+    Ok(resolved)
+}
+
+/// Seeds `resolved` with the root of an absolute `path` and queues up the
+/// rest of its components.
+fn push_components(
+    resolved: &mut PathBuf,
+    remaining: &mut Vec<OsString>,
+    path: &Path,
+) -> Result<(), FsError> {
+    let mut components = path.components();
+    loop {
+        let mut lookahead = components.clone();
+        match lookahead.next() {
+            Some(component @ (Component::Prefix(_) | Component::RootDir)) => {
+                resolved.push(component.as_os_str());
+                components = lookahead;
+            }
+            _ => break,
+        }
+    }
+    if !resolved.has_root() {
+        return Err(FsError::InvalidInput);
+    }
+
+    push_relative_components(remaining, components.as_path())
+}
+
+fn push_relative_components(remaining: &mut Vec<OsString>, path: &Path) -> Result<(), FsError> {
+    for component in path.components().rev() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => remaining.push(OsString::from("..")),
+            Component::Normal(name) => remaining.push(name.to_os_string()),
+            Component::Prefix(_) | Component::RootDir => return Err(FsError::InvalidInput),
+        }
+    }
+    Ok(())
+}
+
 /// Recursively iterate over all paths inside a directory, ignoring any
 /// errors that may occur along the way.
 pub fn walk<F>(fs: &F, path: impl AsRef<Path>) -> Box<dyn Iterator<Item = DirEntry> + '_>
@@ -228,4 +332,39 @@ mod tests {
 
         assert_eq!(super::read(&fs, "/file.txt").await.unwrap(), b"");
     }
+
+    #[test]
+    fn canonicalize_collapses_dots() {
+        let fs = MemFS::default();
+        super::create_dir_all(&fs, "/a/b/c").unwrap();
+
+        assert_eq!(
+            fs.canonicalize(Path::new("/a/./b/../b/c/..")).unwrap(),
+            Path::new("/a/b")
+        );
+        assert_eq!(
+            fs.canonicalize(Path::new("/../a")).unwrap(),
+            Path::new("/a")
+        );
+        assert_eq!(fs.canonicalize(Path::new("/")).unwrap(), Path::new("/"));
+    }
+
+    #[test]
+    fn canonicalize_missing_component() {
+        let fs = MemFS::default();
+        super::create_dir_all(&fs, "/a/b").unwrap();
+
+        assert_eq!(
+            fs.canonicalize(Path::new("/a/missing/../b")).unwrap_err(),
+            FsError::EntryNotFound
+        );
+        assert_eq!(
+            fs.canonicalize(Path::new("/a/b/missing")).unwrap_err(),
+            FsError::EntryNotFound
+        );
+        assert_eq!(
+            fs.canonicalize(Path::new("a/b")).unwrap_err(),
+            FsError::InvalidInput
+        );
+    }
 }
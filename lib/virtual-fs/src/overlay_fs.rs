@@ -199,6 +199,30 @@ where
         self.permission_error_or_not_found(path)
     }
 
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<(), FsError> {
+        match self.primary.write_file(path, contents) {
+            Err(e) if should_continue(e) => {}
+            other => return other,
+        }
+
+        // Just like when opening a file with `create`, a file written inside
+        // a folder that only exists on a secondary filesystem ends up on the
+        // primary
+        if let Some(parent) = path.parent() {
+            let parent_exists_on_secondary_fs = self
+                .secondaries
+                .filesystems()
+                .into_iter()
+                .any(|fs| ops::is_dir(fs, parent));
+            if parent_exists_on_secondary_fs {
+                ops::create_dir_all(&self.primary, parent)?;
+                return self.primary.write_file(path, contents);
+            }
+        }
+
+        Err(FsError::EntryNotFound)
+    }
+
     fn new_open_options(&self) -> OpenOptions<'_> {
         OpenOptions::new(self)
     }
@@ -311,6 +335,10 @@ where
             Err(FsError::PermissionDenied)
         }
 
+        fn shrink_to_fit(&mut self) -> crate::Result<()> {
+            self.inner.shrink_to_fit()
+        }
+
         fn poll_read_ready(
             mut self: Pin<&mut Self>,
             cx: &mut std::task::Context<'_>,
@@ -2,7 +2,7 @@
 //! needed so that a `Box<dyn VirtualFileSystem>` can be wrapped in an Arc and
 //! shared - some of the interfaces pass around a `Box<dyn VirtualFileSystem>`
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 #[allow(unused_imports, dead_code)]
 use tracing::{debug, error, info, trace, warn};
 
@@ -44,10 +44,18 @@ impl FileSystem for PassthruFileSystem {
         self.fs.symlink_metadata(path)
     }
 
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.fs.read_link(path)
+    }
+
     fn remove_file(&self, path: &Path) -> Result<()> {
         self.fs.remove_file(path)
     }
 
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.fs.write_file(path, contents)
+    }
+
     fn new_open_options(&self) -> OpenOptions {
         self.fs.new_open_options()
     }
@@ -129,14 +129,27 @@ impl Seek for Pipe {
     }
 }
 
+/// Pipes have no position, so seeking doesn't do anything, but a seek that
+/// would end up before the byte 0 is still rejected.
+fn check_seek(position: SeekFrom) -> io::Result<()> {
+    match position {
+        SeekFrom::Current(offset) | SeekFrom::End(offset) if offset < 0 => {
+            Err(FsError::InvalidInput.into())
+        }
+        _ => Ok(()),
+    }
+}
+
 impl Seek for PipeRx {
-    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        check_seek(position)?;
         Ok(0)
     }
 }
 
 impl Seek for PipeTx {
-    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        check_seek(position)?;
         Ok(0)
     }
 }
@@ -149,6 +162,11 @@ impl Read for Pipe {
 
 impl Read for PipeRx {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Asking for nothing returns straight away rather than waiting for
+        // data (like `read(2)` on a pipe)
+        if buf.is_empty() {
+            return Ok(0);
+        }
         let max_size = buf.len();
 
         let mut rx = self.rx.lock().unwrap();
@@ -213,6 +231,10 @@ impl std::io::Write for Pipe {
 
 impl std::io::Write for PipeTx {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // There's no point waking the reader up for an empty message
+        if buf.is_empty() {
+            return Ok(0);
+        }
         let tx = self.tx.lock().unwrap();
         tx.send(buf.to_vec())
             .map_err(|_| Into::<std::io::Error>::into(std::io::ErrorKind::BrokenPipe))?;
@@ -237,8 +259,8 @@ impl AsyncSeek for Pipe {
 }
 
 impl AsyncSeek for PipeRx {
-    fn start_seek(self: Pin<&mut Self>, _position: SeekFrom) -> io::Result<()> {
-        Ok(())
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        check_seek(position)
     }
     fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
         Poll::Ready(Ok(0))
@@ -246,8 +268,8 @@ impl AsyncSeek for PipeRx {
 }
 
 impl AsyncSeek for PipeTx {
-    fn start_seek(self: Pin<&mut Self>, _position: SeekFrom) -> io::Result<()> {
-        Ok(())
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        check_seek(position)
     }
     fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
         Poll::Ready(Ok(0))
@@ -293,6 +315,9 @@ impl AsyncWrite for PipeTx {
         _cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
         let guard = self.tx.lock().unwrap();
         match guard.send(buf.to_vec()) {
             Ok(()) => Poll::Ready(Ok(buf.len())),
@@ -329,6 +354,9 @@ impl AsyncRead for PipeRx {
         cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
         let mut rx = self.rx.lock().unwrap();
         loop {
             {
@@ -519,3 +547,46 @@ impl DuplexPipe {
 /// Shared version of BidiPipe for situations where you need
 /// to emulate the old behaviour of `Pipe` (both send and recv on one channel).
 pub type WasiBidirectionalSharedPipePair = ArcFile<DuplexPipe>;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, SeekFrom};
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::Pipe;
+
+    #[tokio::test]
+    async fn seeking_before_the_start_is_rejected() {
+        let (mut tx, mut rx) = Pipe::channel();
+
+        assert_eq!(rx.seek(SeekFrom::Start(10)).await.unwrap(), 0);
+        assert_eq!(tx.seek(SeekFrom::End(0)).await.unwrap(), 0);
+
+        let err = rx.seek(SeekFrom::Current(-1)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = tx.seek(SeekFrom::End(-1)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn zero_length_reads_and_writes_are_no_ops() {
+        let (mut tx, mut rx) = Pipe::channel();
+
+        // Nothing has been written, yet neither of these waits for data
+        assert_eq!(rx.read(&mut []).await.unwrap(), 0);
+        assert_eq!(std::io::Read::read(&mut rx, &mut []).unwrap(), 0);
+
+        assert_eq!(tx.write(&[]).await.unwrap(), 0);
+        assert_eq!(std::io::Write::write(&mut tx, &[]).unwrap(), 0);
+        tx.write_all(b"hi").await.unwrap();
+        assert_eq!(rx.read(&mut []).await.unwrap(), 0);
+
+        // The empty writes didn't reach the reader and the empty read
+        // didn't consume anything
+        tx.close();
+        let mut received = Vec::new();
+        rx.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"hi");
+    }
+}
@@ -0,0 +1,202 @@
+use derivative::Derivative;
+
+use super::*;
+
+use crate::VirtualFile;
+
+/// Wraps a [`VirtualFile`] and prepends a prefix to every line written to it.
+///
+/// Output is line buffered: a line only reaches the inner file once it is
+/// complete, or when the file is flushed or shut down. A line that was
+/// flushed half way through is not prefixed a second time when it is
+/// finished.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct PrefixedFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    prefix: Vec<u8>,
+    /// The line currently being written, including its prefix.
+    line: Vec<u8>,
+    /// Whether the next byte that is written starts a new line.
+    at_line_start: bool,
+    /// Data that is ready to be written to the inner file.
+    #[derivative(Debug = "ignore")]
+    pending: Vec<u8>,
+}
+
+impl PrefixedFile {
+    pub fn new(
+        inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+        prefix: impl Into<Vec<u8>>,
+    ) -> Self {
+        Self {
+            inner,
+            prefix: prefix.into(),
+            line: Vec::new(),
+            at_line_start: true,
+            pending: Vec::new(),
+        }
+    }
+
+    fn buffer(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            if self.at_line_start {
+                self.line.extend_from_slice(&self.prefix);
+                self.at_line_start = false;
+            }
+            match buf.iter().position(|b| *b == b'\n') {
+                Some(end) => {
+                    self.line.extend_from_slice(&buf[..=end]);
+                    self.pending.append(&mut self.line);
+                    self.at_line_start = true;
+                    buf = &buf[end + 1..];
+                }
+                None => {
+                    self.line.extend_from_slice(buf);
+                    buf = &[];
+                }
+            }
+        }
+    }
+
+    /// Writes out everything that is pending.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            match Pin::new(self.inner.as_mut()).poll_write(cx, &self.pending) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(amt)) => {
+                    self.pending.drain(..amt);
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl VirtualFile for PrefixedFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        self.inner.set_len(new_size)
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        self.inner.unlink()
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn get_special_fd(&self) -> Option<u32> {
+        self.inner.get_special_fd()
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_write_ready(cx)
+    }
+}
+
+impl AsyncWrite for PrefixedFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.buffer(buf);
+        // The data is buffered either way, whatever is left over gets
+        // written out on the next write or flush
+        match self.poll_drain(cx) {
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            _ => Poll::Ready(Ok(buf.len())),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.as_mut().get_mut();
+        this.pending.append(&mut this.line);
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(this.inner.as_mut()).poll_flush(cx),
+            res => res,
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.as_mut().poll_flush(cx) {
+            Poll::Ready(Ok(())) => Pin::new(self.inner.as_mut()).poll_shutdown(cx),
+            res => res,
+        }
+    }
+}
+
+impl AsyncRead for PrefixedFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(self.inner.as_mut()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for PrefixedFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(self.inner.as_mut()).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(self.inner.as_mut()).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn lines_are_prefixed() {
+        let (tx, mut rx) = Pipe::channel();
+        let mut file = PrefixedFile::new(Box::new(tx), "app: ");
+
+        file.write_all(b"hello\nwor").await.unwrap();
+        file.write_all(b"ld\n\npartial").await.unwrap();
+        file.flush().await.unwrap();
+        file.write_all(b" line").await.unwrap();
+        file.shutdown().await.unwrap();
+        drop(file);
+
+        let mut output = String::new();
+        rx.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "app: hello\napp: world\napp: \napp: partial line");
+    }
+}
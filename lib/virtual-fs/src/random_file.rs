@@ -1,16 +1,37 @@
-//! Used for /dev/zero - infinitely returns zero
-//! which is useful for commands like `dd if=/dev/zero of=bigfile.img size=1G`
+//! Used for /dev/urandom - infinitely returns random bytes
 
 use std::io::{self, *};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+use derivative::Derivative;
 use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
 
 use crate::VirtualFile;
 
-#[derive(Debug, Default)]
-pub struct RandomFile {}
+/// Fills a buffer with random bytes.
+pub type RandomSource = Box<dyn FnMut(&mut [u8]) + Send + Sync>;
+
+/// Returns random bytes on every read and throws away everything written
+/// to it.
+///
+/// The bytes come from the operating system unless another source is given
+/// with [`RandomFile::with_source()`], e.g. a seeded RNG to make a program
+/// reproducible.
+#[derive(Derivative, Default)]
+#[derivative(Debug)]
+pub struct RandomFile {
+    #[derivative(Debug = "ignore")]
+    source: Option<RandomSource>,
+}
+
+impl RandomFile {
+    pub fn with_source(source: impl FnMut(&mut [u8]) + Send + Sync + 'static) -> Self {
+        RandomFile {
+            source: Some(Box::new(source)),
+        }
+    }
+}
 
 impl AsyncSeek for RandomFile {
     fn start_seek(self: Pin<&mut Self>, _position: SeekFrom) -> io::Result<()> {
@@ -40,7 +61,7 @@ impl AsyncWrite for RandomFile {
         _cx: &mut Context<'_>,
         bufs: &[IoSlice<'_>],
     ) -> Poll<io::Result<usize>> {
-        Poll::Ready(Ok(bufs.len()))
+        Poll::Ready(Ok(bufs.iter().map(|buf| buf.len()).sum()))
     }
     fn is_write_vectored(&self) -> bool {
         false
@@ -49,12 +70,17 @@ impl AsyncWrite for RandomFile {
 
 impl AsyncRead for RandomFile {
     fn poll_read(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         _cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         let mut data = vec![0u8; buf.remaining()];
-        getrandom::getrandom(&mut data).ok();
+        match &mut self.source {
+            Some(source) => source(&mut data),
+            None => {
+                getrandom::getrandom(&mut data).ok();
+            }
+        }
         buf.put_slice(&data[..]);
         Poll::Ready(Ok(()))
     }
@@ -0,0 +1,186 @@
+//! A file system wrapper that rejects every change, except underneath a
+//! handful of explicitly writable directories.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::*;
+
+/// Wraps a [`FileSystem`] so that anything which would modify it fails with
+/// [`FsError::ReadOnly`].
+///
+/// Directories added with [`ReadOnlyFileSystem::with_writable_dir()`] (and
+/// everything below them) remain writable.
+#[derive(Debug, Clone)]
+pub struct ReadOnlyFileSystem {
+    inner: Arc<dyn FileSystem + Send + Sync>,
+    writable: Vec<PathBuf>,
+}
+
+impl ReadOnlyFileSystem {
+    pub fn new(inner: Arc<dyn FileSystem + Send + Sync>) -> Self {
+        Self {
+            inner,
+            writable: Vec::new(),
+        }
+    }
+
+    /// Allows changes to be made within `path`.
+    pub fn with_writable_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.writable.push(path.into());
+        self
+    }
+
+    pub(crate) fn check_writable(&self, path: &Path) -> Result<()> {
+        // Don't let `..` escape from a writable directory
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(FsError::ReadOnly);
+        }
+        if self.writable.iter().any(|dir| path.starts_with(dir)) {
+            Ok(())
+        } else {
+            Err(FsError::ReadOnly)
+        }
+    }
+}
+
+impl FileSystem for ReadOnlyFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.check_writable(path)?;
+        self.inner.create_dir(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.check_writable(path)?;
+        self.inner.remove_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.check_writable(from)?;
+        self.check_writable(to)?;
+        self.inner.rename(from, to)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.inner.read_link(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.check_writable(path)?;
+        self.inner.remove_file(path)
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.check_writable(path)?;
+        self.inner.write_file(path, contents)
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+}
+
+impl FileOpener for ReadOnlyFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        // Writes are refused up front rather than by the file
+        if conf.would_mutate() {
+            self.check_writable(path)?;
+        }
+        self.inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::mem_fs::FileSystem as MemFS;
+
+    fn read_only_fs() -> ReadOnlyFileSystem {
+        let mem_fs = MemFS::default();
+        mem_fs.create_dir(Path::new("/etc")).unwrap();
+        mem_fs
+            .write_file(Path::new("/etc/hosts"), b"127.0.0.1 localhost")
+            .unwrap();
+        mem_fs.create_dir(Path::new("/tmp")).unwrap();
+        ReadOnlyFileSystem::new(Arc::new(mem_fs)).with_writable_dir("/tmp")
+    }
+
+    #[tokio::test]
+    async fn only_writable_dirs_can_be_changed() {
+        let fs = read_only_fs();
+
+        let err = fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/etc/passwd")
+            .unwrap_err();
+        assert_eq!(err, FsError::ReadOnly);
+        let err = fs
+            .new_open_options()
+            .write(true)
+            .open("/etc/hosts")
+            .unwrap_err();
+        assert_eq!(err, FsError::ReadOnly);
+        fs.new_open_options().read(true).open("/etc/hosts").unwrap();
+        assert_eq!(
+            fs.create_dir(Path::new("/etc/ssh")).unwrap_err(),
+            FsError::ReadOnly
+        );
+        assert_eq!(
+            fs.remove_dir(Path::new("/etc")).unwrap_err(),
+            FsError::ReadOnly
+        );
+        assert_eq!(
+            fs.create_dir(Path::new("/tmp/../etc/ssh")).unwrap_err(),
+            FsError::ReadOnly
+        );
+
+        let mut f = fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/tmp/scratch.txt")
+            .unwrap();
+        f.write_all(b"hello").await.unwrap();
+        drop(f);
+
+        let mut contents = String::new();
+        fs.new_open_options()
+            .read(true)
+            .open("/tmp/scratch.txt")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .await
+            .unwrap();
+        assert_eq!(contents, "hello");
+
+        assert_eq!(
+            fs.rename(Path::new("/tmp/scratch.txt"), Path::new("/etc/scratch.txt"))
+                .unwrap_err(),
+            FsError::ReadOnly
+        );
+        fs.remove_file(Path::new("/tmp/scratch.txt")).unwrap();
+    }
+}
@@ -0,0 +1,599 @@
+//! A [`FileSystem`] decorator that keeps a log of every operation that goes
+//! through it, which is handy when debugging or auditing what a guest does
+//! with its file system.
+
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf},
+    sync::mpsc,
+};
+
+use crate::{
+    FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir, Result,
+    VirtualFile,
+};
+
+/// An operation that was performed on a [`RecordingFileSystem`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsOperation {
+    CreateDir {
+        path: PathBuf,
+    },
+    RemoveDir {
+        path: PathBuf,
+    },
+    RemoveFile {
+        path: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Open {
+        path: PathBuf,
+        read: bool,
+        write: bool,
+        create: bool,
+        truncate: bool,
+        append: bool,
+    },
+    /// Bytes were read from a file, starting at `offset`.
+    Read {
+        path: PathBuf,
+        offset: u64,
+        len: usize,
+    },
+    /// Bytes were written to a file, starting at `offset`.
+    Write {
+        path: PathBuf,
+        offset: u64,
+        len: usize,
+    },
+    SetLen {
+        path: PathBuf,
+        len: u64,
+    },
+    /// A whole file was replaced with `len` bytes.
+    WriteFile {
+        path: PathBuf,
+        len: usize,
+    },
+}
+
+/// A single entry in the operation log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsOperationRecord {
+    /// Nanoseconds since the UNIX epoch (always `0` with the `no-time`
+    /// feature).
+    pub timestamp: u64,
+    pub operation: FsOperation,
+    /// The error returned by the inner file system, if any.
+    pub error: Option<FsError>,
+}
+
+#[derive(Debug, Clone)]
+enum Sink {
+    Buffer(Arc<Mutex<Vec<FsOperationRecord>>>),
+    Channel(mpsc::UnboundedSender<FsOperationRecord>),
+}
+
+impl Sink {
+    fn record(&self, operation: FsOperation, error: Option<FsError>) {
+        let record = FsOperationRecord {
+            timestamp: timestamp(),
+            operation,
+            error,
+        };
+        match self {
+            Sink::Buffer(buffer) => buffer.lock().unwrap().push(record),
+            Sink::Channel(tx) => {
+                // Nobody is listening anymore, which is fine
+                let _ = tx.send(record);
+            }
+        }
+    }
+
+    fn record_result<T>(&self, operation: FsOperation, result: &Result<T>) {
+        self.record(operation, result.as_ref().err().copied());
+    }
+}
+
+fn timestamp() -> u64 {
+    #[cfg(not(feature = "no-time"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    }
+
+    #[cfg(feature = "no-time")]
+    {
+        0
+    }
+}
+
+/// A [`FileSystem`] wrapper that records every operation (including the
+/// reads and writes on the files it opens) before forwarding it to the
+/// inner file system.
+///
+/// The log is either kept in memory and retrieved with
+/// [`RecordingFileSystem::log`], or streamed to a channel when created with
+/// [`RecordingFileSystem::with_channel`].
+#[derive(Debug, Clone)]
+pub struct RecordingFileSystem<F> {
+    inner: F,
+    sink: Sink,
+}
+
+impl<F> RecordingFileSystem<F> {
+    /// Wraps `inner`, keeping the log in memory.
+    pub fn new(inner: F) -> Self {
+        RecordingFileSystem {
+            inner,
+            sink: Sink::Buffer(Default::default()),
+        }
+    }
+
+    /// Wraps `inner`, sending every log entry to `tx`.
+    pub fn with_channel(inner: F, tx: mpsc::UnboundedSender<FsOperationRecord>) -> Self {
+        RecordingFileSystem {
+            inner,
+            sink: Sink::Channel(tx),
+        }
+    }
+
+    /// Returns a copy of the operations recorded so far. This is always
+    /// empty when the log is sent to a channel.
+    pub fn log(&self) -> Vec<FsOperationRecord> {
+        match &self.sink {
+            Sink::Buffer(buffer) => buffer.lock().unwrap().clone(),
+            Sink::Channel(_) => Vec::new(),
+        }
+    }
+
+    /// Removes and returns the operations recorded so far.
+    pub fn take_log(&self) -> Vec<FsOperationRecord> {
+        match &self.sink {
+            Sink::Buffer(buffer) => std::mem::take(&mut *buffer.lock().unwrap()),
+            Sink::Channel(_) => Vec::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F> FileSystem for RecordingFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let result = self.inner.create_dir(path);
+        let op = FsOperation::CreateDir {
+            path: path.to_path_buf(),
+        };
+        self.sink.record_result(op, &result);
+        result
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        let result = self.inner.remove_dir(path);
+        let op = FsOperation::RemoveDir {
+            path: path.to_path_buf(),
+        };
+        self.sink.record_result(op, &result);
+        result
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let result = self.inner.rename(from, to);
+        let op = FsOperation::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        };
+        self.sink.record_result(op, &result);
+        result
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.inner.read_link(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let result = self.inner.remove_file(path);
+        let op = FsOperation::RemoveFile {
+            path: path.to_path_buf(),
+        };
+        self.sink.record_result(op, &result);
+        result
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let result = self.inner.write_file(path, contents);
+        let op = FsOperation::WriteFile {
+            path: path.to_path_buf(),
+            len: contents.len(),
+        };
+        self.sink.record_result(op, &result);
+        result
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+}
+
+impl<F> FileOpener for RecordingFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let result = self
+            .inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(path);
+        let op = FsOperation::Open {
+            path: path.to_path_buf(),
+            read: conf.read(),
+            write: conf.write(),
+            create: conf.create() || conf.create_new(),
+            truncate: conf.truncate(),
+            append: conf.append(),
+        };
+        self.sink.record_result(op, &result);
+
+        Ok(Box::new(RecordingFile {
+            inner: result?,
+            path: path.to_path_buf(),
+            offset: 0,
+            sink: self.sink.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct RecordingFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    path: PathBuf,
+    /// Our best guess of the file cursor, used to report read/write offsets
+    offset: u64,
+    sink: Sink,
+}
+
+impl VirtualFile for RecordingFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        let result = self.inner.set_len(new_size);
+        let op = FsOperation::SetLen {
+            path: self.path.clone(),
+            len: new_size,
+        };
+        self.sink.record_result(op, &result);
+        result
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        let result = self.inner.unlink();
+        let op = FsOperation::RemoveFile {
+            path: self.path.clone(),
+        };
+        self.sink.record_result(op, &result);
+        result
+    }
+
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        self.inner.set_times(atime, mtime)
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn get_special_fd(&self) -> Option<u32> {
+        self.inner.get_special_fd()
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn read_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = io::Result<usize>> + 'a>> {
+        Box::pin(async move {
+            let len = self.inner.read_at(offset, buf).await?;
+            if len > 0 {
+                let op = FsOperation::Read {
+                    path: self.path.clone(),
+                    offset,
+                    len,
+                };
+                self.sink.record(op, None);
+            }
+            Ok(len)
+        })
+    }
+
+    fn write_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = io::Result<usize>> + 'a>> {
+        Box::pin(async move {
+            let len = self.inner.write_at(offset, buf).await?;
+            let op = FsOperation::Write {
+                path: self.path.clone(),
+                offset,
+                len,
+            };
+            self.sink.record(op, None);
+            Ok(len)
+        })
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_write_ready(cx)
+    }
+}
+
+impl AsyncRead for RecordingFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &result {
+            let len = buf.filled().len() - before;
+            // Reaching the end of the file is not worth an entry
+            if len > 0 {
+                let op = FsOperation::Read {
+                    path: self.path.clone(),
+                    offset: self.offset,
+                    len,
+                };
+                self.sink.record(op, None);
+                self.offset += len as u64;
+            }
+        }
+        result
+    }
+}
+
+impl AsyncWrite for RecordingFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = &result {
+            let op = FsOperation::Write {
+                path: self.path.clone(),
+                offset: self.offset,
+                len: *len,
+            };
+            self.sink.record(op, None);
+            self.offset += *len as u64;
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for RecordingFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut *self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let result = Pin::new(&mut *self.inner).poll_complete(cx);
+        if let Poll::Ready(Ok(position)) = &result {
+            self.offset = *position;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::SeekFrom;
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::mem_fs;
+
+    fn operations(fs: &RecordingFileSystem<mem_fs::FileSystem>) -> Vec<FsOperation> {
+        fs.take_log().into_iter().map(|r| r.operation).collect()
+    }
+
+    #[tokio::test]
+    async fn records_operations_in_order() {
+        let fs = RecordingFileSystem::new(mem_fs::FileSystem::default());
+
+        fs.create_dir(Path::new("/data")).unwrap();
+        let mut f = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open("/data/a.txt")
+            .unwrap();
+        f.write_all(b"hello world").await.unwrap();
+        f.seek(SeekFrom::Start(6)).await.unwrap();
+        let mut buf = [0u8; 5];
+        f.read_exact(&mut buf).await.unwrap();
+        drop(f);
+        fs.rename(Path::new("/data/a.txt"), Path::new("/data/b.txt"))
+            .unwrap();
+        fs.remove_file(Path::new("/data/b.txt")).unwrap();
+
+        let path = PathBuf::from("/data/a.txt");
+        assert_eq!(
+            operations(&fs),
+            vec![
+                FsOperation::CreateDir {
+                    path: PathBuf::from("/data")
+                },
+                FsOperation::Open {
+                    path: path.clone(),
+                    read: true,
+                    write: true,
+                    create: true,
+                    truncate: false,
+                    append: false,
+                },
+                FsOperation::Write {
+                    path: path.clone(),
+                    offset: 0,
+                    len: 11
+                },
+                FsOperation::Read {
+                    path,
+                    offset: 6,
+                    len: 5
+                },
+                FsOperation::Rename {
+                    from: PathBuf::from("/data/a.txt"),
+                    to: PathBuf::from("/data/b.txt"),
+                },
+                FsOperation::RemoveFile {
+                    path: PathBuf::from("/data/b.txt")
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn write_file_is_forwarded_and_recorded() {
+        let fs = RecordingFileSystem::new(mem_fs::FileSystem::default());
+
+        fs.write_file(Path::new("/config"), b"debug = true")
+            .unwrap();
+
+        let mut contents = String::new();
+        fs.new_open_options()
+            .read(true)
+            .open("/config")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .await
+            .unwrap();
+        assert_eq!(contents, "debug = true");
+        assert_eq!(
+            operations(&fs)[0],
+            FsOperation::WriteFile {
+                path: PathBuf::from("/config"),
+                len: 12,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn file_times_reach_the_inner_file() {
+        let fs = RecordingFileSystem::new(mem_fs::FileSystem::default());
+
+        let mut f = fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/a.txt")
+            .unwrap();
+        f.set_times(Some(1_000), Some(2_000)).unwrap();
+        drop(f);
+
+        let metadata = fs.metadata(Path::new("/a.txt")).unwrap();
+        assert_eq!(metadata.accessed, 1_000);
+        assert_eq!(metadata.modified, 2_000);
+    }
+
+    #[tokio::test]
+    async fn records_failures_and_streams_to_a_channel() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let fs = RecordingFileSystem::with_channel(mem_fs::FileSystem::default(), tx);
+
+        assert_eq!(
+            fs.remove_dir(Path::new("/missing")),
+            Err(FsError::EntryNotFound)
+        );
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(
+            record.operation,
+            FsOperation::RemoveDir {
+                path: PathBuf::from("/missing")
+            }
+        );
+        assert_eq!(record.error, Some(FsError::EntryNotFound));
+        assert!(fs.log().is_empty());
+    }
+}
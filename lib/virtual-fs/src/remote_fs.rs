@@ -0,0 +1,936 @@
+//! Serve a [`FileSystem`] to somebody else over a simple request/response
+//! protocol.
+//!
+//! A [`RemoteFileSystem`] turns every operation into a request, hands it to a
+//! [`RemoteTransport`] and waits for the response. On the other end, a
+//! [`RemoteFileSystemServer`] decodes the request and runs it against a local
+//! [`FileSystem`].
+//!
+//! # Wire format
+//!
+//! Every message starts with a byte holding [`PROTOCOL_VERSION`]. Integers are
+//! little endian, while paths (as UTF-8) and byte buffers are prefixed with
+//! their length as a `u32`.
+//!
+//! A request continues with a one byte opcode and its arguments. Requests for
+//! an open file carry the file's handle right after the opcode.
+//!
+//! A response continues with `0` followed by a one byte tag and the payload,
+//! or with `1` followed by a one byte error code when the operation failed.
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    task::{Context, Poll, Wake},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    DirEntry, FileOpener, FileSystem, FileType, FsError, Metadata, OpenOptions, OpenOptionsConfig,
+    ReadDir, Result, VirtualFile,
+};
+
+/// The version of the wire format spoken by [`RemoteFileSystem`] and
+/// [`RemoteFileSystemServer`].
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Carries requests from a [`RemoteFileSystem`] to its server and brings
+/// back the responses.
+pub trait RemoteTransport: Debug + Send + Sync {
+    /// Sends an encoded request and blocks until its response arrives.
+    fn call(&self, request: Vec<u8>) -> Result<Vec<u8>>;
+}
+
+/// A [`RemoteTransport`] that exchanges messages over a pair of channels,
+/// typically with a thread running [`RemoteFileSystemServer::serve()`].
+#[derive(Debug)]
+pub struct ChannelTransport {
+    requests: Mutex<mpsc::Sender<Vec<u8>>>,
+    responses: Mutex<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl ChannelTransport {
+    pub fn new(requests: mpsc::Sender<Vec<u8>>, responses: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            requests: Mutex::new(requests),
+            responses: Mutex::new(responses),
+        }
+    }
+}
+
+impl RemoteTransport for ChannelTransport {
+    fn call(&self, request: Vec<u8>) -> Result<Vec<u8>> {
+        // Hold on to the receiving end for the whole exchange so responses
+        // can't get mixed up between callers
+        let responses = self.responses.lock().map_err(|_| FsError::Lock)?;
+        self.requests
+            .lock()
+            .map_err(|_| FsError::Lock)?
+            .send(request)
+            .map_err(|_| FsError::BrokenPipe)?;
+        responses.recv().map_err(|_| FsError::BrokenPipe)
+    }
+}
+
+/// A [`FileSystem`] whose operations are carried out by a
+/// [`RemoteFileSystemServer`] on the other end of a [`RemoteTransport`].
+#[derive(Debug, Clone)]
+pub struct RemoteFileSystem {
+    transport: Arc<dyn RemoteTransport>,
+}
+
+impl RemoteFileSystem {
+    pub fn new(transport: impl RemoteTransport + 'static) -> Self {
+        Self {
+            transport: Arc::new(transport),
+        }
+    }
+
+    fn call(&self, request: Request) -> Result<Response> {
+        let response = self.transport.call(request.encode())?;
+        Response::decode(&response)?
+    }
+
+    fn call_unit(&self, request: Request) -> Result<()> {
+        match self.call(request)? {
+            Response::Unit => Ok(()),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+
+    fn call_metadata(&self, request: Request) -> Result<Metadata> {
+        match self.call(request)? {
+            Response::Metadata(metadata) => Ok(metadata),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+}
+
+impl FileSystem for RemoteFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        match self.call(Request::ReadDir(path.to_path_buf()))? {
+            Response::Entries(entries) => Ok(ReadDir::new(
+                entries
+                    .into_iter()
+                    .map(|(path, metadata)| DirEntry { path, metadata })
+                    .collect(),
+            )),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.call_unit(Request::CreateDir(path.to_path_buf()))
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.call_unit(Request::RemoveDir(path.to_path_buf()))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.call_unit(Request::Rename(from.to_path_buf(), to.to_path_buf()))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.call_metadata(Request::Metadata(path.to_path_buf()))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.call_metadata(Request::SymlinkMetadata(path.to_path_buf()))
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        match self.call(Request::ReadLink(path.to_path_buf()))? {
+            Response::Path(path) => Ok(path),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.call_unit(Request::RemoveFile(path.to_path_buf()))
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+}
+
+impl FileOpener for RemoteFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let request = Request::Open {
+            path: path.to_path_buf(),
+            options: conf.clone(),
+        };
+        match self.call(request)? {
+            Response::Handle(handle) => Ok(Box::new(RemoteFile {
+                fs: self.clone(),
+                handle,
+                cursor: 0,
+            })),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+}
+
+/// A file that was opened through a [`RemoteFileSystem`].
+///
+/// The cursor is tracked locally, every read and write says where it starts.
+#[derive(Debug)]
+struct RemoteFile {
+    fs: RemoteFileSystem,
+    handle: u32,
+    cursor: u64,
+}
+
+impl RemoteFile {
+    fn call(&self, request: FileRequest) -> Result<Response> {
+        self.fs.call(Request::File(self.handle, request))
+    }
+
+    fn stat(&self) -> Result<Metadata> {
+        self.fs
+            .call_metadata(Request::File(self.handle, FileRequest::Stat))
+    }
+
+    fn call_unit(&self, request: FileRequest) -> Result<()> {
+        self.fs.call_unit(Request::File(self.handle, request))
+    }
+}
+
+impl Drop for RemoteFile {
+    fn drop(&mut self) {
+        if let Err(err) = self.fs.call_unit(Request::Close(self.handle)) {
+            tracing::debug!(handle = self.handle, %err, "failed to close a remote file");
+        }
+    }
+}
+
+impl VirtualFile for RemoteFile {
+    fn last_accessed(&self) -> u64 {
+        self.stat().map(|m| m.accessed).unwrap_or_default()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.stat().map(|m| m.modified).unwrap_or_default()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.stat().map(|m| m.created).unwrap_or_default()
+    }
+
+    fn size(&self) -> u64 {
+        self.stat().map(|m| m.len).unwrap_or_default()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        self.call_unit(FileRequest::SetLen(new_size))
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        self.call_unit(FileRequest::Unlink)
+    }
+
+    fn poll_read_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let size = self.stat()?.len;
+        Poll::Ready(Ok(size.saturating_sub(self.cursor) as usize))
+    }
+
+    fn poll_write_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(8192))
+    }
+}
+
+impl AsyncRead for RemoteFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let request = FileRequest::Read {
+            offset: self.cursor,
+            len: buf.remaining().min(u32::MAX as usize) as u32,
+        };
+        match self.call(request)? {
+            Response::Data(data) if data.len() <= buf.remaining() => {
+                buf.put_slice(&data);
+                self.cursor += data.len() as u64;
+                Poll::Ready(Ok(()))
+            }
+            _ => Poll::Ready(Err(io::ErrorKind::InvalidData.into())),
+        }
+    }
+}
+
+impl AsyncWrite for RemoteFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let request = FileRequest::Write {
+            offset: self.cursor,
+            data: buf.to_vec(),
+        };
+        match self.call(request)? {
+            // The server says where the write ended, which is not
+            // necessarily `offset + len` for files opened in append mode
+            Response::Position(position) => {
+                self.cursor = position;
+                Poll::Ready(Ok(buf.len()))
+            }
+            _ => Poll::Ready(Err(io::ErrorKind::InvalidData.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.call_unit(FileRequest::Flush).map_err(Into::into))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl AsyncSeek for RemoteFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let (base, delta) = match position {
+            SeekFrom::Start(offset) => {
+                self.cursor = offset;
+                return Ok(());
+            }
+            SeekFrom::Current(delta) => (self.cursor, delta),
+            SeekFrom::End(delta) => (self.stat()?.len, delta),
+        };
+        self.cursor = base
+            .checked_add_signed(delta)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.cursor))
+    }
+}
+
+/// Answers the requests sent by a [`RemoteFileSystem`] using a local
+/// [`FileSystem`].
+#[derive(Debug)]
+pub struct RemoteFileSystemServer {
+    fs: Arc<dyn FileSystem + Send + Sync>,
+    files: Mutex<HashMap<u32, Box<dyn VirtualFile + Send + Sync>>>,
+    next_handle: AtomicU32,
+}
+
+impl RemoteFileSystemServer {
+    pub fn new(fs: Arc<dyn FileSystem + Send + Sync>) -> Self {
+        Self {
+            fs,
+            files: Mutex::new(HashMap::new()),
+            next_handle: AtomicU32::new(0),
+        }
+    }
+
+    /// Decodes a request, runs it and returns the encoded response.
+    pub async fn handle(&self, request: &[u8]) -> Vec<u8> {
+        let result = match Request::decode(request) {
+            Ok(request) => self.dispatch(request).await,
+            Err(err) => Err(err),
+        };
+        Response::encode(result)
+    }
+
+    /// Answers requests coming in over a pair of channels (see
+    /// [`ChannelTransport`]) until the client goes away.
+    ///
+    /// This blocks the current thread. Files from a
+    /// [`host_fs::FileSystem`](crate::host_fs::FileSystem) need to be served
+    /// from within a tokio runtime context.
+    pub fn serve(&self, requests: mpsc::Receiver<Vec<u8>>, responses: mpsc::Sender<Vec<u8>>) {
+        for request in requests {
+            let response = block_on(self.handle(&request));
+            if responses.send(response).is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn dispatch(&self, request: Request) -> Result<Response> {
+        match request {
+            Request::ReadDir(path) => {
+                let entries = self
+                    .fs
+                    .read_dir(&path)?
+                    .map(|entry| entry.map(|entry| (entry.path, entry.metadata)))
+                    .collect::<Result<_>>()?;
+                Ok(Response::Entries(entries))
+            }
+            Request::CreateDir(path) => self.fs.create_dir(&path).map(|_| Response::Unit),
+            Request::RemoveDir(path) => self.fs.remove_dir(&path).map(|_| Response::Unit),
+            Request::Rename(from, to) => self.fs.rename(&from, &to).map(|_| Response::Unit),
+            Request::Metadata(path) => self.fs.metadata(&path).map(Response::Metadata),
+            Request::SymlinkMetadata(path) => {
+                self.fs.symlink_metadata(&path).map(Response::Metadata)
+            }
+            Request::ReadLink(path) => self.fs.read_link(&path).map(Response::Path),
+            Request::RemoveFile(path) => self.fs.remove_file(&path).map(|_| Response::Unit),
+            Request::Open { path, options } => {
+                let file = self.fs.new_open_options().options(options).open(path)?;
+                let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+                self.files.lock().unwrap().insert(handle, file);
+                Ok(Response::Handle(handle))
+            }
+            Request::Close(handle) => match self.files.lock().unwrap().remove(&handle) {
+                Some(_) => Ok(Response::Unit),
+                None => Err(FsError::InvalidFd),
+            },
+            Request::File(handle, request) => {
+                // Take the file out while we work on it, so the lock isn't
+                // held across an await point
+                let mut file = self
+                    .files
+                    .lock()
+                    .unwrap()
+                    .remove(&handle)
+                    .ok_or(FsError::InvalidFd)?;
+                let result = file_request(&mut file, request).await;
+                self.files.lock().unwrap().insert(handle, file);
+                result
+            }
+        }
+    }
+}
+
+async fn file_request(
+    file: &mut Box<dyn VirtualFile + Send + Sync>,
+    request: FileRequest,
+) -> Result<Response> {
+    match request {
+        FileRequest::Read { offset, len } => {
+            file.seek(SeekFrom::Start(offset)).await?;
+            let mut data = vec![0; len as usize];
+            let read = file.read(&mut data).await?;
+            data.truncate(read);
+            Ok(Response::Data(data))
+        }
+        FileRequest::Write { offset, data } => {
+            file.seek(SeekFrom::Start(offset)).await?;
+            file.write_all(&data).await?;
+            Ok(Response::Position(file.stream_position().await?))
+        }
+        FileRequest::SetLen(len) => file.set_len(len).map(|_| Response::Unit),
+        FileRequest::Stat => Ok(Response::Metadata(Metadata {
+            ft: FileType {
+                file: true,
+                ..Default::default()
+            },
+            accessed: file.last_accessed(),
+            created: file.created_time(),
+            modified: file.last_modified(),
+            len: file.size(),
+        })),
+        FileRequest::Unlink => file.unlink().map(|_| Response::Unit),
+        FileRequest::Flush => {
+            file.flush().await?;
+            Ok(Response::Unit)
+        }
+    }
+}
+
+/// Drives a future to completion on the current thread.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Request {
+    ReadDir(PathBuf),
+    CreateDir(PathBuf),
+    RemoveDir(PathBuf),
+    Rename(PathBuf, PathBuf),
+    Metadata(PathBuf),
+    SymlinkMetadata(PathBuf),
+    ReadLink(PathBuf),
+    RemoveFile(PathBuf),
+    Open {
+        path: PathBuf,
+        options: OpenOptionsConfig,
+    },
+    Close(u32),
+    File(u32, FileRequest),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FileRequest {
+    Read { offset: u64, len: u32 },
+    Write { offset: u64, data: Vec<u8> },
+    SetLen(u64),
+    Stat,
+    Unlink,
+    Flush,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Response {
+    Unit,
+    Metadata(Metadata),
+    Entries(Vec<(PathBuf, Result<Metadata>)>),
+    Path(PathBuf),
+    Handle(u32),
+    Data(Vec<u8>),
+    Position(u64),
+}
+
+impl Request {
+    fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            Request::ReadDir(path) => w.u8(1).path(path),
+            Request::CreateDir(path) => w.u8(2).path(path),
+            Request::RemoveDir(path) => w.u8(3).path(path),
+            Request::Rename(from, to) => w.u8(4).path(from).path(to),
+            Request::Metadata(path) => w.u8(5).path(path),
+            Request::SymlinkMetadata(path) => w.u8(6).path(path),
+            Request::ReadLink(path) => w.u8(7).path(path),
+            Request::RemoveFile(path) => w.u8(8).path(path),
+            Request::Open { path, options } => {
+                let flags = [
+                    options.read,
+                    options.write,
+                    options.create_new,
+                    options.create,
+                    options.append,
+                    options.truncate,
+                    options.tmpfile,
+                ];
+                w.u8(9).path(path).flags(&flags)
+            }
+            Request::Close(handle) => w.u8(10).u32(*handle),
+            Request::File(handle, request) => match request {
+                FileRequest::Read { offset, len } => w.u8(11).u32(*handle).u64(*offset).u32(*len),
+                FileRequest::Write { offset, data } => {
+                    w.u8(12).u32(*handle).u64(*offset).bytes(data)
+                }
+                FileRequest::SetLen(len) => w.u8(13).u32(*handle).u64(*len),
+                FileRequest::Stat => w.u8(14).u32(*handle),
+                FileRequest::Unlink => w.u8(15).u32(*handle),
+                FileRequest::Flush => w.u8(16).u32(*handle),
+            },
+        };
+        w.buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self> {
+        let mut r = Reader::new(buf)?;
+        let request = match r.u8()? {
+            1 => Request::ReadDir(r.path()?),
+            2 => Request::CreateDir(r.path()?),
+            3 => Request::RemoveDir(r.path()?),
+            4 => Request::Rename(r.path()?, r.path()?),
+            5 => Request::Metadata(r.path()?),
+            6 => Request::SymlinkMetadata(r.path()?),
+            7 => Request::ReadLink(r.path()?),
+            8 => Request::RemoveFile(r.path()?),
+            9 => {
+                let path = r.path()?;
+                let [read, write, create_new, create, append, truncate, tmpfile] = r.flags()?;
+                Request::Open {
+                    path,
+                    options: OpenOptionsConfig {
+                        read,
+                        write,
+                        create_new,
+                        create,
+                        append,
+                        truncate,
+                        tmpfile,
+                    },
+                }
+            }
+            10 => Request::Close(r.u32()?),
+            op @ 11..=16 => {
+                let handle = r.u32()?;
+                let request = match op {
+                    11 => FileRequest::Read {
+                        offset: r.u64()?,
+                        len: r.u32()?,
+                    },
+                    12 => FileRequest::Write {
+                        offset: r.u64()?,
+                        data: r.bytes()?,
+                    },
+                    13 => FileRequest::SetLen(r.u64()?),
+                    14 => FileRequest::Stat,
+                    15 => FileRequest::Unlink,
+                    _ => FileRequest::Flush,
+                };
+                Request::File(handle, request)
+            }
+            _ => return Err(FsError::InvalidData),
+        };
+        r.finish()?;
+        Ok(request)
+    }
+}
+
+impl Response {
+    fn encode(result: Result<Response>) -> Vec<u8> {
+        let mut w = Writer::new();
+        match result {
+            Ok(response) => {
+                w.u8(0);
+                match response {
+                    Response::Unit => w.u8(0),
+                    Response::Metadata(metadata) => w.u8(1).metadata(&metadata),
+                    Response::Entries(entries) => {
+                        w.u8(2).u32(entries.len() as u32);
+                        for (path, metadata) in &entries {
+                            w.path(path);
+                            match metadata {
+                                Ok(metadata) => w.u8(0).metadata(metadata),
+                                Err(err) => w.u8(1).error(*err),
+                            };
+                        }
+                        &mut w
+                    }
+                    Response::Path(path) => w.u8(3).path(&path),
+                    Response::Handle(handle) => w.u8(4).u32(handle),
+                    Response::Data(data) => w.u8(5).bytes(&data),
+                    Response::Position(position) => w.u8(6).u64(position),
+                };
+            }
+            Err(err) => {
+                w.u8(1).error(err);
+            }
+        }
+        w.buf
+    }
+
+    /// Decodes a response, the outer [`Result`] says whether the response
+    /// could be decoded at all.
+    fn decode(buf: &[u8]) -> Result<Result<Self>> {
+        let mut r = Reader::new(buf)?;
+        let response = match r.u8()? {
+            0 => Ok(match r.u8()? {
+                0 => Response::Unit,
+                1 => Response::Metadata(r.metadata()?),
+                2 => {
+                    let len = r.u32()?;
+                    let mut entries = Vec::new();
+                    for _ in 0..len {
+                        let path = r.path()?;
+                        let metadata = match r.u8()? {
+                            0 => Ok(r.metadata()?),
+                            _ => Err(r.error()?),
+                        };
+                        entries.push((path, metadata));
+                    }
+                    Response::Entries(entries)
+                }
+                3 => Response::Path(r.path()?),
+                4 => Response::Handle(r.u32()?),
+                5 => Response::Data(r.bytes()?),
+                6 => Response::Position(r.u64()?),
+                _ => return Err(FsError::InvalidData),
+            }),
+            1 => Err(r.error()?),
+            _ => return Err(FsError::InvalidData),
+        };
+        r.finish()?;
+        Ok(response)
+    }
+}
+
+/// Errors are sent as their index in this list, so new variants may only
+/// ever be appended.
+const ERRORS: &[FsError] = &[
+    FsError::UnknownError,
+    FsError::BaseNotDirectory,
+    FsError::NotAFile,
+    FsError::InvalidFd,
+    FsError::AlreadyExists,
+    FsError::Lock,
+    FsError::IOError,
+    FsError::AddressInUse,
+    FsError::AddressNotAvailable,
+    FsError::BrokenPipe,
+    FsError::ConnectionAborted,
+    FsError::ConnectionRefused,
+    FsError::ConnectionReset,
+    FsError::Interrupted,
+    FsError::InvalidData,
+    FsError::InvalidInput,
+    FsError::NotConnected,
+    FsError::EntryNotFound,
+    FsError::NoDevice,
+    FsError::PermissionDenied,
+    FsError::TimedOut,
+    FsError::UnexpectedEof,
+    FsError::WouldBlock,
+    FsError::WriteZero,
+    FsError::DirectoryNotEmpty,
+    FsError::TooManySymlinks,
+    FsError::StorageFull,
+    FsError::ReadOnly,
+    FsError::CrossDevice,
+    FsError::IsADirectory,
+];
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            buf: vec![PROTOCOL_VERSION],
+        }
+    }
+
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.buf.push(value);
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    fn path(&mut self, path: &Path) -> &mut Self {
+        self.bytes(path.to_string_lossy().as_bytes())
+    }
+
+    fn flags(&mut self, flags: &[bool]) -> &mut Self {
+        let bits = flags
+            .iter()
+            .enumerate()
+            .fold(0u8, |bits, (i, flag)| bits | ((*flag as u8) << i));
+        self.u8(bits)
+    }
+
+    fn error(&mut self, err: FsError) -> &mut Self {
+        let code = ERRORS.iter().position(|e| *e == err).unwrap_or(0);
+        self.u8(code as u8)
+    }
+
+    fn metadata(&mut self, metadata: &Metadata) -> &mut Self {
+        let ft = &metadata.ft;
+        self.flags(&[
+            ft.dir,
+            ft.file,
+            ft.symlink,
+            ft.char_device,
+            ft.block_device,
+            ft.socket,
+            ft.fifo,
+        ])
+        .u64(metadata.accessed)
+        .u64(metadata.created)
+        .u64(metadata.modified)
+        .u64(metadata.len)
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Starts reading a message, after checking its version.
+    fn new(buf: &'a [u8]) -> Result<Self> {
+        let mut r = Self { buf };
+        if r.u8()? != PROTOCOL_VERSION {
+            return Err(FsError::InvalidData);
+        }
+        Ok(r)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(FsError::InvalidData);
+        }
+        let (taken, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn path(&mut self) -> Result<PathBuf> {
+        let len = self.u32()? as usize;
+        let path = std::str::from_utf8(self.take(len)?).map_err(|_| FsError::InvalidData)?;
+        Ok(PathBuf::from(path))
+    }
+
+    fn flags<const N: usize>(&mut self) -> Result<[bool; N]> {
+        let bits = self.u8()?;
+        let mut flags = [false; N];
+        for (i, flag) in flags.iter_mut().enumerate() {
+            *flag = bits & (1 << i) != 0;
+        }
+        Ok(flags)
+    }
+
+    fn error(&mut self) -> Result<FsError> {
+        let code = self.u8()? as usize;
+        Ok(ERRORS.get(code).copied().unwrap_or(FsError::UnknownError))
+    }
+
+    fn metadata(&mut self) -> Result<Metadata> {
+        let [dir, file, symlink, char_device, block_device, socket, fifo] = self.flags()?;
+        Ok(Metadata {
+            ft: FileType {
+                dir,
+                file,
+                symlink,
+                char_device,
+                block_device,
+                socket,
+                fifo,
+            },
+            accessed: self.u64()?,
+            created: self.u64()?,
+            modified: self.u64()?,
+            len: self.u64()?,
+        })
+    }
+
+    /// Makes sure the whole message was consumed.
+    fn finish(self) -> Result<()> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(FsError::InvalidData)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_fs::FileSystem as MemFS;
+
+    /// Connects a [`RemoteFileSystem`] to a server running on another thread.
+    fn loopback(fs: MemFS) -> RemoteFileSystem {
+        let (request_tx, request_rx) = mpsc::channel();
+        let (response_tx, response_rx) = mpsc::channel();
+        let server = RemoteFileSystemServer::new(Arc::new(fs));
+        std::thread::spawn(move || server.serve(request_rx, response_tx));
+
+        RemoteFileSystem::new(ChannelTransport::new(request_tx, response_rx))
+    }
+
+    #[tokio::test]
+    async fn round_trip_through_a_server() {
+        let local = MemFS::default();
+        let fs = loopback(local.clone());
+
+        fs.create_dir(Path::new("/data")).unwrap();
+        let mut f = fs
+            .new_open_options()
+            .create(true)
+            .write(true)
+            .open("/data/hello.txt")
+            .unwrap();
+        f.write_all(b"hello, ").await.unwrap();
+        f.write_all(b"world").await.unwrap();
+        drop(f);
+
+        // The file really ended up on the server's file system
+        assert_eq!(
+            local.metadata(Path::new("/data/hello.txt")).unwrap().len,
+            12
+        );
+
+        let mut contents = String::new();
+        let mut f = fs
+            .new_open_options()
+            .read(true)
+            .open("/data/hello.txt")
+            .unwrap();
+        f.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "hello, world");
+        assert_eq!(f.size(), 12);
+
+        let entries: Vec<_> = fs
+            .read_dir(Path::new("/data"))
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, PathBuf::from("/data/hello.txt"));
+        assert!(entries[0].metadata.as_ref().unwrap().is_file());
+
+        assert_eq!(
+            fs.metadata(Path::new("/missing")).unwrap_err(),
+            FsError::EntryNotFound
+        );
+    }
+
+    #[test]
+    fn messages_with_another_version_are_rejected() {
+        let mut request = Request::Metadata(PathBuf::from("/")).encode();
+        assert_eq!(
+            Request::decode(&request),
+            Ok(Request::Metadata(PathBuf::from("/")))
+        );
+
+        request[0] = PROTOCOL_VERSION + 1;
+        assert_eq!(Request::decode(&request), Err(FsError::InvalidData));
+    }
+}
@@ -0,0 +1,169 @@
+//! A read-only [`VirtualFile`] that serves a shared in-memory buffer, so
+//! large embedded assets don't have to be copied for every instance.
+
+use std::{
+    fmt,
+    io::{self, SeekFrom},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::{FsError, VirtualFile};
+
+/// A read-only [`VirtualFile`] backed by an [`Arc<[u8]>`].
+///
+/// Every `StaticFile` (and every clone of one) has its own position, but
+/// they all read from the same buffer without copying it. Writing to the
+/// file or changing its length fails with `PermissionDenied`.
+#[derive(Clone)]
+pub struct StaticFile {
+    data: Arc<[u8]>,
+    pos: u64,
+}
+
+impl StaticFile {
+    pub fn new(data: Arc<[u8]>) -> Self {
+        StaticFile { data, pos: 0 }
+    }
+
+    /// The buffer that is being served.
+    pub fn data(&self) -> &Arc<[u8]> {
+        &self.data
+    }
+
+    fn remaining(&self) -> &[u8] {
+        let start = self.pos.min(self.data.len() as u64) as usize;
+        &self.data[start..]
+    }
+}
+
+impl fmt::Debug for StaticFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticFile")
+            .field("len", &self.data.len())
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
+
+impl VirtualFile for StaticFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+
+    fn last_modified(&self) -> u64 {
+        0
+    }
+
+    fn created_time(&self) -> u64 {
+        0
+    }
+
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn set_len(&mut self, _new_size: u64) -> crate::Result<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn unlink(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn poll_read_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(self.remaining().len()))
+    }
+
+    fn poll_write_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+    }
+}
+
+impl AsyncRead for StaticFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let remaining = self.remaining();
+        let amt = remaining.len().min(buf.remaining());
+        buf.put_slice(&remaining[..amt]);
+        self.pos += amt as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for StaticFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for StaticFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let pos = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.data.len() as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn files_share_the_buffer_they_were_created_from() {
+        let data: Arc<[u8]> = Arc::from(&b"[server]\nport = 8080\n"[..]);
+        let mut files: Vec<_> = (0..3).map(|_| StaticFile::new(data.clone())).collect();
+
+        for file in &mut files {
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents).await.unwrap();
+            assert_eq!(contents, &*data);
+            assert_eq!(file.data().as_ptr(), data.as_ptr());
+        }
+        // Every file has its own position
+        files[0].seek(SeekFrom::Start(9)).await.unwrap();
+        let mut port = String::new();
+        files[0].read_to_string(&mut port).await.unwrap();
+        assert_eq!(port, "port = 8080\n");
+
+        assert_eq!(
+            files[1].write_all(b"port = 80").await.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(files[1].set_len(0), Err(FsError::PermissionDenied));
+        assert_eq!(Arc::strong_count(&data), 4);
+    }
+}
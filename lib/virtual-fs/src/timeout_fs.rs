@@ -0,0 +1,626 @@
+//! A [`FileSystem`] decorator that gives up on operations which take too
+//! long, so a slow or stuck host file system (e.g. a network mount) can't
+//! hang the guest forever.
+
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::{
+    FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir, Result,
+    VirtualFile,
+};
+
+/// How many calls into the inner file system the default [`WorkerPool`]
+/// runs at the same time.
+const DEFAULT_MAX_WORKERS: usize = 4;
+
+/// How long an idle worker waits for more work before it exits.
+const IDLE_WORKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the blocking calls a [`TimeoutFileSystem`] makes into the file
+/// system it wraps.
+///
+/// Calls that time out are left to finish in the background, so the
+/// executor should have a bounded number of threads to keep a stuck file
+/// system from piling them up.
+pub trait BlockingExecutor: fmt::Debug + Send + Sync {
+    /// Runs `task` on another thread.
+    fn execute(&self, task: Box<dyn FnOnce() + Send + 'static>) -> Result<()>;
+}
+
+/// Wraps a [`FileSystem`] and fails any operation that doesn't complete
+/// within `timeout` with [`FsError::TimedOut`].
+///
+/// Calls into the inner file system run on a [`BlockingExecutor`], which
+/// defaults to a small pool of threads. A call that times out is left to
+/// finish in the background, and calls that are still queued when their
+/// timeout expires are never started. Reads, writes, flushes and seeks on
+/// files opened through this file system are given the same amount of time
+/// before they fail.
+#[derive(Debug, Clone)]
+pub struct TimeoutFileSystem<F> {
+    inner: Arc<F>,
+    timeout: Duration,
+    executor: Arc<dyn BlockingExecutor>,
+}
+
+impl<F> TimeoutFileSystem<F>
+where
+    F: FileSystem,
+{
+    pub fn new(inner: F, timeout: Duration) -> Self {
+        TimeoutFileSystem {
+            inner: Arc::new(inner),
+            timeout,
+            executor: Arc::new(WorkerPool::new(DEFAULT_MAX_WORKERS)),
+        }
+    }
+
+    /// Runs the calls into the inner file system on `executor` instead of
+    /// the default pool of threads.
+    pub fn with_executor(mut self, executor: Arc<dyn BlockingExecutor>) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Runs `op` against the inner file system, giving up once the timeout
+    /// expires.
+    fn run<T, Op>(&self, op: Op) -> Result<T>
+    where
+        T: Send + 'static,
+        Op: FnOnce(&F) -> Result<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        let deadline = Instant::now() + self.timeout;
+        let (tx, rx) = mpsc::channel();
+        self.executor.execute(Box::new(move || {
+            // Nobody is waiting for the result any more
+            if Instant::now() >= deadline {
+                return;
+            }
+            let _ = tx.send(op(&inner));
+        }))?;
+
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(FsError::TimedOut),
+            // The operation panicked or was never started
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                if Instant::now() >= deadline {
+                    Err(FsError::TimedOut)
+                } else {
+                    Err(FsError::UnknownError)
+                }
+            }
+        }
+    }
+}
+
+type Task = Box<dyn FnOnce() + Send + 'static>;
+
+/// A [`BlockingExecutor`] that runs tasks on at most `max_workers` threads,
+/// which are started when needed and exit again once they have been idle
+/// for a while.
+#[derive(Debug)]
+pub struct WorkerPool {
+    max_workers: usize,
+    shared: Arc<PoolShared>,
+}
+
+#[derive(Default)]
+struct PoolShared {
+    state: Mutex<PoolState>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    queue: VecDeque<Task>,
+    workers: usize,
+    idle: usize,
+}
+
+impl fmt::Debug for PoolShared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("PoolShared")
+            .field("queued", &state.queue.len())
+            .field("workers", &state.workers)
+            .field("idle", &state.idle)
+            .finish()
+    }
+}
+
+impl WorkerPool {
+    pub fn new(max_workers: usize) -> Self {
+        WorkerPool {
+            max_workers: max_workers.max(1),
+            shared: Arc::new(PoolShared::default()),
+        }
+    }
+
+    /// How many worker threads are currently running.
+    pub fn workers(&self) -> usize {
+        self.shared.state.lock().unwrap().workers
+    }
+
+    fn work(shared: &PoolShared) {
+        let mut state = shared.state.lock().unwrap();
+        loop {
+            if let Some(task) = state.queue.pop_front() {
+                drop(state);
+                task();
+                state = shared.state.lock().unwrap();
+                continue;
+            }
+
+            state.idle += 1;
+            let (next, timeout) = shared
+                .condvar
+                .wait_timeout(state, IDLE_WORKER_TIMEOUT)
+                .unwrap();
+            state = next;
+            state.idle -= 1;
+
+            if timeout.timed_out() && state.queue.is_empty() {
+                state.workers -= 1;
+                return;
+            }
+        }
+    }
+}
+
+impl BlockingExecutor for WorkerPool {
+    fn execute(&self, task: Box<dyn FnOnce() + Send + 'static>) -> Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.queue.push_back(task);
+
+        if state.idle > 0 {
+            self.shared.condvar.notify_one();
+        } else if state.workers < self.max_workers {
+            let shared = self.shared.clone();
+            std::thread::Builder::new()
+                .name("timeout-fs-worker".to_string())
+                .spawn(move || WorkerPool::work(&shared))
+                .map_err(|_| FsError::UnknownError)?;
+            state.workers += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Wakes up files whose pending operation has reached its deadline, using
+/// a single thread that is started when needed.
+#[derive(Default)]
+struct Timer {
+    state: Mutex<TimerState>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+    pending: Vec<(Instant, Waker)>,
+    running: bool,
+}
+
+impl Timer {
+    fn shared() -> &'static Arc<Timer> {
+        lazy_static::lazy_static! {
+            static ref TIMER: Arc<Timer> = Arc::new(Timer::default());
+        }
+        &TIMER
+    }
+
+    fn wake_at(self: &Arc<Self>, deadline: Instant, waker: Waker) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.push((deadline, waker));
+        if state.running {
+            self.condvar.notify_one();
+            return;
+        }
+
+        let timer = self.clone();
+        let spawned = std::thread::Builder::new()
+            .name("timeout-fs-timer".to_string())
+            .spawn(move || timer.run());
+        state.running = spawned.is_ok();
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let (expired, pending) = std::mem::take(&mut state.pending)
+                .into_iter()
+                .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+            state.pending = pending;
+            for (_, waker) in expired {
+                waker.wake();
+            }
+
+            let next = match state.pending.iter().map(|(deadline, _)| *deadline).min() {
+                Some(next) => next,
+                None => {
+                    state.running = false;
+                    return;
+                }
+            };
+            state = self
+                .condvar
+                .wait_timeout(state, next.saturating_duration_since(now))
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+impl<F> FileSystem for TimeoutFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        let path = path.to_path_buf();
+        self.run(move |fs| fs.read_dir(&path))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let path = path.to_path_buf();
+        self.run(move |fs| fs.create_dir(&path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        let path = path.to_path_buf();
+        self.run(move |fs| fs.remove_dir(&path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let from = from.to_path_buf();
+        let to = to.to_path_buf();
+        self.run(move |fs| fs.rename(&from, &to))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        let path = path.to_path_buf();
+        self.run(move |fs| fs.metadata(&path))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        let path = path.to_path_buf();
+        self.run(move |fs| fs.symlink_metadata(&path))
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        let path = path.to_path_buf();
+        self.run(move |fs| fs.read_link(&path))
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let path = path.to_path_buf();
+        self.run(move |fs| fs.remove_file(&path))
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let path = path.to_path_buf();
+        let contents = contents.to_vec();
+        self.run(move |fs| fs.write_file(&path, &contents))
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+}
+
+impl<F> FileOpener for TimeoutFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let path = path.to_path_buf();
+        let conf = conf.clone();
+        let file = self.run(move |fs| fs.new_open_options().options(conf).open(&path))?;
+
+        Ok(Box::new(TimeoutFile {
+            inner: file,
+            timeout: self.timeout,
+            deadline: None,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct TimeoutFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    timeout: Duration,
+    /// When the operation that is currently pending gives up.
+    deadline: Option<Instant>,
+}
+
+impl TimeoutFile {
+    /// Fails a pending operation once it has been pending for longer than
+    /// the timeout.
+    fn check_deadline<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        if poll.is_ready() {
+            self.deadline = None;
+            return poll;
+        }
+
+        let now = Instant::now();
+        match self.deadline {
+            Some(deadline) if now >= deadline => {
+                self.deadline = None;
+                Poll::Ready(Err(io::ErrorKind::TimedOut.into()))
+            }
+            Some(_) => Poll::Pending,
+            None => {
+                let deadline = now + self.timeout;
+                self.deadline = Some(deadline);
+                // Make sure we get polled again even if the inner file never
+                // wakes us up
+                Timer::shared().wake_at(deadline, cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl VirtualFile for TimeoutFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        self.inner.set_len(new_size)
+    }
+
+    fn unlink(&mut self) -> Result<()> {
+        self.inner.unlink()
+    }
+
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<()> {
+        self.inner.set_times(atime, mtime)
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<()> {
+        self.inner.shrink_to_fit()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_data(&mut self) -> Result<()> {
+        self.inner.sync_data()
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_read_ready(cx);
+        self.check_deadline(cx, poll)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write_ready(cx);
+        self.check_deadline(cx, poll)
+    }
+}
+
+impl AsyncRead for TimeoutFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        self.check_deadline(cx, poll)
+    }
+}
+
+impl AsyncWrite for TimeoutFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        self.check_deadline(cx, poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut *self.inner).poll_flush(cx);
+        self.check_deadline(cx, poll)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut *self.inner).poll_shutdown(cx);
+        self.check_deadline(cx, poll)
+    }
+}
+
+impl AsyncSeek for TimeoutFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut *self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let poll = Pin::new(&mut *self.inner).poll_complete(cx);
+        self.check_deadline(cx, poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::{mem_fs, Pipe};
+
+    /// A file system that takes `delay` to do anything, and whose files
+    /// never have any data to read.
+    #[derive(Debug)]
+    struct SlowFileSystem {
+        delay: Duration,
+        inner: mem_fs::FileSystem,
+        /// Keeps the other end of the pipes alive so reads never finish.
+        senders: Mutex<Vec<Pipe>>,
+    }
+
+    impl SlowFileSystem {
+        fn new(delay: Duration) -> Self {
+            SlowFileSystem {
+                delay,
+                inner: mem_fs::FileSystem::default(),
+                senders: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl FileSystem for SlowFileSystem {
+        fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+            std::thread::sleep(self.delay);
+            self.inner.read_dir(path)
+        }
+
+        fn create_dir(&self, path: &Path) -> Result<()> {
+            std::thread::sleep(self.delay);
+            self.inner.create_dir(path)
+        }
+
+        fn remove_dir(&self, path: &Path) -> Result<()> {
+            std::thread::sleep(self.delay);
+            self.inner.remove_dir(path)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+            std::thread::sleep(self.delay);
+            self.inner.rename(from, to)
+        }
+
+        fn metadata(&self, path: &Path) -> Result<Metadata> {
+            std::thread::sleep(self.delay);
+            self.inner.metadata(path)
+        }
+
+        fn remove_file(&self, path: &Path) -> Result<()> {
+            std::thread::sleep(self.delay);
+            self.inner.remove_file(path)
+        }
+
+        fn new_open_options(&self) -> OpenOptions {
+            OpenOptions::new(self)
+        }
+    }
+
+    impl FileOpener for SlowFileSystem {
+        fn open(
+            &self,
+            _path: &Path,
+            _conf: &OpenOptionsConfig,
+        ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+            std::thread::sleep(self.delay);
+            let (tx, rx) = Pipe::channel();
+            self.senders.lock().unwrap().push(tx);
+            Ok(Box::new(rx))
+        }
+    }
+
+    #[test]
+    fn slow_operations_time_out() {
+        let fs = TimeoutFileSystem::new(
+            SlowFileSystem::new(Duration::from_secs(5)),
+            Duration::from_millis(50),
+        );
+
+        let started = Instant::now();
+        assert_eq!(fs.create_dir(Path::new("/dir")), Err(FsError::TimedOut));
+        assert_eq!(fs.metadata(Path::new("/")), Err(FsError::TimedOut));
+        assert!(matches!(
+            fs.new_open_options().read(true).open("/file"),
+            Err(FsError::TimedOut)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn timed_out_calls_do_not_pile_up_threads() {
+        let pool = Arc::new(WorkerPool::new(2));
+        let fs = TimeoutFileSystem::new(
+            SlowFileSystem::new(Duration::from_millis(500)),
+            Duration::from_millis(20),
+        )
+        .with_executor(pool.clone());
+
+        for _ in 0..10 {
+            assert_eq!(fs.metadata(Path::new("/")), Err(FsError::TimedOut));
+        }
+        assert_eq!(pool.workers(), 2);
+    }
+
+    #[test]
+    fn fast_operations_are_passed_through() {
+        let fs =
+            TimeoutFileSystem::new(SlowFileSystem::new(Duration::ZERO), Duration::from_secs(5));
+
+        assert_eq!(fs.create_dir(Path::new("/dir")), Ok(()));
+        assert!(fs.metadata(Path::new("/dir")).unwrap().is_dir());
+        assert_eq!(
+            fs.create_dir(Path::new("/dir")),
+            Err(FsError::AlreadyExists)
+        );
+    }
+
+    #[tokio::test]
+    async fn stuck_reads_time_out() {
+        let fs = TimeoutFileSystem::new(
+            SlowFileSystem::new(Duration::ZERO),
+            Duration::from_millis(50),
+        );
+        let mut file = fs.new_open_options().read(true).open("/file").unwrap();
+
+        let mut buf = [0; 16];
+        let err = file.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -17,12 +17,81 @@ use std::sync::Mutex;
 use tracing::{debug, error, info, trace, warn};
 
 use crate::mem_fs;
+use crate::random_file::RandomFile;
 use crate::Result as FsResult;
 use crate::*;
 
-#[derive(Debug, Default, Clone)]
+/// Which side wins when two file systems are layered on top of each other
+/// and both contain the same path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// The file system being merged in takes precedence.
+    Higher,
+    /// The files that are already present take precedence.
+    Lower,
+}
+
+/// Restrictions placed on a file system mounted with
+/// [`TmpFileSystem::mount_with_options()`].
+///
+/// They apply to everything done through the mount point, but not to
+/// anyone holding on to the mounted file system directly.
+///
+/// There is deliberately no "no-exec" option. Files don't carry any
+/// permission bits ([`Metadata`] has none), and executables are loaded
+/// with ordinary reads, so a file system has no way to tell running a
+/// file apart from reading it and such an option couldn't be enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Reject anything that would change the mounted file system, failing
+    /// with [`FsError::ReadOnly`].
+    pub read_only: bool,
+    /// Whether new files and directories can be created. Existing files
+    /// can still be changed when this is `false`.
+    pub allow_create: bool,
+}
+
+impl MountOptions {
+    /// Mount the file system without any restrictions.
+    pub const fn read_write() -> Self {
+        MountOptions {
+            read_only: false,
+            allow_create: true,
+        }
+    }
+
+    /// Mount the file system so it can't be changed at all.
+    pub const fn read_only() -> Self {
+        MountOptions {
+            read_only: true,
+            allow_create: false,
+        }
+    }
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        MountOptions::read_write()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct TmpFileSystem {
     fs: mem_fs::FileSystem,
+    max_symlink_follows: usize,
+    /// Guards the changes made through the [`FileSystem`] trait, see
+    /// [`TmpFileSystem::with_read_only()`].
+    read_only: Option<ReadOnlyFileSystem>,
+}
+
+impl Default for TmpFileSystem {
+    fn default() -> Self {
+        TmpFileSystem {
+            fs: Default::default(),
+            max_symlink_follows: ops::MAX_SYMLINK_FOLLOWS,
+            read_only: None,
+        }
+    }
 }
 
 impl TmpFileSystem {
@@ -30,18 +99,105 @@ impl TmpFileSystem {
         Self::default()
     }
 
+    /// How many symlinks [`FileSystem::canonicalize()`] follows while
+    /// resolving a single path before it gives up with
+    /// [`FsError::TooManySymlinks`]. The symlinks can only come from
+    /// mounted file systems.
+    ///
+    /// Defaults to 40, the same as Linux.
+    pub fn with_max_symlink_follows(mut self, max_symlink_follows: usize) -> Self {
+        self.set_max_symlink_follows(max_symlink_follows);
+        self
+    }
+
+    /// See [`TmpFileSystem::with_max_symlink_follows()`].
+    pub fn set_max_symlink_follows(&mut self, max_symlink_follows: usize) {
+        self.max_symlink_follows = max_symlink_follows;
+    }
+
+    pub fn max_symlink_follows(&self) -> usize {
+        self.max_symlink_follows
+    }
+
+    /// Rejects anything done through the [`FileSystem`] trait that would
+    /// change the file system with [`FsError::ReadOnly`], except within
+    /// `writable_dirs`.
+    ///
+    /// Clones made beforehand, and the methods that are specific to
+    /// [`TmpFileSystem`] such as [`TmpFileSystem::union()`] and
+    /// [`TmpFileSystem::insert_ro_file()`], are not restricted, so the host
+    /// can still add packages to a file system the guest can't change.
+    pub fn with_read_only(mut self, writable_dirs: Vec<PathBuf>) -> Self {
+        let read_only = writable_dirs.into_iter().fold(
+            ReadOnlyFileSystem::new(Arc::new(self.fs.clone())),
+            ReadOnlyFileSystem::with_writable_dir,
+        );
+        self.read_only = Some(read_only);
+        self
+    }
+
+    /// Where the changes made through the [`FileSystem`] trait go.
+    fn guarded(&self) -> &dyn FileSystem {
+        match &self.read_only {
+            Some(read_only) => read_only,
+            None => &self.fs,
+        }
+    }
+
     pub fn set_memory_limiter(&self, limiter: crate::limiter::DynFsMemoryLimiter) {
         self.fs.set_memory_limiter(limiter);
     }
 
+    /// Caps how many files and directories can exist at once, so a program
+    /// can't exhaust memory by creating huge numbers of empty files.
+    ///
+    /// See [`mem_fs::FileSystem::set_max_inodes()`].
+    pub fn set_max_inodes(&self, max_inodes: usize) {
+        self.fs.set_max_inodes(Some(max_inodes));
+    }
+
+    /// How many inodes exist right now, including `/`. Mounted file
+    /// systems only count as the directory they are mounted on.
+    pub fn total_inodes(&self) -> usize {
+        self.fs.total_inodes()
+    }
+
     pub fn new_open_options_ext(&self) -> &mem_fs::FileSystem {
         self.fs.new_open_options_ext()
     }
 
+    /// Registers a callback that is invoked after every successful rename.
+    /// See [`mem_fs::FileSystem::on_rename()`].
+    pub fn on_rename(&self, hook: impl Fn(&Path, &Path) + Send + Sync + 'static) {
+        self.fs.on_rename(hook)
+    }
+
+    /// Merges `other` into this file system, with `other` taking precedence
+    /// on overlapping paths.
     pub fn union(&self, other: &Arc<dyn FileSystem + Send + Sync>) {
         self.fs.union(other)
     }
 
+    /// Merges `other` into this file system without ever writing to it.
+    /// See [`mem_fs::FileSystem::union_copy_on_write()`].
+    pub fn union_copy_on_write(&self, other: &Arc<dyn FileSystem + Send + Sync>) {
+        self.fs.union_copy_on_write(other)
+    }
+
+    /// Merges `other` into this file system as a layer that sits either
+    /// above ([`Priority::Higher`]) or below ([`Priority::Lower`]) the files
+    /// that are already present.
+    ///
+    /// See [`mem_fs::FileSystem::union_with_priority`] for how conflicting
+    /// paths are resolved.
+    pub fn union_with_priority(
+        &self,
+        other: &Arc<dyn FileSystem + Send + Sync>,
+        priority: Priority,
+    ) {
+        self.fs.union_with_priority(other, priority)
+    }
+
     /// See [`mem_fs::FileSystem::mount_directory_entries`].
     pub fn mount_directory_entries(
         &self,
@@ -62,42 +218,905 @@ impl TmpFileSystem {
         self.fs.mount(src_path, other, dst_path)
     }
 
+    /// Like [`TmpFileSystem::mount()`], but anything done through the mount
+    /// point is restricted according to `options`.
+    pub fn mount_with_options(
+        &self,
+        src_path: PathBuf,
+        other: &Arc<dyn FileSystem + Send + Sync>,
+        dst_path: PathBuf,
+        options: MountOptions,
+    ) -> Result<()> {
+        let mut restricted = other.clone();
+        if !options.allow_create {
+            restricted = Arc::new(NoCreateFileSystem { inner: restricted });
+        }
+        if options.read_only {
+            restricted = Arc::new(ReadOnlyFileSystem::new(restricted));
+        }
+        self.fs.mount(src_path, &restricted, dst_path)
+    }
+
+    /// Deep-copies the directory at `root` and everything below it into a
+    /// new, independent file system in which `root` becomes `/`.
+    ///
+    /// See [`mem_fs::FileSystem::clone_subtree()`] for what is copied.
+    pub fn clone_subtree(&self, root: &Path) -> Result<TmpFileSystem> {
+        Ok(TmpFileSystem {
+            fs: self.fs.clone_subtree(root)?,
+            max_symlink_follows: self.max_symlink_follows,
+            read_only: None,
+        })
+    }
+
+    /// Creates `/dev` (unless it exists already) with the character devices
+    /// most programs expect: `/dev/null`, which ignores writes and is
+    /// always at its end, `/dev/zero`, which reads as an endless stream of
+    /// zeros, and `/dev/urandom`, which reads from `urandom`.
+    pub fn mount_standard_dev(&self, urandom: RandomFile) -> Result<()> {
+        match self.fs.create_dir(Path::new("/dev")) {
+            Ok(()) | Err(FsError::AlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+        self.fs
+            .insert_char_device("/dev/null".into(), Box::<NullFile>::default())?;
+        self.fs
+            .insert_char_device("/dev/zero".into(), Box::<ZeroFile>::default())?;
+        self.fs
+            .insert_char_device("/dev/urandom".into(), Box::new(urandom))
+    }
+
     /// Canonicalize a path without validating that it actually exists.
     pub fn canonicalize_unchecked(&self, path: &Path) -> Result<PathBuf> {
         self.fs.canonicalize_unchecked(path)
     }
+
+    /// Follows the symlinks in `path` itself, so they count towards
+    /// [`TmpFileSystem::max_symlink_follows()`] instead of being followed
+    /// by the mounted file system. A last component that doesn't exist
+    /// yet is kept, so it can still be created.
+    fn resolve_symlinks(&self, path: &Path) -> Result<PathBuf> {
+        if !path.has_root() {
+            return Ok(path.to_path_buf());
+        }
+        match ops::canonicalize_with_limit(&self.fs, path, self.max_symlink_follows) {
+            Err(FsError::EntryNotFound) => {}
+            other => return other,
+        }
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                ops::canonicalize_with_limit(&self.fs, parent, self.max_symlink_follows)
+                    .map(|parent| parent.join(name))
+            }
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
 }
 
 impl FileSystem for TmpFileSystem {
     fn read_dir(&self, path: &Path) -> Result<ReadDir> {
-        self.fs.read_dir(path)
+        self.fs.read_dir(&self.resolve_symlinks(path)?)
     }
 
     fn create_dir(&self, path: &Path) -> Result<()> {
-        self.fs.create_dir(path)
+        self.guarded().create_dir(path)
     }
 
     fn remove_dir(&self, path: &Path) -> Result<()> {
-        self.fs.remove_dir(path)
+        self.guarded().remove_dir(path)
     }
 
     fn rename(&self, from: &Path, to: &Path) -> Result<()> {
-        self.fs.rename(from, to)
+        self.guarded().rename(from, to)
     }
 
     fn metadata(&self, path: &Path) -> Result<Metadata> {
-        self.fs.metadata(path)
+        self.fs.metadata(&self.resolve_symlinks(path)?)
     }
 
     fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
         self.fs.symlink_metadata(path)
     }
 
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.fs.read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        ops::canonicalize_with_limit(&self.fs, path, self.max_symlink_follows)
+    }
+
     fn remove_file(&self, path: &Path) -> Result<()> {
-        self.fs.remove_file(path)
+        self.guarded().remove_file(path)
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.guarded().write_file(path, contents)
     }
 
     fn new_open_options(&self) -> OpenOptions {
-        self.fs.new_open_options()
+        OpenOptions::new(self)
+    }
+}
+
+impl FileOpener for TmpFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let path = self.resolve_symlinks(path)?;
+        self.guarded()
+            .new_open_options()
+            .options(conf.clone())
+            .open(path)
+    }
+}
+
+impl ExchangeFileSystem for TmpFileSystem {
+    fn exchange(&self, a: &Path, b: &Path) -> Result<()> {
+        if let Some(read_only) = &self.read_only {
+            read_only.check_writable(a)?;
+            read_only.check_writable(b)?;
+        }
+        self.fs.exchange(a, b)
+    }
+}
+
+impl WatchFileSystem for TmpFileSystem {
+    fn watch(&self, path: &Path) -> Result<FsWatcher> {
+        self.fs.watch(path)
+    }
+}
+
+/// Lets existing files be changed, but refuses to create new ones.
+#[derive(Debug)]
+struct NoCreateFileSystem {
+    inner: Arc<dyn FileSystem + Send + Sync>,
+}
+
+impl NoCreateFileSystem {
+    fn check_exists(&self, path: &Path) -> Result<()> {
+        match self.inner.symlink_metadata(path) {
+            Ok(_) => Ok(()),
+            Err(FsError::EntryNotFound) => Err(FsError::PermissionDenied),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl FileSystem for NoCreateFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        match self.check_exists(path) {
+            Ok(()) => Err(FsError::AlreadyExists),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.check_exists(to)?;
+        self.inner.rename(from, to)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.inner.read_link(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.check_exists(path)?;
+        self.inner.write_file(path, contents)
+    }
+
+    fn new_open_options(&self) -> OpenOptions<'_> {
+        OpenOptions::new(self)
+    }
+}
+
+impl FileOpener for NoCreateFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        if conf.create_new() {
+            self.check_exists(path)?;
+            return Err(FsError::AlreadyExists);
+        }
+        if conf.create() {
+            self.check_exists(path)?;
+        }
+        self.inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::ops;
+
+    async fn layer(contents: &str) -> Arc<dyn FileSystem + Send + Sync> {
+        let fs = TmpFileSystem::new();
+        ops::create_dir_all(&fs, "/etc").unwrap();
+        ops::write(&fs, "/etc/config", contents).await.unwrap();
+        ops::write(&fs, format!("/etc/{contents}"), contents)
+            .await
+            .unwrap();
+        Arc::new(fs)
+    }
+
+    #[tokio::test]
+    async fn read_only_mounts_can_be_read_but_not_changed() {
+        let fs = TmpFileSystem::new();
+        let other = layer("base").await;
+        fs.mount_with_options("/mnt".into(), &other, "/".into(), MountOptions::read_only())
+            .unwrap();
+
+        assert_eq!(
+            ops::read_to_string(&fs, "/mnt/etc/config").await.unwrap(),
+            "base"
+        );
+        let err = fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/mnt/etc/new")
+            .unwrap_err();
+        assert_eq!(err, FsError::ReadOnly);
+        assert_eq!(
+            fs.create_dir(Path::new("/mnt/var")).unwrap_err(),
+            FsError::ReadOnly
+        );
+        assert_eq!(
+            fs.remove_file(Path::new("/mnt/etc/config")).unwrap_err(),
+            FsError::ReadOnly
+        );
+        // The mounted file system itself is untouched
+        assert!(!ops::exists(&*other, "/etc/new"));
+        assert!(ops::is_file(&*other, "/etc/config"));
+    }
+
+    #[tokio::test]
+    async fn read_only_file_systems_can_still_be_extended_by_the_host() {
+        let fs = TmpFileSystem::new();
+        ops::create_dir_all(&fs, "/etc").unwrap();
+        ops::create_dir_all(&fs, "/tmp").unwrap();
+        ops::write(&fs, "/etc/config", "base").await.unwrap();
+        let fs = fs.with_read_only(vec![PathBuf::from("/tmp")]);
+
+        let err = fs
+            .new_open_options()
+            .write(true)
+            .open("/etc/config")
+            .unwrap_err();
+        assert_eq!(err, FsError::ReadOnly);
+        assert_eq!(
+            fs.create_dir(Path::new("/etc/ssh")).unwrap_err(),
+            FsError::ReadOnly
+        );
+        ops::write(&fs, "/tmp/scratch", "scratch").await.unwrap();
+
+        fs.union(&layer("package").await);
+        fs.new_open_options_ext()
+            .insert_ro_file(Path::new("/etc/ro"), b"ro".as_slice().into())
+            .unwrap();
+        assert_eq!(
+            ops::read_to_string(&fs, "/etc/package").await.unwrap(),
+            "package"
+        );
+        assert!(ops::is_file(&fs, "/etc/ro"));
+    }
+
+    #[tokio::test]
+    async fn mounts_can_forbid_creating_files() {
+        let fs = TmpFileSystem::new();
+        let other = layer("base").await;
+        let options = MountOptions {
+            allow_create: false,
+            ..MountOptions::default()
+        };
+        fs.mount_with_options("/mnt".into(), &other, "/".into(), options)
+            .unwrap();
+
+        ops::write(&fs, "/mnt/etc/config", "changed").await.unwrap();
+        assert_eq!(
+            ops::read_to_string(&*other, "/etc/config").await.unwrap(),
+            "changed"
+        );
+        let err = fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/mnt/etc/new")
+            .unwrap_err();
+        assert_eq!(err, FsError::PermissionDenied);
+        assert_eq!(
+            fs.create_dir(Path::new("/mnt/var")).unwrap_err(),
+            FsError::PermissionDenied
+        );
+    }
+
+    #[tokio::test]
+    async fn union_with_higher_priority_overrides() {
+        let fs = TmpFileSystem::new();
+        fs.union(&layer("base").await);
+        fs.union_with_priority(&layer("top").await, Priority::Higher);
+
+        assert_eq!(
+            ops::read_to_string(&fs, "/etc/config").await.unwrap(),
+            "top"
+        );
+        assert!(ops::is_file(&fs, "/etc/base"));
+        assert!(ops::is_file(&fs, "/etc/top"));
+    }
+
+    #[tokio::test]
+    async fn union_with_lower_priority_keeps_existing_files() {
+        let fs = TmpFileSystem::new();
+        fs.union(&layer("top").await);
+        fs.union_with_priority(&layer("base").await, Priority::Lower);
+
+        assert_eq!(
+            ops::read_to_string(&fs, "/etc/config").await.unwrap(),
+            "top"
+        );
+        assert!(ops::is_file(&fs, "/etc/base"));
+        assert!(ops::is_file(&fs, "/etc/top"));
+    }
+
+    #[tokio::test]
+    async fn whiteouts_only_apply_from_a_higher_layer() {
+        let whiteout = TmpFileSystem::new();
+        ops::create_dir_all(&whiteout, "/etc").unwrap();
+        ops::touch(&whiteout, "/etc/.wh.config").unwrap();
+        let whiteout: Arc<dyn FileSystem + Send + Sync> = Arc::new(whiteout);
+
+        let fs = TmpFileSystem::new();
+        fs.union(&layer("base").await);
+        fs.union_with_priority(&whiteout, Priority::Lower);
+        assert!(ops::is_file(&fs, "/etc/config"));
+
+        fs.union_with_priority(&whiteout, Priority::Higher);
+        assert!(!ops::exists(&fs, "/etc/config"));
+        assert!(!ops::exists(&fs, "/etc/.wh.config"));
+    }
+
+    #[tokio::test]
+    async fn one_file_system_mounted_twice() {
+        let shared: Arc<dyn FileSystem + Send + Sync> = Arc::new(TmpFileSystem::new());
+        let fs = TmpFileSystem::new();
+        fs.mount("/a".into(), &shared, "/".into()).unwrap();
+        fs.mount("/b".into(), &shared, "/".into()).unwrap();
+
+        ops::write(&fs, "/a/file.txt", "hello").await.unwrap();
+        assert_eq!(
+            ops::read_to_string(&fs, "/b/file.txt").await.unwrap(),
+            "hello"
+        );
+
+        // Each mount lists its entries under its own path
+        let entries: Vec<_> = fs
+            .read_dir(Path::new("/b"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path)
+            .collect();
+        assert_eq!(entries, vec![PathBuf::from("/b/file.txt")]);
+
+        // Files opened through either mount have their own cursor
+        let mut a = fs
+            .new_open_options()
+            .read(true)
+            .open("/a/file.txt")
+            .unwrap();
+        let mut b = fs
+            .new_open_options()
+            .read(true)
+            .open("/b/file.txt")
+            .unwrap();
+        let mut buf = [0; 2];
+        a.read_exact(&mut buf).await.unwrap();
+        let mut contents = String::new();
+        b.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "hello");
+
+        fs.rename(Path::new("/a/file.txt"), Path::new("/a/renamed.txt"))
+            .unwrap();
+        assert!(!ops::exists(&fs, "/b/file.txt"));
+        assert!(ops::is_file(&fs, "/b/renamed.txt"));
+    }
+
+    #[tokio::test]
+    async fn clone_subtree_copies_only_that_subtree() {
+        let fs = TmpFileSystem::new();
+        ops::create_dir_all(&fs, "/project/src").unwrap();
+        ops::create_dir_all(&fs, "/project/docs").unwrap();
+        ops::create_dir_all(&fs, "/other").unwrap();
+        ops::write(&fs, "/project/README.md", "readme")
+            .await
+            .unwrap();
+        ops::write(&fs, "/project/src/main.rs", "fn main() {}")
+            .await
+            .unwrap();
+        ops::write(&fs, "/other/file.txt", "other").await.unwrap();
+        let modified = fs.metadata(Path::new("/project/src/main.rs")).unwrap();
+
+        let project = fs.clone_subtree(Path::new("/project")).unwrap();
+
+        let mut paths: Vec<_> = ops::walk(&project, "/").map(|entry| entry.path).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/README.md"),
+                PathBuf::from("/docs"),
+                PathBuf::from("/src"),
+                PathBuf::from("/src/main.rs"),
+            ]
+        );
+        assert_eq!(
+            ops::read_to_string(&project, "/src/main.rs").await.unwrap(),
+            "fn main() {}"
+        );
+        assert_eq!(
+            project.metadata(Path::new("/src/main.rs")).unwrap(),
+            modified
+        );
+
+        // The copy is independent of the original
+        ops::write(&project, "/README.md", "changed").await.unwrap();
+        assert_eq!(
+            ops::read_to_string(&fs, "/project/README.md")
+                .await
+                .unwrap(),
+            "readme"
+        );
+    }
+
+    /// Counts how many bytes a file system has allocated.
+    #[cfg(feature = "tracking")]
+    #[derive(Debug, Default)]
+    struct CountingLimiter(std::sync::atomic::AtomicUsize);
+
+    #[cfg(feature = "tracking")]
+    impl CountingLimiter {
+        fn used(&self) -> usize {
+            self.0.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[cfg(feature = "tracking")]
+    impl crate::limiter::FsMemoryLimiter for CountingLimiter {
+        fn on_grow(&self, grown_bytes: usize) -> std::result::Result<(), crate::FsError> {
+            self.0
+                .fetch_add(grown_bytes, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn on_shrink(&self, shrunk_bytes: usize) {
+            self.0
+                .fetch_sub(shrunk_bytes, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[cfg(feature = "tracking")]
+    #[tokio::test]
+    async fn instances_of_a_package_do_not_copy_its_files() {
+        let package = TmpFileSystem::new();
+        ops::create_dir_all(&package, "/lib").unwrap();
+        ops::write(&package, "/lib/module.py", vec![b'x'; 64 * 1024])
+            .await
+            .unwrap();
+        let package: Arc<dyn FileSystem + Send + Sync> = Arc::new(package);
+
+        let instances: Vec<_> = (0..2)
+            .map(|_| {
+                let fs = TmpFileSystem::new();
+                let limiter = Arc::new(CountingLimiter::default());
+                fs.set_memory_limiter(limiter.clone());
+                fs.union_copy_on_write(&package);
+                (fs, limiter)
+            })
+            .collect();
+
+        for (fs, limiter) in &instances {
+            let contents = ops::read(fs, "/lib/module.py").await.unwrap();
+            assert_eq!(contents.len(), 64 * 1024);
+            assert!(limiter.used() < 1024, "{} bytes", limiter.used());
+        }
+
+        // Only the instance that writes pays for its own copy
+        let (first, first_limiter) = &instances[0];
+        let mut file = first
+            .new_open_options()
+            .write(true)
+            .append(true)
+            .open("/lib/module.py")
+            .unwrap();
+        file.write_all(b"!").await.unwrap();
+        drop(file);
+        assert!(first_limiter.used() >= 64 * 1024);
+        assert!(instances[1].1.used() < 1024);
+    }
+
+    #[tokio::test]
+    async fn package_files_are_shared_until_written() {
+        let package = TmpFileSystem::new();
+        ops::create_dir_all(&package, "/lib").unwrap();
+        ops::write(&package, "/lib/module.py", "original")
+            .await
+            .unwrap();
+        let package: Arc<dyn FileSystem + Send + Sync> = Arc::new(package);
+
+        let first = TmpFileSystem::new();
+        first.union_copy_on_write(&package);
+        let second = TmpFileSystem::new();
+        second.union_copy_on_write(&package);
+
+        // Both instances refer to the package's buffer rather than holding
+        // a copy of it, so they see it change
+        ops::write(&*package, "/lib/module.py", "updated")
+            .await
+            .unwrap();
+        assert_eq!(
+            ops::read_to_string(&first, "/lib/module.py").await.unwrap(),
+            "updated"
+        );
+        assert_eq!(
+            ops::read_to_string(&second, "/lib/module.py")
+                .await
+                .unwrap(),
+            "updated"
+        );
+
+        // Writing only changes the writer's own copy
+        let mut file = first
+            .new_open_options()
+            .write(true)
+            .append(true)
+            .open("/lib/module.py")
+            .unwrap();
+        file.write_all(b" locally").await.unwrap();
+        drop(file);
+        assert_eq!(
+            ops::read_to_string(&first, "/lib/module.py").await.unwrap(),
+            "updated locally"
+        );
+        assert_eq!(
+            ops::read_to_string(&second, "/lib/module.py")
+                .await
+                .unwrap(),
+            "updated"
+        );
+        assert_eq!(
+            ops::read_to_string(&*package, "/lib/module.py")
+                .await
+                .unwrap(),
+            "updated"
+        );
+
+        ops::write(&second, "/lib/module.py", "replaced")
+            .await
+            .unwrap();
+        assert_eq!(
+            ops::read_to_string(&second, "/lib/module.py")
+                .await
+                .unwrap(),
+            "replaced"
+        );
+        assert_eq!(
+            ops::read_to_string(&*package, "/lib/module.py")
+                .await
+                .unwrap(),
+            "updated"
+        );
+    }
+
+    #[tokio::test]
+    async fn exchange_swaps_two_files() {
+        let fs = TmpFileSystem::new();
+        ops::create_dir_all(&fs, "/app/releases").unwrap();
+        ops::write(&fs, "/app/current", "v1").await.unwrap();
+        ops::write(&fs, "/app/releases/next", "v2").await.unwrap();
+
+        // Keep looking at both paths while they are being swapped
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let observer = std::thread::spawn({
+            let fs = fs.clone();
+            let done = done.clone();
+            move || {
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    assert!(ops::is_file(&fs, "/app/current"));
+                    assert!(ops::is_file(&fs, "/app/releases/next"));
+                }
+            }
+        });
+        // An odd number of swaps leaves them swapped
+        for _ in 0..1001 {
+            fs.exchange(Path::new("/app/current"), Path::new("/app/releases/next"))
+                .unwrap();
+        }
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        observer.join().unwrap();
+
+        assert_eq!(
+            ops::read_to_string(&fs, "/app/current").await.unwrap(),
+            "v2"
+        );
+        assert_eq!(
+            ops::read_to_string(&fs, "/app/releases/next")
+                .await
+                .unwrap(),
+            "v1"
+        );
+
+        assert_eq!(
+            fs.exchange(Path::new("/app/current"), Path::new("/app/missing")),
+            Err(FsError::EntryNotFound)
+        );
+        assert_eq!(
+            fs.exchange(Path::new("/app/missing"), Path::new("/app/current")),
+            Err(FsError::EntryNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn write_file_never_exposes_a_partial_file() {
+        let fs = TmpFileSystem::new();
+        let old = vec![b'a'; 100_000];
+        let new = vec![b'b'; 300_000];
+        fs.write_file(Path::new("/config"), &old).unwrap();
+
+        // Keep reading the file while it is being rewritten
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let reader = std::thread::spawn({
+            let fs = fs.clone();
+            let done = done.clone();
+            let (old, new) = (old.clone(), new.clone());
+            move || {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                let mut buf = vec![0; 400_000];
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    let len = fs.metadata(Path::new("/config")).unwrap().len;
+                    assert!(len == 100_000 || len == 300_000, "len = {len}");
+
+                    let mut file = fs.new_open_options().read(true).open("/config").unwrap();
+                    // A single read sees the file as it was at that moment
+                    if let Ok(read) = rt.block_on(file.read(&mut buf)) {
+                        let contents = &buf[..read];
+                        assert!(contents == old || contents == new, "read {read} bytes");
+                    }
+                }
+            }
+        });
+        for i in 0..200 {
+            let contents = if i % 2 == 0 { &new } else { &old };
+            fs.write_file(Path::new("/config"), contents).unwrap();
+            assert_eq!(ops::read(&fs, "/config").await.unwrap(), *contents);
+        }
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        reader.join().unwrap();
+
+        assert_eq!(ops::read(&fs, "/config").await.unwrap(), old);
+        assert_eq!(
+            fs.write_file(Path::new("/missing/config"), b"x"),
+            Err(FsError::EntryNotFound)
+        );
+        assert_eq!(
+            fs.write_file(Path::new("/"), b"x"),
+            Err(FsError::BaseNotDirectory)
+        );
+
+        fs.write_file_ext(Path::new("/missing/config"), b"x", true)
+            .unwrap();
+        assert_eq!(ops::read(&fs, "/missing/config").await.unwrap(), b"x");
+        assert_eq!(
+            fs.write_file(Path::new("/missing"), b"x"),
+            Err(FsError::NotAFile)
+        );
+    }
+
+    #[cfg(all(unix, feature = "host-fs"))]
+    #[test]
+    fn symlink_loops_in_mounts_are_not_followed_forever() {
+        let temp = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp.path().join("real")).unwrap();
+        std::os::unix::fs::symlink("real", temp.path().join("one")).unwrap();
+        std::os::unix::fs::symlink("one", temp.path().join("two")).unwrap();
+        std::os::unix::fs::symlink("loop-b", temp.path().join("loop-a")).unwrap();
+        std::os::unix::fs::symlink("loop-a", temp.path().join("loop-b")).unwrap();
+
+        let host: Arc<dyn FileSystem + Send + Sync> = Arc::new(crate::host_fs::FileSystem);
+        let mut fs = TmpFileSystem::new();
+        fs.mount("/host".into(), &host, temp.path().to_path_buf())
+            .unwrap();
+
+        assert_eq!(
+            fs.canonicalize(Path::new("/host/two")),
+            Ok(PathBuf::from("/host/real"))
+        );
+        assert_eq!(
+            fs.canonicalize(Path::new("/host/loop-a")),
+            Err(FsError::TooManySymlinks)
+        );
+
+        assert_eq!(
+            fs.metadata(Path::new("/host/loop-a")).map(|_| ()),
+            Err(FsError::TooManySymlinks)
+        );
+
+        fs.set_max_symlink_follows(1);
+        assert_eq!(
+            fs.canonicalize(Path::new("/host/one")),
+            Ok(PathBuf::from("/host/real"))
+        );
+        assert_eq!(
+            fs.canonicalize(Path::new("/host/two")),
+            Err(FsError::TooManySymlinks)
+        );
+
+        // The limit also applies to everything else that follows symlinks
+        assert!(fs.metadata(Path::new("/host/one")).unwrap().is_dir());
+        assert_eq!(
+            fs.metadata(Path::new("/host/two")).map(|_| ()),
+            Err(FsError::TooManySymlinks)
+        );
+        assert_eq!(
+            fs.read_dir(Path::new("/host/two")).map(|_| ()),
+            Err(FsError::TooManySymlinks)
+        );
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open(Path::new("/host/one/file.txt"))
+            .unwrap();
+        assert_eq!(
+            fs.new_open_options()
+                .read(true)
+                .open(Path::new("/host/two/file.txt"))
+                .map(|_| ()),
+            Err(FsError::TooManySymlinks)
+        );
+    }
+
+    #[tokio::test]
+    async fn standard_devices_behave_like_their_namesakes() {
+        let fs = TmpFileSystem::new();
+        let mut next = 0u8;
+        fs.mount_standard_dev(RandomFile::with_source(move |buf| {
+            for b in buf {
+                next = next.wrapping_add(1);
+                *b = next;
+            }
+        }))
+        .unwrap();
+
+        for dev in ["/dev/null", "/dev/zero", "/dev/urandom"] {
+            let metadata = fs.metadata(Path::new(dev)).unwrap();
+            assert!(metadata.file_type().is_char_device(), "{dev}");
+        }
+
+        let mut zero = fs.new_open_options().read(true).open("/dev/zero").unwrap();
+        let mut buf = [1; 16];
+        zero.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [0; 16]);
+
+        let mut null = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .open("/dev/null")
+            .unwrap();
+        null.write_all(b"discarded").await.unwrap();
+        let mut contents = Vec::new();
+        null.read_to_end(&mut contents).await.unwrap();
+        assert!(contents.is_empty());
+
+        let mut urandom = fs
+            .new_open_options()
+            .read(true)
+            .open("/dev/urandom")
+            .unwrap();
+        let mut buf = [0; 4];
+        urandom.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn creating_fails_once_the_inode_budget_is_used_up() {
+        let fs = TmpFileSystem::new();
+        fs.create_dir(Path::new("/data")).unwrap();
+        // `/` and `/data`, plus room for three files
+        assert_eq!(fs.total_inodes(), 2);
+        fs.set_max_inodes(5);
+
+        let create = |path: &str| {
+            fs.new_open_options()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map(|_| ())
+        };
+        for i in 0..3 {
+            create(&format!("/data/{i}")).unwrap();
+        }
+        assert_eq!(fs.total_inodes(), 5);
+
+        assert_eq!(create("/data/3"), Err(FsError::StorageFull));
+        assert_eq!(
+            fs.create_dir(Path::new("/data/dir")),
+            Err(FsError::StorageFull)
+        );
+        // Existing files can still be opened
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open("/data/0")
+            .unwrap();
+
+        // Deleting a file makes room for another one
+        fs.remove_file(Path::new("/data/0")).unwrap();
+        assert_eq!(fs.total_inodes(), 4);
+        create("/data/3").unwrap();
+    }
+
+    #[test]
+    fn inserting_and_mounting_count_against_the_inode_budget() {
+        let fs = TmpFileSystem::new();
+        let other: Arc<dyn FileSystem + Send + Sync> = Arc::new(TmpFileSystem::new());
+        other.write_file(Path::new("/file.txt"), b"hello").unwrap();
+        fs.set_max_inodes(1);
+
+        let ext = fs.new_open_options_ext();
+        assert_eq!(
+            ext.insert_ro_file(Path::new("/ro"), b"ro".as_slice().into()),
+            Err(FsError::StorageFull)
+        );
+        assert_eq!(
+            ext.insert_device_file("/dev".into(), Box::<NullFile>::default()),
+            Err(FsError::StorageFull)
+        );
+        assert_eq!(
+            ext.insert_arc_file_at("/arc".into(), other.clone(), "/file.txt".into()),
+            Err(FsError::StorageFull)
+        );
+        assert_eq!(
+            fs.mount("/mnt".into(), &other, "/".into()),
+            Err(FsError::StorageFull)
+        );
+        assert_eq!(
+            fs.write_file(Path::new("/new"), b"new"),
+            Err(FsError::StorageFull)
+        );
+        assert_eq!(fs.total_inodes(), 1);
+
+        fs.set_max_inodes(2);
+        fs.mount("/mnt".into(), &other, "/".into()).unwrap();
+        assert_eq!(fs.total_inodes(), 2);
     }
 }
@@ -1,4 +1,5 @@
 use std::{
+    future::Future,
     path::PathBuf,
     pin::Pin,
     task::{Context, Poll},
@@ -68,6 +69,11 @@ where
         self.0.remove_file(path)
     }
 
+    #[tracing::instrument(level = "trace", skip(self, contents), fields(len = contents.len()), err)]
+    fn write_file(&self, path: &std::path::Path, contents: &[u8]) -> crate::Result<()> {
+        self.0.write_file(path, contents)
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     fn new_open_options(&self) -> crate::OpenOptions {
         crate::OpenOptions::new(self)
@@ -125,11 +131,49 @@ impl VirtualFile for TraceFile {
         self.file.set_len(new_size)
     }
 
+    #[tracing::instrument(level = "trace", skip(self), fields(path=%self.path.display()), err)]
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> crate::Result<()> {
+        self.file.set_times(atime, mtime)
+    }
+
     #[tracing::instrument(level = "trace", skip(self), fields(path=%self.path.display()), err)]
     fn unlink(&mut self) -> crate::Result<()> {
         self.file.unlink()
     }
 
+    #[tracing::instrument(level = "trace", skip(self), fields(path=%self.path.display()), err)]
+    fn shrink_to_fit(&mut self) -> crate::Result<()> {
+        self.file.shrink_to_fit()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), fields(path=%self.path.display()), err)]
+    fn sync(&mut self) -> crate::Result<()> {
+        self.file.sync()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), fields(path=%self.path.display()), err)]
+    fn sync_data(&mut self) -> crate::Result<()> {
+        self.file.sync_data()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, buf), fields(path=%self.path.display()))]
+    fn read_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = std::io::Result<usize>> + 'a>> {
+        self.file.read_at(offset, buf)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, buf), fields(path=%self.path.display()))]
+    fn write_at<'a>(
+        &'a self,
+        offset: u64,
+        buf: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = std::io::Result<usize>> + 'a>> {
+        self.file.write_at(offset, buf)
+    }
+
     #[tracing::instrument(level = "trace", skip_all, fields(path=%self.path.display()))]
     fn poll_read_ready(
         mut self: Pin<&mut Self>,
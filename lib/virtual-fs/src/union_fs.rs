@@ -365,6 +365,22 @@ impl FileSystem for UnionFileSystem {
         }
         Err(ret_error)
     }
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        debug!("write_file: path={}", path.display());
+        let mut ret_error = FsError::EntryNotFound;
+        let path = path.to_string_lossy();
+        for (path, mount) in filter_mounts(&self.mounts, path.as_ref()) {
+            match mount.fs.write_file(Path::new(path.as_str()), contents) {
+                Ok(ret) => {
+                    return Ok(ret);
+                }
+                Err(err) => {
+                    ret_error = err;
+                }
+            }
+        }
+        Err(ret_error)
+    }
     fn new_open_options(&self) -> OpenOptions {
         OpenOptions::new(self)
     }
@@ -510,6 +526,29 @@ mod tests {
         let _ = std::fs::remove_file("/test_new_filesystem/foo2.txt");
     }
 
+    #[tokio::test]
+    async fn test_write_file() {
+        let mut fs = UnionFileSystem::new();
+        fs.mount(
+            "mem_fs",
+            "/mnt",
+            false,
+            Box::new(mem_fs::FileSystem::default()),
+            None,
+        );
+
+        fs.write_file(Path::new("/mnt/config"), b"debug = true")
+            .unwrap();
+        assert_eq!(
+            ops::read(&fs, "/mnt/config").await.unwrap(),
+            b"debug = true"
+        );
+        assert_eq!(
+            fs.write_file(Path::new("/elsewhere/config"), b""),
+            Err(FsError::EntryNotFound)
+        );
+    }
+
     #[tokio::test]
     async fn test_create_dir() {
         let fs = gen_filesystem();
@@ -948,25 +987,25 @@ mod tests {
         let mut readdir = readdir.unwrap();
 
         let next = readdir.next().unwrap().unwrap();
-        assert!(next.path.ends_with("foo"), "checking entry #1");
+        assert!(next.path.ends_with("a.txt"), "checking entry #1");
         println!("entry 1: {:#?}", next);
-        assert!(next.file_type().unwrap().is_dir(), "checking entry #1");
+        assert!(next.file_type().unwrap().is_file(), "checking entry #1");
 
         let next = readdir.next().unwrap().unwrap();
-        assert!(next.path.ends_with("bar"), "checking entry #2");
-        assert!(next.file_type().unwrap().is_dir(), "checking entry #2");
+        assert!(next.path.ends_with("b.txt"), "checking entry #2");
+        assert!(next.file_type().unwrap().is_file(), "checking entry #2");
 
         let next = readdir.next().unwrap().unwrap();
-        assert!(next.path.ends_with("baz"), "checking entry #3");
+        assert!(next.path.ends_with("bar"), "checking entry #3");
         assert!(next.file_type().unwrap().is_dir(), "checking entry #3");
 
         let next = readdir.next().unwrap().unwrap();
-        assert!(next.path.ends_with("a.txt"), "checking entry #2");
-        assert!(next.file_type().unwrap().is_file(), "checking entry #4");
+        assert!(next.path.ends_with("baz"), "checking entry #4");
+        assert!(next.file_type().unwrap().is_dir(), "checking entry #4");
 
         let next = readdir.next().unwrap().unwrap();
-        assert!(next.path.ends_with("b.txt"), "checking entry #2");
-        assert!(next.file_type().unwrap().is_file(), "checking entry #5");
+        assert!(next.path.ends_with("foo"), "checking entry #5");
+        assert!(next.file_type().unwrap().is_dir(), "checking entry #5");
 
         if let Some(s) = readdir.next() {
             panic!("next: {:?}", s);
@@ -0,0 +1,200 @@
+//! Notifications about changes made to a file system.
+
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::Result;
+
+/// A change that was made to a watched part of a file system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsEvent {
+    /// A file or directory was created.
+    Created(PathBuf),
+    /// The contents of a file were changed.
+    Modified(PathBuf),
+    /// A file or directory was removed.
+    Removed(PathBuf),
+    /// A file or directory was moved.
+    Renamed { from: PathBuf, to: PathBuf },
+}
+
+impl FsEvent {
+    fn concerns(&self, root: &Path) -> bool {
+        match self {
+            FsEvent::Created(path) | FsEvent::Modified(path) | FsEvent::Removed(path) => {
+                path.starts_with(root)
+            }
+            FsEvent::Renamed { from, to } => from.starts_with(root) || to.starts_with(root),
+        }
+    }
+}
+
+/// File systems that can report the changes made to them.
+pub trait WatchFileSystem {
+    /// Starts watching `path` and everything below it.
+    ///
+    /// Events are delivered until the returned [`FsWatcher`] is dropped.
+    fn watch(&self, path: &Path) -> Result<FsWatcher>;
+}
+
+/// A [`Stream`] of the [`FsEvent`]s under a watched path.
+#[derive(Debug)]
+pub struct FsWatcher {
+    rx: mpsc::UnboundedReceiver<FsEvent>,
+}
+
+impl FsWatcher {
+    /// Returns the next event if one is already available, without waiting.
+    pub fn try_next(&mut self) -> Option<FsEvent> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Stream for FsWatcher {
+    type Item = FsEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// The watchers registered with a file system.
+#[derive(Debug, Default)]
+pub(crate) struct Watchers {
+    watchers: Mutex<Vec<(PathBuf, mpsc::UnboundedSender<FsEvent>)>>,
+    // Mirrors the length of `watchers`, so changes can be made without
+    // taking the lock when nobody is watching
+    len: AtomicUsize,
+}
+
+impl Watchers {
+    pub(crate) fn watch(&self, root: PathBuf) -> FsWatcher {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.push((root, tx));
+        self.len.store(watchers.len(), Ordering::Release);
+        FsWatcher { rx }
+    }
+
+    /// Whether nobody is watching, in which case events don't need to be
+    /// built at all.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Acquire) == 0
+    }
+
+    /// Sends the event to everyone watching the path it concerns, and
+    /// forgets about the watchers that have been dropped.
+    pub(crate) fn notify(&self, event: FsEvent) {
+        if self.is_empty() {
+            return;
+        }
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|(root, tx)| {
+            if tx.is_closed() {
+                return false;
+            }
+            if event.concerns(root) {
+                return tx.send(event.clone()).is_ok();
+            }
+            true
+        });
+        self.len.store(watchers.len(), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::{mem_fs, FileSystem};
+
+    #[tokio::test]
+    async fn watch_file_changes() {
+        let fs = mem_fs::FileSystem::default();
+        fs.create_dir(Path::new("/tmp")).unwrap();
+        fs.create_dir(Path::new("/other")).unwrap();
+
+        let mut watcher = fs.watch(Path::new("/tmp")).unwrap();
+
+        let mut f = fs
+            .new_open_options()
+            .create(true)
+            .write(true)
+            .open("/tmp/file.txt")
+            .unwrap();
+        f.write_all(b"hello").await.unwrap();
+        drop(f);
+        fs.new_open_options()
+            .create(true)
+            .write(true)
+            .open("/other/file.txt")
+            .unwrap();
+        fs.rename(Path::new("/tmp/file.txt"), Path::new("/tmp/moved.txt"))
+            .unwrap();
+        fs.remove_file(Path::new("/tmp/moved.txt")).unwrap();
+
+        assert_eq!(
+            watcher.try_next(),
+            Some(FsEvent::Created(PathBuf::from("/tmp/file.txt")))
+        );
+        assert_eq!(
+            watcher.try_next(),
+            Some(FsEvent::Modified(PathBuf::from("/tmp/file.txt")))
+        );
+        assert_eq!(
+            watcher.try_next(),
+            Some(FsEvent::Renamed {
+                from: PathBuf::from("/tmp/file.txt"),
+                to: PathBuf::from("/tmp/moved.txt"),
+            })
+        );
+        assert_eq!(
+            watcher.try_next(),
+            Some(FsEvent::Removed(PathBuf::from("/tmp/moved.txt")))
+        );
+        assert_eq!(watcher.try_next(), None);
+    }
+
+    #[tokio::test]
+    async fn open_files_report_their_path_after_a_rename() {
+        let fs = mem_fs::FileSystem::default();
+        fs.create_dir(Path::new("/tmp")).unwrap();
+        let mut f = fs
+            .new_open_options()
+            .create(true)
+            .write(true)
+            .open("/tmp/file.txt")
+            .unwrap();
+        fs.rename(Path::new("/tmp/file.txt"), Path::new("/tmp/moved.txt"))
+            .unwrap();
+
+        let mut watcher = fs.watch(Path::new("/tmp")).unwrap();
+        f.write_all(b"hello").await.unwrap();
+
+        assert_eq!(
+            watcher.try_next(),
+            Some(FsEvent::Modified(PathBuf::from("/tmp/moved.txt")))
+        );
+        assert_eq!(watcher.try_next(), None);
+    }
+
+    #[test]
+    fn watch_missing_path() {
+        let fs = mem_fs::FileSystem::default();
+        assert_eq!(
+            fs.watch(Path::new("/missing")).unwrap_err(),
+            crate::FsError::EntryNotFound
+        );
+    }
+}
@@ -48,12 +48,7 @@ impl VirtualNetworking for LocalNetworking {
     ) -> Result<Box<dyn VirtualTcpListener + Sync>> {
         let listener = tokio::net::TcpListener::bind(addr)
             .await
-            .map(|sock| {
-                Box::new(LocalTcpListener {
-                    stream: sock,
-                    backlog: Mutex::new(Vec::new()),
-                })
-            })
+            .map(|sock| Box::new(LocalTcpListener::new(sock)))
             .map_err(io_err_into_net_error)?;
         Ok(listener)
     }
@@ -110,6 +105,17 @@ pub struct LocalTcpListener {
     backlog: Mutex<Vec<(Box<LocalTcpStream>, SocketAddr)>>,
 }
 
+impl LocalTcpListener {
+    /// Wraps a listener that was bound on the host, e.g. so it can be
+    /// handed over to a guest.
+    pub fn new(stream: tokio::net::TcpListener) -> Self {
+        Self {
+            stream,
+            backlog: Mutex::new(Vec::new()),
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl VirtualTcpListener for LocalTcpListener {
     fn try_accept(&mut self) -> Option<Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr)>> {
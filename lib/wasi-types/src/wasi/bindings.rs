@@ -759,6 +759,8 @@ wai_bindgen_rust::bitflags::bitflags! {
         const EXCL = 1 << 2;
         #[doc = " Truncate file to size 0."]
         const TRUNC = 1 << 3;
+        #[doc = " Create an unnamed file in the directory, which goes away once it is closed."]
+        const TMPFILE = 1 << 4;
     }
 }
 impl Oflags {
@@ -11,7 +11,7 @@ use wasm_bindgen::{prelude::*, JsCast};
 use wasmer_wasix::{
     capabilities::Capabilities,
     os::{Console, InputEvent, Tty, TtyOptions},
-    Pipe,
+    Pipe, Runtime,
 };
 use web_sys::{HtmlCanvasElement, WebGl2RenderingContext};
 #[allow(unused_imports)]
@@ -132,6 +132,7 @@ pub fn start() -> Result<(), JsValue> {
         is_mobile,
         tty_options,
     );
+    tty.set_clock(runtime.clock());
 
     let location = url::Url::parse(location.as_str()).unwrap();
     let mut console = if let Some(init) = location
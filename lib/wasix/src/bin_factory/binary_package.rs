@@ -95,16 +95,26 @@ impl BinaryPackage {
     }
 
     /// Load a [`BinaryPackage`] and all its dependencies from a registry.
+    ///
+    /// The runtime gets a chance to [rewrite][Runtime::rewrite_specifier]
+    /// the specifier of the package and of each of its dependencies first.
     pub async fn from_registry(
         specifier: &PackageSpecifier,
         runtime: &dyn Runtime,
     ) -> Result<Self, anyhow::Error> {
+        let specifier = runtime.rewrite_specifier(specifier.clone());
         let source = runtime.source();
-        let root_summary = source.latest(specifier).await?;
+        let root_summary = source.latest(&specifier).await?;
         let root = runtime.package_loader().load(&root_summary).await?;
         let id = root_summary.package_id();
 
-        let resolution = crate::runtime::resolver::resolve(&id, &root_summary.pkg, &source).await?;
+        let resolution = crate::runtime::resolver::resolve_with_rewriter(
+            &id,
+            &root_summary.pkg,
+            &source,
+            |dep| runtime.rewrite_specifier(dep.clone()),
+        )
+        .await?;
         let pkg = runtime
             .package_loader()
             .load_package_tree(&root, &resolution)
@@ -145,7 +155,13 @@ mod tests {
     use virtual_fs::AsyncReadExt;
     use wapm_targz_to_pirita::{webc::v1::DirOrFile, FileMap, TransformManifestFunctions};
 
-    use crate::{runtime::task_manager::VirtualTaskManager, PluggableRuntime};
+    use crate::{
+        runtime::{
+            resolver::{PackageSummary, Source},
+            task_manager::VirtualTaskManager,
+        },
+        PluggableRuntime,
+    };
 
     use super::*;
 
@@ -200,6 +216,56 @@ mod tests {
         assert_eq!(buffer, file_txt);
     }
 
+    #[derive(Debug, Default)]
+    struct RecordingSource {
+        queries: std::sync::Mutex<Vec<PackageSpecifier>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Source for RecordingSource {
+        async fn query(
+            &self,
+            package: &PackageSpecifier,
+        ) -> Result<Vec<PackageSummary>, anyhow::Error> {
+            self.queries.lock().unwrap().push(package.clone());
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(
+        not(feature = "sys-threads"),
+        ignore = "The tokio task manager isn't available on this platform"
+    )]
+    async fn specifiers_are_rewritten_before_resolution() {
+        let source = Arc::new(RecordingSource::default());
+        let mut runtime = PluggableRuntime::new(task_manager());
+        runtime.set_source(source.clone());
+        runtime.set_specifier_rewriter(Arc::new(|specifier: PackageSpecifier| {
+            if specifier == PackageSpecifier::parse("sharrattj/bash").unwrap() {
+                PackageSpecifier::parse("mirror/bash@=1.0.0").unwrap()
+            } else {
+                specifier
+            }
+        }));
+
+        let bash = PackageSpecifier::parse("sharrattj/bash").unwrap();
+        assert!(BinaryPackage::from_registry(&bash, &runtime).await.is_err());
+        let python = PackageSpecifier::parse("wasmer/python").unwrap();
+        assert!(BinaryPackage::from_registry(&python, &runtime)
+            .await
+            .is_err());
+
+        let queries = source.queries.lock().unwrap();
+        assert_eq!(
+            *queries,
+            [
+                PackageSpecifier::parse("mirror/bash@=1.0.0").unwrap(),
+                PackageSpecifier::parse("wasmer/python").unwrap()
+            ]
+        );
+    }
+
     fn construct_webc_in_memory(dir: &Path) -> Vec<u8> {
         let mut files = BTreeMap::new();
         load_files_from_disk(&mut files, dir, dir);
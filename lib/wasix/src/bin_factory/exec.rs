@@ -8,6 +8,7 @@ use crate::{
 };
 use bytes::Bytes;
 use futures::Future;
+use tokio::sync::Semaphore;
 use tracing::*;
 use wasmer::{Function, FunctionEnvMut, Memory32, Memory64, Module, Store};
 use wasmer_wasix_types::wasi::Errno;
@@ -70,6 +71,41 @@ pub async fn spawn_exec(
     spawn_exec_module(module, env, runtime)
 }
 
+/// Like [`spawn_exec`], but waits for a permit from `limiter` first.
+///
+/// Compiling and instantiating a module can briefly use a lot of memory, so
+/// sharing one [`Semaphore`] between many spawns caps how many of them do
+/// that at the same time. The permit is held until the spawned process
+/// exits, so the semaphore also caps how many of them run at once.
+pub async fn spawn_exec_limited(
+    binary: BinaryPackage,
+    name: &str,
+    store: Store,
+    env: WasiEnv,
+    runtime: &Arc<dyn Runtime + Send + Sync + 'static>,
+    limiter: Arc<Semaphore>,
+) -> Result<TaskJoinHandle, SpawnError> {
+    let permit = match limiter.acquire_owned().await {
+        Ok(permit) => permit,
+        Err(_) => {
+            error!("the spawn limiter for [{}] has been closed", name);
+            env.cleanup(Some(Errno::Noexec.into())).await;
+            return Err(SpawnError::UnknownError);
+        }
+    };
+
+    let handle = spawn_exec(binary, name, store, env, runtime).await?;
+
+    // Hold on to the permit until the process has exited
+    let mut finished = handle.clone();
+    runtime.task_manager().runtime().spawn(async move {
+        let _ = finished.wait_finished().await;
+        drop(permit);
+    });
+
+    Ok(handle)
+}
+
 pub fn spawn_exec_module(
     module: Module,
     env: WasiEnv,
@@ -197,6 +233,7 @@ fn call_module(
     let ret = {
         // Call the module
         let call_ret = if let Some(start) = get_start(&ctx, &store) {
+            let _timer = ctx.data(&store).process.usage.cpu_timer();
             start.call(&mut store, &[])
         } else {
             debug!("wasi[{}]::exec-failed: missing _start function", pid);
@@ -206,14 +243,9 @@ fn call_module(
         };
 
         if let Err(err) = call_ret {
+            let err = crate::state::out_of_fuel_trap(&mut store, &ctx, err);
             match err.downcast::<WasiError>() {
-                Ok(WasiError::Exit(code)) => {
-                    if code.is_success() {
-                        Ok(Errno::Success)
-                    } else {
-                        Ok(Errno::Noexec)
-                    }
-                }
+                Ok(WasiError::Exit(code)) => Ok(code),
                 Ok(WasiError::DeepSleep(deep)) => {
                     // Create the callback that will be invoked when the thread respawns after a deep sleep
                     let rewind = deep.rewind;
@@ -234,26 +266,29 @@ fn call_module(
                 }
                 Ok(WasiError::UnknownWasiVersion) => {
                     debug!("failed as wasi version is unknown",);
-                    Ok(Errno::Noexec)
+                    Ok(Errno::Noexec.into())
+                }
+                Ok(WasiError::OutOfFuel) => {
+                    debug!("failed as the process ran out of fuel");
+                    Ok(Errno::Noexec.into())
                 }
                 Err(err) => Err(WasiRuntimeError::from(err)),
             }
         } else {
-            Ok(Errno::Success)
+            Ok(Errno::Success.into())
         }
     };
 
-    let code = if let Err(err) = &ret {
-        err.as_exit_code().unwrap_or_else(|| Errno::Noexec.into())
-    } else {
-        Errno::Success.into()
+    let code = match &ret {
+        Ok(code) => *code,
+        Err(err) => err.as_exit_code().unwrap_or_else(|| Errno::Noexec.into()),
     };
 
     // Cleanup the environment
     ctx.data(&store).blocking_cleanup(Some(code));
 
     debug!("wasi[{pid}]::main() has exited with {code}");
-    handle.thread.set_status_finished(ret.map(|a| a.into()));
+    handle.thread.set_status_finished(ret);
 }
 
 impl BinFactory {
@@ -299,3 +334,184 @@ impl BinFactory {
         Err(SpawnError::NotFound)
     }
 }
+
+#[cfg(all(test, feature = "sys-thread"))]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use once_cell::sync::OnceCell;
+    use semver::Version;
+    use wasmer::Engine;
+
+    use super::*;
+    use crate::{
+        bin_factory::BinaryPackageCommand,
+        runtime::{
+            module_cache::{CacheError, LruCache, ModuleCache, ModuleHash},
+            task_manager::tokio::TokioTaskManager,
+        },
+        PluggableRuntime,
+    };
+
+    #[derive(Debug, Default)]
+    struct Counters {
+        current: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    /// A module cache that keeps track of how many modules are being loaded
+    /// at the same time.
+    #[derive(Debug)]
+    struct TrackingCache(Arc<Counters>);
+
+    #[async_trait::async_trait]
+    impl ModuleCache for TrackingCache {
+        async fn load(&self, _key: ModuleHash, engine: &Engine) -> Result<Module, CacheError> {
+            let current = self.0.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.0.peak.fetch_max(current, Ordering::SeqCst);
+
+            // Give the other spawns a chance to run
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let module = Module::new(
+                engine,
+                r#"(module (memory 1) (export "memory" (memory 0)) (func (export "_start")))"#,
+            )
+            .unwrap();
+
+            self.0.current.fetch_sub(1, Ordering::SeqCst);
+            Ok(module)
+        }
+
+        async fn save(
+            &self,
+            _key: ModuleHash,
+            _engine: &Engine,
+            _module: &Module,
+        ) -> Result<(), CacheError> {
+            Ok(())
+        }
+    }
+
+    fn binary() -> BinaryPackage {
+        BinaryPackage {
+            package_name: "test/limited".to_string(),
+            when_cached: None,
+            entrypoint_cmd: None,
+            hash: OnceCell::new(),
+            webc_fs: Arc::new(virtual_fs::mem_fs::FileSystem::default()),
+            commands: Vec::new(),
+            uses: Vec::new(),
+            version: Version::new(0, 1, 0),
+            module_memory_footprint: 0,
+            file_system_memory_footprint: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn spawns_are_limited() {
+        let counters = Arc::new(Counters::default());
+        let mut rt = PluggableRuntime::new(Arc::new(TokioTaskManager::new(
+            tokio::runtime::Handle::current(),
+        )));
+        rt.set_module_cache(TrackingCache(counters.clone()));
+        let runtime: Arc<dyn Runtime + Send + Sync> = Arc::new(rt);
+        let limiter = Arc::new(Semaphore::new(2));
+
+        let spawns = (0..8).map(|_| {
+            let runtime = runtime.clone();
+            let limiter = limiter.clone();
+            async move {
+                let init = WasiEnv::builder("limited")
+                    .runtime(runtime.clone())
+                    .build_init()
+                    .unwrap();
+                let env = WasiEnv::from_init(init).unwrap();
+                let store = runtime.new_store();
+                spawn_exec_limited(binary(), "limited", store, env, &runtime, limiter).await
+            }
+        });
+        let handles = futures::future::join_all(spawns).await;
+
+        for handle in handles {
+            handle.unwrap().wait_finished().await.unwrap();
+        }
+        assert_eq!(counters.peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn spawn_permits_are_held_until_the_process_exits() {
+        let counters = Arc::new(Counters::default());
+        let mut rt = PluggableRuntime::new(Arc::new(TokioTaskManager::new(
+            tokio::runtime::Handle::current(),
+        )));
+        rt.set_module_cache(TrackingCache(counters));
+        let runtime: Arc<dyn Runtime + Send + Sync> = Arc::new(rt);
+        let limiter = Arc::new(Semaphore::new(1));
+
+        let env = WasiEnv::builder("limited")
+            .runtime(runtime.clone())
+            .build()
+            .unwrap();
+        let store = runtime.new_store();
+        let mut handle =
+            spawn_exec_limited(binary(), "limited", store, env, &runtime, limiter.clone())
+                .await
+                .unwrap();
+        assert_eq!(limiter.available_permits(), 0);
+
+        handle.wait_finished().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while limiter.available_permits() == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn booting_twice_reuses_the_compiled_module() {
+        let cache = Arc::new(LruCache::new(4));
+        let mut rt = PluggableRuntime::new(Arc::new(TokioTaskManager::new(
+            tokio::runtime::Handle::current(),
+        )));
+        rt.set_module_cache(cache.clone());
+        let runtime: Arc<dyn Runtime + Send + Sync> = Arc::new(rt);
+
+        let atom = Bytes::from_static(
+            br#"(module (memory 1) (export "memory" (memory 0)) (func (export "_start")))"#,
+        );
+        let pkg = BinaryPackage {
+            entrypoint_cmd: Some("main".to_string()),
+            commands: vec![BinaryPackageCommand::new(
+                "main".to_string(),
+                webc::metadata::Command::default(),
+                atom.into(),
+            )],
+            ..binary()
+        };
+
+        for _ in 0..2 {
+            let init = WasiEnv::builder("main")
+                .runtime(runtime.clone())
+                .build_init()
+                .unwrap();
+            let env = WasiEnv::from_init(init).unwrap();
+            let store = runtime.new_store();
+            spawn_exec(pkg.clone(), "main", store, env, &runtime)
+                .await
+                .unwrap()
+                .wait_finished()
+                .await
+                .unwrap();
+        }
+
+        // Only the first boot had to compile the module
+        assert_eq!(cache.misses(), 1);
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -6,6 +6,13 @@ pub struct Capabilities {
     pub insecure_allow_all: bool,
     pub http_client: HttpClientCapabilityV1,
     pub threading: CapabilityThreadingV1,
+    pub filesystem: CapabilityFilesystemV1,
+    /// How much fuel the guest may burn before it is stopped with
+    /// [`WasiError::OutOfFuel`](crate::WasiError::OutOfFuel).
+    ///
+    /// This is only enforced for modules that were compiled with the
+    /// metering middleware. [`None`] means no limit.
+    pub fuel: Option<u64>,
 }
 
 impl Capabilities {
@@ -14,6 +21,8 @@ impl Capabilities {
             insecure_allow_all: false,
             http_client: Default::default(),
             threading: Default::default(),
+            filesystem: Default::default(),
+            fuel: None,
         }
     }
 
@@ -24,10 +33,14 @@ impl Capabilities {
             insecure_allow_all,
             http_client,
             threading,
+            filesystem,
+            fuel,
         } = other;
         self.insecure_allow_all |= insecure_allow_all;
         self.http_client.update(http_client);
         self.threading.update(threading);
+        self.filesystem.update(filesystem);
+        self.fuel = fuel.or(self.fuel);
     }
 }
 
@@ -48,6 +61,12 @@ pub struct CapabilityThreadingV1 {
     /// Flag that indicates if asynchronous threading is disabled
     /// (default = false)
     pub enable_asynchronous_threading: bool,
+
+    /// Prefix used to name the OS threads that run the guest, which are
+    /// then called `<prefix>-<tid>`.
+    ///
+    /// [`None`] leaves the naming up to the task manager.
+    pub thread_name_prefix: Option<String>,
 }
 
 impl CapabilityThreadingV1 {
@@ -55,8 +74,46 @@ impl CapabilityThreadingV1 {
         let CapabilityThreadingV1 {
             max_threads,
             enable_asynchronous_threading,
+            thread_name_prefix,
         } = other;
         self.enable_asynchronous_threading |= enable_asynchronous_threading;
         self.max_threads = max_threads.or(self.max_threads);
+        self.thread_name_prefix = thread_name_prefix.or(self.thread_name_prefix.take());
+    }
+}
+
+/// Defines which file system operations the guest may perform, regardless
+/// of the rights of its preopens. Denied operations fail with
+/// [`Errno::Perm`](wasmer_wasix_types::wasi::Errno::Perm).
+#[derive(Debug, Clone)]
+pub struct CapabilityFilesystemV1 {
+    /// Flag that indicates if the guest may create symbolic links
+    /// (default = true)
+    pub allow_symlinks: bool,
+
+    /// Flag that indicates if the guest may create hard links
+    /// (default = true)
+    pub allow_hard_links: bool,
+}
+
+impl CapabilityFilesystemV1 {
+    /// Merges another policy into this one. An operation stays allowed only
+    /// if both of them allow it.
+    pub fn update(&mut self, other: CapabilityFilesystemV1) {
+        let CapabilityFilesystemV1 {
+            allow_symlinks,
+            allow_hard_links,
+        } = other;
+        self.allow_symlinks &= allow_symlinks;
+        self.allow_hard_links &= allow_hard_links;
+    }
+}
+
+impl Default for CapabilityFilesystemV1 {
+    fn default() -> Self {
+        Self {
+            allow_symlinks: true,
+            allow_hard_links: true,
+        }
     }
 }
@@ -0,0 +1,112 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use virtual_fs::{
+    FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir,
+    TmpFileSystem, VirtualFile,
+};
+
+/// The writable layer of an overlay, which copies a file from `lower` into
+/// `upper` the first time it is opened in a way that would modify it.
+///
+/// Use it as the primary of a [`virtual_fs::OverlayFileSystem`] that has
+/// `lower` as its secondary, so files that haven't been modified are still
+/// read straight from `lower`.
+#[derive(Debug)]
+pub(crate) struct CopyUpFileSystem {
+    upper: TmpFileSystem,
+    lower: Arc<dyn FileSystem + Send + Sync>,
+}
+
+impl CopyUpFileSystem {
+    pub(crate) fn new(upper: TmpFileSystem, lower: Arc<dyn FileSystem + Send + Sync>) -> Self {
+        Self { upper, lower }
+    }
+
+    fn copy_up(&self, path: &Path) -> Result<(), FsError> {
+        if self.upper.metadata(path).is_ok() {
+            return Ok(());
+        }
+        match self.lower.metadata(path) {
+            Ok(metadata) if metadata.is_file() => {}
+            _ => return Ok(()),
+        }
+
+        let mut parents: Vec<_> = path.ancestors().skip(1).collect();
+        parents.reverse();
+        for dir in parents.into_iter().filter(|dir| dir.parent().is_some()) {
+            match self.upper.create_dir(dir) {
+                Ok(()) | Err(FsError::AlreadyExists) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.upper.new_open_options_ext().insert_cow_arc_file_at(
+            path.to_path_buf(),
+            self.lower.clone(),
+            path.to_path_buf(),
+        )
+    }
+}
+
+impl FileSystem for CopyUpFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        self.upper.read_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), FsError> {
+        self.upper.create_dir(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<(), FsError> {
+        self.upper.remove_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), FsError> {
+        self.copy_up(from)?;
+        self.upper.rename(from, to)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.upper.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.upper.symlink_metadata(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf, FsError> {
+        self.upper.read_link(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), FsError> {
+        self.upper.remove_file(path)
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<(), FsError> {
+        self.copy_up(path)?;
+        self.upper.write_file(path, contents)
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+}
+
+impl FileOpener for CopyUpFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, FsError> {
+        if conf.would_mutate() && !conf.create_new() {
+            self.copy_up(path)?;
+        }
+
+        self.upper
+            .new_open_options()
+            .options(conf.clone())
+            .open(path)
+    }
+}
@@ -1,14 +1,14 @@
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::PathBuf,
-    sync::{atomic::AtomicU64, Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    sync::{atomic::AtomicU64, Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
 };
 
 #[cfg(feature = "enable-serde")]
 use serde_derive::{Deserialize, Serialize};
 use virtual_fs::{Pipe, VirtualFile};
-use wasmer_wasix_types::wasi::{Fd as WasiFd, Fdflags, Filestat, Rights};
+use wasmer_wasix_types::wasi::{Fd as WasiFd, Fdflags, Filestat, Filetype, Rights};
 
 use crate::net::socket::InodeSocket;
 
@@ -27,6 +27,10 @@ pub struct Fd {
     pub open_flags: u16,
     pub inode: InodeGuard,
     pub is_stdio: bool,
+    /// The directory listing that `fd_readdir()` hands out cookies for,
+    /// which is shared with the duplicates of this [`Fd`] like the offset.
+    #[cfg_attr(feature = "enable-serde", serde(skip))]
+    pub(crate) dir_listing: Arc<Mutex<DirListing>>,
 }
 
 impl Fd {
@@ -47,6 +51,79 @@ impl Fd {
     ///
     /// This permission is currently unused when deserializing.
     pub const CREATE: u16 = 16;
+    /// This [`Fd`] is closed when the process spawns or executes another
+    /// program, so it is not inherited by it.
+    pub const CLOSE_ON_EXEC: u16 = 32;
+
+    /// Whether this [`Fd`] is closed when spawning or executing another
+    /// program.
+    pub fn is_close_on_exec(&self) -> bool {
+        self.open_flags & Self::CLOSE_ON_EXEC != 0
+    }
+}
+
+/// A directory listing that is being handed out by `fd_readdir()`.
+///
+/// The cookies given to the guest are indices into the listing. Entries are
+/// only ever appended while a listing is in progress, so a cookie keeps
+/// pointing at the same entry even if the directory changes between calls.
+#[derive(Debug, Default)]
+pub(crate) struct DirListing {
+    entries: Vec<DirListingEntry>,
+}
+
+#[derive(Debug)]
+pub(crate) struct DirListingEntry {
+    pub name: String,
+    pub filetype: Filetype,
+    pub ino: u64,
+    /// The entry was removed from the directory after it was listed.
+    removed: bool,
+}
+
+impl DirListing {
+    /// Starts listing the directory from scratch.
+    pub(crate) fn restart(&mut self, entries: Vec<(String, Filetype, u64)>) {
+        self.entries.clear();
+        self.refresh(entries);
+    }
+
+    /// Brings the listing up to date with the current `entries` of the
+    /// directory without moving anything that was listed before. Entries
+    /// that went away are skipped from now on, and new ones are added to
+    /// the end.
+    pub(crate) fn refresh(&mut self, entries: Vec<(String, Filetype, u64)>) {
+        let current: HashSet<&str> = entries.iter().map(|(name, ..)| name.as_str()).collect();
+        for entry in &mut self.entries {
+            entry.removed = !current.contains(entry.name.as_str());
+        }
+
+        let known: HashSet<String> = self.entries.iter().map(|e| e.name.clone()).collect();
+        for (name, filetype, ino) in entries {
+            if !known.contains(&name) {
+                self.entries.push(DirListingEntry {
+                    name,
+                    filetype,
+                    ino,
+                    removed: false,
+                });
+            }
+        }
+    }
+
+    /// The entries after `cookie`, along with the cookie that comes after
+    /// each of them.
+    pub(crate) fn entries_from(
+        &self,
+        cookie: u64,
+    ) -> impl Iterator<Item = (u64, &DirListingEntry)> + '_ {
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(cookie as usize)
+            .filter(|(_, entry)| !entry.removed)
+            .map(|(index, entry)| (index as u64 + 1, entry))
+    }
 }
 
 /// A file that Wasi knows about that may or may not be open
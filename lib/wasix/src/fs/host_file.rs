@@ -0,0 +1,111 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use virtual_fs::{
+    FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir, VirtualFile,
+};
+
+/// A [`FileSystem`] that only gives access to a single file, so mounting it
+/// doesn't expose anything else that sits next to that file.
+#[derive(Debug)]
+pub(crate) struct SingleFileSystem {
+    inner: Arc<dyn FileSystem + Send + Sync>,
+    path: PathBuf,
+    read: bool,
+    write: bool,
+}
+
+impl SingleFileSystem {
+    pub(crate) fn new(
+        inner: Arc<dyn FileSystem + Send + Sync>,
+        path: PathBuf,
+        read: bool,
+        write: bool,
+    ) -> Self {
+        Self {
+            inner,
+            path,
+            read,
+            write,
+        }
+    }
+
+    fn check_path(&self, path: &Path) -> Result<(), FsError> {
+        if path == self.path {
+            Ok(())
+        } else {
+            Err(FsError::EntryNotFound)
+        }
+    }
+}
+
+impl FileSystem for SingleFileSystem {
+    fn read_dir(&self, _path: &Path) -> Result<ReadDir, FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn create_dir(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn remove_dir(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.check_path(path)?;
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.check_path(path)?;
+        self.inner.symlink_metadata(path)
+    }
+
+    fn read_link(&self, _path: &Path) -> Result<PathBuf, FsError> {
+        Err(FsError::EntryNotFound)
+    }
+
+    fn remove_file(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<(), FsError> {
+        self.check_path(path)?;
+        if !self.write {
+            return Err(FsError::PermissionDenied);
+        }
+        self.inner.write_file(path, contents)
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+}
+
+impl FileOpener for SingleFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, FsError> {
+        self.check_path(path)?;
+        if (conf.read() && !self.read) || (conf.would_mutate() && !self.write) {
+            return Err(FsError::PermissionDenied);
+        }
+        if conf.create_new() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        self.inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(path)
+    }
+}
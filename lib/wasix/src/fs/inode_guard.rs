@@ -587,6 +587,15 @@ impl VirtualFile for WasiStateFileGuard {
         }
     }
 
+    fn set_times(&mut self, atime: Option<u64>, mtime: Option<u64>) -> Result<(), FsError> {
+        let mut guard = self.lock_write();
+        if let Some(file) = guard.as_mut() {
+            file.set_times(atime, mtime)
+        } else {
+            Err(FsError::IOError)
+        }
+    }
+
     fn unlink(&mut self) -> Result<(), FsError> {
         let mut guard = self.lock_write();
         if let Some(file) = guard.as_mut() {
@@ -596,6 +605,33 @@ impl VirtualFile for WasiStateFileGuard {
         }
     }
 
+    fn shrink_to_fit(&mut self) -> Result<(), FsError> {
+        let mut guard = self.lock_write();
+        if let Some(file) = guard.as_mut() {
+            file.shrink_to_fit()
+        } else {
+            Err(FsError::IOError)
+        }
+    }
+
+    fn sync(&mut self) -> Result<(), FsError> {
+        let mut guard = self.lock_write();
+        if let Some(file) = guard.as_mut() {
+            file.sync()
+        } else {
+            Err(FsError::IOError)
+        }
+    }
+
+    fn sync_data(&mut self) -> Result<(), FsError> {
+        let mut guard = self.lock_write();
+        if let Some(file) = guard.as_mut() {
+            file.sync_data()
+        } else {
+            Err(FsError::IOError)
+        }
+    }
+
     fn is_open(&self) -> bool {
         let guard = self.lock_read();
         if let Some(file) = guard.as_ref() {
@@ -1,6 +1,9 @@
+mod copy_up;
 mod fd;
+mod host_file;
 mod inode_guard;
 mod notification;
+mod proc_fs;
 
 use std::{
     borrow::{Borrow, Cow},
@@ -28,14 +31,19 @@ use wasmer_wasix_types::{
     },
 };
 
+pub(crate) use self::copy_up::CopyUpFileSystem;
 pub use self::fd::{Fd, InodeVal, Kind};
+pub(crate) use self::host_file::SingleFileSystem;
 pub(crate) use self::inode_guard::{
     InodeValFilePollGuard, InodeValFilePollGuardJoin, InodeValFileReadGuard,
     InodeValFileWriteGuard, WasiStateFileGuard,
 };
 pub use self::notification::NotificationInner;
+pub use self::proc_fs::ProcFileSystem;
 use crate::syscalls::map_io_err;
-use crate::{bin_factory::BinaryPackage, state::PreopenedDir, ALL_RIGHTS};
+use crate::{
+    bin_factory::BinaryPackage, net::socket::InodeSocket, state::PreopenedDir, ALL_RIGHTS,
+};
 
 /// the fd value of the virtual root
 pub const VIRTUAL_ROOT_FD: WasiFd = 3;
@@ -277,7 +285,7 @@ impl WasiFsRoot {
     ) -> Result<(), virtual_fs::FsError> {
         match self {
             WasiFsRoot::Sandbox(fs) => {
-                fs.union(other);
+                fs.union_copy_on_write(other);
                 Ok(())
             }
             WasiFsRoot::Backing(fs) => {
@@ -325,12 +333,30 @@ impl FileSystem for WasiFsRoot {
             WasiFsRoot::Backing(fs) => fs.symlink_metadata(path),
         }
     }
+    fn read_link(&self, path: &Path) -> virtual_fs::Result<PathBuf> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.read_link(path),
+            WasiFsRoot::Backing(fs) => fs.read_link(path),
+        }
+    }
+    fn canonicalize(&self, path: &Path) -> virtual_fs::Result<PathBuf> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.canonicalize(path),
+            WasiFsRoot::Backing(fs) => fs.canonicalize(path),
+        }
+    }
     fn remove_file(&self, path: &Path) -> virtual_fs::Result<()> {
         match self {
             WasiFsRoot::Sandbox(fs) => fs.remove_file(path),
             WasiFsRoot::Backing(fs) => fs.remove_file(path),
         }
     }
+    fn write_file(&self, path: &Path, contents: &[u8]) -> virtual_fs::Result<()> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.write_file(path, contents),
+            WasiFsRoot::Backing(fs) => fs.write_file(path, contents),
+        }
+    }
     fn new_open_options(&self) -> OpenOptions {
         match self {
             WasiFsRoot::Sandbox(fs) => fs.new_open_options(),
@@ -417,6 +443,8 @@ fn create_dir_all(fs: &dyn FileSystem, path: &Path) -> Result<(), virtual_fs::Fs
 pub struct WasiFs {
     //pub repo: Repo,
     pub preopen_fds: RwLock<Vec<u32>>,
+    /// The priority each preopened directory was mounted with.
+    pub preopen_priorities: RwLock<HashMap<u32, i32>>,
     pub name_map: HashMap<String, Inode>,
     pub fd_map: Arc<RwLock<HashMap<WasiFd, Fd>>>,
     pub next_fd: AtomicU32,
@@ -431,6 +459,12 @@ pub struct WasiFs {
     // but it shouldn't be necessary
     // It should not be necessary at all.
     is_wasix: AtomicBool,
+    // Whether the stdio file descriptors report themselves as a TTY
+    is_stdio_tty: AtomicBool,
+    // Whether newly opened file descriptors are closed on spawn and exec
+    close_on_exec_default: AtomicBool,
+    // The most file descriptors that can be open at the same time
+    max_open_files: AtomicU32,
 }
 
 impl WasiFs {
@@ -444,16 +478,83 @@ impl WasiFs {
         self.is_wasix.store(is_wasix, Ordering::SeqCst);
     }
 
+    /// Whether `stdin`, `stdout` and `stderr` are reported as a TTY.
+    pub fn is_stdio_tty(&self) -> bool {
+        self.is_stdio_tty.load(Ordering::Relaxed)
+    }
+
+    pub fn set_is_stdio_tty(&self, is_stdio_tty: bool) {
+        self.is_stdio_tty.store(is_stdio_tty, Ordering::SeqCst);
+
+        // Keep the stat of the stdio inodes in line with `fdstat`
+        let fd_map = self.fd_map.read().unwrap();
+        for fd in [
+            __WASI_STDIN_FILENO,
+            __WASI_STDOUT_FILENO,
+            __WASI_STDERR_FILENO,
+        ] {
+            if let Some(fd) = fd_map.get(&fd) {
+                fd.inode.stat.write().unwrap().st_filetype = self.stdio_filetype();
+            }
+        }
+    }
+
+    /// Whether newly opened file descriptors are marked as
+    /// [`Fd::CLOSE_ON_EXEC`].
+    pub fn close_on_exec_default(&self) -> bool {
+        self.close_on_exec_default.load(Ordering::Relaxed)
+    }
+
+    pub fn set_close_on_exec_default(&self, close_on_exec: bool) {
+        self.close_on_exec_default
+            .store(close_on_exec, Ordering::SeqCst);
+    }
+
+    /// The most file descriptors that can be open at the same time,
+    /// including stdio and the preopened directories.
+    pub fn max_open_files(&self) -> u32 {
+        self.max_open_files.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_open_files(&self, max_open_files: u32) {
+        self.max_open_files.store(max_open_files, Ordering::SeqCst);
+    }
+
+    /// Fails with [`Errno::Mfile`] when no more file descriptors can be
+    /// opened.
+    fn check_max_open_files(&self) -> Result<(), Errno> {
+        if self.fd_map.read().unwrap().len() >= self.max_open_files() as usize {
+            return Err(Errno::Mfile);
+        }
+        Ok(())
+    }
+
+    /// The file type reported for the stdio file descriptors, `isatty()`
+    /// only returns true for character devices.
+    fn stdio_filetype(&self) -> Filetype {
+        if self.is_stdio_tty() {
+            Filetype::CharacterDevice
+        } else {
+            Filetype::Unknown
+        }
+    }
+
     /// Forking the WasiState is used when either fork or vfork is called
     pub fn fork(&self) -> Self {
         let fd_map = self.fd_map.read().unwrap().clone();
         Self {
             preopen_fds: RwLock::new(self.preopen_fds.read().unwrap().clone()),
+            preopen_priorities: RwLock::new(self.preopen_priorities.read().unwrap().clone()),
             name_map: self.name_map.clone(),
             fd_map: Arc::new(RwLock::new(fd_map)),
             next_fd: AtomicU32::new(self.next_fd.load(Ordering::SeqCst)),
             current_dir: Mutex::new(self.current_dir.lock().unwrap().clone()),
             is_wasix: AtomicBool::new(self.is_wasix.load(Ordering::Acquire)),
+            is_stdio_tty: AtomicBool::new(self.is_stdio_tty.load(Ordering::Acquire)),
+            close_on_exec_default: AtomicBool::new(
+                self.close_on_exec_default.load(Ordering::Acquire),
+            ),
+            max_open_files: AtomicU32::new(self.max_open_files.load(Ordering::Acquire)),
             root_fs: self.root_fs.clone(),
             root_inode: self.root_inode.clone(),
             has_unioned: Arc::new(Mutex::new(HashSet::new())),
@@ -501,7 +602,7 @@ impl WasiFs {
         let mut guard = self.has_unioned.lock().unwrap();
         if !guard.contains(&package_name) {
             guard.insert(package_name);
-            sandbox_fs.union(&binary.webc_fs);
+            sandbox_fs.union_copy_on_write(&binary.webc_fs);
         }
         true
     }
@@ -562,14 +663,35 @@ impl WasiFs {
             wasi_fs.preopen_fds.write().unwrap().push(fd);
         }
 
-        for PreopenedDir {
-            path,
-            alias,
-            read,
-            write,
-            create,
-        } in preopens
-        {
+        // When several preopens are mounted at the same place, only the one
+        // with the highest priority is kept (the builder rejects ties)
+        let mut winners: HashMap<String, &PreopenedDir> = HashMap::new();
+        for preopen in preopens {
+            let winner = winners.entry(preopen.mount_point()).or_insert(preopen);
+            if preopen.priority > winner.priority {
+                *winner = preopen;
+            }
+        }
+
+        for preopen in preopens {
+            let winner = winners[&preopen.mount_point()];
+            if !std::ptr::eq(winner, preopen) {
+                debug!(
+                    "Skipping preopen {} because it is shadowed by {}",
+                    preopen.path.to_string_lossy(),
+                    winner.path.to_string_lossy()
+                );
+                continue;
+            }
+            let PreopenedDir {
+                path,
+                alias,
+                read,
+                write,
+                create,
+                priority,
+                ..
+            } = preopen;
             debug!(
                 "Attempting to preopen {} with alias {:?}",
                 &path.to_string_lossy(),
@@ -665,26 +787,96 @@ impl WasiFs {
                 .create_fd(rights, rights, Fdflags::empty(), fd_flags, inode.clone())
                 .map_err(|e| format!("Could not open fd for file {:?}: {}", path, e))?;
             {
-                let mut guard = root_inode.write();
-                if let Kind::Root { entries } = guard.deref_mut() {
-                    let key = if let Some(alias) = &alias {
-                        alias.clone()
-                    } else {
-                        path.to_string_lossy().into_owned()
-                    };
-                    let existing_entry = entries.insert(key.clone(), inode);
-                    if existing_entry.is_some() {
-                        return Err(format!("Found duplicate entry for alias `{}`", key));
-                    }
-                    assert!(existing_entry.is_none())
+                let key = if let Some(alias) = &alias {
+                    alias.clone()
+                } else {
+                    path.to_string_lossy().into_owned()
+                };
+                // Aliases like `opt/app/data` are mounted below the root
+                let mut segments = match alias {
+                    Some(alias) => alias.split('/').filter(|s| !s.is_empty()).collect(),
+                    None => Vec::new(),
+                };
+                if segments.is_empty() {
+                    segments.push(key.as_str());
                 }
+                wasi_fs.mount_preopen(inodes, &root_inode, &segments, inode)?;
             }
             wasi_fs.preopen_fds.write().unwrap().push(fd);
+            wasi_fs
+                .preopen_priorities
+                .write()
+                .unwrap()
+                .insert(fd, *priority);
         }
 
         Ok(wasi_fs)
     }
 
+    /// Puts a preopened directory at the path made up of `segments` below
+    /// the root, creating the virtual directories in between when there is
+    /// more than one segment.
+    ///
+    /// The virtual directories only contain the preopened directories that
+    /// were mounted into them, just like the root.
+    fn mount_preopen(
+        &self,
+        inodes: &WasiInodes,
+        root_inode: &InodeGuard,
+        segments: &[&str],
+        inode: InodeGuard,
+    ) -> Result<(), String> {
+        let (name, parents) = segments
+            .split_last()
+            .expect("a preopen is mounted at one or more segments");
+
+        let mut parent = root_inode.clone();
+        let mut path = PathBuf::from("/");
+        for segment in parents {
+            path.push(segment);
+            let next = {
+                let mut guard = parent.write();
+                let entries = match guard.deref_mut() {
+                    Kind::Root { entries } | Kind::Dir { entries, .. } => entries,
+                    _ => return Err(format!("Unable to mount a preopen inside `{}`", segment)),
+                };
+                entries
+                    .entry(segment.to_string())
+                    .or_insert_with(|| {
+                        let stat = Filestat {
+                            st_filetype: Filetype::Directory,
+                            ..Filestat::default()
+                        };
+                        let kind = Kind::Dir {
+                            parent: parent.downgrade(),
+                            path: path.clone(),
+                            entries: HashMap::new(),
+                        };
+                        self.create_inode_with_stat(
+                            inodes,
+                            kind,
+                            false,
+                            segment.to_string().into(),
+                            stat,
+                        )
+                    })
+                    .clone()
+            };
+            parent = next;
+        }
+
+        let mut guard = parent.write();
+        if let Kind::Root { entries } | Kind::Dir { entries, .. } = guard.deref_mut() {
+            if entries.insert(name.to_string(), inode).is_some() {
+                return Err(format!(
+                    "Found duplicate entry for alias `{}`",
+                    segments.join("/")
+                ));
+            }
+        }
+        Ok(())
+    }
+
     /// Converts a relative path into an absolute path
     pub(crate) fn relative_path_to_absolute(&self, mut path: String) -> String {
         if path.starts_with("./") {
@@ -718,11 +910,15 @@ impl WasiFs {
 
         let wasi_fs = Self {
             preopen_fds: RwLock::new(vec![]),
+            preopen_priorities: RwLock::new(HashMap::new()),
             name_map: HashMap::new(),
             fd_map: Arc::new(RwLock::new(HashMap::new())),
             next_fd: AtomicU32::new(3),
             current_dir: Mutex::new("/".to_string()),
             is_wasix: AtomicBool::new(false),
+            is_stdio_tty: AtomicBool::new(true),
+            close_on_exec_default: AtomicBool::new(false),
+            max_open_files: AtomicU32::new(u32::MAX),
             root_fs: fs_backing,
             root_inode: root_inode.clone(),
             has_unioned: Arc::new(Mutex::new(HashSet::new())),
@@ -903,6 +1099,29 @@ impl WasiFs {
         }
     }
 
+    /// Places `socket` at the file descriptor `fd`, which must not be in
+    /// use yet.
+    pub(crate) fn insert_socket_at(
+        &self,
+        inodes: &WasiInodes,
+        socket: InodeSocket,
+        fd: WasiFd,
+    ) -> Result<(), Errno> {
+        if self.fd_map.read().unwrap().contains_key(&fd) {
+            return Err(Errno::Exist);
+        }
+
+        let kind = Kind::Socket { socket };
+        let inode =
+            self.create_inode_with_default_stat(inodes, kind, false, "socket".to_string().into());
+        let rights = Rights::all_socket();
+        self.create_fd_ext(rights, rights, Fdflags::empty(), 0, inode, fd)?;
+
+        // Make sure the file descriptor isn't handed out a second time
+        self.next_fd.fetch_max(fd + 1, Ordering::SeqCst);
+        Ok(())
+    }
+
     /// Change the backing of a given file descriptor
     /// Returns the old backing
     /// TODO: add examples
@@ -1132,60 +1351,54 @@ impl WasiFs {
                                     relative_path: link_value,
                                 }
                             } else {
-                                #[cfg(unix)]
+                                let file_type: Filetype = if file_type.is_char_device() {
+                                    Filetype::CharacterDevice
+                                } else if file_type.is_block_device() {
+                                    Filetype::BlockDevice
+                                } else if file_type.is_fifo() {
+                                    // FIFO doesn't seem to fit any other type, so unknown
+                                    Filetype::Unknown
+                                } else if file_type.is_socket() {
+                                    // TODO: how do we know if it's a `SocketStream` or
+                                    // a `SocketDgram`?
+                                    Filetype::SocketStream
+                                } else {
+                                    unimplemented!("state::get_inode_at_path unknown file type: not file, directory, symlink, char device, block device, fifo, or socket");
+                                };
+
+                                let kind = Kind::File {
+                                    handle: None,
+                                    path: file.clone(),
+                                    fd: None,
+                                };
+                                drop(guard);
+                                let new_inode = self.create_inode_with_stat(
+                                    inodes,
+                                    kind,
+                                    false,
+                                    file.to_string_lossy().to_string().into(),
+                                    Filestat {
+                                        st_filetype: file_type,
+                                        ..Filestat::default()
+                                    },
+                                );
+
+                                let mut guard = cur_inode.write();
+                                if let Kind::Dir {
+                                    ref mut entries, ..
+                                } = guard.deref_mut()
                                 {
-                                    //use std::os::unix::fs::FileTypeExt;
-                                    let file_type: Filetype = if file_type.is_char_device() {
-                                        Filetype::CharacterDevice
-                                    } else if file_type.is_block_device() {
-                                        Filetype::BlockDevice
-                                    } else if file_type.is_fifo() {
-                                        // FIFO doesn't seem to fit any other type, so unknown
-                                        Filetype::Unknown
-                                    } else if file_type.is_socket() {
-                                        // TODO: how do we know if it's a `SocketStream` or
-                                        // a `SocketDgram`?
-                                        Filetype::SocketStream
-                                    } else {
-                                        unimplemented!("state::get_inode_at_path unknown file type: not file, directory, symlink, char device, block device, fifo, or socket");
-                                    };
-
-                                    let kind = Kind::File {
-                                        handle: None,
-                                        path: file.clone(),
-                                        fd: None,
-                                    };
-                                    drop(guard);
-                                    let new_inode = self.create_inode_with_stat(
-                                        inodes,
-                                        kind,
-                                        false,
-                                        file.to_string_lossy().to_string().into(),
-                                        Filestat {
-                                            st_filetype: file_type,
-                                            ..Filestat::default()
-                                        },
+                                    entries.insert(
+                                        component.as_os_str().to_string_lossy().to_string(),
+                                        new_inode.clone(),
+                                    );
+                                } else {
+                                    unreachable!(
+                                        "Attempted to insert special device into non-directory"
                                     );
-
-                                    let mut guard = cur_inode.write();
-                                    if let Kind::Dir {
-                                        ref mut entries, ..
-                                    } = guard.deref_mut()
-                                    {
-                                        entries.insert(
-                                            component.as_os_str().to_string_lossy().to_string(),
-                                            new_inode.clone(),
-                                        );
-                                    } else {
-                                        unreachable!(
-                                            "Attempted to insert special device into non-directory"
-                                        );
-                                    }
-                                    // perhaps just continue with symlink resolution and return at the end
-                                    return Ok(new_inode);
                                 }
-                                #[cfg(not(unix))]
-                                unimplemented!("state::get_inode_at_path unknown file type: not file, directory, or symlink");
+                                // perhaps just continue with symlink resolution and return at the end
+                                return Ok(new_inode);
                             };
                             drop(guard);
 
@@ -1453,7 +1666,7 @@ impl WasiFs {
         match fd {
             __WASI_STDIN_FILENO => {
                 return Ok(Fdstat {
-                    fs_filetype: Filetype::CharacterDevice,
+                    fs_filetype: self.stdio_filetype(),
                     fs_flags: Fdflags::empty(),
                     fs_rights_base: STDIN_DEFAULT_RIGHTS,
                     fs_rights_inheriting: Rights::empty(),
@@ -1461,7 +1674,7 @@ impl WasiFs {
             }
             __WASI_STDOUT_FILENO => {
                 return Ok(Fdstat {
-                    fs_filetype: Filetype::CharacterDevice,
+                    fs_filetype: self.stdio_filetype(),
                     fs_flags: Fdflags::APPEND,
                     fs_rights_base: STDOUT_DEFAULT_RIGHTS,
                     fs_rights_inheriting: Rights::empty(),
@@ -1469,7 +1682,7 @@ impl WasiFs {
             }
             __WASI_STDERR_FILENO => {
                 return Ok(Fdstat {
-                    fs_filetype: Filetype::CharacterDevice,
+                    fs_filetype: self.stdio_filetype(),
                     fs_flags: Fdflags::APPEND,
                     fs_rights_base: STDERR_DEFAULT_RIGHTS,
                     fs_rights_inheriting: Rights::empty(),
@@ -1543,7 +1756,7 @@ impl WasiFs {
                 .map_err(map_io_err)?,
             _ => {
                 let fd = self.get_fd(fd)?;
-                if fd.rights.contains(Rights::FD_DATASYNC) {
+                if !fd.rights.contains(Rights::FD_DATASYNC) {
                     return Err(Errno::Access);
                 }
 
@@ -1565,6 +1778,23 @@ impl WasiFs {
         Ok(())
     }
 
+    /// Makes sure the data written through a file descriptor has reached
+    /// durable storage, see [`VirtualFile::sync_data`].
+    ///
+    /// The file descriptor must have been flushed first.
+    pub fn sync_data(&self, fd: WasiFd) -> Result<(), Errno> {
+        let fd = self.get_fd(fd)?;
+        let guard = fd.inode.read();
+        if let Kind::File {
+            handle: Some(file), ..
+        } = guard.deref()
+        {
+            let mut file = file.write().unwrap();
+            file.sync_data().map_err(fs_error_into_wasi_err)?;
+        }
+        Ok(())
+    }
+
     /// Creates an inode and inserts it given a Kind and some extra data
     pub(crate) fn create_inode(
         &self,
@@ -1630,6 +1860,12 @@ impl WasiFs {
         open_flags: u16,
         inode: InodeGuard,
     ) -> Result<WasiFd, Errno> {
+        let open_flags = if self.close_on_exec_default() {
+            open_flags | Fd::CLOSE_ON_EXEC
+        } else {
+            open_flags
+        };
+        self.check_max_open_files()?;
         let idx = self.next_fd.fetch_add(1, Ordering::SeqCst);
         self.create_fd_ext(rights, rights_inheriting, flags, open_flags, inode, idx)?;
         Ok(idx)
@@ -1658,6 +1894,7 @@ impl WasiFs {
                 open_flags,
                 inode,
                 is_stdio,
+                dir_listing: Default::default(),
             },
         );
         Ok(())
@@ -1665,6 +1902,7 @@ impl WasiFs {
 
     pub fn clone_fd(&self, fd: WasiFd) -> Result<WasiFd, Errno> {
         let fd = self.get_fd(fd)?;
+        self.check_max_open_files()?;
         let idx = self.next_fd.fetch_add(1, Ordering::SeqCst);
         self.fd_map.write().unwrap().insert(
             idx,
@@ -1676,6 +1914,7 @@ impl WasiFs {
                 open_flags: fd.open_flags,
                 inode: fd.inode,
                 is_stdio: fd.is_stdio,
+                dir_listing: fd.dir_listing.clone(),
             },
         );
         Ok(idx)
@@ -1764,6 +2003,7 @@ impl WasiFs {
                 offset: Arc::new(AtomicU64::new(0)),
                 inode,
                 is_stdio: true,
+                dir_listing: Default::default(),
             },
         );
     }
@@ -1830,6 +2070,41 @@ impl WasiFs {
         })
     }
 
+    /// Marks (or unmarks) a file descriptor so that it is closed when the
+    /// process spawns or executes another program.
+    pub fn set_fd_close_on_exec(&self, fd: WasiFd, close_on_exec: bool) -> Result<(), Errno> {
+        let mut fd_map = self.fd_map.write().unwrap();
+        let fd = fd_map.get_mut(&fd).ok_or(Errno::Badf)?;
+        if close_on_exec {
+            fd.open_flags |= Fd::CLOSE_ON_EXEC;
+        } else {
+            fd.open_flags &= !Fd::CLOSE_ON_EXEC;
+        }
+        Ok(())
+    }
+
+    /// Closes all the file descriptors that are marked as
+    /// [`Fd::CLOSE_ON_EXEC`], except for stdio and the preopened directories.
+    pub(crate) fn close_cloexec_fds(&self) {
+        let close_fds = {
+            let preopen_fds = self.preopen_fds.read().unwrap();
+            let fd_map = self.fd_map.read().unwrap();
+            fd_map
+                .iter()
+                .filter(|(idx, fd)| {
+                    **idx > __WASI_STDERR_FILENO
+                        && !preopen_fds.contains(idx)
+                        && fd.is_close_on_exec()
+                })
+                .map(|(idx, _)| *idx)
+                .collect::<Vec<_>>()
+        };
+
+        for fd in close_fds {
+            let _ = self.close_fd(fd);
+        }
+    }
+
     /// Closes an open FD, handling all details such as FD being preopen
     pub(crate) fn close_fd(&self, fd: WasiFd) -> Result<(), Errno> {
         let mut fd_map = self.fd_map.write().unwrap();
@@ -1915,14 +2190,21 @@ impl FileSystem for FallbackFileSystem {
 }
 
 pub fn virtual_file_type_to_wasi_file_type(file_type: virtual_fs::FileType) -> Filetype {
-    // TODO: handle other file types
     if file_type.is_dir() {
         Filetype::Directory
-    } else if file_type.is_file() {
-        Filetype::RegularFile
     } else if file_type.is_symlink() {
         Filetype::SymbolicLink
+    } else if file_type.is_file() {
+        Filetype::RegularFile
+    } else if file_type.is_char_device() {
+        Filetype::CharacterDevice
+    } else if file_type.is_block_device() {
+        Filetype::BlockDevice
+    } else if file_type.is_socket() {
+        // TODO: how do we know if it's a `SocketStream` or a `SocketDgram`?
+        Filetype::SocketStream
     } else {
+        // FIFOs don't fit any of the WASI file types
         Filetype::Unknown
     }
 }
@@ -1949,6 +2231,10 @@ pub fn fs_error_from_wasi_err(err: Errno) -> FsError {
         Errno::Again => FsError::WouldBlock,
         Errno::Nospc => FsError::WriteZero,
         Errno::Notempty => FsError::DirectoryNotEmpty,
+        Errno::Loop => FsError::TooManySymlinks,
+        Errno::Rofs => FsError::ReadOnly,
+        Errno::Xdev => FsError::CrossDevice,
+        Errno::Isdir => FsError::IsADirectory,
         _ => FsError::UnknownError,
     }
 }
@@ -1978,7 +2264,41 @@ pub fn fs_error_into_wasi_err(fs_error: FsError) -> Errno {
         FsError::WouldBlock => Errno::Again,
         FsError::WriteZero => Errno::Nospc,
         FsError::DirectoryNotEmpty => Errno::Notempty,
+        FsError::TooManySymlinks => Errno::Loop,
         FsError::StorageFull => Errno::Overflow,
+        FsError::ReadOnly => Errno::Rofs,
+        FsError::CrossDevice => Errno::Xdev,
+        FsError::IsADirectory => Errno::Isdir,
         FsError::Lock | FsError::UnknownError => Errno::Io,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use virtual_fs::FileType;
+
+    use super::*;
+
+    #[test]
+    fn special_file_types_keep_their_wasi_filetype() {
+        let cases: [(fn(&mut FileType), Filetype); 7] = [
+            (|ft| ft.file = true, Filetype::RegularFile),
+            (|ft| ft.dir = true, Filetype::Directory),
+            (|ft| ft.symlink = true, Filetype::SymbolicLink),
+            (|ft| ft.char_device = true, Filetype::CharacterDevice),
+            (|ft| ft.block_device = true, Filetype::BlockDevice),
+            (|ft| ft.socket = true, Filetype::SocketStream),
+            (|ft| ft.fifo = true, Filetype::Unknown),
+        ];
+
+        for (set, expected) in cases {
+            let mut file_type = FileType::default();
+            set(&mut file_type);
+            assert_eq!(
+                virtual_file_type_to_wasi_file_type(file_type.clone()),
+                expected,
+                "{file_type:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use virtual_fs::{
+    mem_fs, FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir,
+    ReadOnlyFileSystem, VirtualFile,
+};
+
+use crate::state::WasiState;
+
+/// A synthetic, read-only file system that describes the running program,
+/// similar to `/proc` on Linux.
+///
+/// It serves `self/cmdline`, `self/environ` and `self/status`, and is meant
+/// to be mounted at `/proc` with
+/// [`WasiEnvBuilder::with_proc_fs()`][crate::WasiEnvBuilder::with_proc_fs].
+#[derive(Debug, Clone)]
+pub struct ProcFileSystem {
+    inner: ReadOnlyFileSystem,
+}
+
+impl ProcFileSystem {
+    /// Takes a snapshot of the arguments and environment of `state`.
+    pub(crate) fn new(state: &WasiState) -> Self {
+        let fs = mem_fs::FileSystem::default();
+        fs.create_dir(Path::new("/self")).unwrap();
+
+        let mut cmdline = Vec::new();
+        for arg in &state.args {
+            cmdline.extend_from_slice(arg.as_bytes());
+            cmdline.push(b'\0');
+        }
+
+        let mut environ = Vec::new();
+        for env in state.envs.lock().unwrap().iter() {
+            environ.extend_from_slice(env);
+            environ.push(b'\0');
+        }
+
+        let status = format!("Name:\t{}\nState:\tR (running)\n", state.program_name);
+
+        for (name, contents) in [
+            ("/self/cmdline", cmdline),
+            ("/self/environ", environ),
+            ("/self/status", status.into_bytes()),
+        ] {
+            fs.insert_ro_file(Path::new(name), Cow::Owned(contents))
+                .unwrap();
+        }
+
+        Self {
+            inner: ReadOnlyFileSystem::new(Arc::new(fs)),
+        }
+    }
+}
+
+impl FileSystem for ProcFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), FsError> {
+        self.inner.create_dir(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<(), FsError> {
+        self.inner.remove_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), FsError> {
+        self.inner.rename(from, to)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf, FsError> {
+        self.inner.read_link(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), FsError> {
+        self.inner.remove_file(path)
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<(), FsError> {
+        self.inner.write_file(path, contents)
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+}
+
+impl FileOpener for ProcFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, FsError> {
+        self.inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(path)
+    }
+}
@@ -80,7 +80,7 @@ pub use virtual_net::host::{
 use wasmer_wasix_types::wasi::{Errno, ExitCode};
 
 pub use crate::{
-    fs::{default_fs_backing, Fd, WasiFs, WasiInodes, VIRTUAL_ROOT_FD},
+    fs::{default_fs_backing, Fd, ProcFileSystem, WasiFs, WasiInodes, VIRTUAL_ROOT_FD},
     os::{
         task::{
             control_plane::WasiControlPlane,
@@ -117,6 +117,8 @@ pub enum WasiError {
     DeepSleep(DeepSleepWork),
     #[error("The WASI version could not be determined")]
     UnknownWasiVersion,
+    #[error("WASI ran out of fuel")]
+    OutOfFuel,
 }
 
 #[deny(unused, dead_code)]
@@ -169,6 +171,13 @@ pub enum SpawnError {
     UnknownError,
 }
 
+/// Error returned when a WASI program couldn't be run to completion.
+///
+/// A module that couldn't be instantiated (e.g. because of a missing
+/// import or a memory that doesn't match) is reported as
+/// [`WasiRuntimeError::Instantiation`], one that trapped while running as
+/// [`WasiRuntimeError::Runtime`] and one that exited with a non-zero code as
+/// [`WasiRuntimeError::Exit`].
 #[derive(thiserror::Error, Debug)]
 pub enum WasiRuntimeError {
     #[error("WASI state setup failed")]
@@ -185,6 +194,10 @@ pub enum WasiRuntimeError {
     Runtime(#[from] RuntimeError),
     #[error("Memory access error")]
     Thread(#[from] WasiThreadError),
+    #[error("WASI exited with code {0}")]
+    Exit(ExitCode),
+    #[error("Spawning the program failed")]
+    Spawn(#[from] SpawnError),
 }
 
 impl WasiRuntimeError {
@@ -192,7 +205,7 @@ impl WasiRuntimeError {
     ///
     /// Returns [`None`] if a general execution error ocurred.
     pub fn as_exit_code(&self) -> Option<ExitCode> {
-        if let WasiRuntimeError::Wasi(WasiError::Exit(code)) = self {
+        if let WasiRuntimeError::Exit(code) | WasiRuntimeError::Wasi(WasiError::Exit(code)) = self {
             Some(*code)
         } else if let WasiRuntimeError::Runtime(err) = self {
             if let Some(WasiError::Exit(code)) = err.downcast_ref() {
@@ -214,8 +227,10 @@ pub(crate) fn run_wasi_func(
 ) -> Result<Box<[wasmer::Value]>, WasiRuntimeError> {
     func.call(store, params).map_err(|err| {
         if let Some(_werr) = err.downcast_ref::<WasiError>() {
-            let werr = err.downcast::<WasiError>().unwrap();
-            WasiRuntimeError::Wasi(werr)
+            match err.downcast::<WasiError>().unwrap() {
+                WasiError::Exit(code) => WasiRuntimeError::Exit(code),
+                werr => WasiRuntimeError::Wasi(werr),
+            }
         } else {
             WasiRuntimeError::Runtime(err)
         }
@@ -227,7 +242,7 @@ pub(crate) fn run_wasi_func(
 /// This is usually called "_start" in WASI modules.
 /// The function will not receive arguments or return values.
 ///
-/// An exit code that is not 0 will be returned as a `WasiError::Exit`.
+/// An exit code that is not 0 will be returned as a [`WasiRuntimeError::Exit`].
 #[allow(clippy::result_large_err)]
 pub(crate) fn run_wasi_func_start(
     func: &wasmer::Function,
@@ -273,13 +288,18 @@ pub fn generate_import_object_from_env(
     ctx: &FunctionEnv<WasiEnv>,
     version: WasiVersion,
 ) -> Imports {
-    match version {
+    let imports = match version {
         WasiVersion::Snapshot0 => generate_import_object_snapshot0(store, ctx),
         WasiVersion::Snapshot1 | WasiVersion::Latest => {
             generate_import_object_snapshot1(store, ctx)
         }
         WasiVersion::Wasix32v1 => generate_import_object_wasix32_v1(store, ctx),
         WasiVersion::Wasix64v1 => generate_import_object_wasix64_v1(store, ctx),
+    };
+
+    match ctx.as_ref(store).state.syscall_metrics.clone() {
+        Some(metrics) => metrics.instrument_imports(store, ctx, imports),
+        None => imports,
     }
 }
 
@@ -658,11 +678,22 @@ fn import_object_for_all_wasi_versions(
     store: &mut impl AsStoreMut,
     env: &FunctionEnv<WasiEnv>,
 ) -> (Imports, ModuleInitializer) {
-    let exports_wasi_generic = wasi_exports_generic(store, env);
-    let exports_wasi_unstable = wasi_unstable_exports(store, env);
-    let exports_wasi_snapshot_preview1 = wasi_snapshot_preview1_exports(store, env);
-    let exports_wasix_32v1 = wasix_exports_32(store, env);
-    let exports_wasix_64v1 = wasix_exports_64(store, env);
+    let mut exports_wasi_generic = wasi_exports_generic(store, env);
+    let mut exports_wasi_unstable = wasi_unstable_exports(store, env);
+    let mut exports_wasi_snapshot_preview1 = wasi_snapshot_preview1_exports(store, env);
+    let mut exports_wasix_32v1 = wasix_exports_32(store, env);
+    let mut exports_wasix_64v1 = wasix_exports_64(store, env);
+
+    // Syscalls are only wrapped when they are counted so this costs nothing
+    // otherwise
+    if let Some(metrics) = env.as_ref(store).state.syscall_metrics.clone() {
+        exports_wasi_generic = metrics.instrument(store, env, exports_wasi_generic);
+        exports_wasi_unstable = metrics.instrument(store, env, exports_wasi_unstable);
+        exports_wasi_snapshot_preview1 =
+            metrics.instrument(store, env, exports_wasi_snapshot_preview1);
+        exports_wasix_32v1 = metrics.instrument(store, env, exports_wasix_32v1);
+        exports_wasix_64v1 = metrics.instrument(store, env, exports_wasix_64v1);
+    }
 
     // Allowed due to JS feature flag complications.
     #[allow(unused_mut)]
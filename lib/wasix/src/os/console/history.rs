@@ -0,0 +1,94 @@
+//! The commands that were run in a [`Console`](super::Console), kept in a
+//! file so they are still around for the next session.
+
+use std::{
+    collections::VecDeque,
+    io::SeekFrom,
+    sync::{Arc, Mutex},
+};
+
+use virtual_fs::{ArcBoxFile, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, VirtualFile};
+
+/// How many commands are remembered unless told otherwise.
+pub(crate) const DEFAULT_HISTORY_LIMIT: usize = 1000;
+
+/// The command history of a console, with one command per line in the
+/// history file.
+///
+/// Clones share the same commands, so the history can be saved from a
+/// task that waits for the session to exit.
+#[derive(Debug, Clone)]
+pub(crate) struct CommandHistory {
+    file: ArcBoxFile,
+    commands: Arc<Mutex<VecDeque<String>>>,
+    limit: usize,
+}
+
+impl CommandHistory {
+    pub(crate) fn new(file: Box<dyn VirtualFile + Send + Sync + 'static>, limit: usize) -> Self {
+        Self {
+            file: ArcBoxFile::new(file),
+            commands: Default::default(),
+            limit,
+        }
+    }
+
+    pub(crate) fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.truncate();
+    }
+
+    /// The commands from oldest to newest.
+    pub(crate) fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Replaces the commands with the ones in the history file, which has
+    /// every command of the earlier sessions once they have exited.
+    pub(crate) async fn load(&self) -> std::io::Result<()> {
+        let mut file = self.file.clone();
+        file.seek(SeekFrom::Start(0)).await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+
+        *self.commands.lock().unwrap() = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.to_string())
+            .collect();
+        self.truncate();
+        Ok(())
+    }
+
+    /// Remembers a command that was run.
+    pub(crate) fn push(&self, command: &str) {
+        self.commands
+            .lock()
+            .unwrap()
+            .push_back(command.trim().to_string());
+        self.truncate();
+    }
+
+    /// Replaces the contents of the history file with the commands.
+    pub(crate) async fn save(&self) -> std::io::Result<()> {
+        let mut contents = String::new();
+        for command in self.commands.lock().unwrap().iter() {
+            contents.push_str(command);
+            contents.push('\n');
+        }
+
+        let mut file = self.file.clone();
+        file.seek(SeekFrom::Start(0)).await?;
+        file.set_len(0)?;
+        file.write_all(contents.as_bytes()).await?;
+        file.flush().await
+    }
+
+    /// Forgets the oldest commands beyond the limit.
+    fn truncate(&self) {
+        let mut commands = self.commands.lock().unwrap();
+        while commands.len() > self.limit {
+            commands.pop_front();
+        }
+    }
+}
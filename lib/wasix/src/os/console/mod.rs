@@ -2,37 +2,94 @@
 #![allow(dead_code)]
 
 pub mod cconst;
+mod history;
 
 use std::{
     collections::HashMap,
     io::Write,
     ops::{Deref, DerefMut},
-    path::Path,
-    sync::{atomic::AtomicBool, Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
+use bytes::Bytes;
 use derivative::*;
+use futures::Stream;
 use linked_hash_set::LinkedHashSet;
 use tokio::sync::{mpsc, RwLock};
 #[allow(unused_imports, dead_code)]
 use tracing::{debug, error, info, trace, warn};
 use virtual_fs::{
-    ArcBoxFile, ArcFile, AsyncWriteExt, CombineFile, DeviceFile, DuplexPipe, FileSystem, Pipe,
-    PipeRx, PipeTx, RootFileSystemBuilder, VirtualFile,
+    ArcBoxFile, ArcFile, AsyncWriteExt, CombineFile, CrlfFile, DeviceFile, DisconnectTolerantFile,
+    DuplexPipe, FileSystem, MeteredFile, MeteredFileStats, Pipe, PipeRx, PipeTx,
+    RootFileSystemBuilder, TmpFileSystem, VirtualFile,
 };
+use virtual_net::DynVirtualNetworking;
 #[cfg(feature = "sys")]
 use wasmer::Engine;
-use wasmer_wasix_types::{types::__WASI_STDIN_FILENO, wasi::Errno};
+use wasmer_wasix_types::{
+    types::__WASI_STDIN_FILENO,
+    wasi::{Errno, ExitCode, Signal},
+};
 
+use self::history::{CommandHistory, DEFAULT_HISTORY_LIMIT};
 use super::{cconst::ConsoleConst, common::*, task::TaskJoinHandle};
 use crate::{
     bin_factory::{spawn_exec, BinFactory, BinaryPackage},
     capabilities::Capabilities,
-    os::task::{control_plane::WasiControlPlane, process::WasiProcess},
-    runtime::resolver::PackageSpecifier,
-    Runtime, SpawnError, VirtualTaskManagerExt, WasiEnv,
+    http::DynHttpClient,
+    os::{
+        task::{
+            control_plane::WasiControlPlane, process::WasiProcess, resource_usage::ResourceUsage,
+        },
+        ConsoleRect, TtyBridge, WasiTtyState,
+    },
+    runtime::{
+        module_cache::ModuleCache,
+        package_loader::PackageLoader,
+        resolver::{PackageSpecifier, Source},
+        Clock, DefaultTty,
+    },
+    Runtime, SpawnError, VirtualTaskManager, VirtualTaskManagerExt, WasiEnv, WasiEnvInit,
 };
 
+/// The phases of a session started with [`Console::run`], as reported by
+/// [`Console::events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleEvent {
+    /// The welcome banner and the message of the day were written, which
+    /// happens before the package is looked up. Also sent when the welcome
+    /// banner was turned off.
+    WelcomeDrawn,
+    /// The package of the boot command is being looked up.
+    Resolving { webc: String },
+    /// The package of the boot command was found.
+    Resolved { package_name: String },
+    /// The boot command is running.
+    Started,
+    /// The boot command has finished.
+    Exited { exit_code: ExitCode },
+}
+
+/// The resources used by a session so far, as reported to the callback
+/// given to [`Console::with_resource_accounting`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceSample {
+    /// How long the session has been running for, according to the clock
+    /// of the runtime.
+    pub elapsed: Duration,
+    /// The CPU time and memory used by the process of the boot command.
+    pub usage: ResourceUsage,
+    /// Bytes the program has read from stdin.
+    pub bytes_read: u64,
+    /// Bytes the program has written to stdout and stderr.
+    pub bytes_written: u64,
+}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct Console {
@@ -44,14 +101,51 @@ pub struct Console {
     whitelabel: bool,
     token: Option<String>,
     no_welcome: bool,
+    show_motd: bool,
+    #[derivative(Debug = "ignore")]
+    motd_fn: Option<Box<dyn Fn() -> String + Send + Sync>>,
     prompt: String,
     env: HashMap<String, String>,
+    /// Unknown variables in the boot command are left as they are instead
+    /// of being expanded to nothing.
+    keep_unknown_vars: bool,
+    #[derivative(Debug = "ignore")]
+    init_files: HashMap<PathBuf, Bytes>,
     runtime: Arc<dyn Runtime + Send + Sync + 'static>,
     stdin: ArcBoxFile,
     stdout: ArcBoxFile,
     stderr: ArcBoxFile,
+    /// Set once the program tried to write to a closed `stderr`.
+    stderr_closed: Arc<AtomicBool>,
+    log_sink: Option<ArcBoxFile>,
     capabilities: Capabilities,
     memfs_memory_limiter: Option<virtual_fs::limiter::DynFsMemoryLimiter>,
+    idle_timeout: Option<Duration>,
+    shutdown_grace: Option<Duration>,
+    #[cfg(feature = "sys")]
+    engine: Option<Engine>,
+    allowed_commands: Option<Vec<String>>,
+    #[derivative(Debug = "ignore")]
+    exit_callback: Option<Box<dyn FnOnce(ExitCode) + Send>>,
+    #[derivative(Debug = "ignore")]
+    resource_accounting: Option<(Duration, Box<dyn FnMut(ResourceSample) + Send>)>,
+    window_size: Arc<Mutex<Option<ConsoleRect>>>,
+    #[derivative(Debug = "ignore")]
+    process: Option<WasiProcess>,
+    /// The file system of the running session, which is shared with the
+    /// programs started by [`Console::spawn_extra`].
+    #[derivative(Debug = "ignore")]
+    root_fs: Option<TmpFileSystem>,
+    #[derivative(Debug = "ignore")]
+    control_plane: Option<WasiControlPlane>,
+    /// Used by every session instead of a fresh control plane.
+    #[derivative(Debug = "ignore")]
+    shared_control_plane: Option<Arc<WasiControlPlane>>,
+    crlf_translation: bool,
+    history: Option<CommandHistory>,
+    history_limit: usize,
+    #[derivative(Debug = "ignore")]
+    event_senders: Vec<mpsc::UnboundedSender<ConsoleEvent>>,
 }
 
 impl Console {
@@ -72,14 +166,36 @@ impl Console {
             whitelabel: false,
             token: None,
             no_welcome: false,
+            show_motd: true,
+            motd_fn: None,
             env: HashMap::new(),
+            keep_unknown_vars: false,
+            init_files: HashMap::new(),
             runtime,
             prompt: "wasmer.sh".to_string(),
             stdin: ArcBoxFile::new(Box::new(Pipe::channel().0)),
             stdout: ArcBoxFile::new(Box::new(Pipe::channel().0)),
             stderr: ArcBoxFile::new(Box::new(Pipe::channel().0)),
+            stderr_closed: Arc::new(AtomicBool::new(false)),
+            log_sink: None,
             capabilities: Default::default(),
             memfs_memory_limiter: None,
+            idle_timeout: None,
+            shutdown_grace: None,
+            #[cfg(feature = "sys")]
+            engine: None,
+            allowed_commands: None,
+            exit_callback: None,
+            resource_accounting: None,
+            window_size: Arc::new(Mutex::new(None)),
+            process: None,
+            root_fs: None,
+            control_plane: None,
+            shared_control_plane: None,
+            crlf_translation: false,
+            history: None,
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            event_senders: Vec::new(),
         }
     }
 
@@ -105,6 +221,43 @@ impl Console {
         self
     }
 
+    /// Sets an environment variable for the program, replacing any previous
+    /// value.
+    ///
+    /// Unlike [`Console::with_env`] this works on a console that was already
+    /// built, e.g. to hand over a token that was only obtained afterwards.
+    /// It applies from the next [`Console::run`] onwards.
+    pub fn set_env(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.env.insert(key.into(), value.into());
+    }
+
+    /// Removes an environment variable so the program doesn't see it,
+    /// returning its previous value.
+    pub fn remove_env(&mut self, key: &str) -> Option<String> {
+        self.env.remove(key)
+    }
+
+    /// Whether variables in the boot command that aren't in the
+    /// environment are kept as they are (e.g. `$MISSING`) instead of being
+    /// expanded to nothing, which is the default.
+    ///
+    /// The boot command can refer to the environment with `$VAR` or
+    /// `${VAR}`, and `$$` stands for a literal `$`.
+    pub fn with_keep_unknown_vars(mut self, keep_unknown_vars: bool) -> Self {
+        self.keep_unknown_vars = keep_unknown_vars;
+        self
+    }
+
+    /// Writes these files into the filesystem of the session before the
+    /// boot command is started, creating their parent directories as
+    /// needed.
+    ///
+    /// This is handy for dropping dotfiles or configuration in place.
+    pub fn with_init_files(mut self, init_files: HashMap<PathBuf, Bytes>) -> Self {
+        self.init_files = init_files;
+        self
+    }
+
     pub fn with_user_agent(mut self, user_agent: &str) -> Self {
         self.is_mobile = is_mobile(user_agent);
         self.is_ssh = is_ssh(user_agent);
@@ -117,6 +270,23 @@ impl Console {
         self
     }
 
+    /// Sets a callback that produces a message-of-the-day, which is written
+    /// to stderr after the welcome banner and before the program starts.
+    ///
+    /// The callback is invoked every time the console is run so the message
+    /// can change between sessions.
+    pub fn with_motd_fn(mut self, motd_fn: Box<dyn Fn() -> String + Send + Sync>) -> Self {
+        self.motd_fn = Some(motd_fn);
+        self
+    }
+
+    /// Controls whether the message-of-the-day is shown. This is independent
+    /// of [`Console::with_no_welcome`] and of whitelabeling.
+    pub fn with_show_motd(mut self, show_motd: bool) -> Self {
+        self.show_motd = show_motd;
+        self
+    }
+
     pub fn with_token(mut self, token: String) -> Self {
         self.token = Some(token);
         self
@@ -137,11 +307,34 @@ impl Console {
         self
     }
 
+    /// Expands every lone `\n` the program writes to stdout into `\r\n`
+    /// before it reaches the terminal.
+    pub fn with_crlf_translation(mut self, crlf_translation: bool) -> Self {
+        self.crlf_translation = crlf_translation;
+        self
+    }
+
     pub fn with_stderr(mut self, stderr: Box<dyn VirtualFile + Send + Sync + 'static>) -> Self {
         self.stderr = ArcBoxFile::new(stderr);
         self
     }
 
+    /// Whether the program tried to write to `stderr` after it was closed
+    /// (e.g. because the client disconnected).
+    ///
+    /// Those writes are discarded instead of failing, so the program can
+    /// keep running.
+    pub fn is_stderr_closed(&self) -> bool {
+        self.stderr_closed.load(Ordering::Acquire)
+    }
+
+    /// Sends the console's own error messages (e.g. when a package can't be
+    /// resolved) here instead of mixing them into the program's stderr.
+    pub fn with_log_sink(mut self, log_sink: Box<dyn VirtualFile + Send + Sync + 'static>) -> Self {
+        self.log_sink = Some(ArcBoxFile::new(log_sink));
+        self
+    }
+
     pub fn with_mem_fs_memory_limiter(
         mut self,
         limiter: virtual_fs::limiter::DynFsMemoryLimiter,
@@ -150,38 +343,330 @@ impl Console {
         self
     }
 
+    /// Terminates the session once nothing has been read from stdin or
+    /// written to stdout or stderr for the given amount of time.
+    ///
+    /// Any activity on the stdio streams resets the timer.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Gives the program this long to exit after [`Console::shutdown`] sent
+    /// it a `SIGTERM`, before it is killed with a `SIGKILL`.
+    pub fn with_shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = Some(grace);
+        self
+    }
+
+    /// Compiles and runs the boot command with this engine rather than the
+    /// one provided by the runtime.
+    ///
+    /// Compiled modules are cached per engine, so artifacts produced by a
+    /// different engine are never loaded into this one.
+    #[cfg(feature = "sys")]
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Only allows the console to boot the commands in this list.
+    ///
+    /// Entries match either the program name (`bash`) or the full package
+    /// (`sharrattj/bash`), and an entry ending in `*` matches any command
+    /// starting with what comes before it. Other commands are rejected
+    /// before anything is fetched from the registry.
+    pub fn with_allowed_commands(mut self, allowed_commands: Vec<String>) -> Self {
+        self.allowed_commands = Some(allowed_commands);
+        self
+    }
+
+    /// Invokes the callback with the exit code of the boot command once it
+    /// has finished, which is handy for releasing resources that are tied
+    /// to the session.
+    ///
+    /// The callback only fires for the next session that is successfully
+    /// started with [`Console::run`].
+    pub fn with_exit_callback(mut self, callback: Box<dyn FnOnce(ExitCode) + Send>) -> Self {
+        self.exit_callback = Some(callback);
+        self
+    }
+
+    /// Invokes the callback every `interval` with the resources the boot
+    /// command has used so far, until it exits.
+    ///
+    /// Like [`Console::with_exit_callback`], the callback only applies to
+    /// the next session that is successfully started with [`Console::run`].
+    pub fn with_resource_accounting(
+        mut self,
+        interval: Duration,
+        callback: Box<dyn FnMut(ResourceSample) + Send>,
+    ) -> Self {
+        self.resource_accounting = Some((interval, callback));
+        self
+    }
+
+    /// Runs every session in the given control plane, so they share its
+    /// process table and thread limits.
+    ///
+    /// By default each [`Console::run`] gets a control plane of its own,
+    /// which keeps one session from using up the threads of another.
+    ///
+    /// A session whose thread limit or deterministic scheduling differs
+    /// from the one of the shared control plane fails to start.
+    pub fn with_shared_control_plane(mut self, control_plane: Arc<WasiControlPlane>) -> Self {
+        self.shared_control_plane = Some(control_plane);
+        self
+    }
+
+    /// Keeps the commands that are run in this file, one per line.
+    ///
+    /// The commands of earlier sessions are loaded from the file when the
+    /// console is started, and the file is updated with the new ones once
+    /// the session has exited. Only the most recent commands are kept, see
+    /// [`Console::with_history_limit`].
+    pub fn with_history_file(mut self, file: Box<dyn VirtualFile + Send + Sync + 'static>) -> Self {
+        self.history = Some(CommandHistory::new(file, self.history_limit));
+        self
+    }
+
+    /// How many commands are kept in the history (1000 by default).
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        if let Some(history) = &mut self.history {
+            history.set_limit(limit);
+        }
+        self
+    }
+
+    /// The commands in the history, from oldest to newest.
+    pub fn history(&self) -> Vec<String> {
+        self.history
+            .as_ref()
+            .map(|history| history.commands())
+            .unwrap_or_default()
+    }
+
+    /// Reads the commands of earlier sessions from the history file.
+    async fn load_history(&self) {
+        if let Some(history) = &self.history {
+            if let Err(err) = history.load().await {
+                tracing::debug!("failed to load the command history - {}", err);
+            }
+        }
+    }
+
+    /// Reports the phases that the next sessions go through, from resolving
+    /// the boot command until it exits.
+    ///
+    /// The stream ends once the console was dropped and the last session
+    /// it started has exited.
+    pub fn events(&mut self) -> impl Stream<Item = ConsoleEvent> + Send + 'static {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.event_senders.push(tx);
+        futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        })
+    }
+
+    /// Hands the event to everyone who is still listening.
+    fn emit(&mut self, event: ConsoleEvent) {
+        self.event_senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    /// Puts a new session into the shared control plane, if there is one.
+    ///
+    /// The limits of the shared control plane apply to every session, so a
+    /// session that asks for different ones is refused.
+    fn apply_shared_control_plane(&self, env_init: &mut WasiEnvInit) -> Result<(), String> {
+        let shared = match &self.shared_control_plane {
+            Some(control_plane) => control_plane,
+            None => return Ok(()),
+        };
+
+        let session_config = env_init.control_plane.config();
+        let shared_config = shared.config();
+        if let Some(max_threads) = session_config.max_task_count {
+            if shared_config.max_task_count != Some(max_threads) {
+                return Err(format!(
+                    "the session is limited to {max_threads} threads but the shared control plane allows {}",
+                    shared_config
+                        .max_task_count
+                        .map_or_else(|| "any number".to_string(), |max| max.to_string()),
+                ));
+            }
+        }
+        if session_config.deterministic_scheduling.is_some()
+            && session_config.deterministic_scheduling != shared_config.deterministic_scheduling
+        {
+            return Err(
+                "the session is scheduled deterministically but the shared control plane is not scheduled the same way"
+                    .to_string(),
+            );
+        }
+
+        env_init.control_plane = WasiControlPlane::clone(shared);
+        Ok(())
+    }
+
+    /// Sets the size of the terminal window, which the program sees when it
+    /// queries the TTY.
+    ///
+    /// Unless a size is set, the program sees whatever the TTY of the
+    /// runtime reports.
+    pub fn set_window_size(&self, cols: u32, rows: u32) {
+        *self.window_size.lock().unwrap() = Some(ConsoleRect { cols, rows });
+    }
+
+    /// Changes the size of the terminal window and notifies the running
+    /// program with a `SIGWINCH`, e.g. when the SSH client was resized.
+    pub fn resize(&self, cols: u32, rows: u32) {
+        self.set_window_size(cols, rows);
+        if let Some(process) = &self.process {
+            process.signal_process(Signal::Sigwinch);
+        }
+    }
+
+    /// Terminates the running program.
+    ///
+    /// With a [grace period][Console::with_shutdown_grace] the program is
+    /// sent a `SIGTERM` first, and only killed if it is still running once
+    /// the grace period is over. Otherwise it is killed straight away.
+    pub async fn shutdown(&self) {
+        let process = match &self.process {
+            Some(process) => process.clone(),
+            None => return,
+        };
+        match self.shutdown_grace {
+            Some(grace) => {
+                let tasks = self.runtime.task_manager().clone();
+                terminate_gracefully(tasks, process, self.runtime.clock(), grace).await;
+            }
+            None => process.signal_process(Signal::Sigkill),
+        }
+    }
+
+    /// Wraps the runtime so the program sees the window size of the console.
+    fn runtime_with_tty(&self) -> Arc<dyn Runtime + Send + Sync + 'static> {
+        #[cfg(feature = "sys")]
+        let engine = self.engine.clone();
+        #[cfg(not(feature = "sys"))]
+        let engine = None;
+
+        Arc::new(ConsoleRuntime {
+            inner: self.runtime.clone(),
+            engine,
+            tty: ConsoleTty {
+                runtime: self.runtime.clone(),
+                fallback: DefaultTty::default(),
+                window_size: self.window_size.clone(),
+            },
+        })
+    }
+
+    /// The stdout that the program writes to, with newlines translated if
+    /// that was asked for.
+    fn translated_stdout(&self) -> ArcBoxFile {
+        if self.crlf_translation {
+            ArcBoxFile::new(Box::new(CrlfFile::new(Box::new(self.stdout.clone()))))
+        } else {
+            self.stdout.clone()
+        }
+    }
+
+    /// The stderr that the program writes to, which keeps accepting writes
+    /// after the other end was closed.
+    fn guest_stderr(&self, stderr: ArcBoxFile) -> DisconnectTolerantFile {
+        DisconnectTolerantFile::new(Box::new(stderr), self.stderr_closed.clone())
+    }
+
+    /// The boot command with the environment variables it refers to
+    /// filled in.
+    fn expanded_boot_cmd(&self) -> String {
+        expand_env_vars(&self.boot_cmd, &self.env, self.keep_unknown_vars)
+    }
+
+    fn is_command_allowed(&self, webc: &str, prog: &str) -> bool {
+        let allowed_commands = match &self.allowed_commands {
+            Some(allowed_commands) => allowed_commands,
+            None => return true,
+        };
+        allowed_commands.iter().any(|allowed| {
+            [webc, prog]
+                .iter()
+                .any(|cmd| match allowed.strip_suffix('*') {
+                    Some(prefix) => cmd.starts_with(prefix),
+                    None => cmd == allowed,
+                })
+        })
+    }
+
+    /// Creates the store the boot command will run in.
+    fn new_store(&self) -> wasmer::Store {
+        self.runtime_with_tty().new_store()
+    }
+
     pub fn run(&mut self) -> Result<(TaskJoinHandle, WasiProcess), SpawnError> {
+        let tasks = self.runtime.task_manager().clone();
+        tasks.block_on(self.run_async())
+    }
+
+    /// Like [`Console::run()`], but for callers that are already running
+    /// inside an async runtime (e.g. a web server), where blocking on the
+    /// runtime would not be allowed.
+    pub async fn run_async(&mut self) -> Result<(TaskJoinHandle, WasiProcess), SpawnError> {
+        self.load_history().await;
+
         // Extract the program name from the arguments
-        let empty_args: Vec<&[u8]> = Vec::new();
-        let (webc, prog, args) = match self.boot_cmd.split_once(' ') {
-            Some((webc, args)) => (
-                webc,
-                webc.split_once('/').map(|a| a.1).unwrap_or(webc),
-                args.split(' ').map(|a| a.as_bytes()).collect::<Vec<_>>(),
-            ),
-            None => (
-                self.boot_cmd.as_str(),
-                self.boot_cmd
-                    .split_once('/')
-                    .map(|a| a.1)
-                    .unwrap_or(self.boot_cmd.as_str()),
-                empty_args,
-            ),
-        };
+        let boot_cmd = self.expanded_boot_cmd();
+        let (webc, prog, args) = split_cmd(&boot_cmd);
         let envs = self.env.clone();
 
+        if !self.is_command_allowed(webc, prog) {
+            let mut log = self.log_sink();
+            virtual_fs::AsyncWriteExt::write_all(
+                &mut log,
+                format!("Error: the command `{webc}` is not allowed\r\n").as_bytes(),
+            )
+            .await
+            .ok();
+            tracing::debug!("refused to boot command - {}", webc);
+            return Err(SpawnError::BadRequest);
+        }
+        if let Some(history) = &self.history {
+            history.push(&self.boot_cmd);
+        }
+
         // Build a new store that will be passed to the threadimpo
-        let store = self.runtime.new_store();
+        let store = self.new_store();
+
+        // Keep track of the stdio activity so idle sessions can be reaped
+        // and the traffic can be accounted for
+        let mut activity = Vec::new();
+        let metered = self.idle_timeout.is_some() || self.resource_accounting.is_some();
+        let mut meter = |file: &ArcBoxFile| {
+            if !metered {
+                return file.clone();
+            }
+            let file = MeteredFile::new(Box::new(file.clone()));
+            activity.push(file.stats());
+            ArcBoxFile::new(Box::new(file))
+        };
+        let stdin = meter(&self.stdin);
+        let stdout = meter(&self.translated_stdout());
+        let stderr = self.guest_stderr(meter(&self.stderr));
 
         let root_fs = RootFileSystemBuilder::new()
             .with_tty(Box::new(CombineFile::new(
-                Box::new(self.stdout.clone()),
-                Box::new(self.stdin.clone()),
+                Box::new(stdout.clone()),
+                Box::new(stdin.clone()),
             )))
             .build();
+        self.root_fs = Some(root_fs.clone());
 
-        let env_init = WasiEnv::builder(prog)
-            .stdin(Box::new(self.stdin.clone()))
+        let mut env_init = WasiEnv::builder(prog)
+            .stdin(Box::new(stdin))
             .args(args.iter())
             .envs(envs.iter())
             .sandbox_fs(root_fs)
@@ -189,21 +674,33 @@ impl Console {
             .unwrap()
             .map_dir(".", "/")
             .unwrap()
-            .stdout(Box::new(self.stdout.clone()))
-            .stderr(Box::new(self.stderr.clone()))
-            .runtime(self.runtime.clone())
+            .stdout(Box::new(stdout))
+            .stderr(Box::new(stderr))
+            .runtime(self.runtime_with_tty())
             .capabilities(self.capabilities.clone())
             .build_init()
             // TODO: propagate better error
             .map_err(|_e| SpawnError::InternalError)?;
+        if let Err(reason) = self.apply_shared_control_plane(&mut env_init) {
+            let mut log = self.log_sink();
+            virtual_fs::AsyncWriteExt::write_all(
+                &mut log,
+                format!("Error: {reason}\r\n").as_bytes(),
+            )
+            .await
+            .ok();
+            tracing::debug!("refused to join the shared control plane - {}", reason);
+            return Err(SpawnError::BadRequest);
+        }
 
         // TODO: no unwrap!
         let env = WasiEnv::from_init(env_init).unwrap();
+        self.control_plane = Some(env.control_plane.clone());
 
         if let Some(limiter) = &self.memfs_memory_limiter {
             match &env.state.fs.root_fs {
                 crate::fs::WasiFsRoot::Sandbox(tmpfs) => {
-                    tmpfs.set_memory_limiter(limiter.clone());
+                    tmpfs.set_memory_limiter(env.process.fs_memory_tracker(Some(limiter.clone())));
                 }
                 crate::fs::WasiFsRoot::Backing(_) => {
                     tracing::error!("tried to set a tmpfs memory limiter on a backing fs");
@@ -212,12 +709,23 @@ impl Console {
             }
         }
 
+        if let Err((path, e)) = self.write_init_files(&env.state.fs.root_fs) {
+            let mut log = self.log_sink();
+            virtual_fs::AsyncWriteExt::write_all(
+                &mut log,
+                format!("Error: unable to write `{}` - {e}\r\n", path.display()).as_bytes(),
+            )
+            .await
+            .ok();
+            tracing::debug!("failed to write init file {} - {}", path.display(), e);
+            return Err(SpawnError::BadRequest);
+        }
+
         // TODO: this should not happen here...
-        // Display the welcome message
+        // Display the welcome message and the message-of-the-day
         let tasks = env.tasks().clone();
-        if !self.whitelabel && !self.no_welcome {
-            tasks.block_on(self.draw_welcome());
-        }
+        self.draw_banners().await;
+        self.emit(ConsoleEvent::WelcomeDrawn);
 
         let webc_ident: PackageSpecifier = match webc.parse() {
             Ok(ident) => ident,
@@ -227,59 +735,237 @@ impl Console {
             }
         };
 
-        let resolved_package =
-            tasks.block_on(BinaryPackage::from_registry(&webc_ident, env.runtime()));
+        self.emit(ConsoleEvent::Resolving {
+            webc: webc.to_string(),
+        });
+        let resolved_package = BinaryPackage::from_registry(&webc_ident, env.runtime()).await;
 
         let binary = match resolved_package {
             Ok(pkg) => pkg,
             Err(e) => {
-                let mut stderr = self.stderr.clone();
-                tasks.block_on(async {
-                    let mut buffer = Vec::new();
-                    writeln!(buffer, "Error: {e}").ok();
-                    let mut source = e.source();
-                    while let Some(s) = source {
-                        writeln!(buffer, "  Caused by: {s}").ok();
-                        source = s.source();
-                    }
-
-                    virtual_fs::AsyncWriteExt::write_all(&mut stderr, &buffer)
-                        .await
-                        .ok();
-                });
+                let mut log = self.log_sink();
+                let mut buffer = Vec::new();
+                writeln!(buffer, "Error: {e}").ok();
+                let mut source = e.source();
+                while let Some(s) = source {
+                    writeln!(buffer, "  Caused by: {s}").ok();
+                    source = s.source();
+                }
+
+                virtual_fs::AsyncWriteExt::write_all(&mut log, &buffer)
+                    .await
+                    .ok();
                 tracing::debug!("failed to get webc dependency - {}", webc);
                 return Err(SpawnError::NotFound);
             }
         };
+        self.emit(ConsoleEvent::Resolved {
+            package_name: binary.package_name.clone(),
+        });
 
         let wasi_process = env.process.clone();
+        self.process = Some(wasi_process.clone());
 
         // TODO: fetching dependencies should be moved to the builder!
         // TODO: the Console only makes sense in the context of SSH and the terminal.
         // We should make this just take a WasiBuilder and the console related configs
         // and not add so much custom logic in here.
-        if let Err(err) = env.uses(self.uses.clone()) {
-            let mut stderr = self.stderr.clone();
-            tasks.block_on(async {
-                virtual_fs::AsyncWriteExt::write_all(
-                    &mut stderr,
-                    format!("{}\r\n", err).as_bytes(),
-                )
+        if let Err(err) = env.uses_async(self.uses.clone()).await {
+            let mut log = self.log_sink();
+            virtual_fs::AsyncWriteExt::write_all(&mut log, format!("{}\r\n", err).as_bytes())
                 .await
                 .ok();
-            });
             tracing::debug!("failed to load used dependency - {}", err);
             return Err(SpawnError::BadRequest);
         }
 
         // Build the config
         // Run the binary
-        let process = tasks.block_on(spawn_exec(binary, prog, store, env, &self.runtime))?;
+        let process = spawn_exec(binary, prog, store, env, &self.runtime_with_tty()).await?;
+        self.emit(ConsoleEvent::Started);
+
+        if let Some(callback) = self.exit_callback.take() {
+            tasks
+                .runtime()
+                .spawn(call_on_exit(process.clone(), callback));
+        }
+
+        if let Some(history) = &self.history {
+            tasks
+                .runtime()
+                .spawn(save_history_on_exit(process.clone(), history.clone()));
+        }
+
+        if !self.event_senders.is_empty() {
+            tasks
+                .runtime()
+                .spawn(emit_on_exit(process.clone(), self.event_senders.clone()));
+        }
+
+        if let Some((interval, callback)) = self.resource_accounting.take() {
+            tasks.runtime().spawn(sample_resources(
+                tasks.clone(),
+                wasi_process.clone(),
+                self.runtime.clock(),
+                activity.clone(),
+                interval,
+                callback,
+            ));
+        }
+
+        if let Some(idle_timeout) = self.idle_timeout {
+            tasks.runtime().spawn(reap_when_idle(
+                tasks.clone(),
+                wasi_process.clone(),
+                self.runtime.clock(),
+                activity,
+                idle_timeout,
+            ));
+        }
 
         // Return the process
         Ok((process, wasi_process))
     }
 
+    /// Launches another program (e.g. a background daemon) in the session
+    /// that was started with [`Console::run`].
+    ///
+    /// The program shares the file system, the environment variables and
+    /// the stdout and stderr of the boot command, and its process lives in
+    /// the same control plane.
+    pub fn spawn_extra(&self, cmd: &str) -> Result<WasiProcess, SpawnError> {
+        let tasks = self.runtime.task_manager().clone();
+        tasks.block_on(self.spawn_extra_async(cmd))
+    }
+
+    /// Like [`Console::spawn_extra()`], but for callers that are already
+    /// running inside an async runtime.
+    pub async fn spawn_extra_async(&self, cmd: &str) -> Result<WasiProcess, SpawnError> {
+        let (webc, prog, args) = split_cmd(cmd);
+
+        if !self.is_command_allowed(webc, prog) {
+            let mut log = self.log_sink();
+            virtual_fs::AsyncWriteExt::write_all(
+                &mut log,
+                format!("Error: the command `{webc}` is not allowed\r\n").as_bytes(),
+            )
+            .await
+            .ok();
+            tracing::debug!("refused to spawn command - {}", webc);
+            return Err(SpawnError::BadRequest);
+        }
+
+        let webc_ident: PackageSpecifier = match webc.parse() {
+            Ok(ident) => ident,
+            Err(e) => {
+                tracing::debug!(webc, error = &*e, "Unable to parse the WEBC identifier");
+                return Err(SpawnError::BadRequest);
+            }
+        };
+
+        let env = WasiEnv::from_init(self.extra_env_init(prog, &args)?)
+            .map_err(|_e| SpawnError::InternalError)?;
+
+        let binary = match BinaryPackage::from_registry(&webc_ident, env.runtime()).await {
+            Ok(pkg) => pkg,
+            Err(e) => {
+                let mut log = self.log_sink();
+                virtual_fs::AsyncWriteExt::write_all(
+                    &mut log,
+                    format!("Error: {e}\r\n").as_bytes(),
+                )
+                .await
+                .ok();
+                tracing::debug!("failed to get webc dependency - {}", webc);
+                return Err(SpawnError::NotFound);
+            }
+        };
+
+        let store = self.new_store();
+        let process = env.process.clone();
+        spawn_exec(binary, prog, store, env, &self.runtime_with_tty()).await?;
+        // Saved together with the boot command once the session exits
+        if let Some(history) = &self.history {
+            history.push(cmd);
+        }
+        Ok(process)
+    }
+
+    /// Prepares the environment of a program started by
+    /// [`Console::spawn_extra`], which reuses the file system and the
+    /// control plane of the running session.
+    fn extra_env_init(&self, prog: &str, args: &[&[u8]]) -> Result<WasiEnvInit, SpawnError> {
+        let (root_fs, control_plane) = match (&self.root_fs, &self.control_plane) {
+            (Some(root_fs), Some(control_plane)) => (root_fs.clone(), control_plane.clone()),
+            _ => {
+                tracing::debug!("tried to spawn an extra program before the boot command");
+                return Err(SpawnError::BadRequest);
+            }
+        };
+
+        let mut env_init = WasiEnv::builder(prog)
+            .args(args.iter())
+            .envs(self.env.iter())
+            .sandbox_fs(root_fs)
+            .preopen_dir(Path::new("/"))
+            .unwrap()
+            .map_dir(".", "/")
+            .unwrap()
+            .stdout(Box::new(self.translated_stdout()))
+            .stderr(Box::new(self.guest_stderr(self.stderr.clone())))
+            .runtime(self.runtime_with_tty())
+            .capabilities(self.capabilities.clone())
+            .build_init()
+            .map_err(|_e| SpawnError::InternalError)?;
+        env_init.control_plane = control_plane;
+        Ok(env_init)
+    }
+
+    /// Writes the files given to [`Console::with_init_files`] into `fs`,
+    /// stopping at the first one that can't be written.
+    fn write_init_files(&self, fs: &dyn FileSystem) -> Result<(), (PathBuf, virtual_fs::FsError)> {
+        for (path, contents) in &self.init_files {
+            fs.write_file_ext(path, contents, true)
+                .map_err(|e| (path.clone(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Where the console's own error messages are written.
+    fn log_sink(&self) -> ArcBoxFile {
+        self.log_sink.clone().unwrap_or_else(|| self.stderr.clone())
+    }
+
+    /// Writes everything that is shown before the program starts, which
+    /// is the welcome banner followed by the message-of-the-day.
+    async fn draw_banners(&self) {
+        if !self.whitelabel && !self.no_welcome {
+            self.draw_welcome().await;
+        }
+        if self.show_motd {
+            self.draw_motd().await;
+        }
+    }
+
+    pub async fn draw_motd(&self) {
+        let motd = match &self.motd_fn {
+            Some(motd_fn) => motd_fn(),
+            None => return,
+        };
+        if motd.is_empty() {
+            return;
+        }
+        let mut data = motd.replace("\r\n", "\n").replace('\n', "\r\n");
+        if !data.ends_with("\r\n") {
+            data.push_str("\r\n");
+        }
+
+        let mut stderr = self.stderr.clone();
+        virtual_fs::AsyncWriteExt::write_all(&mut stderr, data.as_bytes())
+            .await
+            .ok();
+    }
+
     pub async fn draw_welcome(&self) {
         let welcome = match (self.is_mobile, self.is_ssh) {
             (true, _) => ConsoleConst::WELCOME_MEDIUM,
@@ -298,3 +984,1173 @@ impl Console {
             .ok();
     }
 }
+
+/// Replaces the `$VAR` and `${VAR}` references in a command with their
+/// values from `env`, and `$$` with a single `$`.
+///
+/// Variables that aren't in `env` expand to nothing, unless `keep_unknown`
+/// is set, in which case they are left as they are.
+fn expand_env_vars(cmd: &str, env: &HashMap<String, String>, keep_unknown: bool) -> String {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut expanded = String::with_capacity(cmd.len());
+    let mut rest = cmd;
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+
+        let (name, reference_len) = if after.starts_with('$') {
+            expanded.push('$');
+            rest = &after[1..];
+            continue;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = after.find(|c: char| !is_name(c)).unwrap_or(after.len());
+            (&after[..end], end)
+        };
+
+        // A `$` that doesn't start a reference is just a `$`
+        if name.is_empty() || !name.chars().all(is_name) {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+
+        match env.get(name) {
+            Some(value) => expanded.push_str(value),
+            None if keep_unknown => expanded.push_str(&rest[dollar..dollar + 1 + reference_len]),
+            None => {}
+        }
+        rest = &after[reference_len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Splits a command into the package, the name of the program and its
+/// arguments.
+fn split_cmd(cmd: &str) -> (&str, &str, Vec<&[u8]>) {
+    let (webc, args) = match cmd.split_once(' ') {
+        Some((webc, args)) => (webc, args.split(' ').map(|a| a.as_bytes()).collect()),
+        None => (cmd, Vec::new()),
+    };
+    let prog = webc.split_once('/').map(|a| a.1).unwrap_or(webc);
+    (webc, prog, args)
+}
+
+/// Waits for a task to finish and returns its exit code, even when it
+/// failed.
+async fn wait_for_exit_code(handle: &mut TaskJoinHandle) -> ExitCode {
+    match handle.wait_finished().await {
+        Ok(exit_code) => exit_code,
+        Err(err) => err.as_exit_code().unwrap_or_else(|| Errno::Noexec.into()),
+    }
+}
+
+/// Waits for a task to finish and hands its exit code to the callback.
+async fn call_on_exit(mut handle: TaskJoinHandle, callback: Box<dyn FnOnce(ExitCode) + Send>) {
+    let exit_code = wait_for_exit_code(&mut handle).await;
+    callback(exit_code);
+}
+
+/// Waits for the session to end and tells the listeners of
+/// [`Console::events`] how it exited.
+async fn emit_on_exit(
+    mut handle: TaskJoinHandle,
+    event_senders: Vec<mpsc::UnboundedSender<ConsoleEvent>>,
+) {
+    let exit_code = wait_for_exit_code(&mut handle).await;
+    for sender in event_senders {
+        sender.send(ConsoleEvent::Exited { exit_code }).ok();
+    }
+}
+
+/// Waits for the session to end and saves the commands that were run.
+async fn save_history_on_exit(mut handle: TaskJoinHandle, history: CommandHistory) {
+    handle.wait_finished().await.ok();
+    if let Err(err) = history.save().await {
+        tracing::debug!("failed to save the command history - {}", err);
+    }
+}
+
+/// Kills a process once there has been no activity on any of its stdio
+/// streams for `idle_timeout`.
+async fn reap_when_idle(
+    tasks: Arc<dyn VirtualTaskManager>,
+    process: WasiProcess,
+    clock: Arc<dyn Clock + Send + Sync>,
+    activity: Vec<MeteredFileStats>,
+    idle_timeout: Duration,
+) {
+    let total = || {
+        activity
+            .iter()
+            .map(|stats| stats.bytes_read() + stats.bytes_written())
+            .sum::<u64>()
+    };
+
+    // Check a few times per interval so the session is reaped close to
+    // the moment it has been idle for long enough
+    let tick = (idle_timeout / 4).max(Duration::from_millis(1));
+    let mut last = total();
+    let mut idle = Duration::ZERO;
+    loop {
+        clock.sleep(tasks.as_ref(), tick).await;
+        if process.try_join().is_some() {
+            return;
+        }
+
+        let now = total();
+        if now != last {
+            last = now;
+            idle = Duration::ZERO;
+            continue;
+        }
+
+        idle += tick;
+        if idle >= idle_timeout {
+            tracing::debug!(pid = %process.pid(), "terminating idle session");
+            process.signal_process(Signal::Sigkill);
+            return;
+        }
+    }
+}
+
+/// Reports the resources used by a process every `interval` until it has
+/// exited.
+async fn sample_resources(
+    tasks: Arc<dyn VirtualTaskManager>,
+    process: WasiProcess,
+    clock: Arc<dyn Clock + Send + Sync>,
+    activity: Vec<MeteredFileStats>,
+    interval: Duration,
+    mut callback: Box<dyn FnMut(ResourceSample) + Send>,
+) {
+    let interval = interval.max(Duration::from_millis(1));
+    let started = clock.monotonic();
+    loop {
+        clock.sleep(tasks.as_ref(), interval).await;
+        if process.try_join().is_some() {
+            return;
+        }
+
+        callback(ResourceSample {
+            elapsed: clock.monotonic().saturating_sub(started),
+            usage: process.resource_usage(),
+            bytes_read: activity.iter().map(|stats| stats.bytes_read()).sum(),
+            bytes_written: activity.iter().map(|stats| stats.bytes_written()).sum(),
+        });
+    }
+}
+
+/// Sends a process a `SIGTERM`, and kills it if it hasn't exited once
+/// `grace` has passed.
+async fn terminate_gracefully(
+    tasks: Arc<dyn VirtualTaskManager>,
+    process: WasiProcess,
+    clock: Arc<dyn Clock + Send + Sync>,
+    grace: Duration,
+) {
+    process.signal_process(Signal::Sigterm);
+
+    // Check a few times during the grace period so we stop waiting soon
+    // after the process has exited
+    let tick = (grace / 4).max(Duration::from_millis(1));
+    let mut waited = Duration::ZERO;
+    while waited < grace {
+        if process.try_join().is_some() {
+            return;
+        }
+        clock.sleep(tasks.as_ref(), tick).await;
+        waited += tick;
+    }
+
+    if process.try_join().is_none() {
+        tracing::debug!(pid = %process.pid(), "killing a process that ignored SIGTERM");
+        process.signal_process(Signal::Sigkill);
+    }
+}
+
+/// A [`Runtime`] that hands out the TTY and the engine of the console, and
+/// otherwise defers to the runtime the console was created with.
+#[derive(Debug)]
+struct ConsoleRuntime {
+    inner: Arc<dyn Runtime + Send + Sync + 'static>,
+    /// Set by [`Console::with_engine()`], so the processes and threads the
+    /// program spawns end up in stores of the same engine.
+    engine: Option<wasmer::Engine>,
+    tty: ConsoleTty,
+}
+
+impl Runtime for ConsoleRuntime {
+    fn networking(&self) -> &DynVirtualNetworking {
+        self.inner.networking()
+    }
+
+    fn task_manager(&self) -> &Arc<dyn VirtualTaskManager> {
+        self.inner.task_manager()
+    }
+
+    fn package_loader(&self) -> Arc<dyn PackageLoader + Send + Sync> {
+        self.inner.package_loader()
+    }
+
+    fn module_cache(&self) -> Arc<dyn ModuleCache + Send + Sync> {
+        self.inner.module_cache()
+    }
+
+    fn source(&self) -> Arc<dyn Source + Send + Sync> {
+        self.inner.source()
+    }
+
+    fn engine(&self) -> Option<wasmer::Engine> {
+        self.engine.clone().or_else(|| self.inner.engine())
+    }
+
+    fn new_store(&self) -> wasmer::Store {
+        #[cfg(feature = "sys")]
+        if let Some(engine) = &self.engine {
+            return wasmer::Store::new(engine.clone());
+        }
+        self.inner.new_store()
+    }
+
+    fn http_client(&self) -> Option<&DynHttpClient> {
+        self.inner.http_client()
+    }
+
+    fn tty(&self) -> Option<&(dyn TtyBridge + Send + Sync)> {
+        if self.inner.tty().is_none() && self.tty.window_size.lock().unwrap().is_none() {
+            return None;
+        }
+        Some(&self.tty)
+    }
+
+    fn clock(&self) -> Arc<dyn Clock + Send + Sync> {
+        self.inner.clock()
+    }
+
+    fn rewrite_specifier(&self, specifier: PackageSpecifier) -> PackageSpecifier {
+        self.inner.rewrite_specifier(specifier)
+    }
+}
+
+/// Reports the window size of the console on top of the TTY of the runtime.
+#[derive(Debug)]
+struct ConsoleTty {
+    runtime: Arc<dyn Runtime + Send + Sync + 'static>,
+    /// Used when the runtime doesn't have a TTY of its own.
+    fallback: DefaultTty,
+    window_size: Arc<Mutex<Option<ConsoleRect>>>,
+}
+
+impl ConsoleTty {
+    fn bridge(&self) -> &(dyn TtyBridge + Send + Sync) {
+        self.runtime.tty().unwrap_or(&self.fallback)
+    }
+}
+
+impl TtyBridge for ConsoleTty {
+    fn reset(&self) {
+        self.bridge().reset();
+    }
+
+    fn tty_get(&self) -> WasiTtyState {
+        let mut state = self.bridge().tty_get();
+        if let Some(rect) = self.window_size.lock().unwrap().as_ref() {
+            state.cols = rect.cols;
+            state.rows = rect.rows;
+        }
+        state
+    }
+
+    fn tty_set(&self, tty_state: WasiTtyState) {
+        self.bridge().tty_set(tty_state);
+    }
+}
+
+#[cfg(all(test, feature = "sys"))]
+mod tests {
+    use virtual_fs::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{
+        os::task::{control_plane::ControlPlaneConfig, signal::SignalDisposition},
+        runtime::{task_manager::tokio::TokioTaskManager, MockClock},
+        PluggableRuntime, WasiError,
+    };
+
+    fn console(stderr: Pipe) -> Console {
+        let rt = PluggableRuntime::new(Arc::new(TokioTaskManager::default()));
+        Console::new("sharrattj/bash", Arc::new(rt)).with_stderr(Box::new(stderr))
+    }
+
+    async fn read_all(mut rx: Pipe) -> String {
+        let mut buf = Vec::new();
+        rx.read_to_end(&mut buf).await.unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    /// Reads from the pipe until `needle` shows up, returning everything
+    /// that was read so far.
+    async fn read_until(rx: &mut Pipe, needle: &str) -> String {
+        let mut output = String::new();
+        let mut buf = [0u8; 1024];
+        while !output.contains(needle) {
+            let read = rx.read(&mut buf).await.unwrap();
+            assert_ne!(read, 0, "{needle:?} never showed up in {output:?}");
+            output.push_str(&String::from_utf8_lossy(&buf[..read]));
+        }
+        output
+    }
+
+    const DASH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../tests/integration/cli/tests/webc/dash-1.0.18-f0d13233-bcda-4cf1-9a23-3460bffaae2a.webc"
+    );
+    const COREUTILS: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../tests/integration/cli/tests/webc/coreutils-1.0.16-e27dbb4f-2ef2-4b44-b46a-ddd86497c6d7.webc"
+    );
+
+    /// A runtime that finds `sharrattj/dash` (and the coreutils it depends
+    /// on) without going to the registry.
+    fn dash_runtime() -> PluggableRuntime {
+        let mut source = crate::runtime::resolver::InMemorySource::new();
+        source.add_webc(DASH).unwrap();
+        source.add_webc(COREUTILS).unwrap();
+        let mut rt = PluggableRuntime::new(Arc::new(TokioTaskManager::default()));
+        rt.set_source(source);
+        rt
+    }
+
+    /// A console whose boot command runs `script` with dash.
+    fn dash_console(script: &str) -> Console {
+        dash_console_on(dash_runtime(), script)
+    }
+
+    /// Like [`dash_console`], but on a runtime that has been set up
+    /// beforehand.
+    fn dash_console_on(runtime: PluggableRuntime, script: &str) -> Console {
+        Console::new("sharrattj/dash /script.sh", Arc::new(runtime))
+            .with_uses(Vec::new())
+            .with_no_welcome(true)
+            .with_init_files(HashMap::from([(
+                PathBuf::from("/script.sh"),
+                Bytes::from(script.to_string()),
+            )]))
+    }
+
+    /// Runs the boot command of the console and waits for it to exit.
+    fn run_to_completion(console: &mut Console) -> ExitCode {
+        let (mut handle, _) = console.run().unwrap();
+        let tasks = console.runtime.task_manager().clone();
+        tasks.block_on(handle.wait_finished()).unwrap()
+    }
+
+    #[test]
+    fn motd_is_written_after_the_welcome_banner() {
+        // The banners and the program share the terminal
+        let (tx, mut rx) = Pipe::channel();
+        let mut console = dash_console("echo program output\n")
+            .with_no_welcome(false)
+            .with_stdout(Box::new(tx.clone()))
+            .with_stderr(Box::new(tx))
+            .with_motd_fn(Box::new(|| "maintenance at 10pm".to_string()));
+
+        let exit_code = run_to_completion(&mut console);
+        assert_eq!(exit_code.raw(), 0);
+
+        let tasks = console.runtime.task_manager().clone();
+        let output = tasks.block_on(read_until(&mut rx, "program output"));
+        let motd = output.find("maintenance at 10pm\r\n").unwrap();
+        let program = output.find("program output").unwrap();
+        assert!(output.starts_with(ConsoleConst::TERM_NO_WRAPAROUND));
+        assert!(motd < program);
+    }
+
+    #[tokio::test]
+    async fn motd_is_gated_separately_from_the_welcome() {
+        let (tx, rx) = Pipe::channel();
+        let no_welcome = console(tx)
+            .with_no_welcome(true)
+            .with_motd_fn(Box::new(|| "hello".to_string()));
+
+        no_welcome.draw_banners().await;
+        drop(no_welcome);
+        assert_eq!(read_all(rx).await, "hello\r\n");
+
+        let (tx, rx) = Pipe::channel();
+        let no_motd = console(tx)
+            .with_show_motd(false)
+            .with_motd_fn(Box::new(|| "hello".to_string()));
+
+        no_motd.draw_banners().await;
+        drop(no_motd);
+        assert!(!read_all(rx).await.contains("hello"));
+    }
+
+    #[tokio::test]
+    async fn crlf_translation_expands_newlines_on_stdout() {
+        let (tx, rx) = Pipe::channel();
+        let console = console(Pipe::channel().0)
+            .with_stdout(Box::new(tx))
+            .with_crlf_translation(true);
+
+        let mut stdout = console.translated_stdout();
+        stdout.write_all(b"a\nb\n").await.unwrap();
+        stdout.flush().await.unwrap();
+        drop(stdout);
+        drop(console);
+
+        assert_eq!(read_all(rx).await, "a\r\nb\r\n");
+    }
+
+    #[test]
+    fn only_allowed_commands_can_be_booted() {
+        let (tx, rx) = Pipe::channel();
+        let rt = PluggableRuntime::new(Arc::new(TokioTaskManager::default()));
+        let mut console = Console::new("sharrattj/bash", Arc::new(rt))
+            .with_stderr(Box::new(tx))
+            .with_allowed_commands(vec!["python".to_string()]);
+
+        assert!(console.is_command_allowed("wasmer/python", "python"));
+        assert!(!console.is_command_allowed("sharrattj/bash", "bash"));
+
+        assert!(matches!(console.run(), Err(SpawnError::BadRequest)));
+        drop(console);
+
+        let output = futures::executor::block_on(read_all(rx));
+        assert!(output.contains("`sharrattj/bash` is not allowed"));
+    }
+
+    #[test]
+    fn allowed_commands_can_be_prefixes() {
+        let (tx, _rx) = Pipe::channel();
+        let console = console(tx).with_allowed_commands(vec!["sharrattj/*".to_string()]);
+
+        assert!(console.is_command_allowed("sharrattj/bash", "bash"));
+        assert!(!console.is_command_allowed("wasmer/python", "python"));
+    }
+
+    #[tokio::test]
+    async fn run_async_does_not_block_the_runtime() {
+        let (tx, rx) = Pipe::channel();
+        let rt = PluggableRuntime::new(Arc::new(TokioTaskManager::default()));
+        let mut console = Console::new("sharrattj/ba$h", Arc::new(rt))
+            .with_stderr(Box::new(tx))
+            .with_no_welcome(true)
+            .with_motd_fn(Box::new(|| "hello".to_string()));
+
+        // Blocking on the runtime from in here would panic
+        let result = console.run_async().await;
+        assert!(matches!(result, Err(SpawnError::BadRequest)));
+        drop(console);
+
+        assert_eq!(read_all(rx).await, "hello\r\n");
+    }
+
+    #[tokio::test]
+    async fn resolution_errors_go_to_the_log_sink() {
+        let (stderr_tx, stderr_rx) = Pipe::channel();
+        let (log_tx, log_rx) = Pipe::channel();
+        let mut rt = PluggableRuntime::new(Arc::new(TokioTaskManager::default()));
+        // Nothing can be found in an empty source
+        rt.set_source(crate::runtime::resolver::MultiSource::new());
+        let mut console = Console::new("sharrattj/bash", Arc::new(rt))
+            .with_stderr(Box::new(stderr_tx))
+            .with_log_sink(Box::new(log_tx))
+            .with_no_welcome(true);
+
+        let result = console.run_async().await;
+        assert!(matches!(result, Err(SpawnError::NotFound)));
+        drop(console);
+
+        assert!(read_all(log_rx)
+            .await
+            .contains("Unable to find any packages"));
+        assert_eq!(read_all(stderr_rx).await, "");
+    }
+
+    #[test]
+    fn idle_sessions_are_reaped() {
+        let mut store = wasmer::Store::default();
+        let module = wasmer::Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+
+                (memory 1)
+                (export "memory" (memory 0))
+
+                (data (i32.const 200) "hi")
+
+                (func $main (export "_start")
+                    (local $i i32)
+
+                    ;; Print something and then go quiet
+                    (i32.store (i32.const 0) (i32.const 200))
+                    (i32.store (i32.const 4) (i32.const 2))
+                    (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+                    drop
+
+                    ;; Sleep 50ms at a time for 10 seconds (monotonic clock subscription)
+                    (i32.store8 (i32.const 72) (i32.const 0))
+                    (i32.store (i32.const 80) (i32.const 1))
+                    (i64.store (i32.const 88) (i64.const 50000000))
+                    (loop $sleep
+                        (call $poll_oneoff (i32.const 64) (i32.const 128) (i32.const 1) (i32.const 192))
+                        drop
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $sleep (i32.lt_u (local.get $i) (i32.const 200))))))
+            "#,
+        )
+        .unwrap();
+
+        let stdout = MeteredFile::new(Box::new(Pipe::channel().0));
+        let stats = stdout.stats();
+        let (instance, env) = WasiEnv::builder("idle")
+            .stdout(Box::new(stdout))
+            .instantiate(module, &mut store)
+            .unwrap();
+        let process = env.data(&store).process.clone();
+        let tasks = env.data(&store).tasks().clone();
+        let clock = env.data(&store).runtime().clock();
+
+        tasks.runtime().spawn(reap_when_idle(
+            tasks.clone(),
+            process,
+            clock,
+            vec![stats.clone()],
+            Duration::from_millis(200),
+        ));
+
+        let started = std::time::Instant::now();
+        let start = instance.exports.get_function("_start").unwrap();
+        let result = start.call(&mut store, &[]);
+
+        // The guest was killed long before it would have finished sleeping
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(stats.bytes_written(), 2);
+    }
+
+    #[test]
+    fn resource_samples_follow_the_runtime_clock_until_the_session_exits() {
+        use std::sync::mpsc::RecvTimeoutError;
+
+        let clock = MockClock::default();
+        let mut runtime = dash_runtime();
+        runtime.set_clock(clock.clone());
+        let interval = Duration::from_millis(10);
+
+        // The session waits for a line on stdin, so it keeps running until
+        // the test lets it exit
+        let (mut stdin_tx, stdin_rx) = Pipe::channel();
+        let (samples_tx, samples_rx) = std::sync::mpsc::channel();
+        let mut console = dash_console_on(runtime, "read line\n")
+            .with_stdin(Box::new(stdin_rx))
+            .with_resource_accounting(
+                interval,
+                Box::new(move |sample| {
+                    let _ = samples_tx.send(sample);
+                }),
+            );
+        let (mut handle, _) = console.run().unwrap();
+        let tasks = console.runtime.task_manager().clone();
+
+        // Nothing is sampled while the clock stands still
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(samples_rx.try_recv().is_err());
+
+        let next_sample = || loop {
+            clock.advance(interval);
+            match samples_rx.recv_timeout(Duration::from_millis(10)) {
+                Ok(sample) => break sample,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => panic!("sampling stopped early"),
+            }
+        };
+        let first = next_sample();
+        let second = next_sample();
+        assert!(first.elapsed >= interval);
+        assert!(second.elapsed > first.elapsed);
+        assert!(second.usage.peak_memory > 0);
+
+        tasks.block_on(stdin_tx.write_all(b"\n")).unwrap();
+        let exit_code = tasks.block_on(handle.wait_finished()).unwrap();
+        assert_eq!(exit_code.raw(), 0);
+
+        // The callback is dropped once sampling stops, and nothing is
+        // sampled after the session has exited
+        loop {
+            clock.advance(interval);
+            match samples_rx.recv_timeout(Duration::from_millis(10)) {
+                Ok(sample) => panic!("sampled after the session exited: {sample:?}"),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    #[test]
+    fn sigterm_is_followed_by_sigkill_after_the_grace_period() {
+        let mut store = wasmer::Store::default();
+        let module = wasmer::Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+
+                (memory 1)
+                (export "memory" (memory 0))
+
+                (func $main (export "_start")
+                    (local $i i32)
+
+                    ;; Sleep 50ms at a time for 10 seconds (monotonic clock subscription)
+                    (i32.store8 (i32.const 72) (i32.const 0))
+                    (i32.store (i32.const 80) (i32.const 1))
+                    (i64.store (i32.const 88) (i64.const 50000000))
+                    (loop $sleep
+                        (call $poll_oneoff (i32.const 64) (i32.const 128) (i32.const 1) (i32.const 192))
+                        drop
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $sleep (i32.lt_u (local.get $i) (i32.const 200))))))
+            "#,
+        )
+        .unwrap();
+
+        // The program doesn't react to SIGTERM at all
+        let (instance, env) = WasiEnv::builder("stubborn")
+            .with_signal_disposition(Signal::Sigterm, SignalDisposition::Ignore)
+            .instantiate(module, &mut store)
+            .unwrap();
+        let process = env.data(&store).process.clone();
+        let tasks = env.data(&store).tasks().clone();
+        let clock = env.data(&store).runtime().clock();
+
+        tasks.runtime().spawn(terminate_gracefully(
+            tasks.clone(),
+            process,
+            clock,
+            Duration::from_millis(300),
+        ));
+
+        let started = std::time::Instant::now();
+        let start = instance.exports.get_function("_start").unwrap();
+        let result = start.call(&mut store, &[]);
+
+        // It was killed once the grace period was over, long before it
+        // would have finished sleeping
+        assert!(result.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn exit_callback_gets_the_exit_code() {
+        let mut store = wasmer::Store::default();
+        let module = wasmer::Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (func (export "_start")
+                    (call $proc_exit (i32.const 3))))
+            "#,
+        )
+        .unwrap();
+        let (instance, env) = WasiEnv::builder("quick")
+            .instantiate(module, &mut store)
+            .unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tasks = env.data(&store).tasks().clone();
+        tasks.runtime().spawn(call_on_exit(
+            env.data(&store).thread.join_handle(),
+            Box::new(move |exit_code| tx.send(exit_code).unwrap()),
+        ));
+
+        let start = instance.exports.get_function("_start").unwrap();
+        let exit_code = match start
+            .call(&mut store, &[])
+            .unwrap_err()
+            .downcast::<WasiError>()
+        {
+            Ok(WasiError::Exit(exit_code)) => exit_code,
+            other => panic!("unexpected result: {other:?}"),
+        };
+        env.cleanup(&mut store, Some(exit_code));
+
+        let exit_code = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(exit_code.raw(), 3);
+    }
+
+    #[test]
+    fn env_vars_can_be_changed_after_construction() {
+        let mut store = wasmer::Store::default();
+        let module = wasmer::Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "environ_sizes_get" (func $environ_sizes_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "environ_get" (func $environ_get (param i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                ;; The size of the environment is written at offset 4, and
+                ;; the variables themselves at offset 1024
+                (func (export "_start")
+                    (call $environ_sizes_get (i32.const 0) (i32.const 4))
+                    drop
+                    (call $environ_get (i32.const 64) (i32.const 1024))
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        let (tx, _rx) = Pipe::channel();
+        let mut console = console(tx).with_env(HashMap::from([
+            ("TERM".to_string(), "xterm".to_string()),
+            ("API_TOKEN".to_string(), "placeholder".to_string()),
+        ]));
+        console.set_env("API_TOKEN", "resolved-later");
+        console.set_env("USER", "wasmer");
+        assert_eq!(console.remove_env("TERM"), Some("xterm".to_string()));
+
+        let (instance, _env) = WasiEnv::builder("env")
+            .envs(console.env.iter())
+            .instantiate(module, &mut store)
+            .unwrap();
+        let start = instance.exports.get_function("_start").unwrap();
+        start.call(&mut store, &[]).unwrap();
+
+        let memory = instance.exports.get_memory("memory").unwrap();
+        let view = memory.view(&store);
+        let mut len = [0u8; 4];
+        view.read(4, &mut len).unwrap();
+        let mut environ = vec![0u8; u32::from_le_bytes(len) as usize];
+        view.read(1024, &mut environ).unwrap();
+        let mut vars: Vec<_> = environ
+            .split(|b| *b == 0)
+            .filter(|var| !var.is_empty())
+            .map(|var| String::from_utf8(var.to_vec()).unwrap())
+            .collect();
+        vars.sort();
+        assert_eq!(vars, ["API_TOKEN=resolved-later", "USER=wasmer"]);
+    }
+
+    #[test]
+    fn init_files_are_seeded_before_the_program_runs() {
+        let mut store = wasmer::Store::default();
+        let module = wasmer::Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+                (memory 1)
+                (export "memory" (memory 0))
+
+                (data (i32.const 200) "root/.config/app.toml")
+
+                ;; Reads the file into memory at offset 300 and stores how
+                ;; many bytes were read at offset 16
+                (func (export "_start")
+                    (call $path_open (i32.const 3) (i32.const 0) (i32.const 200) (i32.const 21)
+                        (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0) (i32.const 0))
+                    drop
+                    (i32.store (i32.const 8) (i32.const 300))
+                    (i32.store (i32.const 12) (i32.const 100))
+                    (call $fd_read (i32.load (i32.const 0)) (i32.const 8) (i32.const 1) (i32.const 16))
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        let (tx, _rx) = Pipe::channel();
+        let contents = Bytes::from_static(b"theme = \"dark\"\n");
+        let console = console(tx).with_init_files(HashMap::from([(
+            PathBuf::from("/root/.config/app.toml"),
+            contents.clone(),
+        )]));
+        let root_fs = RootFileSystemBuilder::new().build();
+        console.write_init_files(&root_fs).unwrap();
+
+        let (instance, _env) = WasiEnv::builder("init-files")
+            .sandbox_fs(root_fs)
+            .preopen_dir("/")
+            .unwrap()
+            .instantiate(module, &mut store)
+            .unwrap();
+        let start = instance.exports.get_function("_start").unwrap();
+        start.call(&mut store, &[]).unwrap();
+
+        let memory = instance.exports.get_memory("memory").unwrap();
+        let view = memory.view(&store);
+        let mut len = [0u8; 4];
+        view.read(16, &mut len).unwrap();
+        let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+        view.read(300, &mut buf).unwrap();
+        assert_eq!(buf, contents);
+    }
+
+    #[test]
+    fn init_files_that_cant_be_written_are_reported() {
+        let (tx, _rx) = Pipe::channel();
+        let console = console(tx).with_init_files(HashMap::from([(
+            PathBuf::from("/dev/null/app.toml"),
+            Bytes::from_static(b""),
+        )]));
+        let root_fs = RootFileSystemBuilder::new().build();
+
+        let (path, _) = console.write_init_files(&root_fs).unwrap_err();
+        assert_eq!(path, Path::new("/dev/null/app.toml"));
+    }
+
+    #[test]
+    fn extra_programs_share_the_file_system_of_the_session() {
+        let (stdout_tx, mut stdout_rx) = Pipe::channel();
+        // The boot command leaves a message and a script that reads it
+        let mut console = dash_console(concat!(
+            "echo ping > /tmp/handoff.txt\n",
+            "echo 'read line < /tmp/handoff.txt; echo \"got $line\"' > /tmp/reader.sh\n",
+        ))
+        .with_stdout(Box::new(stdout_tx));
+        let tasks = console.runtime.task_manager().clone();
+
+        // There is no session to join before the boot command was started
+        assert!(matches!(
+            console.spawn_extra("sharrattj/dash /tmp/reader.sh"),
+            Err(SpawnError::BadRequest)
+        ));
+
+        let exit_code = run_to_completion(&mut console);
+        assert_eq!(exit_code.raw(), 0);
+
+        let extra = console
+            .spawn_extra("sharrattj/dash /tmp/reader.sh")
+            .unwrap();
+        let exit_code = tasks.block_on(extra.join()).unwrap();
+        assert_eq!(exit_code.raw(), 0);
+
+        tasks.block_on(read_until(&mut stdout_rx, "got ping"));
+    }
+
+    #[test]
+    fn writes_to_a_closed_stderr_do_not_stop_the_program() {
+        let (mut stdin_tx, stdin_rx) = Pipe::channel();
+        let (stdout_tx, mut stdout_rx) = Pipe::channel();
+        let (stderr_tx, mut stderr_rx) = Pipe::channel();
+        // The program waits for the client to go away before writing to
+        // stderr again
+        let mut console = dash_console(concat!(
+            "echo first >&2\n",
+            "read line\n",
+            "echo second >&2\n",
+            "echo third >&2\n",
+            "echo done\n",
+        ))
+        .with_stdin(Box::new(stdin_rx))
+        .with_stdout(Box::new(stdout_tx))
+        .with_stderr(Box::new(stderr_tx));
+        let tasks = console.runtime.task_manager().clone();
+
+        let (mut handle, _) = console.run().unwrap();
+        tasks.block_on(read_until(&mut stderr_rx, "first"));
+        drop(stderr_rx);
+        tasks.block_on(stdin_tx.write_all(b"go\n")).unwrap();
+
+        let exit_code = tasks.block_on(handle.wait_finished()).unwrap();
+        assert_eq!(exit_code.raw(), 0);
+        tasks.block_on(read_until(&mut stdout_rx, "done"));
+        assert!(console.is_stderr_closed());
+    }
+
+    #[cfg(feature = "sys")]
+    #[test]
+    fn console_uses_the_supplied_engine() {
+        let engine = Engine::from(wasmer::EngineBuilder::new(wasmer::Cranelift::default()));
+        let (tx, _rx) = Pipe::channel();
+        let console = console(tx).with_engine(engine.clone());
+
+        let mut store = console.new_store();
+        assert_eq!(store.engine().deterministic_id(), engine.deterministic_id());
+        // Whatever the program spawns gets a store of the same engine
+        let runtime = console.runtime_with_tty();
+        assert_eq!(
+            runtime.engine().unwrap().deterministic_id(),
+            engine.deterministic_id()
+        );
+        assert_eq!(
+            runtime.new_store().engine().deterministic_id(),
+            engine.deterministic_id()
+        );
+
+        let module = wasmer::Module::new(
+            &store,
+            r#"
+            (module
+                (memory 1)
+                (export "memory" (memory 0))
+                (func (export "_start")))
+            "#,
+        )
+        .unwrap();
+        let (instance, _env) = WasiEnv::builder("engine")
+            .instantiate(module, &mut store)
+            .unwrap();
+        let start = instance.exports.get_function("_start").unwrap();
+        start.call(&mut store, &[]).unwrap();
+    }
+
+    #[test]
+    fn resizing_notifies_the_program() {
+        let mut store = wasmer::Store::default();
+        let module = wasmer::Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasix_32v1" "tty_get" (func $tty_get (param i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (func (export "_start"))
+                ;; Writes the TTY state to the start of memory
+                (func (export "query")
+                    (call $tty_get (i32.const 0))
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        let (tx, _rx) = Pipe::channel();
+        let mut console = console(tx);
+        console.set_window_size(120, 40);
+
+        let (instance, env) = WasiEnv::builder("resize")
+            .runtime(console.runtime_with_tty())
+            .instantiate(module, &mut store)
+            .unwrap();
+        console.process = Some(env.data(&store).process.clone());
+        let query = instance.exports.get_function("query").unwrap();
+        let memory = instance.exports.get_memory("memory").unwrap();
+        let window_size = |store: &mut wasmer::Store| {
+            query.call(store, &[]).unwrap();
+            let mut buf = [0u8; 8];
+            memory.view(&*store).read(0, &mut buf).unwrap();
+            let cols = u32::from_le_bytes(buf[..4].try_into().unwrap());
+            let rows = u32::from_le_bytes(buf[4..].try_into().unwrap());
+            (cols, rows)
+        };
+
+        assert_eq!(window_size(&mut store), (120, 40));
+
+        console.resize(100, 30);
+        assert!(env.data(&store).thread.has_signal(&[Signal::Sigwinch]));
+        assert_eq!(window_size(&mut store), (100, 30));
+    }
+
+    #[test]
+    fn sessions_get_their_own_control_plane_unless_it_is_shared() {
+        // Each session waits for a line on stdin, so they are both still
+        // running while their control planes are looked at
+        let start = |shared: Option<&Arc<WasiControlPlane>>| {
+            let (stdin_tx, stdin_rx) = Pipe::channel();
+            let mut console = dash_console("read line\n").with_stdin(Box::new(stdin_rx));
+            if let Some(shared) = shared {
+                console = console.with_shared_control_plane(shared.clone());
+            }
+            let (handle, _) = console.run().unwrap();
+            (console, stdin_tx, handle)
+        };
+        let finish = |(console, mut stdin_tx, mut handle): (Console, Pipe, TaskJoinHandle)| {
+            let tasks = console.runtime.task_manager().clone();
+            tasks.block_on(stdin_tx.write_all(b"\n")).unwrap();
+            let exit_code = tasks.block_on(handle.wait_finished()).unwrap();
+            assert_eq!(exit_code.raw(), 0);
+        };
+
+        let first = start(None);
+        let second = start(None);
+        assert_eq!(
+            first.0.control_plane.as_ref().unwrap().active_task_count(),
+            1
+        );
+        assert_eq!(
+            second.0.control_plane.as_ref().unwrap().active_task_count(),
+            1
+        );
+        finish(first);
+        finish(second);
+
+        let shared = Arc::new(WasiControlPlane::default());
+        let first = start(Some(&shared));
+        let second = start(Some(&shared));
+        assert_eq!(shared.active_task_count(), 2);
+        assert_eq!(
+            first.0.control_plane.as_ref().unwrap().active_task_count(),
+            2
+        );
+        finish(first);
+        finish(second);
+    }
+
+    #[test]
+    fn sessions_with_other_thread_limits_cannot_share_a_control_plane() {
+        let shared = Arc::new(WasiControlPlane::new(ControlPlaneConfig {
+            max_task_count: Some(8),
+            ..ControlPlaneConfig::new()
+        }));
+        let mut capabilities = Capabilities::default();
+        capabilities.threading.max_threads = Some(4);
+        let (stderr_tx, mut stderr_rx) = Pipe::channel();
+        let mut console = dash_console("echo hi\n")
+            .with_capabilities(capabilities)
+            .with_stderr(Box::new(stderr_tx))
+            .with_shared_control_plane(shared.clone());
+
+        assert!(matches!(console.run(), Err(SpawnError::BadRequest)));
+        let tasks = console.runtime.task_manager().clone();
+        tasks.block_on(read_until(&mut stderr_rx, "limited to 4 threads"));
+        assert_eq!(shared.active_task_count(), 0);
+    }
+
+    #[test]
+    fn history_is_loaded_when_the_next_console_starts() {
+        let file = ArcBoxFile::new(Box::new(virtual_fs::BufferFile::default()));
+        let session = |script: &str| {
+            Console::new(
+                &format!("sharrattj/dash {script}"),
+                Arc::new(dash_runtime()),
+            )
+            .with_uses(Vec::new())
+            .with_no_welcome(true)
+            .with_init_files(HashMap::from([(
+                PathBuf::from(script),
+                Bytes::from_static(b"echo hello\n"),
+            )]))
+            .with_history_file(Box::new(file.clone()))
+        };
+        // The history is saved by a task that waits for the session to exit
+        let wait_until_saved = |console: &Console, count: usize| {
+            let tasks = console.runtime.task_manager().clone();
+            let saved = CommandHistory::new(Box::new(file.clone()), DEFAULT_HISTORY_LIMIT);
+            for _ in 0..500 {
+                tasks.block_on(saved.load()).unwrap();
+                if saved.commands().len() == count {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            panic!("the history was never saved: {:?}", saved.commands());
+        };
+
+        let mut first = session("/first.sh");
+        assert_eq!(run_to_completion(&mut first).raw(), 0);
+        wait_until_saved(&first, 1);
+
+        let mut next = session("/next.sh");
+        assert_eq!(run_to_completion(&mut next).raw(), 0);
+        assert_eq!(
+            next.history(),
+            ["sharrattj/dash /first.sh", "sharrattj/dash /next.sh"]
+        );
+        wait_until_saved(&next, 2);
+
+        // Only the most recent commands are kept
+        let mut capped = session("/capped.sh").with_history_limit(2);
+        assert_eq!(run_to_completion(&mut capped).raw(), 0);
+        assert_eq!(
+            capped.history(),
+            ["sharrattj/dash /next.sh", "sharrattj/dash /capped.sh"]
+        );
+    }
+
+    #[test]
+    fn boot_command_expands_environment_variables() {
+        let runtime = console(Pipe::channel().0).runtime;
+        let console = Console::new("$EDITOR file", runtime)
+            .with_env(HashMap::from([("EDITOR".to_string(), "vim".to_string())]));
+        let boot_cmd = console.expanded_boot_cmd();
+        let (webc, prog, args) = split_cmd(&boot_cmd);
+        assert_eq!(webc, "vim");
+        assert_eq!(prog, "vim");
+        assert_eq!(args, vec!["file".as_bytes()]);
+
+        let env = HashMap::from([("NAME".to_string(), "world".to_string())]);
+        assert_eq!(
+            expand_env_vars("echo ${NAME}s costs $$5", &env, false),
+            "echo worlds costs $5"
+        );
+        assert_eq!(expand_env_vars("echo $MISSING!", &env, false), "echo !");
+        assert_eq!(
+            expand_env_vars("echo $MISSING ${MISSING}", &env, true),
+            "echo $MISSING ${MISSING}"
+        );
+        assert_eq!(expand_env_vars("echo $ 1", &env, false), "echo $ 1");
+    }
+
+    #[tokio::test]
+    async fn events_stop_at_the_phase_that_failed() {
+        use futures::StreamExt;
+
+        let mut rt = PluggableRuntime::new(Arc::new(TokioTaskManager::default()));
+        // Nothing can be found in an empty source
+        rt.set_source(crate::runtime::resolver::MultiSource::new());
+        let mut console = Console::new("sharrattj/bash", Arc::new(rt))
+            .with_stderr(Box::new(Pipe::channel().0))
+            .with_no_welcome(true);
+        let events = console.events();
+
+        let result = console.run_async().await;
+        assert!(matches!(result, Err(SpawnError::NotFound)));
+        drop(console);
+
+        assert_eq!(
+            events.collect::<Vec<_>>().await,
+            [
+                ConsoleEvent::WelcomeDrawn,
+                ConsoleEvent::Resolving {
+                    webc: "sharrattj/bash".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn events_follow_a_session_from_start_to_exit() {
+        use futures::StreamExt;
+
+        let mut console = dash_console("echo hello\n").with_stdout(Box::new(Pipe::channel().0));
+        let events = console.events();
+        assert_eq!(run_to_completion(&mut console).raw(), 0);
+        let tasks = console.runtime.task_manager().clone();
+        drop(console);
+
+        assert_eq!(
+            tasks.block_on(events.collect::<Vec<_>>()),
+            [
+                ConsoleEvent::WelcomeDrawn,
+                ConsoleEvent::Resolving {
+                    webc: "sharrattj/dash".to_string()
+                },
+                ConsoleEvent::Resolved {
+                    package_name: "sharrattj/dash".to_string()
+                },
+                ConsoleEvent::Started,
+                ConsoleEvent::Exited {
+                    exit_code: ExitCode::from(0)
+                },
+            ]
+        );
+    }
+}
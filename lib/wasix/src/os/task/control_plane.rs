@@ -6,6 +6,7 @@ use std::{
     },
 };
 
+use super::scheduler::DeterministicScheduler;
 use crate::{WasiProcess, WasiProcessId};
 
 #[derive(Debug, Clone)]
@@ -41,6 +42,13 @@ pub struct ControlPlaneConfig {
     pub max_task_count: Option<usize>,
     /// Flag that indicates if asynchronous threading is enables (opt-in)
     pub enable_asynchronous_threading: bool,
+    /// Runs the threads one at a time, in an order picked using this seed,
+    /// instead of letting them run in parallel. See
+    /// [`DeterministicScheduler`].
+    ///
+    /// This is meant for tests that need multi-threaded programs to behave
+    /// the same way every time.
+    pub deterministic_scheduling: Option<u64>,
 }
 
 impl ControlPlaneConfig {
@@ -48,6 +56,7 @@ impl ControlPlaneConfig {
         Self {
             max_task_count: None,
             enable_asynchronous_threading: false,
+            deterministic_scheduling: None,
         }
     }
 }
@@ -65,6 +74,9 @@ struct State {
     /// Total number of active tasks (threads) across all processes.
     task_count: Arc<AtomicUsize>,
 
+    /// Decides which thread runs when scheduling deterministically.
+    scheduler: Option<Arc<DeterministicScheduler>>,
+
     /// Mutable state.
     mutable: RwLock<MutableState>,
 }
@@ -80,10 +92,14 @@ struct MutableState {
 
 impl WasiControlPlane {
     pub fn new(config: ControlPlaneConfig) -> Self {
+        let scheduler = config
+            .deterministic_scheduling
+            .map(|seed| Arc::new(DeterministicScheduler::new(seed)));
         Self {
             state: Arc::new(State {
                 config,
                 task_count: Arc::new(AtomicUsize::new(0)),
+                scheduler,
                 mutable: RwLock::new(MutableState {
                     process_seed: 0,
                     processes: Default::default(),
@@ -97,7 +113,7 @@ impl WasiControlPlane {
     }
 
     /// Get the current count of active tasks (threads).
-    fn active_task_count(&self) -> usize {
+    pub(crate) fn active_task_count(&self) -> usize {
         self.state.task_count.load(Ordering::SeqCst)
     }
 
@@ -106,6 +122,12 @@ impl WasiControlPlane {
         &self.state.config
     }
 
+    /// The scheduler that decides which thread runs, if threads are
+    /// scheduled deterministically.
+    pub(crate) fn scheduler(&self) -> Option<Arc<DeterministicScheduler>> {
+        self.state.scheduler.clone()
+    }
+
     /// Register a new task.
     ///
     // Currently just increments the task counter.
@@ -211,6 +233,7 @@ mod tests {
         let p = WasiControlPlane::new(ControlPlaneConfig {
             max_task_count: Some(2),
             enable_asynchronous_threading: false,
+            deterministic_scheduling: None,
         });
 
         let p1 = p.new_process().unwrap();
@@ -229,6 +252,7 @@ mod tests {
         let p = WasiControlPlane::new(ControlPlaneConfig {
             max_task_count: Some(2),
             enable_asynchronous_threading: false,
+            deterministic_scheduling: None,
         });
 
         let p1 = p.new_process().unwrap();
@@ -2,6 +2,8 @@
 
 pub mod control_plane;
 pub mod process;
+pub mod resource_usage;
+pub mod scheduler;
 pub mod signal;
 mod task_join_handle;
 pub mod thread;
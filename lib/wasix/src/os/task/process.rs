@@ -12,16 +12,17 @@ use std::{
 use tracing::trace;
 use wasmer_wasix_types::{
     types::Signal,
-    wasi::{Errno, ExitCode, Snapshot0Clockid},
+    wasi::{Errno, ExitCode},
 };
 
 use crate::{
-    os::task::signal::WasiSignalInterval, syscalls::platform_clock_time_get, WasiThread,
-    WasiThreadHandle, WasiThreadId,
+    os::task::signal::WasiSignalInterval, runtime::Clock, WasiThread, WasiThreadHandle,
+    WasiThreadId,
 };
 
 use super::{
     control_plane::{ControlPlaneError, WasiControlPlaneHandle},
+    resource_usage::{ResourceCounters, ResourceUsage},
     signal::{SignalDeliveryError, SignalHandlerAbi},
     task_join_handle::OwnedTaskStatus,
 };
@@ -90,6 +91,8 @@ pub struct WasiProcess {
     pub(crate) finished: Arc<OwnedTaskStatus>,
     /// Number of threads waiting for children to exit
     pub(crate) waiting: Arc<AtomicU32>,
+    /// Resources consumed by the process so far
+    pub(crate) usage: Arc<ResourceCounters>,
 }
 
 // TODO: fields should be private and only accessed via methods.
@@ -142,6 +145,7 @@ impl WasiProcess {
             })),
             finished: Arc::new(OwnedTaskStatus::default()),
             waiting: Arc::new(AtomicU32::new(0)),
+            usage: Arc::new(ResourceCounters::default()),
         }
     }
 
@@ -267,8 +271,15 @@ impl WasiProcess {
         }
     }
 
-    /// Signals one of the threads every interval
-    pub fn signal_interval(&self, signal: Signal, interval: Option<Duration>, repeat: bool) {
+    /// Signals one of the threads every interval, as measured by the
+    /// monotonic time of `clock`
+    pub fn signal_interval(
+        &self,
+        signal: Signal,
+        interval: Option<Duration>,
+        repeat: bool,
+        clock: &dyn Clock,
+    ) {
         let mut inner = self.inner.write().unwrap();
 
         let interval = match interval {
@@ -279,7 +290,7 @@ impl WasiProcess {
             Some(a) => a,
         };
 
-        let now = platform_clock_time_get(Snapshot0Clockid::Monotonic, 1_000_000).unwrap() as u128;
+        let now = clock.monotonic().as_nanos();
         inner.signal_intervals.insert(
             signal,
             WasiSignalInterval {
@@ -291,6 +302,21 @@ impl WasiProcess {
         );
     }
 
+    /// Returns the resources (CPU time, peak memory) this process has
+    /// consumed so far.
+    pub fn resource_usage(&self) -> ResourceUsage {
+        self.usage.usage()
+    }
+
+    /// Wraps a memory limiter for the in-memory file system so that the
+    /// memory it uses is reported by [`WasiProcess::resource_usage`].
+    pub fn fs_memory_tracker(
+        &self,
+        inner: Option<virtual_fs::limiter::DynFsMemoryLimiter>,
+    ) -> virtual_fs::limiter::DynFsMemoryLimiter {
+        self.usage.fs_memory_tracker(inner)
+    }
+
     /// Returns the number of active threads for this process
     pub fn active_threads(&self) -> u32 {
         let inner = self.inner.read().unwrap();
@@ -0,0 +1,141 @@
+//! Accounting of the resources consumed by a process.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use virtual_fs::{limiter::DynFsMemoryLimiter, limiter::FsMemoryLimiter, FsError};
+use wasmer_wasix_types::wasi::Snapshot0Clockid;
+
+use crate::syscalls::platform_clock_time_get;
+
+/// A snapshot of the resources a process has consumed so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// Time spent executing guest code, across all the threads of the process.
+    pub cpu_time: Duration,
+    /// Largest size of the linear memory that has been observed, in bytes.
+    pub peak_memory: u64,
+    /// Largest amount of memory used by the in-memory file system, in bytes.
+    ///
+    /// This is only tracked when the file system has been given a limiter
+    /// created by [`ResourceCounters::fs_memory_tracker`].
+    pub peak_fs_memory: u64,
+}
+
+/// Shared counters that are updated while a process runs.
+#[derive(Debug, Default)]
+pub struct ResourceCounters {
+    cpu_time_nanos: AtomicU64,
+    peak_memory: AtomicU64,
+    fs_memory: AtomicU64,
+    peak_fs_memory: AtomicU64,
+}
+
+impl ResourceCounters {
+    pub fn usage(&self) -> ResourceUsage {
+        ResourceUsage {
+            cpu_time: Duration::from_nanos(self.cpu_time_nanos.load(Ordering::Relaxed)),
+            peak_memory: self.peak_memory.load(Ordering::Relaxed),
+            peak_fs_memory: self.peak_fs_memory.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn add_cpu_time(&self, time: Duration) {
+        self.cpu_time_nanos
+            .fetch_add(time.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Records the current size of the linear memory.
+    pub(crate) fn record_memory(&self, size: u64) {
+        self.peak_memory.fetch_max(size, Ordering::Relaxed);
+    }
+
+    /// Starts measuring the time spent executing guest code, which is added
+    /// to the counters when the returned guard is dropped.
+    pub(crate) fn cpu_timer(self: &Arc<Self>) -> CpuTimer {
+        CpuTimer {
+            counters: self.clone(),
+            started: now(),
+        }
+    }
+
+    /// Wraps a file system memory limiter (if any) so the memory used by
+    /// the file system is accounted against this process.
+    pub fn fs_memory_tracker(
+        self: &Arc<Self>,
+        inner: Option<DynFsMemoryLimiter>,
+    ) -> DynFsMemoryLimiter {
+        Arc::new(FsMemoryTracker {
+            inner,
+            counters: self.clone(),
+        })
+    }
+}
+
+/// The CPU time used by the calling thread, so the time a guest spends
+/// blocked (e.g. sleeping or waiting for input) isn't counted.
+///
+/// Platforms without a thread CPU clock fall back to the monotonic clock.
+fn now() -> u64 {
+    let clock_id = if cfg!(unix) {
+        Snapshot0Clockid::ThreadCputimeId
+    } else {
+        Snapshot0Clockid::Monotonic
+    };
+    platform_clock_time_get(clock_id, 1_000).unwrap_or_default() as u64
+}
+
+/// Adds the CPU time the current thread used since it was created to the
+/// CPU time of a process, so it must be dropped on the thread that created
+/// it.
+pub(crate) struct CpuTimer {
+    counters: Arc<ResourceCounters>,
+    started: u64,
+}
+
+impl Drop for CpuTimer {
+    fn drop(&mut self) {
+        let elapsed = now().saturating_sub(self.started);
+        self.counters.add_cpu_time(Duration::from_nanos(elapsed));
+    }
+}
+
+#[derive(Debug)]
+struct FsMemoryTracker {
+    inner: Option<DynFsMemoryLimiter>,
+    counters: Arc<ResourceCounters>,
+}
+
+impl FsMemoryLimiter for FsMemoryTracker {
+    fn on_grow(&self, grown_bytes: usize) -> Result<(), FsError> {
+        if let Some(inner) = &self.inner {
+            inner.on_grow(grown_bytes)?;
+        }
+        let used = self
+            .counters
+            .fs_memory
+            .fetch_add(grown_bytes as u64, Ordering::Relaxed)
+            + grown_bytes as u64;
+        self.counters
+            .peak_fs_memory
+            .fetch_max(used, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn on_shrink(&self, shrunk_bytes: usize) {
+        if let Some(inner) = &self.inner {
+            inner.on_shrink(shrunk_bytes);
+        }
+        let _ =
+            self.counters
+                .fs_memory
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                    Some(used.saturating_sub(shrunk_bytes as u64))
+                });
+    }
+}
@@ -0,0 +1,208 @@
+//! Runs the threads of a guest one at a time, in an order decided by a
+//! seeded random number generator, so multi-threaded programs behave the
+//! same way every time they are run.
+
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::thread::WasiThreadId;
+
+/// Hands out turns to the threads of a guest.
+///
+/// Only the thread whose turn it is gets to run. It keeps running until it
+/// yields (`sched_yield()`), blocks or exits, at which point the next
+/// thread is picked from the ones waiting for their turn.
+///
+/// The order only depends on the seed as long as the threads coordinate
+/// by yielding. When a thread blocks (e.g. while sleeping or waiting on a
+/// futex), it rejoins the queue whenever the wait is over, which depends
+/// on timing.
+#[derive(Debug)]
+pub struct DeterministicScheduler {
+    state: Mutex<SchedulerState>,
+    turn_changed: Condvar,
+}
+
+#[derive(Debug)]
+struct SchedulerState {
+    rng: StdRng,
+    /// The thread whose turn it is.
+    running: Option<WasiThreadId>,
+    /// Threads waiting for their turn.
+    ready: BTreeSet<WasiThreadId>,
+}
+
+impl SchedulerState {
+    /// Gives the turn to one of the ready threads, if nobody has it.
+    fn pick_next(&mut self) {
+        if self.running.is_some() || self.ready.is_empty() {
+            return;
+        }
+        let index = self.rng.gen_range(0..self.ready.len());
+        let next = *self.ready.iter().nth(index).unwrap();
+        self.ready.remove(&next);
+        self.running = Some(next);
+    }
+}
+
+impl DeterministicScheduler {
+    pub fn new(seed: u64) -> Self {
+        DeterministicScheduler {
+            state: Mutex::new(SchedulerState {
+                rng: StdRng::seed_from_u64(seed),
+                running: None,
+                ready: BTreeSet::new(),
+            }),
+            turn_changed: Condvar::new(),
+        }
+    }
+
+    /// Queues up a thread that was just spawned by `parent`.
+    ///
+    /// This must be called by the parent before the thread starts, so the
+    /// thread is queued at the same point every time.
+    pub(crate) fn spawned(&self, parent: WasiThreadId, child: WasiThreadId) {
+        let mut state = self.state.lock().unwrap();
+        // The main thread implicitly has the first turn
+        state.running.get_or_insert(parent);
+        state.ready.insert(child);
+    }
+
+    /// Blocks until it's the turn of `tid`.
+    pub(crate) fn wait_for_turn(&self, tid: WasiThreadId) {
+        let mut state = self.state.lock().unwrap();
+        if state.running != Some(tid) {
+            state.ready.insert(tid);
+            state.pick_next();
+            self.turn_changed.notify_all();
+        }
+        self.wait(state, tid);
+    }
+
+    /// Gives up the turn of `tid` and waits until it comes around again.
+    pub(crate) fn yield_now(&self, tid: WasiThreadId) {
+        let mut state = self.state.lock().unwrap();
+        if state.running == Some(tid) {
+            state.running = None;
+        }
+        // Queue up again before anyone else is picked, so `tid` is always
+        // one of the candidates
+        state.ready.insert(tid);
+        state.pick_next();
+        self.turn_changed.notify_all();
+        self.wait(state, tid);
+    }
+
+    /// Passes the turn of `tid` on to the next thread, without queueing up
+    /// `tid` again (e.g. because it is about to block or exit).
+    pub(crate) fn release(&self, tid: WasiThreadId) {
+        let mut state = self.state.lock().unwrap();
+        state.ready.remove(&tid);
+        if state.running == Some(tid) {
+            state.running = None;
+        }
+        state.pick_next();
+        self.turn_changed.notify_all();
+    }
+
+    fn wait(&self, state: MutexGuard<'_, SchedulerState>, tid: WasiThreadId) {
+        let _state = self
+            .turn_changed
+            .wait_while(state, |state| state.running != Some(tid))
+            .unwrap();
+    }
+}
+
+/// Gives up the turn of a thread while it is blocked on something, so the
+/// other threads can run in the meantime, and waits for its turn to come
+/// around again when dropped.
+///
+/// Does nothing unless threads are scheduled deterministically.
+pub(crate) struct BlockedTurn {
+    scheduler: Option<Arc<DeterministicScheduler>>,
+    tid: WasiThreadId,
+    released: AtomicBool,
+}
+
+impl BlockedTurn {
+    pub(crate) fn new(scheduler: Option<Arc<DeterministicScheduler>>, tid: WasiThreadId) -> Self {
+        BlockedTurn {
+            scheduler,
+            tid,
+            released: AtomicBool::new(false),
+        }
+    }
+
+    /// Called when the thread would block.
+    pub(crate) fn release(&self) {
+        if let Some(scheduler) = &self.scheduler {
+            if !self.released.swap(true, Ordering::AcqRel) {
+                scheduler.release(self.tid);
+            }
+        }
+    }
+}
+
+impl Drop for BlockedTurn {
+    fn drop(&mut self) {
+        if let Some(scheduler) = &self.scheduler {
+            if *self.released.get_mut() {
+                scheduler.wait_for_turn(self.tid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn run(seed: u64) -> Vec<(u32, u32)> {
+        let scheduler = Arc::new(DeterministicScheduler::new(seed));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let main = WasiThreadId::from(1);
+        let threads: Vec<_> = (2..5)
+            .map(|id| {
+                let tid = WasiThreadId::from(id);
+                scheduler.spawned(main, tid);
+                let scheduler = scheduler.clone();
+                let order = order.clone();
+                std::thread::spawn(move || {
+                    scheduler.wait_for_turn(tid);
+                    for step in 0..5 {
+                        order.lock().unwrap().push((id, step));
+                        scheduler.yield_now(tid);
+                    }
+                    scheduler.release(tid);
+                })
+            })
+            .collect();
+        scheduler.release(main);
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let order = order.lock().unwrap().clone();
+        order
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_order() {
+        let first = run(42);
+
+        assert_eq!(first.len(), 15);
+        for _ in 0..5 {
+            assert_eq!(run(42), first);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use wasmer_wasix_types::types::Signal;
 
@@ -15,6 +15,41 @@ where
     fn signal(&self, signal: u8) -> Result<(), SignalDeliveryError>;
 }
 
+/// What happens when a signal reaches a program that hasn't registered a
+/// signal handler of its own.
+#[derive(Clone)]
+pub enum SignalDisposition {
+    /// The program exits, as if it was killed by the signal.
+    Terminate,
+    /// The signal is dropped and the program keeps running.
+    Ignore,
+    /// The signal is handed to the host and the program keeps running.
+    Callback(Arc<dyn Fn(Signal) + Send + Sync>),
+}
+
+impl SignalDisposition {
+    /// What happens to `signal` unless the embedder asked for something
+    /// else.
+    pub fn default_for(signal: Signal) -> Self {
+        match signal {
+            Signal::Sigint | Signal::Sigquit | Signal::Sigkill | Signal::Sigabrt => {
+                SignalDisposition::Terminate
+            }
+            _ => SignalDisposition::Ignore,
+        }
+    }
+}
+
+impl std::fmt::Debug for SignalDisposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignalDisposition::Terminate => f.write_str("Terminate"),
+            SignalDisposition::Ignore => f.write_str("Ignore"),
+            SignalDisposition::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct WasiSignalInterval {
     /// Signal that will be raised
@@ -6,9 +6,9 @@ use std::{
 use derivative::*;
 use futures::future::BoxFuture;
 use virtual_fs::{AsyncWriteExt, NullFile, VirtualFile};
-use wasmer_wasix_types::wasi::{Signal, Snapshot0Clockid};
+use wasmer_wasix_types::wasi::Signal;
 
-use crate::syscalls::platform_clock_time_get;
+use crate::runtime::{Clock, SystemClock};
 
 use super::task::signal::SignalHandlerAbi;
 
@@ -123,6 +123,7 @@ pub struct Tty {
     last: Option<(String, u128)>,
     options: TtyOptions,
     line: String,
+    clock: Arc<dyn Clock + Send + Sync>,
 }
 
 impl Tty {
@@ -140,6 +141,7 @@ impl Tty {
             options,
             is_mobile,
             line: String::new(),
+            clock: Arc::new(SystemClock),
         }
     }
 
@@ -173,6 +175,12 @@ impl Tty {
         self.signaler.replace(signaler);
     }
 
+    /// The clock used to tell repeated key presses apart, which should be
+    /// the clock of the runtime.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock + Send + Sync>) {
+        self.clock = clock;
+    }
+
     pub fn on_event(mut self, event: InputEvent) -> BoxFuture<'static, Self> {
         Box::pin(async move {
             match event {
@@ -184,8 +192,7 @@ impl Tty {
                     // Due to a nasty bug in xterm.js on Android mobile it sends the keys you press
                     // twice in a row with a short interval between - this hack will avoid that bug
                     if self.is_mobile {
-                        let now = platform_clock_time_get(Snapshot0Clockid::Monotonic, 1_000_000)
-                            .unwrap() as u128;
+                        let now = self.clock.monotonic().as_nanos();
                         if let Some((what, when)) = self.last.as_ref() {
                             if what.as_str() == data && now - *when < TTY_MOBILE_PAUSE {
                                 self.last = None;
@@ -16,6 +16,7 @@ use crate::{bin_factory::BinaryPackage, Runtime};
 #[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EmscriptenRunner {
     args: Vec<String>,
+    fuel: Option<u64>,
 }
 
 impl EmscriptenRunner {
@@ -39,6 +40,18 @@ impl EmscriptenRunner {
     pub fn set_args(&mut self, args: Vec<String>) {
         self.args = args;
     }
+
+    /// Builder method to limit how much work the module may do before it
+    /// is stopped (see [`crate::capabilities::Capabilities::fuel`])
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.set_fuel(Some(fuel));
+        self
+    }
+
+    /// Set the fuel limit
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
 }
 
 impl crate::runners::Runner for EmscriptenRunner {
@@ -74,6 +87,7 @@ impl crate::runners::Runner for EmscriptenRunner {
             env,
             command_name,
             main_args.unwrap_or_default(),
+            self.fuel,
         )?;
 
         Ok(())
@@ -105,12 +119,17 @@ fn exec_module(
     em_env: FunctionEnv<EmEnv>,
     name: &str,
     args: Vec<String>,
+    fuel: Option<u64>,
 ) -> Result<(), anyhow::Error> {
     let import_object = generate_emscripten_env(store, &em_env, globals);
 
     let mut instance = Instance::new(store, module, &import_object)
         .map_err(|e| anyhow!("Cant instantiate emscripten module {name:?}: {e}"))?;
 
+    if let Some(fuel) = fuel {
+        crate::state::set_fuel(store, &instance, fuel);
+    }
+
     run_emscripten_instance(
         &mut instance,
         em_env.into_mut(store),
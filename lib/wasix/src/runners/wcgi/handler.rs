@@ -51,6 +51,9 @@ impl Handler {
             .prepare_environment_variables(parts, &mut request_specific_env);
         builder.add_envs(request_specific_env);
 
+        // Keep the fuel limit the runner was configured with
+        let fuel = builder.capabilities_mut().fuel;
+
         let builder = builder
             .stdin(Box::new(req_body_receiver))
             .stdout(Box::new(res_body_sender))
@@ -59,6 +62,8 @@ impl Handler {
                 insecure_allow_all: true,
                 http_client: HttpClientCapabilityV1::new_allow_all(),
                 threading: Default::default(),
+                filesystem: Default::default(),
+                fuel,
             });
 
         let module = self.module.clone();
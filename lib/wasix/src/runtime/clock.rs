@@ -0,0 +1,135 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use wasmer_wasix_types::wasi::Snapshot0Clockid;
+
+use crate::{syscalls::platform_clock_time_get, VirtualTaskManager};
+
+/// Where the realtime and monotonic clocks seen by the guest get their time
+/// from.
+pub trait Clock: fmt::Debug {
+    /// The current wall-clock time, as a duration since the unix epoch.
+    fn now(&self) -> Duration;
+
+    /// A clock that never goes backwards, as a duration since some
+    /// arbitrary point in the past.
+    fn monotonic(&self) -> Duration;
+
+    /// Waits until `duration` has passed on the monotonic clock.
+    ///
+    /// Timers of the guest (like `poll_oneoff` and `thread_sleep`) wait on
+    /// this so they expire together with the clock. The default sleeps on
+    /// `tasks`, which follows the clock of the host.
+    fn sleep(
+        &self,
+        tasks: &dyn VirtualTaskManager,
+        duration: Duration,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + Sync + 'static>> {
+        tasks.sleep_now(duration)
+    }
+}
+
+/// The [`Clock`] of the host.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl SystemClock {
+    fn read(clock_id: Snapshot0Clockid) -> Duration {
+        let nanos = platform_clock_time_get(clock_id, 1).unwrap_or_default();
+        Duration::from_nanos(nanos.max(0) as u64)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemClock::read(Snapshot0Clockid::Realtime)
+    }
+
+    fn monotonic(&self) -> Duration {
+        SystemClock::read(Snapshot0Clockid::Monotonic)
+    }
+}
+
+/// A [`Clock`] that only moves when it is told to, for tests.
+///
+/// Clones share the same time, and sleeping on it only finishes once the
+/// clock has been advanced far enough.
+#[derive(Debug, Default, Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockClockState>>,
+}
+
+#[derive(Debug, Default)]
+struct MockClockState {
+    now: Duration,
+    monotonic: Duration,
+    sleepers: Vec<Waker>,
+}
+
+impl MockClock {
+    /// Creates a clock whose wall-clock time is `now` and whose monotonic
+    /// clock starts at zero.
+    pub fn new(now: Duration) -> Self {
+        MockClock {
+            state: Arc::new(Mutex::new(MockClockState {
+                now,
+                monotonic: Duration::ZERO,
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves both the wall-clock and the monotonic time forward.
+    pub fn advance(&self, by: Duration) {
+        let sleepers = {
+            let mut state = self.state.lock().unwrap();
+            state.now += by;
+            state.monotonic += by;
+            std::mem::take(&mut state.sleepers)
+        };
+        // Each sleeper checks its own deadline again when it is polled
+        for waker in sleepers {
+            waker.wake();
+        }
+    }
+
+    /// Sets the wall-clock time, leaving the monotonic time alone.
+    pub fn set_now(&self, now: Duration) {
+        self.state.lock().unwrap().now = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        self.state.lock().unwrap().now
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.state.lock().unwrap().monotonic
+    }
+
+    fn sleep(
+        &self,
+        _tasks: &dyn VirtualTaskManager,
+        duration: Duration,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + Sync + 'static>> {
+        let state = self.state.clone();
+        let deadline = self.monotonic().saturating_add(duration);
+        Box::pin(futures::future::poll_fn(move |cx| {
+            let mut state = state.lock().unwrap();
+            if state.monotonic >= deadline {
+                return Poll::Ready(());
+            }
+            if !state.sleepers.iter().any(|w| w.will_wake(cx.waker())) {
+                state.sleepers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }))
+    }
+}
@@ -1,9 +1,13 @@
+pub mod clock;
 pub mod module_cache;
 pub mod package_loader;
 pub mod resolver;
 pub mod task_manager;
 
-pub use self::task_manager::{SpawnMemoryType, VirtualTaskManager};
+pub use self::{
+    clock::{Clock, MockClock, SystemClock},
+    task_manager::{SpawnMemoryType, VirtualTaskManager},
+};
 
 use std::{
     fmt,
@@ -18,8 +22,8 @@ use crate::{
     os::TtyBridge,
     runtime::{
         module_cache::ModuleCache,
-        package_loader::{BuiltinPackageLoader, PackageLoader},
-        resolver::{MultiSource, Source, WapmSource},
+        package_loader::{BuiltinPackageLoader, CachedPackageInfo, PackageLoader},
+        resolver::{MultiSource, PackageSpecifier, Source, WapmSource},
     },
     WasiTtyState,
 };
@@ -76,6 +80,30 @@ where
     fn tty(&self) -> Option<&(dyn TtyBridge + Send + Sync)> {
         None
     }
+
+    /// The clock that the realtime and monotonic clocks of the guest read
+    /// from, and that its timers wait on.
+    fn clock(&self) -> Arc<dyn Clock + Send + Sync> {
+        Arc::new(SystemClock)
+    }
+
+    /// Rewrites the specifier of a package before it is resolved, e.g. to
+    /// redirect it to a mirror or to pin it to a particular version.
+    fn rewrite_specifier(&self, specifier: PackageSpecifier) -> PackageSpecifier {
+        specifier
+    }
+
+    /// The packages cached by the [`Runtime::package_loader()`], and how
+    /// much space each one takes up.
+    fn cached_packages(&self) -> Vec<CachedPackageInfo> {
+        self.package_loader().cached_packages()
+    }
+
+    /// Removes the packages matching `specifier` from the cache of the
+    /// [`Runtime::package_loader()`], returning how many were removed.
+    fn evict(&self, specifier: &PackageSpecifier) -> Result<usize, anyhow::Error> {
+        self.package_loader().evict(specifier)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -114,6 +142,10 @@ pub struct PluggableRuntime {
     pub module_cache: Arc<dyn ModuleCache + Send + Sync>,
     #[derivative(Debug = "ignore")]
     pub tty: Option<Arc<dyn TtyBridge + Send + Sync>>,
+    pub clock: Arc<dyn Clock + Send + Sync>,
+    #[derivative(Debug = "ignore")]
+    #[allow(clippy::type_complexity)]
+    pub specifier_rewriter: Option<Arc<dyn Fn(PackageSpecifier) -> PackageSpecifier + Send + Sync>>,
 }
 
 impl PluggableRuntime {
@@ -146,6 +178,8 @@ impl PluggableRuntime {
             http_client,
             engine: None,
             tty: None,
+            clock: Arc::new(SystemClock),
+            specifier_rewriter: None,
             source: Arc::new(source),
             package_loader: Arc::new(loader),
             module_cache: Arc::new(module_cache::in_memory()),
@@ -170,6 +204,20 @@ impl PluggableRuntime {
         self
     }
 
+    pub fn set_clock(&mut self, clock: impl Clock + Send + Sync + 'static) -> &mut Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Rewrites the specifier of every package before it is resolved.
+    pub fn set_specifier_rewriter(
+        &mut self,
+        rewriter: Arc<dyn Fn(PackageSpecifier) -> PackageSpecifier + Send + Sync>,
+    ) -> &mut Self {
+        self.specifier_rewriter = Some(rewriter);
+        self
+    }
+
     pub fn set_module_cache(
         &mut self,
         module_cache: impl ModuleCache + Send + Sync + 'static,
@@ -228,7 +276,18 @@ impl Runtime for PluggableRuntime {
         self.tty.as_deref()
     }
 
+    fn clock(&self) -> Arc<dyn Clock + Send + Sync> {
+        Arc::clone(&self.clock)
+    }
+
     fn module_cache(&self) -> Arc<dyn ModuleCache + Send + Sync> {
         self.module_cache.clone()
     }
+
+    fn rewrite_specifier(&self, specifier: PackageSpecifier) -> PackageSpecifier {
+        match &self.specifier_rewriter {
+            Some(rewriter) => rewriter(specifier),
+            None => specifier,
+        }
+    }
 }
@@ -0,0 +1,141 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use wasmer::{Engine, Module};
+
+use crate::runtime::module_cache::{CacheError, ModuleCache, ModuleHash};
+
+/// An in-memory [`ModuleCache`] that holds on to at most `capacity` modules.
+///
+/// When the cache is full, saving another module evicts the one that was
+/// used least recently. The cache also counts how many lookups it could
+/// answer, so callers can check that modules aren't needlessly recompiled.
+#[derive(Debug)]
+pub struct LruCache {
+    capacity: usize,
+    /// The cached modules, with the most recently used one at the back.
+    modules: Mutex<VecDeque<((ModuleHash, String), Module)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl LruCache {
+    pub fn new(capacity: usize) -> LruCache {
+        LruCache {
+            capacity,
+            modules: Mutex::new(VecDeque::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The number of lookups that found a cached module.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of lookups that didn't find a cached module.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The number of modules currently in the cache.
+    pub fn len(&self) -> usize {
+        self.modules.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait::async_trait]
+impl ModuleCache for LruCache {
+    async fn load(&self, key: ModuleHash, engine: &Engine) -> Result<Module, CacheError> {
+        let key = (key, engine.deterministic_id().to_string());
+        let mut modules = self.modules.lock().unwrap();
+
+        match modules.iter().position(|(k, _)| *k == key) {
+            Some(index) => {
+                // Move the module to the back so it is evicted last
+                let entry = modules.remove(index).unwrap();
+                let module = entry.1.clone();
+                modules.push_back(entry);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(module)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Err(CacheError::NotFound)
+            }
+        }
+    }
+
+    async fn save(
+        &self,
+        key: ModuleHash,
+        engine: &Engine,
+        module: &Module,
+    ) -> Result<(), CacheError> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+
+        let key = (key, engine.deterministic_id().to_string());
+        let mut modules = self.modules.lock().unwrap();
+
+        modules.retain(|(k, _)| *k != key);
+        while modules.len() >= self.capacity {
+            modules.pop_front();
+        }
+        modules.push_back((key, module.clone()));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADD_WAT: &[u8] = br#"(
+        module
+            (func
+                (export "add")
+                (param $x i64)
+                (param $y i64)
+                (result i64)
+                (i64.add (local.get $x) (local.get $y)))
+        )"#;
+
+    #[tokio::test]
+    async fn least_recently_used_module_is_evicted() {
+        let engine = Engine::default();
+        let module = Module::new(&engine, ADD_WAT).unwrap();
+        let cache = LruCache::new(2);
+        let first = ModuleHash::from_bytes([1; 32]);
+        let second = ModuleHash::from_bytes([2; 32]);
+        let third = ModuleHash::from_bytes([3; 32]);
+
+        cache.save(first, &engine, &module).await.unwrap();
+        cache.save(second, &engine, &module).await.unwrap();
+        // Using the first module makes the second one the oldest
+        cache.load(first, &engine).await.unwrap();
+        cache.save(third, &engine, &module).await.unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.load(first, &engine).await.is_ok());
+        assert!(matches!(
+            cache.load(second, &engine).await,
+            Err(CacheError::NotFound)
+        ));
+        assert!(cache.load(third, &engine).await.is_ok());
+        assert_eq!(cache.hits(), 3);
+        assert_eq!(cache.misses(), 1);
+    }
+}
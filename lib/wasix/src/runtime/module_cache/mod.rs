@@ -2,9 +2,10 @@
 //!
 //! The core of this module is the [`ModuleCache`] trait, which is designed to
 //! be implemented by different cache storage strategies, such as in-memory
-//! caches ([`SharedCache`] and [`ThreadLocalCache`]), file-based caches
-//! ([`FileSystemCache`]), or distributed caches. Implementing custom caching
-//! strategies allows you to optimize for your specific use case.
+//! caches ([`SharedCache`], [`ThreadLocalCache`] and [`LruCache`]),
+//! file-based caches ([`FileSystemCache`]), or distributed caches.
+//! Implementing custom caching strategies allows you to optimize for your
+//! specific use case.
 //!
 //! ## Assumptions and Requirements
 //!
@@ -33,6 +34,7 @@
 
 mod fallback;
 mod filesystem;
+mod lru;
 mod shared;
 mod thread_local;
 mod types;
@@ -40,6 +42,7 @@ mod types;
 pub use self::{
     fallback::FallbackCache,
     filesystem::FileSystemCache,
+    lru::LruCache,
     shared::SharedCache,
     thread_local::ThreadLocalCache,
     types::{CacheError, ModuleCache, ModuleHash},
@@ -8,8 +8,10 @@ use std::{
 
 use anyhow::{Context, Error};
 use bytes::Bytes;
-use http::{HeaderMap, Method};
+use http::{HeaderMap, Method, StatusCode};
+use semver::{Comparator, Op, Version, VersionReq};
 use tempfile::NamedTempFile;
+use url::Url;
 use webc::{
     compat::{Container, ContainerError},
     DetectError,
@@ -17,13 +19,17 @@ use webc::{
 
 use crate::{
     bin_factory::BinaryPackage,
-    http::{HttpClient, HttpRequest, USER_AGENT},
+    http::{HttpClient, HttpRequest, HttpResponse, USER_AGENT},
     runtime::{
-        package_loader::PackageLoader,
-        resolver::{DistributionInfo, PackageSummary, Resolution, WebcHash},
+        package_loader::{CachedPackageInfo, PackageLoader},
+        resolver::{DistributionInfo, PackageSpecifier, PackageSummary, Resolution, WebcHash},
     },
 };
 
+/// How many requests are sent for a single package before an interrupted
+/// download is given up on.
+const MAX_DOWNLOAD_ATTEMPTS: usize = 5;
+
 /// The builtin [`PackageLoader`] that is used by the `wasmer` CLI and
 /// respects `$WASMER_DIR`.
 #[derive(Debug)]
@@ -71,8 +77,9 @@ impl BuiltinPackageLoader {
         ))
     }
 
-    #[tracing::instrument(level = "debug", skip_all, fields(pkg.hash=%hash))]
-    async fn get_cached(&self, hash: &WebcHash) -> Result<Option<Container>, Error> {
+    #[tracing::instrument(level = "debug", skip_all, fields(pkg.hash=%summary.dist.webc_sha256))]
+    async fn get_cached(&self, summary: &PackageSummary) -> Result<Option<Container>, Error> {
+        let hash = &summary.dist.webc_sha256;
         if let Some(cached) = self.in_memory.lookup(hash) {
             return Ok(Some(cached));
         }
@@ -80,14 +87,22 @@ impl BuiltinPackageLoader {
         if let Some(cached) = self.fs.lookup(hash).await? {
             // Note: We want to propagate it to the in-memory cache, too
             tracing::debug!("Copying from the filesystem cache to the in-memory cache");
-            self.in_memory.save(&cached, *hash);
+            self.in_memory.save(&cached, summary, self.fs.size(hash));
             return Ok(Some(cached));
         }
 
         Ok(None)
     }
 
+    /// Downloads a package and makes sure it matches the digest from the
+    /// registry.
     async fn download(&self, dist: &DistributionInfo) -> Result<Bytes, Error> {
+        let webc = self.fetch(dist).await?;
+        verify_digest(&webc, dist)?;
+        Ok(webc)
+    }
+
+    async fn fetch(&self, dist: &DistributionInfo) -> Result<Bytes, Error> {
         if dist.webc.scheme() == "file" {
             match crate::runtime::resolver::utils::file_path_from_url(&dist.webc) {
                 Ok(path) => {
@@ -106,34 +121,83 @@ impl BuiltinPackageLoader {
             }
         }
 
-        let request = HttpRequest {
-            url: dist.webc.clone(),
-            method: Method::GET,
-            headers: headers(),
-            body: None,
-            options: Default::default(),
-        };
+        let url = &dist.webc;
+        let mut webc = Vec::new();
 
-        let response = self.client.request(request).await?;
+        for attempt in 1..=MAX_DOWNLOAD_ATTEMPTS {
+            let mut headers = headers();
+            if !webc.is_empty() {
+                // Only ask for the part we haven't got yet
+                let range = format!("bytes={}-", webc.len());
+                headers.insert("Range", range.parse().unwrap());
+            }
 
-        if !response.is_ok() {
-            let url = &dist.webc;
-            return Err(crate::runtime::resolver::utils::http_error(&response)
-                .context(format!("The GET request to \"{url}\" failed")));
-        }
+            let request = HttpRequest {
+                url: url.clone(),
+                method: Method::GET,
+                headers,
+                body: None,
+                options: Default::default(),
+            };
+
+            let response = match self.client.request(request).await {
+                Ok(response) => response,
+                Err(e) if attempt < MAX_DOWNLOAD_ATTEMPTS => {
+                    tracing::debug!(
+                        error=&*e,
+                        %url,
+                        attempt,
+                        "The download failed, retrying",
+                    );
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if !response.is_ok() {
+                return Err(crate::runtime::resolver::utils::http_error(&response)
+                    .context(format!("The GET request to \"{url}\" failed")));
+            }
 
-        let body = response
-            .body
-            .context("The response didn't contain a body")?;
+            let total_size = total_size(&response);
+            let resumed = response.status == StatusCode::PARTIAL_CONTENT;
+            let body = response
+                .body
+                .context("The response didn't contain a body")?;
 
-        Ok(body.into())
+            if !resumed {
+                // The server ignored the range and sent the whole package
+                webc.clear();
+            }
+            webc.extend_from_slice(&body);
+
+            match total_size {
+                Some(total_size) if webc.len() < total_size => {
+                    tracing::debug!(
+                        %url,
+                        attempt,
+                        received=webc.len(),
+                        total_size,
+                        "The download was cut short, resuming",
+                    );
+                }
+                _ => return Ok(webc.into()),
+            }
+        }
+
+        anyhow::bail!(
+            "The download from \"{url}\" was interrupted {MAX_DOWNLOAD_ATTEMPTS} times, giving up after {} bytes",
+            webc.len(),
+        );
     }
 
     async fn save_and_load_as_mmapped(
         &self,
         webc: &[u8],
-        dist: &DistributionInfo,
+        summary: &PackageSummary,
     ) -> Result<Container, Error> {
+        let dist = &summary.dist;
+
         // First, save it to disk
         self.fs.save(webc, dist).await?;
 
@@ -142,7 +206,7 @@ impl BuiltinPackageLoader {
         match self.fs.lookup(&dist.webc_sha256).await? {
             Some(container) => {
                 // we also want to make sure it's in the in-memory cache
-                self.in_memory.save(&container, dist.webc_sha256);
+                self.in_memory.save(&container, summary, webc.len() as u64);
 
                 Ok(container)
             }
@@ -167,7 +231,7 @@ impl PackageLoader for BuiltinPackageLoader {
         ),
     )]
     async fn load(&self, summary: &PackageSummary) -> Result<Container, Error> {
-        if let Some(container) = self.get_cached(&summary.dist.webc_sha256).await? {
+        if let Some(container) = self.get_cached(summary).await? {
             tracing::debug!("Cache hit!");
             return Ok(container);
         }
@@ -181,7 +245,7 @@ impl PackageLoader for BuiltinPackageLoader {
         // We want to cache the container we downloaded, but we want to do it
         // in a smart way to keep memory usage down.
 
-        match self.save_and_load_as_mmapped(&bytes, &summary.dist).await {
+        match self.save_and_load_as_mmapped(&bytes, summary).await {
             Ok(container) => {
                 tracing::debug!("Cached to disk");
                 // The happy path - we've saved to both caches and loaded the
@@ -199,9 +263,10 @@ impl PackageLoader for BuiltinPackageLoader {
                 );
                 // The sad path - looks like we'll need to keep the whole thing
                 // in memory.
+                let size = bytes.len() as u64;
                 let container = Container::from_bytes(bytes)?;
                 // We still want to cache it, of course
-                self.in_memory.save(&container, summary.dist.webc_sha256);
+                self.in_memory.save(&container, summary, size);
                 Ok(container)
             }
         }
@@ -214,6 +279,21 @@ impl PackageLoader for BuiltinPackageLoader {
     ) -> Result<BinaryPackage, Error> {
         super::load_package_tree(root, self, resolution).await
     }
+
+    fn cached_packages(&self) -> Vec<CachedPackageInfo> {
+        let cache = self.in_memory.0.read().unwrap();
+        let mut packages: Vec<_> = cache.values().collect();
+        packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        packages.into_iter().map(CachedPackage::info).collect()
+    }
+
+    fn evict(&self, specifier: &PackageSpecifier) -> Result<usize, Error> {
+        let evicted = self.in_memory.remove_matching(specifier);
+        for hash in &evicted {
+            self.fs.remove(hash)?;
+        }
+        Ok(evicted.len())
+    }
 }
 
 fn headers() -> HeaderMap {
@@ -223,6 +303,34 @@ fn headers() -> HeaderMap {
     headers
 }
 
+/// How big the whole package is, according to the `Content-Range` header of a
+/// partial response or the `Content-Length` header of a full one.
+fn total_size(response: &HttpResponse) -> Option<usize> {
+    if response.status == StatusCode::PARTIAL_CONTENT {
+        // e.g. "bytes 1024-2047/2048"
+        let content_range = response.headers.get("Content-Range")?.to_str().ok()?;
+        let (_, total) = content_range.rsplit_once('/')?;
+        total.trim().parse().ok()
+    } else {
+        let content_length = response.headers.get("Content-Length")?.to_str().ok()?;
+        content_length.trim().parse().ok()
+    }
+}
+
+/// Makes sure a downloaded package is the one the registry told us about.
+fn verify_digest(webc: &[u8], dist: &DistributionInfo) -> Result<(), Error> {
+    let actual = WebcHash::sha256(webc);
+    if actual != dist.webc_sha256 {
+        anyhow::bail!(
+            "The package from \"{}\" is corrupted: its SHA-256 digest is {actual}, but {} was expected",
+            dist.webc,
+            dist.webc_sha256,
+        );
+    }
+
+    Ok(())
+}
+
 fn discover_wasmer_dir() -> Option<PathBuf> {
     // TODO: We should reuse the same logic from the wasmer CLI.
     std::env::var("WASMER_DIR")
@@ -286,6 +394,24 @@ impl FileSystemCache {
         Ok(())
     }
 
+    /// How many bytes the cached package takes up on disk.
+    fn size(&self, hash: &WebcHash) -> u64 {
+        std::fs::metadata(self.path(hash))
+            .map(|metadata| metadata.len())
+            .unwrap_or_default()
+    }
+
+    fn remove(&self, hash: &WebcHash) -> Result<(), Error> {
+        let path = self.path(hash);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                Err(Error::new(e).context(format!("Unable to remove \"{}\"", path.display())))
+            }
+        }
+    }
+
     fn path(&self, hash: &WebcHash) -> PathBuf {
         let hash = hash.as_bytes();
         let mut filename = String::with_capacity(hash.len() * 2);
@@ -299,16 +425,84 @@ impl FileSystemCache {
 }
 
 #[derive(Debug, Default)]
-struct InMemoryCache(RwLock<HashMap<WebcHash, Container>>);
+struct InMemoryCache(RwLock<HashMap<WebcHash, CachedPackage>>);
 
 impl InMemoryCache {
     fn lookup(&self, hash: &WebcHash) -> Option<Container> {
-        self.0.read().unwrap().get(hash).cloned()
+        self.0
+            .read()
+            .unwrap()
+            .get(hash)
+            .map(|cached| cached.container.clone())
     }
 
-    fn save(&self, container: &Container, hash: WebcHash) {
+    fn save(&self, container: &Container, summary: &PackageSummary, size: u64) {
         let mut cache = self.0.write().unwrap();
-        cache.entry(hash).or_insert_with(|| container.clone());
+        cache
+            .entry(summary.dist.webc_sha256)
+            .or_insert_with(|| CachedPackage {
+                container: container.clone(),
+                name: summary.pkg.name.clone(),
+                version: summary.pkg.version.clone(),
+                url: summary.dist.webc.clone(),
+                size,
+            });
+    }
+
+    /// Removes the packages matching `specifier`, returning their hashes.
+    fn remove_matching(&self, specifier: &PackageSpecifier) -> Vec<WebcHash> {
+        let mut cache = self.0.write().unwrap();
+        let evicted: Vec<WebcHash> = cache
+            .iter()
+            .filter(|(_, cached)| cached.matches(specifier))
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in &evicted {
+            cache.remove(hash);
+        }
+        evicted
+    }
+}
+
+#[derive(Debug)]
+struct CachedPackage {
+    container: Container,
+    name: String,
+    version: Version,
+    /// Where the package was downloaded from.
+    url: Url,
+    size: u64,
+}
+
+impl CachedPackage {
+    fn matches(&self, specifier: &PackageSpecifier) -> bool {
+        match specifier {
+            PackageSpecifier::Registry { full_name, version } => {
+                *full_name == self.name && version.matches(&self.version)
+            }
+            PackageSpecifier::Url(url) => *url == self.url,
+            PackageSpecifier::Path(_) => false,
+        }
+    }
+
+    fn info(&self) -> CachedPackageInfo {
+        let exact = Comparator {
+            op: Op::Exact,
+            major: self.version.major,
+            minor: Some(self.version.minor),
+            patch: Some(self.version.patch),
+            pre: self.version.pre.clone(),
+        };
+        CachedPackageInfo {
+            specifier: PackageSpecifier::Registry {
+                full_name: self.name.clone(),
+                version: VersionReq {
+                    comparators: vec![exact],
+                },
+            },
+            version: self.version.clone(),
+            size: self.size,
+        }
     }
 }
 
@@ -328,6 +522,8 @@ mod tests {
     use super::*;
 
     const PYTHON: &[u8] = include_bytes!("../../../../c-api/examples/assets/python-0.1.0.wasmer");
+    const STATIC_SERVER: &[u8] =
+        include_bytes!("../../../../c-api/examples/assets/staticserver.webc");
 
     #[derive(Debug)]
     pub(crate) struct DummyClient {
@@ -376,7 +572,7 @@ mod tests {
             },
             dist: DistributionInfo {
                 webc: "https://wapm.io/python/python".parse().unwrap(),
-                webc_sha256: [0xaa; 32].into(),
+                webc_sha256: WebcHash::sha256(PYTHON),
             },
         };
 
@@ -401,4 +597,159 @@ mod tests {
         let in_memory = loader.in_memory.0.read().unwrap();
         assert!(in_memory.contains_key(&summary.dist.webc_sha256));
     }
+
+    #[tokio::test]
+    async fn cached_packages_can_be_listed_and_evicted() {
+        let temp = TempDir::new().unwrap();
+        let response = |webc: &[u8]| HttpResponse {
+            body: Some(webc.to_vec()),
+            redirected: false,
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+        };
+        let client = Arc::new(DummyClient::with_responses([
+            response(PYTHON),
+            response(STATIC_SERVER),
+        ]));
+        let loader = BuiltinPackageLoader::new_with_client(temp.path(), client);
+        let summary = |name: &str, version: &str, webc: &[u8]| PackageSummary {
+            pkg: PackageInfo {
+                name: name.to_string(),
+                version: version.parse().unwrap(),
+                dependencies: Vec::new(),
+                commands: Vec::new(),
+                entrypoint: None,
+                filesystem: Vec::new(),
+            },
+            dist: DistributionInfo {
+                webc: format!("https://wapm.io/{name}").parse().unwrap(),
+                webc_sha256: WebcHash::sha256(webc),
+            },
+        };
+        let python = summary("python/python", "0.1.0", PYTHON);
+        let other = summary("wasmer/other", "1.2.3", STATIC_SERVER);
+        loader.load(&python).await.unwrap();
+        loader.load(&other).await.unwrap();
+
+        let cached = loader.cached_packages();
+
+        assert_eq!(
+            cached,
+            vec![
+                CachedPackageInfo {
+                    specifier: "python/python@=0.1.0".parse().unwrap(),
+                    version: "0.1.0".parse().unwrap(),
+                    size: PYTHON.len() as u64,
+                },
+                CachedPackageInfo {
+                    specifier: "wasmer/other@=1.2.3".parse().unwrap(),
+                    version: "1.2.3".parse().unwrap(),
+                    size: STATIC_SERVER.len() as u64,
+                },
+            ]
+        );
+
+        let evicted = loader
+            .evict(&"python/python@^0.1".parse().unwrap())
+            .unwrap();
+
+        assert_eq!(evicted, 1);
+        let remaining = loader.cached_packages();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].specifier, cached[1].specifier);
+        // the copy on disk should be gone, too
+        assert!(!loader.fs.path(&python.dist.webc_sha256).exists());
+        assert!(loader.fs.path(&other.dist.webc_sha256).exists());
+        // evicting something that isn't cached is a no-op
+        assert_eq!(
+            loader
+                .evict(&PackageSpecifier::Url(python.dist.webc.clone()))
+                .unwrap(),
+            0
+        );
+    }
+
+    fn python_summary() -> PackageSummary {
+        PackageSummary {
+            pkg: PackageInfo {
+                name: "python/python".to_string(),
+                version: "0.1.0".parse().unwrap(),
+                dependencies: Vec::new(),
+                commands: Vec::new(),
+                entrypoint: Some("python".to_string()),
+                filesystem: Vec::new(),
+            },
+            dist: DistributionInfo {
+                webc: "https://wapm.io/python/python".parse().unwrap(),
+                webc_sha256: WebcHash::sha256(PYTHON),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_downloads_are_resumed() {
+        let temp = TempDir::new().unwrap();
+        let half = PYTHON.len() / 2;
+        let mut first_headers = HeaderMap::new();
+        first_headers.insert("Content-Length", PYTHON.len().into());
+        let mut second_headers = HeaderMap::new();
+        let content_range = format!("bytes {half}-{}/{}", PYTHON.len() - 1, PYTHON.len());
+        second_headers.insert("Content-Range", content_range.parse().unwrap());
+        let client = Arc::new(DummyClient::with_responses([
+            // The connection drops halfway through the package
+            HttpResponse {
+                body: Some(PYTHON[..half].to_vec()),
+                redirected: false,
+                status: StatusCode::OK,
+                headers: first_headers,
+            },
+            HttpResponse {
+                body: Some(PYTHON[half..].to_vec()),
+                redirected: false,
+                status: StatusCode::PARTIAL_CONTENT,
+                headers: second_headers,
+            },
+        ]));
+        let loader = BuiltinPackageLoader::new_with_client(temp.path(), client.clone());
+        let summary = python_summary();
+
+        let container = loader.load(&summary).await.unwrap();
+
+        // The second request only asked for what was missing
+        let requests = client.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].headers.contains_key("Range"));
+        assert_eq!(requests[1].headers["Range"], format!("bytes={half}-"));
+        // and the pieces add up to a package with the right digest
+        assert_eq!(container.manifest().entrypoint.as_deref(), Some("python"));
+        let path = loader.fs.path(&summary.dist.webc_sha256);
+        assert_eq!(std::fs::read(path).unwrap(), PYTHON);
+    }
+
+    #[tokio::test]
+    async fn packages_with_the_wrong_digest_are_rejected() {
+        let temp = TempDir::new().unwrap();
+        let mut corrupted = PYTHON.to_vec();
+        corrupted[PYTHON.len() / 2] ^= 0xff;
+        let client = Arc::new(DummyClient::with_responses([HttpResponse {
+            body: Some(corrupted),
+            redirected: false,
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+        }]));
+        let loader = BuiltinPackageLoader::new_with_client(temp.path(), client);
+        let summary = python_summary();
+
+        let err = loader.load(&summary).await.unwrap_err();
+
+        let message = format!("{err:#}");
+        assert!(message.contains("is corrupted"), "{message}");
+        assert!(
+            message.contains(&summary.dist.webc_sha256.to_string()),
+            "{message}"
+        );
+        // Nothing was cached
+        assert!(!loader.fs.path(&summary.dist.webc_sha256).exists());
+        assert!(loader.cached_packages().is_empty());
+    }
 }
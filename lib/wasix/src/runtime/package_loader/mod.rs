@@ -3,6 +3,7 @@ mod load_package_tree;
 mod types;
 
 pub use self::{
-    builtin_loader::BuiltinPackageLoader, load_package_tree::load_package_tree,
-    types::PackageLoader,
+    builtin_loader::BuiltinPackageLoader,
+    load_package_tree::load_package_tree,
+    types::{CachedPackageInfo, PackageLoader},
 };
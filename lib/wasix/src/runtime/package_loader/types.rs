@@ -1,13 +1,25 @@
 use std::{fmt::Debug, ops::Deref};
 
 use anyhow::Error;
+use semver::Version;
 use webc::compat::Container;
 
 use crate::{
     bin_factory::BinaryPackage,
-    runtime::resolver::{PackageSummary, Resolution},
+    runtime::resolver::{PackageSpecifier, PackageSummary, Resolution},
 };
 
+/// A package that has been cached by a [`PackageLoader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedPackageInfo {
+    /// The package, pinned to the exact version that was cached.
+    pub specifier: PackageSpecifier,
+    /// The version of the package.
+    pub version: Version,
+    /// How many bytes the package takes up in the cache.
+    pub size: u64,
+}
+
 #[async_trait::async_trait]
 pub trait PackageLoader: Send + Sync + Debug {
     async fn load(&self, summary: &PackageSummary) -> Result<Container, Error>;
@@ -21,6 +33,19 @@ pub trait PackageLoader: Send + Sync + Debug {
         root: &Container,
         resolution: &Resolution,
     ) -> Result<BinaryPackage, Error>;
+
+    /// The packages that have been cached so far.
+    ///
+    /// Loaders that don't cache anything return an empty list.
+    fn cached_packages(&self) -> Vec<CachedPackageInfo> {
+        Vec::new()
+    }
+
+    /// Removes the cached packages matching `specifier`, returning how many
+    /// were removed.
+    fn evict(&self, _specifier: &PackageSpecifier) -> Result<usize, Error> {
+        Ok(0)
+    }
 }
 
 #[async_trait::async_trait]
@@ -40,4 +65,12 @@ where
     ) -> Result<BinaryPackage, Error> {
         (**self).load_package_tree(root, resolution).await
     }
+
+    fn cached_packages(&self) -> Vec<CachedPackageInfo> {
+        (**self).cached_packages()
+    }
+
+    fn evict(&self, specifier: &PackageSpecifier) -> Result<usize, Error> {
+        (**self).evict(specifier)
+    }
 }
@@ -9,6 +9,7 @@ pub(crate) mod utils;
 mod wapm_source;
 mod web_source;
 
+pub(crate) use self::resolve::resolve_with_rewriter;
 pub use self::{
     filesystem_source::FileSystemSource,
     in_memory_source::InMemorySource,
@@ -11,8 +11,8 @@ use semver::Version;
 
 use crate::runtime::resolver::{
     outputs::{Edge, Node},
-    DependencyGraph, ItemLocation, PackageId, PackageInfo, PackageSummary, Resolution,
-    ResolvedPackage, Source,
+    DependencyGraph, ItemLocation, PackageId, PackageInfo, PackageSpecifier, PackageSummary,
+    Resolution, ResolvedPackage, Source,
 };
 
 use super::ResolvedFileSystemMapping;
@@ -25,7 +25,21 @@ pub async fn resolve(
     root: &PackageInfo,
     source: &dyn Source,
 ) -> Result<Resolution, ResolveError> {
-    let graph = resolve_dependency_graph(root_id, root, source).await?;
+    resolve_with_rewriter(root_id, root, source, |pkg| pkg.clone()).await
+}
+
+/// Like [`resolve()`], but passes the specifier of every dependency through
+/// `rewrite` before it is looked up.
+pub(crate) async fn resolve_with_rewriter<F>(
+    root_id: &PackageId,
+    root: &PackageInfo,
+    source: &dyn Source,
+    rewrite: F,
+) -> Result<Resolution, ResolveError>
+where
+    F: Fn(&PackageSpecifier) -> PackageSpecifier,
+{
+    let graph = resolve_dependency_graph(root_id, root, source, &rewrite).await?;
     let package = resolve_package(&graph)?;
 
     Ok(Resolution { graph, package })
@@ -71,17 +85,21 @@ fn print_cycle(packages: &[PackageId]) -> String {
         .join(" → ")
 }
 
-async fn resolve_dependency_graph(
+async fn resolve_dependency_graph<F>(
     root_id: &PackageId,
     root: &PackageInfo,
     source: &dyn Source,
-) -> Result<DependencyGraph, ResolveError> {
+    rewrite: &F,
+) -> Result<DependencyGraph, ResolveError>
+where
+    F: Fn(&PackageSpecifier) -> PackageSpecifier,
+{
     let DiscoveredPackages {
         root,
         graph,
         indices,
         packages,
-    } = discover_dependencies(root_id, root, source).await?;
+    } = discover_dependencies(root_id, root, source, rewrite).await?;
 
     check_for_duplicate_versions(indices.iter().copied().map(|ix| &graph[ix].id))?;
     log_dependencies(&graph, root);
@@ -91,11 +109,15 @@ async fn resolve_dependency_graph(
     Ok(graph)
 }
 
-async fn discover_dependencies(
+async fn discover_dependencies<F>(
     root_id: &PackageId,
     root: &PackageInfo,
     source: &dyn Source,
-) -> Result<DiscoveredPackages, ResolveError> {
+    rewrite: &F,
+) -> Result<DiscoveredPackages, ResolveError>
+where
+    F: Fn(&PackageSpecifier) -> PackageSpecifier,
+{
     let mut nodes: BTreeMap<PackageId, NodeIndex> = BTreeMap::new();
     let mut graph: DiGraph<Node, Edge> = DiGraph::new();
 
@@ -118,7 +140,7 @@ async fn discover_dependencies(
             // down using existing requirements and trying to reuse the same
             // dependency when possible.
             let dep_summary = source
-                .latest(&dep.pkg)
+                .latest(&rewrite(&dep.pkg))
                 .await
                 .map_err(ResolveError::Registry)?;
             let dep_id = dep_summary.package_id();
@@ -652,6 +674,35 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn dependencies_are_rewritten_before_they_are_looked_up() {
+        let mut builder = RegistryBuilder::new();
+        builder
+            .register("root", "1.0.0")
+            .with_dependency("dep", "=1.0.0");
+        builder.register("dep", "1.0.0");
+        builder.register("mirror/dep", "2.0.0");
+        let registry = builder.finish();
+        let root = builder.get("root", "1.0.0");
+
+        let resolution = resolve_with_rewriter(&root.package_id(), &root.pkg, &registry, |pkg| {
+            if *pkg == PackageSpecifier::parse("dep@=1.0.0").unwrap() {
+                PackageSpecifier::parse("mirror/dep@=2.0.0").unwrap()
+            } else {
+                pkg.clone()
+            }
+        })
+        .await
+        .unwrap();
+
+        let mut dependency_graph = builder.start_dependency_graph();
+        dependency_graph
+            .insert("root", "1.0.0")
+            .with_aliased_dependency("dep", "mirror/dep", "2.0.0");
+        dependency_graph.insert("mirror/dep", "2.0.0");
+        assert_eq!(deps(&resolution), dependency_graph.finish());
+    }
+
     #[tokio::test]
     async fn no_deps_one_command() {
         let mut builder = RegistryBuilder::new();
@@ -158,6 +158,16 @@ pub trait VirtualTaskManager: std::fmt::Debug + Send + Sync + 'static {
         task: Box<dyn FnOnce() + Send + 'static>,
     ) -> Result<(), WasiThreadError>;
 
+    /// Like [`VirtualTaskManager::task_dedicated`], but the thread is given
+    /// `name` if the task manager is able to name its threads.
+    fn task_dedicated_named(
+        &self,
+        name: String,
+        task: Box<dyn FnOnce() + Send + 'static>,
+    ) -> Result<(), WasiThreadError> {
+        self.task_dedicated(task)
+    }
+
     /// Returns the amount of parallelism that is possible on this platform
     fn thread_parallelism(&self) -> Result<usize, WasiThreadError>;
 }
@@ -211,6 +221,14 @@ where
         (**self).task_dedicated(task)
     }
 
+    fn task_dedicated_named(
+        &self,
+        name: String,
+        task: Box<dyn FnOnce() + Send + 'static>,
+    ) -> Result<(), WasiThreadError> {
+        (**self).task_dedicated_named(name, task)
+    }
+
     fn thread_parallelism(&self) -> Result<usize, WasiThreadError> {
         (**self).thread_parallelism()
     }
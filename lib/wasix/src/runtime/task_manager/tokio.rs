@@ -115,6 +115,14 @@ impl VirtualTaskManager for TokioTaskManager {
 
     /// See [`VirtualTaskManager::task_wasm`].
     fn task_wasm(&self, task: TaskWasm) -> Result<(), WasiThreadError> {
+        let thread_name = task
+            .env
+            .capabilities
+            .threading
+            .thread_name_prefix
+            .as_ref()
+            .map(|prefix| format!("{prefix}-{}", task.env.tid()));
+
         // Create the context on a new store
         let run = task.run;
         let (ctx, store) = WasiFunctionEnv::new_with_store(
@@ -133,7 +141,7 @@ impl VirtualTaskManager for TokioTaskManager {
             self.0.spawn(async move {
                 let result = trigger.await;
                 // Build the task that will go on the callback
-                handle.spawn_blocking(move || {
+                let spawned = spawn_blocking(&handle, thread_name, move || {
                     // Invoke the callback
                     run(TaskWasmRunProperties {
                         ctx,
@@ -141,17 +149,21 @@ impl VirtualTaskManager for TokioTaskManager {
                         trigger_result: Some(result),
                     });
                 });
+                if let Err(err) = spawned {
+                    tracing::error!("failed to spawn the thread for a resumed task: {err}");
+                }
             });
         } else {
             // Run the callback on a dedicated thread
-            self.0.spawn_blocking(move || {
+            spawn_blocking(&self.0, thread_name, move || {
                 // Invoke the callback
                 run(TaskWasmRunProperties {
                     ctx,
                     store,
                     trigger_result: None,
                 });
-            });
+            })
+            .map_err(|err| WasiThreadError::InitFailed(err.into()))?;
         }
         Ok(())
     }
@@ -167,6 +179,18 @@ impl VirtualTaskManager for TokioTaskManager {
         Ok(())
     }
 
+    /// See [`VirtualTaskManager::task_dedicated_named`].
+    fn task_dedicated_named(
+        &self,
+        name: String,
+        task: Box<dyn FnOnce() + Send + 'static>,
+    ) -> Result<(), WasiThreadError> {
+        spawn_blocking(&self.0, Some(name), move || {
+            task();
+        })
+        .map_err(|err| WasiThreadError::InitFailed(err.into()))
+    }
+
     /// See [`VirtualTaskManager::thread_parallelism`].
     fn thread_parallelism(&self) -> Result<usize, WasiThreadError> {
         Ok(std::thread::available_parallelism()
@@ -174,3 +198,32 @@ impl VirtualTaskManager for TokioTaskManager {
             .unwrap_or(8))
     }
 }
+
+/// Runs `f` on a blocking thread of the runtime, or on a new thread called
+/// `name` (which still has the runtime entered) when a name is given.
+fn spawn_blocking(
+    handle: &Handle,
+    name: Option<String>,
+    f: impl FnOnce() + Send + 'static,
+) -> std::io::Result<()> {
+    let Some(name) = name else {
+        handle.spawn_blocking(f);
+        return Ok(());
+    };
+
+    let runtime = handle.clone();
+    std::thread::Builder::new().name(name).spawn(move || {
+        let _guard = runtime.enter();
+        f();
+    })?;
+    Ok(())
+}
+
+/// Lets a [`virtual_fs::TimeoutFileSystem`] run its calls on tokio's
+/// blocking pool, which has a bounded number of threads.
+impl virtual_fs::BlockingExecutor for TokioTaskManager {
+    fn execute(&self, task: Box<dyn FnOnce() + Send + 'static>) -> virtual_fs::Result<()> {
+        self.0.spawn_blocking(task);
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 //! Builder system for configuring a [`WasiState`] and creating it.
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -9,20 +9,29 @@ use std::{
 use bytes::Bytes;
 use rand::Rng;
 use thiserror::Error;
-use virtual_fs::{ArcFile, FsError, TmpFileSystem, VirtualFile};
+use virtual_fs::{
+    ArcFile, FileSystem, FsError, OverlayFileSystem, PrefixedFile, ReadOnlyFileSystem,
+    TmpFileSystem, VirtualFile,
+};
+use virtual_net::VirtualTcpListener;
 use wasmer::{AsStoreMut, Instance, Module, RuntimeError, Store};
-use wasmer_wasix_types::wasi::{Errno, ExitCode};
+use wasmer_wasix_types::wasi::{Errno, ExitCode, Fd as WasiFd, Signal};
 
 #[cfg(feature = "sys")]
 use crate::PluggableRuntime;
 use crate::{
-    bin_factory::{BinFactory, BinaryPackage},
+    bin_factory::{spawn_exec, BinFactory, BinaryPackage},
     capabilities::Capabilities,
-    fs::{WasiFs, WasiFsRoot, WasiInodes},
-    os::task::control_plane::{ControlPlaneConfig, ControlPlaneError, WasiControlPlane},
-    state::WasiState,
+    fs::{CopyUpFileSystem, ProcFileSystem, SingleFileSystem, WasiFs, WasiFsRoot, WasiInodes},
+    net::socket::{InodeSocket, InodeSocketKind},
+    os::task::{
+        control_plane::{ControlPlaneConfig, ControlPlaneError, WasiControlPlane},
+        signal::SignalDisposition,
+    },
+    state::{SyscallMetrics, WasiState},
     syscalls::types::{__WASI_STDERR_FILENO, __WASI_STDIN_FILENO, __WASI_STDOUT_FILENO},
-    RewindState, Runtime, WasiEnv, WasiError, WasiFunctionEnv, WasiRuntimeError,
+    RewindState, Runtime, VirtualTaskManagerExt, WasiEnv, WasiError, WasiFunctionEnv,
+    WasiRuntimeError,
 };
 
 use super::env::WasiEnvInit;
@@ -51,6 +60,13 @@ pub struct WasiEnvBuilder {
     pub(super) envs: Vec<(String, Vec<u8>)>,
     /// Pre-opened directories that will be accessible from WASI.
     pub(super) preopens: Vec<PreopenedDir>,
+    /// Individual host files that will be accessible from WASI.
+    pub(super) preopen_host_files: Vec<PreopenedHostFile>,
+    /// Host directories that are exposed read-only, with any changes going
+    /// to a scratch file system.
+    pub(super) preopen_overlays: Vec<PreopenedOverlay>,
+    /// Host directories that the program may read but never modify.
+    pub(super) preopen_readonly_dirs: Vec<PreopenedReadOnlyDir>,
     /// Pre-opened virtual directories that will be accessible from WASI.
     vfs_preopens: Vec<String>,
     #[allow(clippy::type_complexity)]
@@ -59,16 +75,66 @@ pub struct WasiEnvBuilder {
     pub(super) stdout: Option<Box<dyn VirtualFile + Send + Sync + 'static>>,
     pub(super) stderr: Option<Box<dyn VirtualFile + Send + Sync + 'static>>,
     pub(super) stdin: Option<Box<dyn VirtualFile + Send + Sync + 'static>>,
+    /// A file that is opened read-only and used as `stdin`.
+    pub(super) stdin_file_path: Option<PathBuf>,
     pub(super) fs: Option<WasiFsRoot>,
     pub(super) runtime: Option<Arc<dyn crate::Runtime + Send + Sync + 'static>>,
 
     /// List of webc dependencies to be injected.
     pub(super) uses: Vec<BinaryPackage>,
 
+    /// A package that is run without going through the registry, see
+    /// [`WasiEnvBuilder::run_resolved_package()`].
+    pub(super) resolved_package: Option<BinaryPackage>,
+
     /// List of host commands to map into the WASI instance.
     pub(super) map_commands: HashMap<String, PathBuf>,
 
     pub(super) capabilites: Capabilities,
+
+    /// Whether the stdio file descriptors report themselves as a TTY.
+    pub(super) stdio_tty: Option<bool>,
+
+    /// Whether newly opened file descriptors are closed on spawn and exec.
+    pub(super) close_on_exec_defaults: Option<bool>,
+
+    /// The most file descriptors that can be open at the same time.
+    pub(super) max_open_files: Option<u32>,
+
+    /// Whether a [`ProcFileSystem`] is mounted at `/proc`.
+    pub(super) proc_fs: bool,
+
+    /// Overrides what happens to signals the program has no handler for.
+    pub(super) signal_dispositions: HashMap<Signal, SignalDisposition>,
+
+    /// Whether the syscalls the program makes are counted.
+    pub(super) syscall_metrics: bool,
+
+    /// Runs the threads one at a time, in an order picked using this seed.
+    pub(super) deterministic_scheduling: Option<u64>,
+
+    /// Called with the exit code once the program run by
+    /// [`WasiEnvBuilder::run_with_store()`] or
+    /// [`WasiEnvBuilder::run_with_store_async()`] has finished.
+    #[allow(clippy::type_complexity)]
+    pub(super) on_exit: Option<Box<dyn FnOnce(ExitCode) + Send>>,
+
+    /// Overrides the first argument passed to the program.
+    pub(super) argv0: Option<String>,
+
+    /// Makes the root file system read-only, optionally keeping `/tmp`
+    /// writable.
+    pub(super) read_only_fs: Option<bool>,
+
+    /// Prepended to every line the program writes to stdout.
+    pub(super) stdout_prefix: Option<String>,
+
+    /// Prepended to every line the program writes to stderr.
+    pub(super) stderr_prefix: Option<String>,
+
+    /// Listeners from the host that are handed to the program as already
+    /// listening sockets, keyed by file descriptor.
+    pub(super) bridged_listeners: Vec<(WasiFd, Box<dyn VirtualTcpListener + Sync>)>,
 }
 
 impl std::fmt::Debug for WasiEnvBuilder {
@@ -78,12 +144,29 @@ impl std::fmt::Debug for WasiEnvBuilder {
             .field("args", &self.args)
             .field("envs", &self.envs)
             .field("preopens", &self.preopens)
+            .field("preopen_host_files", &self.preopen_host_files)
+            .field("preopen_overlays", &self.preopen_overlays)
+            .field("preopen_readonly_dirs", &self.preopen_readonly_dirs)
             .field("uses", &self.uses)
+            .field("resolved_package", &self.resolved_package)
             .field("setup_fs_fn exists", &self.setup_fs_fn.is_some())
             .field("stdout_override exists", &self.stdout.is_some())
             .field("stderr_override exists", &self.stderr.is_some())
             .field("stdin_override exists", &self.stdin.is_some())
+            .field("stdin_file_path", &self.stdin_file_path)
             .field("runtime_override_exists", &self.runtime.is_some())
+            .field("signal_dispositions", &self.signal_dispositions)
+            .field("syscall_metrics", &self.syscall_metrics)
+            .field("deterministic_scheduling", &self.deterministic_scheduling)
+            .field("on_exit exists", &self.on_exit.is_some())
+            .field(
+                "bridged_listeners",
+                &self
+                    .bridged_listeners
+                    .iter()
+                    .map(|(fd, _)| fd)
+                    .collect::<Vec<_>>(),
+            )
             .finish()
     }
 }
@@ -101,6 +184,8 @@ pub enum WasiStateCreationError {
     PreopenedDirectoryError(String),
     #[error("mapped dir alias has wrong format: `{0}`")]
     MappedDirAliasFormattingError(String),
+    #[error("more than one directory is mapped to `{0}`")]
+    DuplicateMappedDirAlias(String),
     #[error("wasi filesystem creation error: `{0}`")]
     WasiFsCreationError(String),
     #[error("wasi filesystem setup error: `{0}`")]
@@ -125,6 +210,77 @@ fn validate_mapped_dir_alias(alias: &str) -> Result<(), WasiStateCreationError>
     Ok(())
 }
 
+/// Opens a host file that the guest's output is written to.
+#[cfg(all(feature = "sys", feature = "host-fs"))]
+fn open_host_output_file(
+    path: &Path,
+    append: bool,
+) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, WasiStateCreationError> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .map_err(|err| {
+            WasiStateCreationError::WasiFsSetupError(format!(
+                "unable to open \"{}\" for output: {err}",
+                path.display()
+            ))
+        })?;
+    Ok(Box::new(virtual_fs::host_fs::File::new(
+        file,
+        path.to_path_buf(),
+        false,
+        true,
+        append,
+    )))
+}
+
+/// Checks that an environment variable can be handed to the guest as
+/// `key=value`.
+pub(crate) fn validate_env_var(key: &str, value: &[u8]) -> Result<(), WasiStateCreationError> {
+    enum InvalidCharacter {
+        Nul,
+        Equal,
+    }
+
+    match key.as_bytes().iter().find_map(|&ch| {
+        if ch == 0 {
+            Some(InvalidCharacter::Nul)
+        } else if ch == b'=' {
+            Some(InvalidCharacter::Equal)
+        } else {
+            None
+        }
+    }) {
+        Some(InvalidCharacter::Nul) => {
+            return Err(WasiStateCreationError::EnvironmentVariableFormatError(
+                format!("found nul byte in env var key \"{}\" (key=value)", key),
+            ))
+        }
+
+        Some(InvalidCharacter::Equal) => {
+            return Err(WasiStateCreationError::EnvironmentVariableFormatError(
+                format!("found equal sign in env var key \"{}\" (key=value)", key),
+            ))
+        }
+
+        None => (),
+    }
+
+    if value.iter().any(|&ch| ch == 0) {
+        return Err(WasiStateCreationError::EnvironmentVariableFormatError(
+            format!(
+                "found nul byte in env var value \"{}\" (key=value)",
+                String::from_utf8_lossy(value),
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
 pub type SetupFsFn = Box<dyn Fn(&WasiInodes, &mut WasiFs) -> Result<(), String> + Send>;
 
 // TODO add other WasiFS APIs here like swapping out stdout, for example (though we need to
@@ -260,6 +416,22 @@ impl WasiEnvBuilder {
         }
     }
 
+    /// Overrides the first argument (`argv[0]`) seen by the program without
+    /// changing the program name, which is useful for multicall binaries
+    /// that behave differently depending on how they were invoked.
+    ///
+    /// The argument must not contain the nul (0x0) byte.
+    pub fn with_argv0(mut self, argv0: impl Into<String>) -> Self {
+        self.set_argv0(argv0);
+        self
+    }
+
+    /// Overrides the first argument (`argv[0]`) seen by the program without
+    /// changing the program name.
+    pub fn set_argv0(&mut self, argv0: impl Into<String>) {
+        self.argv0 = Some(argv0.into());
+    }
+
     /// Get a reference to the configured arguments.
     pub fn get_args(&self) -> &[String] {
         &self.args
@@ -288,6 +460,24 @@ impl WasiEnvBuilder {
         self
     }
 
+    /// Sets the package that [`WasiEnvBuilder::run_resolved_package()`]
+    /// runs.
+    ///
+    /// The package has already been loaded, so running it doesn't need a
+    /// registry. Its files are made available to the instance the same way
+    /// as with [`WasiEnvBuilder::use_webc()`].
+    pub fn with_resolved_package(mut self, pkg: BinaryPackage) -> Self {
+        self.set_resolved_package(pkg);
+        self
+    }
+
+    /// Sets the package that [`WasiEnvBuilder::run_resolved_package()`]
+    /// runs.
+    pub fn set_resolved_package(&mut self, pkg: BinaryPackage) -> &mut Self {
+        self.resolved_package = Some(pkg);
+        self
+    }
+
     /// Adds a list of other containers this module inherits from.
     ///
     /// This will make all of the container's files and commands available to the
@@ -466,6 +656,139 @@ impl WasiEnvBuilder {
         Ok(())
     }
 
+    /// Preopen a directory with a different name exposed to the WASI, taking
+    /// precedence over the other preopens at `alias` with a lower
+    /// `priority`.
+    ///
+    /// This is useful to layer an override directory on top of a base
+    /// directory. See [`PreopenDirBuilder::priority()`] for the rules.
+    pub fn with_preopen_priority<P>(
+        mut self,
+        alias: &str,
+        po_dir: P,
+        priority: i32,
+    ) -> Result<Self, WasiStateCreationError>
+    where
+        P: AsRef<Path>,
+    {
+        self.add_preopen_priority(alias, po_dir, priority)?;
+        Ok(self)
+    }
+
+    /// Preopen a directory with a different name exposed to the WASI, with
+    /// the given `priority`.
+    pub fn add_preopen_priority<P>(
+        &mut self,
+        alias: &str,
+        po_dir: P,
+        priority: i32,
+    ) -> Result<(), WasiStateCreationError>
+    where
+        P: AsRef<Path>,
+    {
+        self.add_preopen_build(|p| {
+            p.directory(po_dir.as_ref())
+                .alias(alias)
+                .read(true)
+                .write(true)
+                .create(true)
+                .priority(priority)
+        })
+    }
+
+    /// Makes a single file from the host visible to WASI at `alias`,
+    /// without giving access to anything else in its directory. This is a
+    /// finer-grained alternative to [`WasiEnvBuilder::map_dir()`].
+    ///
+    /// The file can only be opened for reading or writing when `read` or
+    /// `write` are set respectively. It can't be removed or renamed.
+    ///
+    /// This only works with the default, sandboxed, file system.
+    pub fn with_preopen_host_file(
+        mut self,
+        alias: &str,
+        host_path: &Path,
+        read: bool,
+        write: bool,
+    ) -> Self {
+        self.add_preopen_host_file(alias, host_path, read, write);
+        self
+    }
+
+    /// Makes a single file from the host visible to WASI at `alias`.
+    pub fn add_preopen_host_file(
+        &mut self,
+        alias: &str,
+        host_path: &Path,
+        read: bool,
+        write: bool,
+    ) {
+        self.preopen_host_files.push(PreopenedHostFile {
+            alias: Path::new("/").join(alias),
+            host_path: host_path.to_path_buf(),
+            read,
+            write,
+        });
+    }
+
+    /// Exposes the host directory `host_dir` to WASI at `alias` without ever
+    /// writing to it.
+    ///
+    /// Files are read from the host until the program modifies them. They
+    /// are then copied into `scratch`, and that copy is what gets written to
+    /// and read back from then on. New files are created in `scratch`, too.
+    /// Keep a clone of `scratch` around to inspect the changes afterwards.
+    ///
+    /// This only works with the default, sandboxed, file system.
+    pub fn with_preopen_overlay_writes_to(
+        mut self,
+        alias: &str,
+        host_dir: &Path,
+        scratch: TmpFileSystem,
+    ) -> Self {
+        self.add_preopen_overlay_writes_to(alias, host_dir, scratch);
+        self
+    }
+
+    /// Exposes the host directory `host_dir` to WASI at `alias`, sending
+    /// all changes to `scratch`.
+    pub fn add_preopen_overlay_writes_to(
+        &mut self,
+        alias: &str,
+        host_dir: &Path,
+        scratch: TmpFileSystem,
+    ) {
+        self.preopen_overlays.push(PreopenedOverlay {
+            alias: Path::new("/").join(alias),
+            host_dir: host_dir.to_path_buf(),
+            scratch,
+        });
+    }
+
+    /// Exposes the host directory `host_dir` to WASI at `alias`, only
+    /// allowing the program to read from it.
+    ///
+    /// The preopen only gets the rights to read, and the directory is
+    /// mounted read-only as well, so writes are refused with `EROFS` even
+    /// when the program goes through `/` instead of the preopen. This is
+    /// shorthand for the common case of [`WasiEnvBuilder::preopen_build()`]
+    /// with only `read(true)`.
+    ///
+    /// This only works with the default, sandboxed, file system.
+    pub fn with_preopen_readonly_dir(mut self, alias: &str, host_dir: &Path) -> Self {
+        self.add_preopen_readonly_dir(alias, host_dir);
+        self
+    }
+
+    /// Exposes the host directory `host_dir` to WASI at `alias`, without
+    /// allowing any changes to it.
+    pub fn add_preopen_readonly_dir(&mut self, alias: &str, host_dir: &Path) {
+        self.preopen_readonly_dirs.push(PreopenedReadOnlyDir {
+            alias: Path::new("/").join(alias),
+            host_dir: host_dir.to_path_buf(),
+        });
+    }
+
     /// Preopen directorys with a different names exposed to the WASI.
     pub fn map_dirs<I, P>(mut self, mapped_dirs: I) -> Result<Self, WasiStateCreationError>
     where
@@ -506,10 +829,64 @@ impl WasiEnvBuilder {
         self.stderr = Some(new_file);
     }
 
+    /// Writes `stdout` to the file at `path` on the host, creating it if
+    /// it doesn't exist.
+    ///
+    /// The output is added to the end of an existing file when `append` is
+    /// set, otherwise the file is truncated first.
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    pub fn with_stdout_to_file(
+        mut self,
+        path: &Path,
+        append: bool,
+    ) -> Result<Self, WasiStateCreationError> {
+        self.set_stdout_to_file(path, append)?;
+        Ok(self)
+    }
+
+    /// Writes `stdout` to the file at `path` on the host, creating it if
+    /// it doesn't exist.
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    pub fn set_stdout_to_file(
+        &mut self,
+        path: &Path,
+        append: bool,
+    ) -> Result<(), WasiStateCreationError> {
+        self.stdout = Some(open_host_output_file(path, append)?);
+        Ok(())
+    }
+
+    /// Writes `stderr` to the file at `path` on the host, creating it if
+    /// it doesn't exist.
+    ///
+    /// The output is added to the end of an existing file when `append` is
+    /// set, otherwise the file is truncated first.
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    pub fn with_stderr_to_file(
+        mut self,
+        path: &Path,
+        append: bool,
+    ) -> Result<Self, WasiStateCreationError> {
+        self.set_stderr_to_file(path, append)?;
+        Ok(self)
+    }
+
+    /// Writes `stderr` to the file at `path` on the host, creating it if
+    /// it doesn't exist.
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    pub fn set_stderr_to_file(
+        &mut self,
+        path: &Path,
+        append: bool,
+    ) -> Result<(), WasiStateCreationError> {
+        self.stderr = Some(open_host_output_file(path, append)?);
+        Ok(())
+    }
+
     /// Overwrite the default WASI `stdin`, if you want to hold on to the
     /// original `stdin` use [`WasiFs::swap_file`] after building.
     pub fn stdin(mut self, new_file: Box<dyn VirtualFile + Send + Sync + 'static>) -> Self {
-        self.stdin = Some(new_file);
+        self.set_stdin(new_file);
 
         self
     }
@@ -518,6 +895,240 @@ impl WasiEnvBuilder {
     /// original `stdin` use [`WasiFs::swap_file`] after building.
     pub fn set_stdin(&mut self, new_file: Box<dyn VirtualFile + Send + Sync + 'static>) {
         self.stdin = Some(new_file);
+        self.stdin_file_path = None;
+    }
+
+    /// Reads `stdin` from the file at `path`.
+    ///
+    /// The file is opened read-only from the configured file system, or from
+    /// the host when no file system was configured and the `host-fs` feature
+    /// is enabled. Building fails if the file can't be opened.
+    pub fn with_stdin_file_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.set_stdin_file_path(path);
+
+        self
+    }
+
+    /// Reads `stdin` from the file at `path`.
+    ///
+    /// The file is opened read-only from the configured file system, or from
+    /// the host when no file system was configured and the `host-fs` feature
+    /// is enabled. Building fails if the file can't be opened.
+    pub fn set_stdin_file_path(&mut self, path: impl AsRef<Path>) {
+        self.stdin_file_path = Some(path.as_ref().to_path_buf());
+        self.stdin = None;
+    }
+
+    /// Connects the program's `stdin`, `stdout` and `stderr` to those of the
+    /// host process, like a subprocess that inherits its parent's stdio.
+    ///
+    /// Reading from `stdin` waits for the host's `stdin` without blocking
+    /// the async runtime. This replaces any stdio that was set before.
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    pub fn inherit_stdio(mut self) -> Self {
+        self.set_inherit_stdio();
+        self
+    }
+
+    /// Connects the program's stdio to those of the host process.
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    pub fn set_inherit_stdio(&mut self) {
+        self.set_stdin(Box::new(ArcFile::new(
+            Box::<virtual_fs::host_fs::Stdin>::default(),
+        )));
+        self.set_stdout(Box::<virtual_fs::host_fs::Stdout>::default());
+        self.set_stderr(Box::<virtual_fs::host_fs::Stderr>::default());
+    }
+
+    /// Prepends `prefix` to every line written to `stdout`.
+    ///
+    /// Output is line buffered, a trailing partial line is written out
+    /// (with its prefix) when `stdout` is flushed or closed.
+    pub fn with_stdout_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.set_stdout_prefix(prefix);
+        self
+    }
+
+    /// Prepends `prefix` to every line written to `stdout`.
+    pub fn set_stdout_prefix(&mut self, prefix: impl Into<String>) {
+        self.stdout_prefix = Some(prefix.into());
+    }
+
+    /// Prepends `prefix` to every line written to `stderr`.
+    ///
+    /// Output is line buffered, a trailing partial line is written out
+    /// (with its prefix) when `stderr` is flushed or closed.
+    pub fn with_stderr_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.set_stderr_prefix(prefix);
+        self
+    }
+
+    /// Prepends `prefix` to every line written to `stderr`.
+    pub fn set_stderr_prefix(&mut self, prefix: impl Into<String>) {
+        self.stderr_prefix = Some(prefix.into());
+    }
+
+    /// Controls whether `stdin`, `stdout` and `stderr` are reported as a TTY
+    /// (character device) by `fd_fdstat_get`, which is what `isatty()` uses.
+    ///
+    /// By default the stdio file descriptors are reported as a TTY.
+    pub fn with_stdio_tty(mut self, is_tty: bool) -> Self {
+        self.set_stdio_tty(is_tty);
+        self
+    }
+
+    /// Controls whether `stdin`, `stdout` and `stderr` are reported as a TTY
+    /// (character device) by `fd_fdstat_get`, which is what `isatty()` uses.
+    pub fn set_stdio_tty(&mut self, is_tty: bool) {
+        self.stdio_tty = Some(is_tty);
+    }
+
+    /// Controls whether file descriptors opened by the program are marked
+    /// as close-on-exec, which means they are not inherited by the
+    /// processes it spawns or the programs it executes.
+    ///
+    /// Individual file descriptors can be changed afterwards with
+    /// [`WasiFs::set_fd_close_on_exec`]. Stdio and preopened directories
+    /// are always inherited.
+    ///
+    /// By default file descriptors are inherited.
+    pub fn with_close_on_exec_defaults(mut self, close_on_exec: bool) -> Self {
+        self.set_close_on_exec_defaults(close_on_exec);
+        self
+    }
+
+    /// Controls whether file descriptors opened by the program are marked
+    /// as close-on-exec.
+    pub fn set_close_on_exec_defaults(&mut self, close_on_exec: bool) {
+        self.close_on_exec_defaults = Some(close_on_exec);
+    }
+
+    /// Limits how many file descriptors can be open at the same time,
+    /// counting stdio and the preopened directories. Once the limit is
+    /// reached, opening another file fails with [`Errno::Mfile`].
+    ///
+    /// By default the number of open file descriptors is not limited.
+    pub fn with_max_open_files(mut self, max_open_files: u32) -> Self {
+        self.set_max_open_files(max_open_files);
+        self
+    }
+
+    /// Limits how many file descriptors can be open at the same time.
+    pub fn set_max_open_files(&mut self, max_open_files: u32) {
+        self.max_open_files = Some(max_open_files);
+    }
+
+    /// Mounts a read-only [`ProcFileSystem`] at `/proc`, so the program can
+    /// read its own `/proc/self/cmdline`, `/proc/self/environ` and
+    /// `/proc/self/status`.
+    ///
+    /// This only works with the default, sandboxed, file system.
+    pub fn with_proc_fs(mut self, enabled: bool) -> Self {
+        self.set_proc_fs(enabled);
+        self
+    }
+
+    /// Mounts a read-only [`ProcFileSystem`] at `/proc`.
+    pub fn set_proc_fs(&mut self, enabled: bool) {
+        self.proc_fs = enabled;
+    }
+
+    /// Decides what happens when `signal` is sent to the program while it
+    /// hasn't registered a handler for it, e.g. whether a `SIGTERM` lets the
+    /// embedder shut down gracefully instead of being ignored.
+    ///
+    /// `SIGKILL` always terminates the program.
+    pub fn with_signal_disposition(
+        mut self,
+        signal: Signal,
+        disposition: SignalDisposition,
+    ) -> Self {
+        self.set_signal_disposition(signal, disposition);
+        self
+    }
+
+    /// Decides what happens when `signal` is sent to the program while it
+    /// hasn't registered a handler for it.
+    pub fn set_signal_disposition(&mut self, signal: Signal, disposition: SignalDisposition) {
+        self.signal_dispositions.insert(signal, disposition);
+    }
+
+    /// Counts how many times the program makes each syscall, which can be
+    /// read back with [`WasiEnv::syscall_metrics()`].
+    ///
+    /// Every syscall goes through an extra indirection while this is
+    /// enabled, so it is meant for profiling rather than production use.
+    pub fn enable_syscall_metrics(mut self) -> Self {
+        self.set_syscall_metrics(true);
+        self
+    }
+
+    /// Sets whether the syscalls the program makes are counted.
+    ///
+    /// See [`WasiEnvBuilder::enable_syscall_metrics()`] for details.
+    pub fn set_syscall_metrics(&mut self, enabled: bool) {
+        self.syscall_metrics = enabled;
+    }
+
+    /// Runs the program's threads one at a time instead of in parallel,
+    /// switching between them whenever a thread yields, blocks or exits.
+    ///
+    /// The next thread is picked using `seed`, so a program whose threads
+    /// coordinate by yielding does the same thing on every run with the same
+    /// seed. This trades realism for reproducibility and is meant for tests.
+    pub fn with_deterministic_scheduling(mut self, seed: u64) -> Self {
+        self.set_deterministic_scheduling(Some(seed));
+        self
+    }
+
+    /// Sets the seed used to schedule the program's threads, or lets them
+    /// run in parallel when `None`.
+    ///
+    /// See [`WasiEnvBuilder::with_deterministic_scheduling()`] for details.
+    pub fn set_deterministic_scheduling(&mut self, seed: Option<u64>) {
+        self.deterministic_scheduling = seed;
+    }
+
+    /// Invokes the callback with the exit code of the program once it has
+    /// finished and the environment has been cleaned up.
+    ///
+    /// Only [`WasiEnvBuilder::run_with_store()`] and
+    /// [`WasiEnvBuilder::run_with_store_async()`] (and the methods built on
+    /// them) call it, and only if the program was started.
+    pub fn on_exit(mut self, callback: Box<dyn FnOnce(ExitCode) + Send>) -> Self {
+        self.set_on_exit(callback);
+        self
+    }
+
+    /// Invokes the callback with the exit code of the program once it has
+    /// finished.
+    pub fn set_on_exit(&mut self, callback: Box<dyn FnOnce(ExitCode) + Send>) {
+        self.on_exit = Some(callback);
+    }
+
+    /// Hands `listener` to the program as a socket that is already
+    /// listening at the file descriptor `guest_fd`, so the program can
+    /// `sock_accept()` connections that arrive on the host.
+    ///
+    /// The listener is given to the program as is, there is no need for it
+    /// to be allowed to bind or listen on its own.
+    pub fn bridge_listener(
+        mut self,
+        listener: Box<dyn VirtualTcpListener + Sync>,
+        guest_fd: WasiFd,
+    ) -> Self {
+        self.add_bridged_listener(listener, guest_fd);
+        self
+    }
+
+    /// Hands `listener` to the program as a socket that is already
+    /// listening at the file descriptor `guest_fd`.
+    pub fn add_bridged_listener(
+        &mut self,
+        listener: Box<dyn VirtualTcpListener + Sync>,
+        guest_fd: WasiFd,
+    ) {
+        self.bridged_listeners.push((guest_fd, listener));
     }
 
     /// Sets the FileSystem to be used with this WASI instance.
@@ -532,6 +1143,22 @@ impl WasiEnvBuilder {
         self.fs = Some(WasiFsRoot::Backing(Arc::new(fs)));
     }
 
+    /// Makes the whole file system read-only, so anything that would change
+    /// it fails with [`Errno::Rofs`].
+    ///
+    /// When `writable_tmp` is set, `/tmp` is created if needed and stays
+    /// writable.
+    pub fn with_read_only_fs(mut self, writable_tmp: bool) -> Self {
+        self.set_read_only_fs(writable_tmp);
+        self
+    }
+
+    /// Makes the whole file system read-only, except `/tmp` when
+    /// `writable_tmp` is set.
+    pub fn set_read_only_fs(&mut self, writable_tmp: bool) {
+        self.read_only_fs = Some(writable_tmp);
+    }
+
     /// Sets a new sandbox FileSystem to be used with this WASI instance.
     ///
     /// This is usually used in case a custom `virtual_fs::FileSystem` is needed.
@@ -572,66 +1199,124 @@ impl WasiEnvBuilder {
         self.capabilites = capabilities;
     }
 
-    /// Consumes the [`WasiEnvBuilder`] and produces a [`WasiEnvInit`], which
-    /// can be used to construct a new [`WasiEnv`].
+    /// Limits how much work the guest may do before it is stopped with
+    /// [`WasiError::OutOfFuel`].
     ///
-    /// Returns the error from `WasiFs::new` if there's an error
+    /// The limit is only enforced for modules that were compiled with the
+    /// metering middleware from `wasmer-middlewares`, which decides how much
+    /// fuel each instruction costs.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.set_fuel(fuel);
+        self
+    }
+
+    /// Limits how much work the guest may do before it is stopped with
+    /// [`WasiError::OutOfFuel`].
     ///
-    /// NOTE: You should prefer to not work directly with [`WasiEnvInit`].
-    /// Use [`WasiEnvBuilder::run`] or [`WasiEnvBuilder::run_with_store`] instead
-    /// to ensure proper invokation of WASI modules.
-    pub fn build_init(mut self) -> Result<WasiEnvInit, WasiStateCreationError> {
-        for arg in self.args.iter() {
-            for b in arg.as_bytes().iter() {
-                if *b == 0 {
-                    return Err(WasiStateCreationError::ArgumentContainsNulByte(arg.clone()));
-                }
+    /// See [`WasiEnvBuilder::with_fuel()`] for details.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.capabilites.fuel = Some(fuel);
+    }
+
+    /// Names the OS threads that run the guest `<prefix>-<tid>`, so they
+    /// can be told apart in a debugger or profiler.
+    #[cfg(feature = "sys-thread")]
+    pub fn with_thread_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.set_thread_name_prefix(prefix);
+        self
+    }
+
+    /// Names the OS threads that run the guest `<prefix>-<tid>`.
+    ///
+    /// See [`WasiEnvBuilder::with_thread_name_prefix()`] for details.
+    #[cfg(feature = "sys-thread")]
+    pub fn set_thread_name_prefix(&mut self, prefix: impl Into<String>) {
+        self.capabilites.threading.thread_name_prefix = Some(prefix.into());
+    }
+
+    /// Checks the arguments, environment variables and directory aliases
+    /// without building anything.
+    ///
+    /// Unlike [`WasiEnvBuilder::build_init()`], which stops at the first
+    /// problem, this reports every problem that was found.
+    pub fn validate(&self) -> Result<(), Vec<WasiStateCreationError>> {
+        let mut errors = Vec::new();
+
+        for arg in self.args.iter().chain(self.argv0.iter()) {
+            if arg.as_bytes().contains(&0) {
+                errors.push(WasiStateCreationError::ArgumentContainsNulByte(arg.clone()));
             }
         }
 
-        enum InvalidCharacter {
-            Nul,
-            Equal,
+        for (env_key, env_value) in self.envs.iter() {
+            if let Err(err) = validate_env_var(env_key, env_value) {
+                errors.push(err);
+            }
         }
 
-        for (env_key, env_value) in self.envs.iter() {
-            match env_key.as_bytes().iter().find_map(|&ch| {
-                if ch == 0 {
-                    Some(InvalidCharacter::Nul)
-                } else if ch == b'=' {
-                    Some(InvalidCharacter::Equal)
-                } else {
-                    None
-                }
-            }) {
-                Some(InvalidCharacter::Nul) => {
-                    return Err(WasiStateCreationError::EnvironmentVariableFormatError(
-                        format!("found nul byte in env var key \"{}\" (key=value)", env_key),
-                    ))
-                }
+        for alias in self.preopens.iter().filter_map(|dir| dir.alias.as_ref()) {
+            if let Err(err) = validate_mapped_dir_alias(alias) {
+                errors.push(err);
+            }
+        }
 
-                Some(InvalidCharacter::Equal) => {
-                    return Err(WasiStateCreationError::EnvironmentVariableFormatError(
-                        format!(
-                            "found equal sign in env var key \"{}\" (key=value)",
-                            env_key
-                        ),
-                    ))
-                }
+        // Preopens may only share a guest path when their priorities decide
+        // which one is used. Overlays and read-only directories are only
+        // preopened by `build_init()`, with the default priority.
+        let late_mount_points = self
+            .preopen_overlays
+            .iter()
+            .map(|overlay| &overlay.alias)
+            .chain(self.preopen_readonly_dirs.iter().map(|dir| &dir.alias))
+            .map(|alias| (normalize_mount_point(&alias.to_string_lossy()), 0));
+        let mut mount_points = HashSet::new();
+        for (mount_point, priority) in self
+            .preopens
+            .iter()
+            .map(|preopen| (preopen.mount_point(), preopen.priority))
+            .chain(late_mount_points)
+        {
+            if !mount_points.insert((mount_point.clone(), priority)) {
+                errors.push(WasiStateCreationError::DuplicateMappedDirAlias(mount_point));
+            }
+        }
+
+        for file in &self.preopen_host_files {
+            if let Err(err) = validate_mapped_dir_alias(&file.alias.to_string_lossy()) {
+                errors.push(err);
+            }
+        }
 
-                None => (),
+        for overlay in &self.preopen_overlays {
+            if let Err(err) = validate_mapped_dir_alias(&overlay.alias.to_string_lossy()) {
+                errors.push(err);
             }
+        }
 
-            if env_value.iter().any(|&ch| ch == 0) {
-                return Err(WasiStateCreationError::EnvironmentVariableFormatError(
-                    format!(
-                        "found nul byte in env var value \"{}\" (key=value)",
-                        String::from_utf8_lossy(env_value),
-                    ),
-                ));
+        for dir in &self.preopen_readonly_dirs {
+            if let Err(err) = validate_mapped_dir_alias(&dir.alias.to_string_lossy()) {
+                errors.push(err);
             }
         }
 
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Consumes the [`WasiEnvBuilder`] and produces a [`WasiEnvInit`], which
+    /// can be used to construct a new [`WasiEnv`].
+    ///
+    /// Returns the error from `WasiFs::new` if there's an error
+    ///
+    /// NOTE: You should prefer to not work directly with [`WasiEnvInit`].
+    /// Use [`WasiEnvBuilder::run`] or [`WasiEnvBuilder::run_with_store`] instead
+    /// to ensure proper invokation of WASI modules.
+    pub fn build_init(mut self) -> Result<WasiEnvInit, WasiStateCreationError> {
+        self.validate().map_err(|mut errors| errors.remove(0))?;
+
         // TODO: must be used! (runtime was removed from env, must ensure configured runtime is used)
         // // Get a reference to the runtime
         // let runtime = self
@@ -645,25 +1330,224 @@ impl WasiEnvBuilder {
             .take()
             .unwrap_or_else(|| Box::new(ArcFile::new(Box::<super::Stdin>::default())));
 
+        let host_stdin = self.fs.is_none();
         let fs_backing = self
             .fs
             .take()
             .unwrap_or_else(|| WasiFsRoot::Sandbox(Arc::new(TmpFileSystem::new())));
 
-        // self.preopens are checked in [`PreopenDirBuilder::build`]
-        let inodes = crate::state::WasiInodes::new();
-        let wasi_fs = {
-            // self.preopens are checked in [`PreopenDirBuilder::build`]
-            let mut wasi_fs =
-                WasiFs::new_with_preopen(&inodes, &self.preopens, &self.vfs_preopens, fs_backing)
-                    .map_err(WasiStateCreationError::WasiFsCreationError)?;
+        // Anything that is mounted later on goes into the sandbox, even when
+        // it ends up being wrapped
+        let sandbox_fs = match &fs_backing {
+            WasiFsRoot::Sandbox(fs) => Some(fs.clone()),
+            WasiFsRoot::Backing(_) => None,
+        };
 
-            // set up the file system, overriding base files and calling the setup function
-            wasi_fs
-                .swap_file(__WASI_STDIN_FILENO, stdin)
-                .map_err(WasiStateCreationError::FileSystemError)?;
+        for file in &self.preopen_host_files {
+            let root_fs = match &sandbox_fs {
+                Some(fs) => fs,
+                None => {
+                    return Err(WasiStateCreationError::WasiFsSetupError(format!(
+                        "unable to expose \"{}\" because the file system is not sandboxed",
+                        file.host_path.display()
+                    )));
+                }
+            };
+
+            let mut parents: Vec<_> = file.alias.ancestors().skip(1).collect();
+            parents.reverse();
+            for dir in parents.into_iter().filter(|dir| dir.parent().is_some()) {
+                match root_fs.create_dir(dir) {
+                    Ok(()) | Err(FsError::AlreadyExists) => {}
+                    Err(err) => return Err(WasiStateCreationError::FileSystemError(err)),
+                }
+            }
 
-            if let Some(stdout_override) = self.stdout.take() {
+            let host_fs = SingleFileSystem::new(
+                Arc::new(crate::default_fs_backing()),
+                file.host_path.clone(),
+                file.read,
+                file.write,
+            );
+            root_fs.new_open_options_ext().insert_arc_file_at(
+                file.alias.clone(),
+                Arc::new(host_fs),
+                file.host_path.clone(),
+            )?;
+        }
+
+        for overlay in std::mem::take(&mut self.preopen_overlays) {
+            let root_fs = match &sandbox_fs {
+                Some(fs) => fs,
+                None => {
+                    return Err(WasiStateCreationError::WasiFsSetupError(format!(
+                        "unable to expose \"{}\" because the file system is not sandboxed",
+                        overlay.host_dir.display()
+                    )));
+                }
+            };
+
+            let host_fs: Arc<dyn FileSystem + Send + Sync> = Arc::from(crate::default_fs_backing());
+            match host_fs.metadata(&overlay.host_dir) {
+                Ok(metadata) if metadata.is_dir() => {}
+                _ => {
+                    return Err(WasiStateCreationError::PreopenedDirectoryNotFound(
+                        overlay.host_dir.clone(),
+                    ));
+                }
+            }
+
+            let mut parents: Vec<_> = overlay.alias.ancestors().skip(1).collect();
+            parents.reverse();
+            for dir in parents.into_iter().filter(|dir| dir.parent().is_some()) {
+                match root_fs.create_dir(dir) {
+                    Ok(()) | Err(FsError::AlreadyExists) => {}
+                    Err(err) => return Err(WasiStateCreationError::FileSystemError(err)),
+                }
+            }
+
+            // The host directory is the read-only lower layer and the
+            // scratch file system the upper one, which files get copied
+            // into the first time they are modified
+            let lower = TmpFileSystem::new();
+            lower.mount_directory_entries(Path::new("/"), &host_fs, &overlay.host_dir)?;
+            let lower: Arc<dyn FileSystem + Send + Sync> = Arc::new(lower);
+            let upper = CopyUpFileSystem::new(overlay.scratch.clone(), lower.clone());
+            let overlay_fs: Arc<dyn FileSystem + Send + Sync> =
+                Arc::new(OverlayFileSystem::new(upper, [lower]));
+            root_fs.mount(overlay.alias.clone(), &overlay_fs, PathBuf::from("/"))?;
+
+            let alias = overlay.alias.to_string_lossy();
+            self.add_preopen_build(|p| {
+                p.directory(&overlay.alias)
+                    .alias(&alias)
+                    .read(true)
+                    .write(true)
+                    .create(true)
+            })?;
+        }
+
+        for dir in std::mem::take(&mut self.preopen_readonly_dirs) {
+            let root_fs = match &sandbox_fs {
+                Some(fs) => fs,
+                None => {
+                    return Err(WasiStateCreationError::WasiFsSetupError(format!(
+                        "unable to expose \"{}\" because the file system is not sandboxed",
+                        dir.host_dir.display()
+                    )));
+                }
+            };
+
+            let host_fs: Arc<dyn FileSystem + Send + Sync> = Arc::from(crate::default_fs_backing());
+            match host_fs.metadata(&dir.host_dir) {
+                Ok(metadata) if metadata.is_dir() => {}
+                _ => {
+                    return Err(WasiStateCreationError::PreopenedDirectoryNotFound(
+                        dir.host_dir.clone(),
+                    ));
+                }
+            }
+
+            let mut parents: Vec<_> = dir.alias.ancestors().skip(1).collect();
+            parents.reverse();
+            for parent in parents.into_iter().filter(|path| path.parent().is_some()) {
+                match root_fs.create_dir(parent) {
+                    Ok(()) | Err(FsError::AlreadyExists) => {}
+                    Err(err) => return Err(WasiStateCreationError::FileSystemError(err)),
+                }
+            }
+
+            let read_only: Arc<dyn FileSystem + Send + Sync> =
+                Arc::new(ReadOnlyFileSystem::new(host_fs));
+            root_fs.mount(dir.alias.clone(), &read_only, dir.host_dir.clone())?;
+
+            // The mount point is then preopened with nothing but read rights
+            let alias = dir.alias.to_string_lossy();
+            self.add_preopen_build(|p| p.directory(&dir.alias).alias(&alias).read(true))?;
+        }
+
+        let fs_backing = match self.read_only_fs {
+            Some(writable_tmp) => {
+                let mut writable_dirs = Vec::new();
+                if writable_tmp {
+                    match fs_backing.create_dir(Path::new("/tmp")) {
+                        Ok(()) | Err(FsError::AlreadyExists) => {}
+                        Err(err) => return Err(WasiStateCreationError::FileSystemError(err)),
+                    }
+                    writable_dirs.push(PathBuf::from("/tmp"));
+                }
+                match fs_backing {
+                    // The sandbox stays a sandbox, so packages can still be
+                    // added to it
+                    WasiFsRoot::Sandbox(fs) => {
+                        // The commands of packages are installed in `/bin`
+                        match fs.create_dir(Path::new("/bin")) {
+                            Ok(()) | Err(FsError::AlreadyExists) => {}
+                            Err(err) => return Err(WasiStateCreationError::FileSystemError(err)),
+                        }
+                        let fs = TmpFileSystem::clone(&fs).with_read_only(writable_dirs);
+                        WasiFsRoot::Sandbox(Arc::new(fs))
+                    }
+                    WasiFsRoot::Backing(fs) => {
+                        let read_only = writable_dirs.into_iter().fold(
+                            ReadOnlyFileSystem::new(Arc::new(WasiFsRoot::Backing(fs))),
+                            ReadOnlyFileSystem::with_writable_dir,
+                        );
+                        WasiFsRoot::Backing(Arc::new(Box::new(read_only)))
+                    }
+                }
+            }
+            None => fs_backing,
+        };
+
+        let stdin = match &self.stdin_file_path {
+            Some(path) => {
+                let open = |fs: &dyn FileSystem| fs.new_open_options().read(true).open(path);
+                let file = if host_stdin {
+                    open(&*crate::default_fs_backing())
+                } else {
+                    open(&fs_backing)
+                };
+                file.map_err(|err| {
+                    WasiStateCreationError::WasiFsSetupError(format!(
+                        "unable to open \"{}\" as stdin: {err}",
+                        path.display()
+                    ))
+                })?
+            }
+            None => stdin,
+        };
+
+        // self.preopens are checked in [`PreopenDirBuilder::build`]
+        let inodes = crate::state::WasiInodes::new();
+        let wasi_fs = {
+            // self.preopens are checked in [`PreopenDirBuilder::build`]
+            let mut wasi_fs =
+                WasiFs::new_with_preopen(&inodes, &self.preopens, &self.vfs_preopens, fs_backing)
+                    .map_err(WasiStateCreationError::WasiFsCreationError)?;
+
+            // set up the file system, overriding base files and calling the setup function
+            wasi_fs
+                .swap_file(__WASI_STDIN_FILENO, stdin)
+                .map_err(WasiStateCreationError::FileSystemError)?;
+
+            if let Some(prefix) = self.stdout_prefix.take() {
+                let stdout = self
+                    .stdout
+                    .take()
+                    .unwrap_or_else(|| Box::<super::Stdout>::default());
+                self.stdout = Some(Box::new(PrefixedFile::new(stdout, prefix)));
+            }
+
+            if let Some(prefix) = self.stderr_prefix.take() {
+                let stderr = self
+                    .stderr
+                    .take()
+                    .unwrap_or_else(|| Box::<super::Stderr>::default());
+                self.stderr = Some(Box::new(PrefixedFile::new(stderr, prefix)));
+            }
+
+            if let Some(stdout_override) = self.stdout.take() {
                 wasi_fs
                     .swap_file(__WASI_STDOUT_FILENO, stdout_override)
                     .map_err(WasiStateCreationError::FileSystemError)?;
@@ -675,6 +1559,32 @@ impl WasiEnvBuilder {
                     .map_err(WasiStateCreationError::FileSystemError)?;
             }
 
+            if let Some(is_tty) = self.stdio_tty {
+                wasi_fs.set_is_stdio_tty(is_tty);
+            }
+
+            if let Some(close_on_exec) = self.close_on_exec_defaults {
+                wasi_fs.set_close_on_exec_default(close_on_exec);
+            }
+
+            if let Some(max_open_files) = self.max_open_files {
+                wasi_fs.set_max_open_files(max_open_files);
+            }
+
+            for (fd, listener) in self.bridged_listeners.drain(..) {
+                let socket = InodeSocket::new(InodeSocketKind::TcpListener {
+                    socket: listener,
+                    accept_timeout: None,
+                });
+                wasi_fs
+                    .insert_socket_at(&inodes, socket, fd)
+                    .map_err(|err| {
+                        WasiStateCreationError::WasiFsSetupError(format!(
+                            "unable to bridge a listener to file descriptor {fd}: {err}"
+                        ))
+                    })?;
+            }
+
             if let Some(f) = &self.setup_fs_fn {
                 f(&inodes, &mut wasi_fs).map_err(WasiStateCreationError::WasiFsSetupError)?;
             }
@@ -692,19 +1602,40 @@ impl WasiEnvBuilder {
 
                 env
             })
-            .collect();
+            .collect::<Vec<_>>();
+
+        let program_name = self.args.first().cloned().unwrap_or_default();
+        let mut args = self.args.clone();
+        if let (Some(argv0), Some(first)) = (&self.argv0, args.first_mut()) {
+            *first = argv0.clone();
+        }
 
         let state = WasiState {
             fs: wasi_fs,
             secret: rand::thread_rng().gen::<[u8; 32]>(),
             inodes,
-            args: self.args.clone(),
+            program_name,
+            args,
             preopen: self.vfs_preopens.clone(),
             futexs: Default::default(),
             clock_offset: Default::default(),
-            envs,
+            envs: std::sync::Mutex::new(envs),
+            signal_dispositions: self.signal_dispositions.clone(),
+            syscall_metrics: self
+                .syscall_metrics
+                .then(|| Arc::new(SyscallMetrics::default())),
         };
 
+        if self.proc_fs {
+            let root_fs = sandbox_fs.ok_or_else(|| {
+                WasiStateCreationError::WasiFsSetupError(
+                    "unable to mount /proc because the file system is not sandboxed".to_string(),
+                )
+            })?;
+            let proc_fs: Arc<dyn FileSystem + Send + Sync> = Arc::new(ProcFileSystem::new(&state));
+            root_fs.mount(PathBuf::from("/proc"), &proc_fs, PathBuf::from("/"))?;
+        }
+
         let runtime = self.runtime.unwrap_or_else(|| {
             #[cfg(feature = "sys-thread")]
             {
@@ -727,6 +1658,7 @@ impl WasiEnvBuilder {
         let plane_config = ControlPlaneConfig {
             max_task_count: capabilities.threading.max_threads,
             enable_asynchronous_threading: capabilities.threading.enable_asynchronous_threading,
+            deterministic_scheduling: self.deterministic_scheduling,
         };
         let control_plane = WasiControlPlane::new(plane_config);
 
@@ -792,43 +1724,36 @@ impl WasiEnvBuilder {
     }
 
     #[allow(clippy::result_large_err)]
-    pub fn run_with_store(self, module: Module, store: &mut Store) -> Result<(), WasiRuntimeError> {
+    pub fn run_with_store(
+        mut self,
+        module: Module,
+        store: &mut Store,
+    ) -> Result<(), WasiRuntimeError> {
         if self.capabilites.threading.enable_asynchronous_threading {
             tracing::warn!(
                 "The enable_asynchronous_threading capability is enabled. Use WasiEnvBuilder::run_with_store_async() to avoid spurious errors.",
             );
         }
 
+        let on_exit = self.on_exit.take();
         let (instance, env) = self.instantiate(module, store)?;
+        let result = run_instance(instance, env, store);
 
-        let start = instance.exports.get_function("_start")?;
-        env.data(&store).thread.set_status_running();
-
-        let result = crate::run_wasi_func_start(start, store);
         let (result, exit_code) = wasi_exit_code(result);
-
-        let pid = env.data(&store).pid();
-        let tid = env.data(&store).tid();
-        tracing::trace!(
-            %pid,
-            %tid,
-            %exit_code,
-            error=result.as_ref().err().map(|e| e as &dyn std::error::Error),
-            "main exit",
-        );
-
-        env.cleanup(store, Some(exit_code));
-
+        if let Some(on_exit) = on_exit {
+            on_exit(exit_code);
+        }
         result
     }
 
     /// Start the WASI executable with async threads enabled.
     #[allow(clippy::result_large_err)]
     pub fn run_with_store_async(
-        self,
+        mut self,
         module: Module,
         mut store: Store,
     ) -> Result<(), WasiRuntimeError> {
+        let on_exit = self.on_exit.take();
         let (_, env) = self.instantiate(module, &mut store)?;
 
         env.data(&store).thread.set_status_running();
@@ -842,9 +1767,20 @@ impl WasiEnvBuilder {
         // exit the dedicated thread
         let (tx, rx) = std::sync::mpsc::channel();
 
-        tasks.task_dedicated(Box::new(move || {
+        let thread_name = env
+            .data(&store)
+            .capabilities
+            .threading
+            .thread_name_prefix
+            .as_ref()
+            .map(|prefix| format!("{prefix}-{tid}"));
+        let task = Box::new(move || {
             run_with_deep_sleep(store, None, env, tx);
-        }))?;
+        });
+        match thread_name {
+            Some(name) => tasks.task_dedicated_named(name, task)?,
+            None => tasks.task_dedicated(task)?,
+        }
 
         let result = rx.recv().expect(
             "main thread terminated without a result, this normally means a panic occurred",
@@ -859,8 +1795,61 @@ impl WasiEnvBuilder {
             "main exit",
         );
 
+        if let Some(on_exit) = on_exit {
+            on_exit(exit_code);
+        }
         result
     }
+
+    /// Runs the entrypoint of the package given to
+    /// [`WasiEnvBuilder::with_resolved_package()`] and waits for it to
+    /// finish.
+    #[allow(clippy::result_large_err)]
+    pub fn run_resolved_package(mut self) -> Result<(), WasiRuntimeError> {
+        let pkg = self.resolved_package.take().ok_or_else(|| {
+            WasiStateCreationError::WasiIncludePackageError(
+                "no package was given to run".to_string(),
+            )
+        })?;
+        let on_exit = self.on_exit.take();
+        let name = self.args.first().cloned().unwrap_or_default();
+
+        let env = self.build()?;
+        let runtime = env.runtime.clone();
+        let store = runtime.new_store();
+        let tasks = env.tasks().clone();
+
+        let finished = tasks.block_on(async {
+            // The program may run the other commands of its package
+            env.use_package_async(&pkg).await?;
+            let mut handle = spawn_exec(pkg, &name, store, env, &runtime).await?;
+            Ok::<_, WasiRuntimeError>(handle.wait_finished().await)
+        })?;
+        let result = match finished {
+            Ok(exit_code) if exit_code.is_success() => Ok(()),
+            Ok(exit_code) => Err(WasiRuntimeError::Exit(exit_code)),
+            Err(err) => Err(unshare_runtime_error(err)),
+        };
+        let (result, exit_code) = wasi_exit_code(result);
+
+        if let Some(on_exit) = on_exit {
+            on_exit(exit_code);
+        }
+        result
+    }
+}
+
+/// Takes the error a process finished with out of the [`Arc`] it is shared
+/// through with everyone else waiting on the process.
+fn unshare_runtime_error(err: Arc<WasiRuntimeError>) -> WasiRuntimeError {
+    match Arc::try_unwrap(err) {
+        Ok(err) => err,
+        Err(err) => match &*err {
+            WasiRuntimeError::Runtime(err) => WasiRuntimeError::Runtime(err.clone()),
+            WasiRuntimeError::Exit(code) => WasiRuntimeError::Exit(*code),
+            other => WasiRuntimeError::Runtime(RuntimeError::new(other.to_string())),
+        },
+    }
 }
 
 /// Extract the exit code from a `Result<(), WasiRuntimeError>`.
@@ -888,6 +1877,45 @@ fn wasi_exit_code(
     (result, exit_code)
 }
 
+/// Runs the `_start` function of an instance that was created by
+/// [`WasiEnvBuilder::instantiate`] and cleans up the environment afterwards.
+#[allow(clippy::result_large_err)]
+fn run_instance(
+    instance: Instance,
+    env: WasiFunctionEnv,
+    store: &mut Store,
+) -> Result<(), WasiRuntimeError> {
+    let start = instance.exports.get_function("_start")?;
+    env.data(&store).thread.set_status_running();
+
+    let result = {
+        let _timer = env.data(&store).process.usage.cpu_timer();
+        crate::run_wasi_func_start(start, store)
+    };
+    let result = result.map_err(|err| {
+        if super::is_out_of_fuel(store, &instance) {
+            WasiError::OutOfFuel.into()
+        } else {
+            err
+        }
+    });
+    let (result, exit_code) = wasi_exit_code(result);
+
+    let pid = env.data(&store).pid();
+    let tid = env.data(&store).tid();
+    tracing::trace!(
+        %pid,
+        %tid,
+        %exit_code,
+        error=result.as_ref().err().map(|e| e as &dyn std::error::Error),
+        "main exit",
+    );
+
+    env.cleanup(store, Some(exit_code));
+
+    result
+}
+
 fn run_with_deep_sleep(
     mut store: Store,
     rewind_state: Option<(RewindState, Bytes)>,
@@ -917,7 +1945,7 @@ fn run_with_deep_sleep(
         if errno != Errno::Success {
             let exit_code = ExitCode::from(errno);
             env.cleanup(&mut store, Some(exit_code));
-            let _ = sender.send(Err(WasiRuntimeError::Wasi(WasiError::Exit(exit_code))));
+            let _ = sender.send(Err(WasiRuntimeError::Exit(exit_code)));
             return;
         }
     }
@@ -927,9 +1955,7 @@ fn run_with_deep_sleep(
         None => {
             tracing::debug!("Unable to clone the instance");
             env.cleanup(&mut store, None);
-            let _ = sender.send(Err(WasiRuntimeError::Wasi(WasiError::Exit(
-                Errno::Noexec.into(),
-            ))));
+            let _ = sender.send(Err(WasiRuntimeError::Exit(Errno::Noexec.into())));
             return;
         }
     };
@@ -944,7 +1970,10 @@ fn run_with_deep_sleep(
         }
     };
 
-    let result = start.call(&mut store, &[]);
+    let result = {
+        let _timer = env.data(&store).process.usage.cpu_timer();
+        start.call(&mut store, &[])
+    };
     handle_result(store, env, result, sender);
 }
 
@@ -975,8 +2004,20 @@ fn handle_result(
             return;
         }
         Ok(_) => Ok(()),
+        Err(Ok(WasiError::Exit(code))) => Err(WasiRuntimeError::Exit(code)),
         Err(Ok(other)) => Err(other.into()),
-        Err(Err(e)) => Err(e.into()),
+        Err(Err(e)) => {
+            let out_of_fuel = env
+                .data(&store)
+                .try_clone_instance()
+                .map(|instance| super::is_out_of_fuel(&mut store, &instance))
+                .unwrap_or(false);
+            if out_of_fuel {
+                Err(WasiError::OutOfFuel.into())
+            } else {
+                Err(e.into())
+            }
+        }
     };
 
     let (result, exit_code) = wasi_exit_code(result);
@@ -984,6 +2025,32 @@ fn handle_result(
     let _ = sender.send(result);
 }
 
+/// A host file exposed with [`WasiEnvBuilder::with_preopen_host_file()`].
+#[derive(Debug, Clone)]
+pub(crate) struct PreopenedHostFile {
+    alias: PathBuf,
+    host_path: PathBuf,
+    read: bool,
+    write: bool,
+}
+
+/// A host directory exposed with
+/// [`WasiEnvBuilder::with_preopen_overlay_writes_to()`].
+#[derive(Debug, Clone)]
+pub(crate) struct PreopenedOverlay {
+    alias: PathBuf,
+    host_dir: PathBuf,
+    scratch: TmpFileSystem,
+}
+
+/// A host directory exposed with
+/// [`WasiEnvBuilder::with_preopen_readonly_dir()`].
+#[derive(Debug, Clone)]
+pub(crate) struct PreopenedReadOnlyDir {
+    alias: PathBuf,
+    host_dir: PathBuf,
+}
+
 /// Builder for preopened directories.
 #[derive(Debug, Default)]
 pub struct PreopenDirBuilder {
@@ -992,6 +2059,7 @@ pub struct PreopenDirBuilder {
     read: bool,
     write: bool,
     create: bool,
+    priority: i32,
 }
 
 /// The built version of `PreopenDirBuilder`
@@ -1002,6 +2070,27 @@ pub(crate) struct PreopenedDir {
     pub(crate) read: bool,
     pub(crate) write: bool,
     pub(crate) create: bool,
+    pub(crate) priority: i32,
+}
+
+impl PreopenedDir {
+    /// The guest path this directory is mounted at, relative to the root.
+    pub(crate) fn mount_point(&self) -> String {
+        match &self.alias {
+            Some(alias) => normalize_mount_point(alias),
+            None => self.path.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+/// Strips the leading, trailing and repeated slashes from a guest path so
+/// aliases that name the same directory compare equal.
+fn normalize_mount_point(alias: &str) -> String {
+    alias
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
 }
 
 impl PreopenDirBuilder {
@@ -1057,6 +2146,20 @@ impl PreopenDirBuilder {
         self
     }
 
+    /// Decide which directory is exposed when several preopens are mounted
+    /// at the same guest path.
+    ///
+    /// The preopen with the highest priority wins and the others are left
+    /// out. The default priority is `0`. Preopens at the same guest path
+    /// must have different priorities, otherwise
+    /// [`WasiEnvBuilder::build_init()`] fails with
+    /// [`WasiStateCreationError::DuplicateMappedDirAlias`].
+    pub fn priority(&mut self, priority: i32) -> &mut Self {
+        self.priority = priority;
+
+        self
+    }
+
     pub(crate) fn build(&self) -> Result<PreopenedDir, WasiStateCreationError> {
         // ensure at least one is set
         if !(self.read || self.write || self.create) {
@@ -1086,13 +2189,17 @@ impl PreopenDirBuilder {
             read: self.read,
             write: self.write,
             create: self.create,
+            priority: self.priority,
         })
     }
 }
 
 #[cfg(test)]
 mod test {
+    use wasmer_wasix_types::wasi::{Fdflags, Rights};
+
     use super::*;
+    use crate::fs::Kind;
 
     #[test]
     fn env_var_errors() {
@@ -1153,4 +2260,1326 @@ mod test {
             WasiStateCreationError::ArgumentContainsNulByte(_)
         ));
     }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let builder = WasiEnvBuilder::new("test_prog")
+            .env("BAD=KEY", "value")
+            .arg("--h\0elp");
+
+        let errors = builder.validate().expect_err("should fail");
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .any(|err| matches!(err, WasiStateCreationError::ArgumentContainsNulByte(_))));
+        assert!(errors.iter().any(|err| matches!(
+            err,
+            WasiStateCreationError::EnvironmentVariableFormatError(_)
+        )));
+
+        assert!(WasiEnvBuilder::new("test_prog")
+            .env("KEY", "value")
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn mapping_two_dirs_to_the_same_alias_is_an_error() {
+        let result = WasiEnvBuilder::new("test_prog")
+            .map_dir("/data", "/first")
+            .unwrap()
+            .map_dir("data/", "/second")
+            .unwrap()
+            .build_init();
+
+        assert_eq!(
+            result.unwrap_err(),
+            WasiStateCreationError::DuplicateMappedDirAlias("data".to_string())
+        );
+    }
+
+    #[test]
+    fn a_readonly_dir_cannot_share_the_alias_of_a_mapped_dir() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let result = WasiEnvBuilder::new("test_prog")
+            .map_dir("/data", "/first")
+            .unwrap()
+            .with_preopen_readonly_dir("/data", temp.path())
+            .build_init();
+
+        assert_eq!(
+            result.unwrap_err(),
+            WasiStateCreationError::DuplicateMappedDirAlias("data".to_string())
+        );
+    }
+
+    #[test]
+    fn resource_usage_reports_cpu_time() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (memory 1)
+                (export "memory" (memory 0))
+                (func (export "_start")
+                    (local $i i32)
+                    (loop $busy
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $busy (i32.lt_u (local.get $i) (i32.const 10000000))))))
+            "#,
+        )
+        .unwrap();
+
+        let (instance, env) = WasiEnvBuilder::new("busy-loop")
+            .instantiate(module, &mut store)
+            .unwrap();
+        let process = env.data(&store).process.clone();
+        run_instance(instance, env, &mut store).unwrap();
+
+        let usage = process.resource_usage();
+        assert!(usage.cpu_time > std::time::Duration::ZERO);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn time_spent_blocked_is_not_cpu_time() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (func (export "_start")
+                    ;; Sleep for 500ms (monotonic clock subscription)
+                    (i32.store8 (i32.const 72) (i32.const 0))
+                    (i32.store (i32.const 80) (i32.const 1))
+                    (i64.store (i32.const 88) (i64.const 500000000))
+                    (call $poll_oneoff (i32.const 64) (i32.const 128) (i32.const 1) (i32.const 192))
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        let (instance, env) = WasiEnvBuilder::new("sleepy")
+            .instantiate(module, &mut store)
+            .unwrap();
+        let process = env.data(&store).process.clone();
+        let started = std::time::Instant::now();
+        run_instance(instance, env, &mut store).unwrap();
+        assert!(started.elapsed() >= std::time::Duration::from_millis(500));
+
+        let usage = process.resource_usage();
+        assert!(usage.cpu_time < std::time::Duration::from_millis(250));
+    }
+
+    #[test]
+    fn files_written_by_the_guest_can_be_read_back() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 200) "hello")
+                (data (i32.const 300) "out.txt")
+                (func (export "_start")
+                    (call $path_open (i32.const 3) (i32.const 0) (i32.const 300) (i32.const 7) (i32.const 1)
+                        (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0) (i32.const 16))
+                    drop
+                    (i32.store (i32.const 0) (i32.const 200))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (call $fd_write (i32.load (i32.const 16)) (i32.const 0) (i32.const 1) (i32.const 8))
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        let (instance, env) = WasiEnvBuilder::new("writer")
+            .sandbox_fs(TmpFileSystem::new())
+            .instantiate(module, &mut store)
+            .unwrap();
+        run_instance(instance, env.clone(), &mut store).unwrap();
+
+        let env = env.data(&store);
+        assert_eq!(env.read_file(Path::new("/out.txt")).unwrap(), b"hello");
+        assert_eq!(
+            env.read_file(Path::new("/missing.txt")).unwrap_err(),
+            FsError::EntryNotFound
+        );
+    }
+
+    #[test]
+    fn stdio_handles_follow_the_overridden_stdio() {
+        use virtual_fs::AsyncWriteExt;
+
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 200) "hello")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 200))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+            "#,
+        )
+        .unwrap();
+
+        let (stdout_tx, mut stdout_rx) = virtual_fs::Pipe::channel();
+        let (instance, env) = WasiEnvBuilder::new("printer")
+            .stdout(Box::new(stdout_tx))
+            .instantiate(module, &mut store)
+            .unwrap();
+        let mut stdout = env.data(&store).stdout_handle().unwrap();
+        run_instance(instance, env.clone(), &mut store).unwrap();
+
+        let mut buffer = [0; 16];
+        let read = std::io::Read::read(&mut stdout_rx, &mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"hello");
+
+        // Writing through the handle goes to the same pipe as the program
+        env.data(&store)
+            .tasks()
+            .block_on(stdout.write_all(b"world"))
+            .unwrap();
+        let read = std::io::Read::read(&mut stdout_rx, &mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"world");
+    }
+
+    #[cfg(feature = "sys-thread")]
+    #[test]
+    fn poll_oneoff_sleeps_on_the_clock_of_the_runtime() {
+        use std::time::Duration;
+
+        use crate::runtime::{task_manager::tokio::TokioTaskManager, MockClock};
+
+        // Waits an hour on the monotonic clock, then prints how long that
+        // took and the userdata of the event as little-endian bytes
+        let wat = r#"
+            (module
+                (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (func (export "_start")
+                    (i64.store (i32.const 64) (i64.const 7))
+                    (i32.store8 (i32.const 72) (i32.const 0))
+                    (i32.store (i32.const 80) (i32.const 1))
+                    (i64.store (i32.const 88) (i64.const 3600000000000))
+                    (drop (call $clock_time_get (i32.const 1) (i64.const 1) (i32.const 300)))
+                    (drop (call $poll_oneoff (i32.const 64) (i32.const 128) (i32.const 1) (i32.const 192)))
+                    (drop (call $clock_time_get (i32.const 1) (i64.const 1) (i32.const 308)))
+                    (i64.store (i32.const 316) (i64.sub (i64.load (i32.const 308)) (i64.load (i32.const 300))))
+                    (i64.store (i32.const 324) (i64.load (i32.const 128)))
+                    (i32.store (i32.const 0) (i32.const 316))
+                    (i32.store (i32.const 4) (i32.const 16))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+            "#;
+
+        let clock = MockClock::default();
+        let mut runtime = PluggableRuntime::new(Arc::new(TokioTaskManager::shared()));
+        runtime.set_clock(clock.clone());
+        let runtime = Arc::new(runtime);
+        let (stdout_tx, mut stdout_rx) = virtual_fs::Pipe::channel();
+
+        let guest = std::thread::spawn(move || {
+            let mut store = Store::default();
+            let module = Module::new(&store, wat).unwrap();
+            WasiEnvBuilder::new("sleeper")
+                .runtime(runtime)
+                .stdout(Box::new(stdout_tx))
+                .run_with_store(module, &mut store)
+                .unwrap();
+        });
+
+        // Only the clock can wake the guest up, so keep moving it forward
+        // until the hour is over
+        while !guest.is_finished() {
+            clock.advance(Duration::from_secs(60));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        guest.join().unwrap();
+
+        let mut buffer = [0; 16];
+        std::io::Read::read_exact(&mut stdout_rx, &mut buffer).unwrap();
+        let waited = u64::from_le_bytes(buffer[..8].try_into().unwrap());
+        let userdata = u64::from_le_bytes(buffer[8..].try_into().unwrap());
+        assert!(waited >= Duration::from_secs(3600).as_nanos() as u64);
+        assert_eq!(userdata, 7);
+    }
+
+    #[cfg(feature = "sys-thread")]
+    #[test]
+    fn the_guest_reads_the_time_from_the_clock_of_the_runtime() {
+        use std::time::Duration;
+
+        use crate::runtime::{task_manager::tokio::TokioTaskManager, MockClock};
+
+        let mut store = Store::default();
+        // Prints the realtime and monotonic clocks as little-endian bytes
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (func (export "_start")
+                    (drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 100)))
+                    (drop (call $clock_time_get (i32.const 1) (i64.const 1) (i32.const 108)))
+                    (i32.store (i32.const 0) (i32.const 100))
+                    (i32.store (i32.const 4) (i32.const 16))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+            "#,
+        )
+        .unwrap();
+
+        let clock = MockClock::new(Duration::from_secs(1_000));
+        let mut runtime = PluggableRuntime::new(Arc::new(TokioTaskManager::shared()));
+        runtime.set_clock(clock.clone());
+        let runtime = Arc::new(runtime);
+
+        let mut read_clocks = || {
+            let (stdout_tx, mut stdout_rx) = virtual_fs::Pipe::channel();
+            WasiEnvBuilder::new("clock")
+                .runtime(runtime.clone())
+                .stdout(Box::new(stdout_tx))
+                .run_with_store(module.clone(), &mut store)
+                .unwrap();
+            let mut buffer = [0; 16];
+            std::io::Read::read_exact(&mut stdout_rx, &mut buffer).unwrap();
+            let realtime = u64::from_le_bytes(buffer[..8].try_into().unwrap());
+            let monotonic = u64::from_le_bytes(buffer[8..].try_into().unwrap());
+            (realtime, monotonic)
+        };
+
+        assert_eq!(read_clocks(), (1_000_000_000_000, 0));
+
+        clock.advance(Duration::from_millis(1_500));
+        assert_eq!(read_clocks(), (1_001_500_000_000, 1_500_000_000));
+    }
+
+    #[cfg(feature = "sys-thread")]
+    #[test]
+    fn deterministically_scheduled_threads_interleave_the_same_way_every_time() {
+        use crate::runtime::task_manager::tokio::TokioTaskManager;
+
+        let mut store = Store::default();
+        // The main thread and the thread it spawns each print their letter
+        // five times, yielding after every letter
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi" "thread-spawn" (func $thread_spawn (param i32) (result i32)))
+                (import "wasi_snapshot_preview1" "sched_yield" (func $sched_yield (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "env" "memory" (memory 1 1 shared))
+                (export "memory" (memory 0))
+                (data (i32.const 100) "mc")
+                (func $print_five_times (param $iovec i32) (local $i i32)
+                    (loop $again
+                        (drop (call $fd_write (i32.const 1) (local.get $iovec) (i32.const 1) (i32.add (local.get $iovec) (i32.const 8))))
+                        (drop (call $sched_yield))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $again (i32.lt_u (local.get $i) (i32.const 5)))))
+                (func (export "wasi_thread_start") (param i32 i32)
+                    (call $print_five_times (i32.const 16))
+                    (i32.atomic.store (i32.const 300) (i32.const 1)))
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 100))
+                    (i32.store (i32.const 4) (i32.const 1))
+                    (i32.store (i32.const 16) (i32.const 101))
+                    (i32.store (i32.const 20) (i32.const 1))
+                    ;; The stack of the new thread
+                    (i32.store (i32.const 200) (i32.const 65536))
+                    (i32.store (i32.const 256) (i32.const 4096))
+                    (if (i32.lt_s (call $thread_spawn (i32.const 200)) (i32.const 0))
+                        (then unreachable))
+                    (call $print_five_times (i32.const 0))
+                    ;; Wait for the other thread to finish
+                    (loop $wait
+                        (if (i32.eqz (i32.atomic.load (i32.const 300)))
+                            (then
+                                (drop (call $sched_yield))
+                                (br $wait))))))
+            "#,
+        )
+        .unwrap();
+        let runtime = Arc::new(PluggableRuntime::new(Arc::new(TokioTaskManager::shared())));
+
+        let mut run = |seed: u64| {
+            let (stdout_tx, mut stdout_rx) = virtual_fs::Pipe::channel();
+            WasiEnvBuilder::new("threads")
+                .runtime(runtime.clone())
+                .with_deterministic_scheduling(seed)
+                .stdout(Box::new(stdout_tx))
+                .run_with_store(module.clone(), &mut store)
+                .unwrap();
+            let mut output = [0; 10];
+            std::io::Read::read_exact(&mut stdout_rx, &mut output).unwrap();
+            String::from_utf8(output.to_vec()).unwrap()
+        };
+
+        let first = run(42);
+        assert_eq!(first.matches('m').count(), 5);
+        assert_eq!(first.matches('c').count(), 5);
+        for _ in 0..5 {
+            assert_eq!(run(42), first);
+        }
+    }
+
+    #[test]
+    fn the_preopen_with_the_highest_priority_serves_the_path() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 200) "hello")
+                (data (i32.const 300) "data/out.txt")
+                (func (export "_start")
+                    (call $path_open (i32.const 3) (i32.const 0) (i32.const 300) (i32.const 12) (i32.const 1)
+                        (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0) (i32.const 16))
+                    drop
+                    (i32.store (i32.const 0) (i32.const 200))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (call $fd_write (i32.load (i32.const 16)) (i32.const 0) (i32.const 1) (i32.const 8))
+                    drop))
+            "#,
+        )
+        .unwrap();
+        let fs = TmpFileSystem::new();
+        fs.create_dir(Path::new("/base")).unwrap();
+        fs.create_dir(Path::new("/override")).unwrap();
+
+        // The override is added last, so it only wins because of its priority
+        let (instance, env) = WasiEnvBuilder::new("writer")
+            .sandbox_fs(fs)
+            .map_dir("data", "/base")
+            .unwrap()
+            .with_preopen_priority("data", "/override", 1)
+            .unwrap()
+            .instantiate(module, &mut store)
+            .unwrap();
+        run_instance(instance, env.clone(), &mut store).unwrap();
+
+        let env = env.data(&store);
+        assert_eq!(
+            env.read_file(Path::new("/override/out.txt")).unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            env.read_file(Path::new("/base/out.txt")).unwrap_err(),
+            FsError::EntryNotFound
+        );
+    }
+
+    #[test]
+    fn syscall_metrics_count_every_call() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "sched_yield" (func $sched_yield (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 200) "hi")
+                (func $write
+                    (i32.store (i32.const 0) (i32.const 200))
+                    (i32.store (i32.const 4) (i32.const 2))
+                    (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+                    drop)
+                (func (export "_start")
+                    (call $write)
+                    (call $write)
+                    (call $write)
+                    (call $sched_yield)
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        let (instance, env) = WasiEnvBuilder::new("metrics")
+            .stdout(Box::new(virtual_fs::NullFile::default()))
+            .enable_syscall_metrics()
+            .instantiate(module.clone(), &mut store)
+            .unwrap();
+        run_instance(instance, env.clone(), &mut store).unwrap();
+
+        let metrics = env.data(&store).syscall_metrics();
+        assert_eq!(metrics.get("fd_write"), Some(&3));
+        assert_eq!(metrics.get("sched_yield"), Some(&1));
+        assert_eq!(metrics.get("path_open"), None);
+
+        // The imports handed out by the function env are counted too
+        let mut env = WasiEnvBuilder::new("metrics")
+            .stdout(Box::new(virtual_fs::NullFile::default()))
+            .enable_syscall_metrics()
+            .finalize(&mut store)
+            .unwrap();
+        let imports = env.import_object(&mut store, &module).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports).unwrap();
+        env.initialize(&mut store, instance.clone()).unwrap();
+        run_instance(instance, env.clone(), &mut store).unwrap();
+        let metrics = env.data(&store).syscall_metrics();
+        assert_eq!(metrics.get("fd_write"), Some(&3));
+        assert_eq!(metrics.get("sched_yield"), Some(&1));
+
+        // Nothing is counted unless it was asked for
+        let (instance, env) = WasiEnvBuilder::new("metrics")
+            .stdout(Box::new(virtual_fs::NullFile::default()))
+            .instantiate(module, &mut store)
+            .unwrap();
+        run_instance(instance, env.clone(), &mut store).unwrap();
+        assert!(env.data(&store).syscall_metrics().is_empty());
+    }
+
+    #[test]
+    fn unhandled_signals_follow_their_disposition() {
+        let run = |disposition: SignalDisposition| {
+            let mut store = Store::default();
+            let module = Module::new(
+                &store,
+                r#"
+                (module
+                    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                    (memory 1)
+                    (export "memory" (memory 0))
+                    (data (i32.const 200) "hi")
+                    ;; Signals are only looked at when the program makes a syscall
+                    (func (export "_start")
+                        (i32.store (i32.const 0) (i32.const 200))
+                        (i32.store (i32.const 4) (i32.const 2))
+                        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+                        drop))
+                "#,
+            )
+            .unwrap();
+
+            let (instance, env) = WasiEnvBuilder::new("sigterm")
+                .with_signal_disposition(Signal::Sigterm, disposition)
+                .instantiate(module, &mut store)
+                .unwrap();
+            env.data(&store).process.signal_process(Signal::Sigterm);
+            run_instance(instance, env, &mut store)
+        };
+
+        let result = run(SignalDisposition::Terminate);
+        assert_eq!(result.unwrap_err().as_exit_code(), Some(Errno::Intr.into()));
+
+        assert!(run(SignalDisposition::Ignore).is_ok());
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let callback = move |signal| tx.lock().unwrap().send(signal).unwrap();
+        assert!(run(SignalDisposition::Callback(Arc::new(callback))).is_ok());
+        assert_eq!(rx.try_recv().unwrap(), Signal::Sigterm);
+    }
+
+    #[cfg(feature = "sys-thread")]
+    #[test]
+    fn guest_threads_are_named_after_the_prefix() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "proc_raise" (func $proc_raise (param i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                ;; SIGTERM
+                (func (export "_start")
+                    (call $proc_raise (i32.const 15))
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        // The signal handler runs on the thread that is running the guest
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let callback = move |_| {
+            let name = std::thread::current().name().map(String::from);
+            tx.lock().unwrap().send(name).unwrap();
+        };
+
+        WasiEnvBuilder::new("named")
+            .with_thread_name_prefix("guest")
+            .with_signal_disposition(
+                Signal::Sigterm,
+                SignalDisposition::Callback(Arc::new(callback)),
+            )
+            .run_with_store_async(module, store)
+            .unwrap();
+
+        let name = rx.try_recv().unwrap().expect("the thread has no name");
+        assert!(
+            name.starts_with("guest-"),
+            "unexpected thread name {name:?}"
+        );
+    }
+
+    #[test]
+    fn on_exit_gets_the_exit_code() {
+        let run = |body: &str| {
+            let mut store = Store::default();
+            let module = Module::new(
+                &store,
+                format!(
+                    r#"
+                    (module
+                        (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                        (memory 1)
+                        (export "memory" (memory 0))
+                        (func (export "_start") {body}))
+                    "#
+                ),
+            )
+            .unwrap();
+
+            let (tx, rx) = std::sync::mpsc::channel();
+            let result = WasiEnvBuilder::new("exit")
+                .on_exit(Box::new(move |exit_code| tx.send(exit_code).unwrap()))
+                .run_with_store(module, &mut store);
+            (result, rx.try_recv().unwrap())
+        };
+
+        let (result, exit_code) = run("");
+        assert!(result.is_ok());
+        assert_eq!(exit_code.raw(), 0);
+
+        let (result, exit_code) = run("(call $proc_exit (i32.const 3))");
+        assert_eq!(result.unwrap_err().as_exit_code().unwrap().raw(), 3);
+        assert_eq!(exit_code.raw(), 3);
+    }
+
+    #[test]
+    fn run_errors_tell_instantiation_traps_and_exits_apart() {
+        let run = |wat: &str| {
+            let mut store = Store::default();
+            let module = Module::new(&store, wat).unwrap();
+            WasiEnvBuilder::new("errors").run_with_store(module, &mut store)
+        };
+
+        let result = run(r#"
+            (module
+                (import "env" "missing" (func $missing))
+                (func (export "_start")))
+            "#);
+        assert!(matches!(result, Err(WasiRuntimeError::Instantiation(_))));
+
+        let result = run(r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "_start") unreachable))
+            "#);
+        assert!(matches!(result, Err(WasiRuntimeError::Runtime(_))));
+
+        let result = run(r#"
+            (module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start") (call $proc_exit (i32.const 3))))
+            "#);
+        match result {
+            Err(WasiRuntimeError::Exit(code)) => assert_eq!(code.raw(), 3),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn resolved_packages_run_without_a_registry() {
+        let package = |body: &str| {
+            let atom = format!(
+                r#"
+                (module
+                    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                    (memory 1)
+                    (export "memory" (memory 0))
+                    (func (export "_start") {body}))
+                "#
+            );
+            BinaryPackage {
+                package_name: "test/prebuilt".to_string(),
+                when_cached: None,
+                entrypoint_cmd: Some("main".to_string()),
+                hash: Default::default(),
+                webc_fs: Arc::new(virtual_fs::mem_fs::FileSystem::default()),
+                commands: vec![crate::bin_factory::BinaryPackageCommand::new(
+                    "main".to_string(),
+                    webc::metadata::Command::default(),
+                    Bytes::from(atom).into(),
+                )],
+                uses: Vec::new(),
+                version: semver::Version::new(0, 1, 0),
+                module_memory_footprint: 0,
+                file_system_memory_footprint: 0,
+            }
+        };
+
+        WasiEnvBuilder::new("prebuilt")
+            .with_resolved_package(package(""))
+            .run_resolved_package()
+            .unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let result = WasiEnvBuilder::new("prebuilt")
+            .with_resolved_package(package("(call $proc_exit (i32.const 3))"))
+            .on_exit(Box::new(move |exit_code| tx.send(exit_code).unwrap()))
+            .run_resolved_package();
+        assert_eq!(result.unwrap_err().as_exit_code().unwrap().raw(), 3);
+        assert_eq!(rx.try_recv().unwrap().raw(), 3);
+
+        // Traps are reported as such instead of as an exit code
+        assert!(matches!(
+            WasiEnvBuilder::new("prebuilt")
+                .with_resolved_package(package("unreachable"))
+                .run_resolved_package(),
+            Err(WasiRuntimeError::Runtime(_))
+        ));
+
+        // The commands of the package can be run from `/bin`
+        let fs = TmpFileSystem::new();
+        WasiEnvBuilder::new("prebuilt")
+            .sandbox_fs(fs.clone())
+            .with_resolved_package(package(""))
+            .run_resolved_package()
+            .unwrap();
+        assert!(fs.metadata(Path::new("/bin/main")).unwrap().is_file());
+
+        assert!(matches!(
+            WasiEnvBuilder::new("prebuilt").run_resolved_package(),
+            Err(WasiRuntimeError::Init(
+                WasiStateCreationError::WasiIncludePackageError(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn argv0_overrides_only_the_first_argument() {
+        let init = WasiEnvBuilder::new("busybox")
+            .with_argv0("ls")
+            .arg("-l")
+            .build_init()
+            .unwrap();
+        assert_eq!(init.state.args, ["ls", "-l"]);
+        assert_eq!(init.state.program_name, "busybox");
+
+        let env = WasiEnv::from_init(init).unwrap();
+        assert_eq!(env.program_name(), "busybox");
+
+        let output = WasiEnvBuilder::new("busybox")
+            .with_argv0("l\0s")
+            .build_init();
+        assert!(matches!(
+            output,
+            Err(WasiStateCreationError::ArgumentContainsNulByte(_))
+        ));
+    }
+
+    #[test]
+    fn close_on_exec_fds_are_not_inherited() {
+        let init = WasiEnvBuilder::new("test_prog")
+            .with_close_on_exec_defaults(true)
+            .build_init()
+            .unwrap();
+        let state = &init.state;
+
+        let open = |name: &'static str| {
+            let inode = state.fs.create_inode_with_default_stat(
+                &state.inodes,
+                Kind::Buffer { buffer: Vec::new() },
+                false,
+                name.into(),
+            );
+            state
+                .fs
+                .create_fd(Rights::all(), Rights::all(), Fdflags::empty(), 0, inode)
+                .unwrap()
+        };
+        let cloexec = open("cloexec");
+        let inherited = open("inherited");
+        state.fs.set_fd_close_on_exec(inherited, false).unwrap();
+        assert!(state.fs.get_fd(cloexec).unwrap().is_close_on_exec());
+        // Duplicates keep the flag of the original
+        let duplicate = state.fs.clone_fd(cloexec).unwrap();
+        assert!(state.fs.get_fd(duplicate).unwrap().is_close_on_exec());
+
+        // Spawning a child forks the file system and drops close-on-exec fds
+        let child = state.fork();
+        child.fs.close_cloexec_fds();
+
+        assert_eq!(child.fs.get_fd(cloexec).unwrap_err(), Errno::Badf);
+        assert_eq!(child.fs.get_fd(duplicate).unwrap_err(), Errno::Badf);
+        assert!(child.fs.get_fd(inherited).is_ok());
+        assert!(child.fs.get_fd(__WASI_STDOUT_FILENO).is_ok());
+        assert!(state.fs.get_fd(cloexec).is_ok());
+    }
+
+    #[cfg(feature = "sys-thread")]
+    #[test]
+    fn spawned_programs_only_inherit_fds_without_close_on_exec() {
+        use std::io::{BufRead, BufReader};
+
+        use crate::runtime::{resolver::InMemorySource, task_manager::tokio::TokioTaskManager};
+
+        const DASH: &str = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../tests/integration/cli/tests/webc/dash-1.0.18-f0d13233-bcda-4cf1-9a23-3460bffaae2a.webc"
+        );
+        const COREUTILS: &str = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../tests/integration/cli/tests/webc/coreutils-1.0.16-e27dbb4f-2ef2-4b44-b46a-ddd86497c6d7.webc"
+        );
+
+        let mut source = InMemorySource::new();
+        source.add_webc(DASH).unwrap();
+        source.add_webc(COREUTILS).unwrap();
+        let tasks = Arc::new(TokioTaskManager::shared());
+        let mut runtime = PluggableRuntime::new(tasks.clone());
+        runtime.set_source(source);
+        let runtime: Arc<dyn Runtime + Send + Sync> = Arc::new(runtime);
+        let dash = tasks
+            .block_on(BinaryPackage::from_registry(
+                &"sharrattj/dash".parse().unwrap(),
+                runtime.as_ref(),
+            ))
+            .unwrap();
+
+        // The parent opens fd 3 and a new dash program writes to it
+        let script = "exec 3>/fd3.txt
+            /bin/dash -c 'echo child >&3'
+            read line </fd3.txt
+            echo \"got $line\"";
+        let run = |close_on_exec: bool| {
+            let (stdout_tx, stdout_rx) = virtual_fs::Pipe::channel();
+            WasiEnvBuilder::new("dash")
+                .args(["-c", script])
+                .runtime(runtime.clone())
+                .uses([dash.clone()])
+                .with_resolved_package(dash.clone())
+                .with_close_on_exec_defaults(close_on_exec)
+                .stdout(Box::new(stdout_tx))
+                .run_resolved_package()
+                .unwrap();
+            let mut line = String::new();
+            BufReader::new(stdout_rx).read_line(&mut line).unwrap();
+            line
+        };
+
+        assert_eq!(run(false), "got child\n");
+        assert_eq!(run(true), "got \n");
+    }
+
+    #[test]
+    fn opening_too_many_files_fails() {
+        let init = WasiEnvBuilder::new("test_prog")
+            .with_max_open_files(8)
+            .build_init()
+            .unwrap();
+        let state = &init.state;
+
+        let open = || {
+            let inode = state.fs.create_inode_with_default_stat(
+                &state.inodes,
+                Kind::Buffer { buffer: Vec::new() },
+                false,
+                "file".into(),
+            );
+            state
+                .fs
+                .create_fd(Rights::all(), Rights::all(), Fdflags::empty(), 0, inode)
+        };
+        // Stdio and the preopened directories count towards the limit
+        let already_open = state.fs.fd_map.read().unwrap().len();
+        for _ in already_open..8 {
+            open().unwrap();
+        }
+        assert_eq!(open().unwrap_err(), Errno::Mfile);
+        assert_eq!(
+            state.fs.clone_fd(__WASI_STDOUT_FILENO).unwrap_err(),
+            Errno::Mfile
+        );
+
+        // Closing a file makes room for another one
+        let fd = *state.fs.fd_map.read().unwrap().keys().max().unwrap();
+        state.fs.close_fd(fd).unwrap();
+        open().unwrap();
+    }
+    #[test]
+    fn read_only_fs_only_allows_writing_to_tmp() {
+        let fs = TmpFileSystem::new();
+        fs.create_dir(Path::new("/etc")).unwrap();
+        fs.write_file(Path::new("/etc/hosts"), b"127.0.0.1 localhost")
+            .unwrap();
+        let init = WasiEnvBuilder::new("test_prog")
+            .sandbox_fs(fs)
+            .with_read_only_fs(true)
+            .build_init()
+            .unwrap();
+        let root_fs = &init.state.fs.root_fs;
+        assert!(matches!(root_fs, WasiFsRoot::Sandbox(_)));
+
+        // Existing files can't be opened for writing either
+        let err = root_fs
+            .new_open_options()
+            .write(true)
+            .open("/etc/hosts")
+            .unwrap_err();
+        assert_eq!(crate::fs::fs_error_into_wasi_err(err), Errno::Rofs);
+
+        let err = root_fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/etc/passwd")
+            .unwrap_err();
+        assert_eq!(crate::fs::fs_error_into_wasi_err(err), Errno::Rofs);
+        assert_eq!(
+            root_fs.create_dir(Path::new("/etc/ssh")).unwrap_err(),
+            FsError::ReadOnly
+        );
+
+        root_fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/tmp/scratch.txt")
+            .unwrap();
+        assert!(root_fs.metadata(Path::new("/tmp/scratch.txt")).is_ok());
+    }
+
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    #[test]
+    fn stdout_and_stderr_can_go_to_host_files() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 200) "first\nsecond\n")
+                (data (i32.const 300) "oops\n")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 200))
+                    (i32.store (i32.const 4) (i32.const 13))
+                    (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+                    drop
+                    (i32.store (i32.const 0) (i32.const 300))
+                    (i32.store (i32.const 4) (i32.const 5))
+                    (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8))
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let stdout = temp.path().join("stdout.log");
+        let stderr = temp.path().join("stderr.log");
+        std::fs::write(&stdout, "left over from before\n").unwrap();
+
+        let mut run = |append: bool| {
+            let (instance, env) = WasiEnvBuilder::new("logging")
+                .with_stdout_to_file(&stdout, append)
+                .unwrap()
+                .with_stderr_to_file(&stderr, append)
+                .unwrap()
+                .instantiate(module.clone(), &mut store)
+                .unwrap();
+            run_instance(instance, env, &mut store).unwrap();
+        };
+
+        // The old contents are truncated away
+        run(false);
+        assert_eq!(std::fs::read_to_string(&stdout).unwrap(), "first\nsecond\n");
+        assert_eq!(std::fs::read_to_string(&stderr).unwrap(), "oops\n");
+
+        run(true);
+        assert_eq!(
+            std::fs::read_to_string(&stdout).unwrap(),
+            "first\nsecond\nfirst\nsecond\n"
+        );
+        assert_eq!(std::fs::read_to_string(&stderr).unwrap(), "oops\noops\n");
+    }
+
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    #[test]
+    fn inherited_stdio_is_the_stdio_of_the_host_process() {
+        use std::{
+            io::Write,
+            process::{Command, Stdio},
+        };
+
+        const CHILD: &str = "WASIX_INHERIT_STDIO_CHILD";
+
+        if std::env::var_os(CHILD).is_some() {
+            // The guest echoes what it reads from stdin
+            let mut store = Store::default();
+            let module = Module::new(
+                &store,
+                r#"
+                (module
+                    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                    (memory 1)
+                    (export "memory" (memory 0))
+                    (func (export "_start")
+                        (i32.store (i32.const 0) (i32.const 100))
+                        (i32.store (i32.const 4) (i32.const 64))
+                        (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (i32.store (i32.const 4) (i32.load (i32.const 8)))
+                        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+                "#,
+            )
+            .unwrap();
+            WasiEnvBuilder::new("echo")
+                .inherit_stdio()
+                .run_with_store(module, &mut store)
+                .unwrap();
+            return;
+        }
+
+        // Runs this test again in a process whose stdio are pipes
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "inherited_stdio_is_the_stdio_of_the_host_process",
+                "--nocapture",
+            ])
+            .env(CHILD, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"ping from the host\n")
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("ping from the host\n"), "{stdout}");
+    }
+
+    #[cfg(feature = "host-fs")]
+    #[tokio::test]
+    async fn nested_map_dir_aliases_create_the_dirs_in_between() {
+        use virtual_fs::AsyncReadExt;
+        use wasmer_wasix_types::wasi::Filetype;
+
+        use crate::fs::VIRTUAL_ROOT_FD;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        std::fs::write(temp.path().join("file"), "nested").unwrap();
+
+        let init = WasiEnvBuilder::new("test_prog")
+            .fs(crate::default_fs_backing())
+            .map_dir("/a/b/c", temp.path())
+            .unwrap()
+            .build_init()
+            .unwrap();
+        let wasi_fs = &init.state.fs;
+        let inodes = &init.state.inodes;
+
+        for dir in ["a", "a/b", "a/b/c"] {
+            let inode = wasi_fs
+                .get_inode_at_path(inodes, VIRTUAL_ROOT_FD, dir, true)
+                .unwrap();
+            let filetype = inode.stat.read().unwrap().st_filetype;
+            assert_eq!(filetype, Filetype::Directory, "{dir}");
+        }
+
+        // The directories in between know where they are and who their
+        // parent is
+        let a = wasi_fs
+            .get_inode_at_path(inodes, VIRTUAL_ROOT_FD, "a", true)
+            .unwrap();
+        let b = wasi_fs
+            .get_inode_at_path(inodes, VIRTUAL_ROOT_FD, "a/b", true)
+            .unwrap();
+        match &*b.read() {
+            Kind::Dir { parent, path, .. } => {
+                assert_eq!(path, Path::new("/a/b"));
+                assert_eq!(parent.upgrade().unwrap().ino(), a.ino());
+            }
+            _ => panic!("a/b is not a directory"),
+        }
+
+        let inode = wasi_fs
+            .get_inode_at_path(inodes, VIRTUAL_ROOT_FD, "a/b/c/file", true)
+            .unwrap();
+        let path = match &*inode.read() {
+            Kind::File { path, .. } => path.clone(),
+            _ => panic!("a/b/c/file is not a file"),
+        };
+        let mut contents = String::new();
+        wasi_fs
+            .root_fs
+            .new_open_options()
+            .read(true)
+            .open(path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .await
+            .unwrap();
+        assert_eq!(contents, "nested");
+
+        // Anything else in the directories in between is looked up in the
+        // root file system
+        assert_eq!(
+            wasi_fs
+                .get_inode_at_path(inodes, VIRTUAL_ROOT_FD, "a/other", true)
+                .unwrap_err(),
+            Errno::Noent
+        );
+    }
+
+    #[cfg(feature = "host-fs")]
+    #[tokio::test]
+    async fn preopened_host_file_hides_its_siblings() {
+        use virtual_fs::AsyncReadExt;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let config = temp.path().join("config.toml");
+        std::fs::write(&config, "debug = true").unwrap();
+        std::fs::write(temp.path().join("secret.txt"), "hunter2").unwrap();
+
+        let init = WasiEnvBuilder::new("test_prog")
+            .with_preopen_host_file("/etc/app/config.toml", &config, true, false)
+            .build_init()
+            .unwrap();
+        let root_fs = &init.state.fs.root_fs;
+
+        let mut contents = String::new();
+        root_fs
+            .new_open_options()
+            .read(true)
+            .open("/etc/app/config.toml")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .await
+            .unwrap();
+        assert_eq!(contents, "debug = true");
+
+        // Only the file itself is visible, not the rest of its directory
+        let entries: Vec<_> = root_fs
+            .read_dir(Path::new("/etc/app"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path)
+            .collect();
+        assert_eq!(entries, [PathBuf::from("/etc/app/config.toml")]);
+        assert_eq!(
+            root_fs
+                .new_open_options()
+                .read(true)
+                .open("/etc/app/secret.txt")
+                .unwrap_err(),
+            FsError::EntryNotFound
+        );
+
+        // The file was exposed read-only
+        assert!(root_fs
+            .new_open_options()
+            .write(true)
+            .open("/etc/app/config.toml")
+            .is_err());
+    }
+
+    #[cfg(feature = "host-fs")]
+    #[tokio::test]
+    async fn preopen_overlay_sends_writes_to_the_scratch_fs() {
+        use virtual_fs::{AsyncReadExt, AsyncWriteExt};
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let config = temp.path().join("config.txt");
+        std::fs::write(&config, "original").unwrap();
+        std::fs::create_dir(temp.path().join("nested")).unwrap();
+        std::fs::write(temp.path().join("nested").join("file.txt"), "nested").unwrap();
+        let scratch = TmpFileSystem::new();
+
+        let init = WasiEnvBuilder::new("test_prog")
+            .with_preopen_overlay_writes_to("/data", temp.path(), scratch.clone())
+            .build_init()
+            .unwrap();
+        let root_fs = &init.state.fs.root_fs;
+        let read = |path: &'static str| async move {
+            let mut contents = String::new();
+            root_fs
+                .new_open_options()
+                .read(true)
+                .open(path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .await
+                .unwrap();
+            contents
+        };
+
+        // The directory is preopened...
+        let fs = &init.state.fs;
+        assert!(fs
+            .preopen_fds
+            .read()
+            .unwrap()
+            .iter()
+            .any(|fd| fs.get_fd(*fd).unwrap().inode.name == "data"));
+        // ... and everything on the host is visible
+        assert_eq!(read("/data/config.txt").await, "original");
+        assert_eq!(read("/data/nested/file.txt").await, "nested");
+
+        let mut f = root_fs
+            .new_open_options()
+            .write(true)
+            .truncate(true)
+            .open("/data/config.txt")
+            .unwrap();
+        f.write_all(b"modified").await.unwrap();
+        f.flush().await.unwrap();
+        drop(f);
+        let mut f = root_fs
+            .new_open_options()
+            .write(true)
+            .create_new(true)
+            .open("/data/new.txt")
+            .unwrap();
+        f.write_all(b"new").await.unwrap();
+        drop(f);
+
+        // The program sees its changes...
+        assert_eq!(read("/data/config.txt").await, "modified");
+        assert_eq!(read("/data/new.txt").await, "new");
+        // ... which went to the scratch file system...
+        assert_eq!(scratch.metadata(Path::new("/config.txt")).unwrap().len(), 8);
+        assert!(scratch.metadata(Path::new("/new.txt")).is_ok());
+        // ... without touching the host
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), "original");
+        assert!(!temp.path().join("new.txt").exists());
+    }
+
+    #[cfg(all(feature = "sys", feature = "host-fs"))]
+    #[test]
+    fn readonly_preopens_can_be_read_but_not_written() {
+        let mut store = Store::default();
+        // fd 3 is `/` and fd 4 is the preopen
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 100) "data.txt")
+                (data (i32.const 120) "new.txt")
+                (data (i32.const 140) "data/data.txt")
+                (func (export "_start")
+                    ;; Reading works, and what was read goes to stdout
+                    (if (call $path_open (i32.const 4) (i32.const 0) (i32.const 100) (i32.const 8) (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 0))
+                        (then (call $proc_exit (i32.const 1))))
+                    (i32.store (i32.const 16) (i32.const 200))
+                    (i32.store (i32.const 20) (i32.const 64))
+                    (if (call $fd_read (i32.load (i32.const 0)) (i32.const 16) (i32.const 1) (i32.const 32))
+                        (then (call $proc_exit (i32.const 2))))
+                    (i32.store (i32.const 20) (i32.load (i32.const 32)))
+                    (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 32)))
+                    ;; Creating files doesn't
+                    (if (i32.eqz (call $path_open (i32.const 4) (i32.const 0) (i32.const 120) (i32.const 7) (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 0)))
+                        (then (call $proc_exit (i32.const 3))))
+                    ;; Neither does writing, even when going through `/`
+                    (if (i32.eqz (call $path_open (i32.const 3) (i32.const 0) (i32.const 140) (i32.const 13) (i32.const 0) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 0)))
+                        (then
+                            (i32.store (i32.const 20) (i32.const 1))
+                            (if (i32.eqz (call $fd_write (i32.load (i32.const 0)) (i32.const 16) (i32.const 1) (i32.const 32)))
+                                (then (call $proc_exit (i32.const 4)))))))
+            "#,
+        )
+        .unwrap();
+
+        let temp = tempfile::TempDir::new().unwrap();
+        std::fs::write(temp.path().join("data.txt"), "hello").unwrap();
+        let (stdout_tx, mut stdout_rx) = virtual_fs::Pipe::channel();
+
+        WasiEnvBuilder::new("readonly")
+            .with_preopen_readonly_dir("/data", temp.path())
+            .stdout(Box::new(stdout_tx))
+            .run_with_store(module, &mut store)
+            .unwrap();
+
+        let mut buffer = [0; 16];
+        let read = std::io::Read::read(&mut stdout_rx, &mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"hello");
+        assert_eq!(
+            std::fs::read_to_string(temp.path().join("data.txt")).unwrap(),
+            "hello"
+        );
+        assert!(!temp.path().join("new.txt").exists());
+    }
+
+    #[test]
+    fn capabilities_can_deny_symlinks_on_writable_preopens() {
+        let mut store = Store::default();
+        // fd 3 is `/` and fd 4 is the preopen; the guest exits with the errno
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "path_symlink" (func $path_symlink (param i32 i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 100) "data.txt")
+                (data (i32.const 120) "link.txt")
+                (func (export "_start")
+                    (call $proc_exit (call $path_symlink (i32.const 100) (i32.const 8) (i32.const 4) (i32.const 120) (i32.const 8)))))
+            "#,
+        )
+        .unwrap();
+
+        let mut run = |capabilities: Capabilities| {
+            let fs = TmpFileSystem::new();
+            fs.create_dir(Path::new("/data")).unwrap();
+            WasiEnvBuilder::new("symlinker")
+                .sandbox_fs(fs)
+                .preopen_dir("/data")
+                .unwrap()
+                .capabilities(capabilities)
+                .run_with_store(module.clone(), &mut store)
+        };
+
+        // The preopen allows creating symlinks by default
+        run(Capabilities::default()).unwrap();
+
+        let mut capabilities = Capabilities::default();
+        capabilities.filesystem.allow_symlinks = false;
+        match run(capabilities) {
+            Err(WasiRuntimeError::Exit(code)) => assert_eq!(code.raw(), Errno::Perm as i32),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
 }
@@ -9,7 +9,7 @@ use std::{
 use derivative::Derivative;
 use rand::Rng;
 use tracing::{trace, warn};
-use virtual_fs::{AsyncWriteExt, FileSystem, FsError, VirtualFile};
+use virtual_fs::{ArcBoxFile, AsyncReadExt, AsyncWriteExt, FileSystem, FsError, VirtualFile};
 use virtual_net::DynVirtualNetworking;
 use wasmer::{
     AsStoreMut, AsStoreRef, FunctionEnvMut, Global, Instance, Memory, MemoryType, MemoryView,
@@ -17,7 +17,7 @@ use wasmer::{
 };
 use wasmer_wasix_types::{
     types::Signal,
-    wasi::{Errno, ExitCode, Snapshot0Clockid},
+    wasi::{Errno, ExitCode},
 };
 
 use crate::{
@@ -28,10 +28,11 @@ use crate::{
     os::task::{
         control_plane::ControlPlaneError,
         process::{WasiProcess, WasiProcessId},
+        signal::SignalDisposition,
         thread::{WasiMemoryLayout, WasiThread, WasiThreadHandle, WasiThreadId},
     },
     runtime::{resolver::PackageSpecifier, SpawnMemoryType},
-    syscalls::{__asyncify_light, platform_clock_time_get},
+    syscalls::__asyncify_light,
     Runtime, VirtualTaskManager, WasiControlPlane, WasiEnvBuilder, WasiError, WasiFunctionEnv,
     WasiRuntimeError, WasiStateCreationError, WasiVFork,
 };
@@ -246,9 +247,12 @@ impl WasiEnvInit {
                 clock_offset: std::sync::Mutex::new(
                     self.state.clock_offset.lock().unwrap().clone(),
                 ),
+                program_name: self.state.program_name.clone(),
                 args: self.state.args.clone(),
-                envs: self.state.envs.clone(),
+                envs: std::sync::Mutex::new(self.state.envs.lock().unwrap().clone()),
                 preopen: self.state.preopen.clone(),
+                signal_dispositions: self.state.signal_dispositions.clone(),
+                syscall_metrics: self.state.syscall_metrics.clone(),
             },
             runtime: self.runtime.clone(),
             webc_dependencies: self.webc_dependencies.clone(),
@@ -371,6 +375,55 @@ impl WasiEnv {
         self.thread.tid()
     }
 
+    /// Name of the program that is running, which stays the same even when
+    /// `argv[0]` was overridden with [`WasiEnvBuilder::with_argv0`].
+    pub fn program_name(&self) -> &str {
+        &self.state.program_name
+    }
+
+    /// How many times the program made each syscall, keyed by the name it
+    /// was imported under.
+    ///
+    /// This is empty unless counting was turned on with
+    /// [`WasiEnvBuilder::enable_syscall_metrics()`].
+    pub fn syscall_metrics(&self) -> HashMap<&'static str, u64> {
+        self.state
+            .syscall_metrics
+            .as_ref()
+            .map(|metrics| metrics.counts())
+            .unwrap_or_default()
+    }
+
+    /// Sets an environment variable, replacing any previous value.
+    ///
+    /// The guest reads its environment once at startup, so this needs to be
+    /// called before `_start` runs. The same rules as for
+    /// [`WasiEnvBuilder::env`] apply to the key and value.
+    pub fn set_env_var(
+        &self,
+        key: &str,
+        value: impl AsRef<[u8]>,
+    ) -> Result<(), WasiStateCreationError> {
+        let value = value.as_ref();
+        super::builder::validate_env_var(key, value)?;
+
+        let mut env = Vec::with_capacity(key.len() + value.len() + 1);
+        env.extend_from_slice(key.as_bytes());
+        env.push(b'=');
+        env.extend_from_slice(value);
+
+        let mut envs = self.state.envs.lock().unwrap();
+        let prefix = &env[..=key.len()];
+        match envs
+            .iter_mut()
+            .find(|existing| existing.starts_with(prefix))
+        {
+            Some(existing) => *existing = env,
+            None => envs.push(env),
+        }
+        Ok(())
+    }
+
     /// Returns true if this module is capable of deep sleep
     /// (needs asyncify to unwind and rewin)
     ///
@@ -382,6 +435,10 @@ impl WasiEnv {
         if !self.control_plane.config().enable_asynchronous_threading {
             return false;
         }
+        // Deterministically scheduled threads have to keep their turn
+        if self.control_plane.scheduler().is_some() {
+            return false;
+        }
         let inner = self.inner();
         inner.asyncify_get_state.is_some()
             && inner.asyncify_start_rewind.is_some()
@@ -405,6 +462,8 @@ impl WasiEnv {
         } else {
             process.new_thread()?
         };
+        let enable_deep_sleep = init.capabilities.threading.enable_asynchronous_threading
+            && init.control_plane.scheduler().is_none();
 
         let mut env = Self {
             control_plane: init.control_plane,
@@ -418,7 +477,7 @@ impl WasiEnv {
             owned_handles: Vec::new(),
             runtime: init.runtime,
             bin_factory: init.bin_factory,
-            enable_deep_sleep: init.capabilities.threading.enable_asynchronous_threading,
+            enable_deep_sleep,
             capabilities: init.capabilities,
         };
         env.owned_handles.push(thread);
@@ -490,6 +549,10 @@ impl WasiEnv {
             }
         };
 
+        if let Some(fuel) = func_env.data(&store).capabilities.fuel {
+            super::set_fuel(&mut store, &instance, fuel);
+        }
+
         // Run initializers.
         instance_init_callback(&instance, &store).unwrap();
 
@@ -560,15 +623,15 @@ impl WasiEnv {
             let signals = env.thread.pop_signals();
             let signal_cnt = signals.len();
             for sig in signals {
-                if sig == Signal::Sigint
-                    || sig == Signal::Sigquit
-                    || sig == Signal::Sigkill
-                    || sig == Signal::Sigabrt
-                {
-                    let exit_code = env.thread.set_or_get_exit_code_for_signal(sig);
-                    return Err(WasiError::Exit(exit_code));
-                } else {
-                    trace!("wasi[{}]::signal-ignored: {:?}", env.pid(), sig);
+                match env.state.signal_disposition(sig) {
+                    SignalDisposition::Terminate => {
+                        let exit_code = env.thread.set_or_get_exit_code_for_signal(sig);
+                        return Err(WasiError::Exit(exit_code));
+                    }
+                    SignalDisposition::Ignore => {
+                        trace!("wasi[{}]::signal-ignored: {:?}", env.pid(), sig);
+                    }
+                    SignalDisposition::Callback(callback) => callback(sig),
                 }
             }
             return Ok(Ok(signal_cnt > 0));
@@ -621,8 +684,7 @@ impl WasiEnv {
                 let mut any = false;
                 let inner = env.process.inner.read().unwrap();
                 if !inner.signal_intervals.is_empty() {
-                    now = platform_clock_time_get(Snapshot0Clockid::Monotonic, 1_000_000).unwrap()
-                        as u128;
+                    now = env.runtime().clock().monotonic().as_nanos();
                     for signal in inner.signal_intervals.values() {
                         let elapsed = now - signal.last_signal;
                         if elapsed >= signal.interval.as_nanos() {
@@ -765,9 +827,11 @@ impl WasiEnv {
         &self,
         store: &'a (impl AsStoreRef + ?Sized),
     ) -> MemoryView<'a> {
-        self.try_memory_view(store).expect(
+        let view = self.try_memory_view(store).expect(
             "You must initialize the WasiEnv before using it and can not pass it between threads",
-        )
+        );
+        self.process.usage.record_memory(view.data_size());
+        view
     }
 
     /// Copy the lazy reference so that when it's initialized during the
@@ -797,6 +861,47 @@ impl WasiEnv {
         self.state.stdin()
     }
 
+    /// Get a handle on whatever stdout currently is, so the host can read
+    /// from and write to it without knowing if it was overridden.
+    ///
+    /// Fails with [`FsError::NotAFile`] if stdout isn't backed by a file.
+    pub fn stdout_handle(&self) -> Result<ArcBoxFile, FsError> {
+        self.stdout()?.map(ArcBoxFile::new).ok_or(FsError::NotAFile)
+    }
+
+    /// Get a handle on whatever stderr currently is.
+    ///
+    /// See [`WasiEnv::stdout_handle()`].
+    pub fn stderr_handle(&self) -> Result<ArcBoxFile, FsError> {
+        self.stderr()?.map(ArcBoxFile::new).ok_or(FsError::NotAFile)
+    }
+
+    /// Get a handle on whatever stdin currently is.
+    ///
+    /// See [`WasiEnv::stdout_handle()`].
+    pub fn stdin_handle(&self) -> Result<ArcBoxFile, FsError> {
+        self.stdin()?.map(ArcBoxFile::new).ok_or(FsError::NotAFile)
+    }
+
+    /// Opens a file in the file system of the sandbox for reading, e.g. to
+    /// look at what the program wrote once it has finished running.
+    pub fn open_file(
+        &self,
+        path: &Path,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, FsError> {
+        self.fs_root().new_open_options().read(true).open(path)
+    }
+
+    /// Reads the whole contents of a file in the file system of the sandbox.
+    ///
+    /// See [`WasiEnv::open_file()`].
+    pub fn read_file(&self, path: &Path) -> Result<Vec<u8>, FsError> {
+        let mut file = self.open_file(path)?;
+        let mut data = Vec::new();
+        self.tasks().block_on(file.read_to_end(&mut data))?;
+        Ok(data)
+    }
+
     /// Internal helper function to get a standard device handle.
     /// Expects one of `__WASI_STDIN_FILENO`, `__WASI_STDOUT_FILENO`, `__WASI_STDERR_FILENO`.
     pub fn std_dev_get(
@@ -849,11 +954,20 @@ impl WasiEnv {
     /// [cmd-atom]: crate::bin_factory::BinaryPackageCommand::atom()
     /// [pkg-fs]: crate::bin_factory::BinaryPackage::webc_fs
     pub fn use_package(&self, pkg: &BinaryPackage) -> Result<(), WasiStateCreationError> {
+        self.tasks().block_on(self.use_package_async(pkg))
+    }
+
+    /// Like [`WasiEnv::use_package()`], but for callers that are already
+    /// running inside an async runtime.
+    pub async fn use_package_async(
+        &self,
+        pkg: &BinaryPackage,
+    ) -> Result<(), WasiStateCreationError> {
         let root_fs = &self.state.fs.root_fs;
 
         // We first need to copy any files in the package over to the
         // main file system
-        if let Err(e) = self.tasks().block_on(root_fs.merge(&pkg.webc_fs)) {
+        if let Err(e) = root_fs.merge(&pkg.webc_fs).await {
             warn!(
                 error = &e as &dyn std::error::Error,
                 "Unable to merge the package's filesystem into the main one",
@@ -905,15 +1019,13 @@ impl WasiEnv {
                     WasiFsRoot::Backing(fs) => {
                         // Looks like we need to make the copy
                         let mut f = fs.new_open_options().create(true).write(true).open(path)?;
-                        self.tasks()
-                            .block_on(f.write_all(command.atom()))
-                            .map_err(|e| {
-                                WasiStateCreationError::WasiIncludePackageError(format!(
-                                    "Unable to save \"{}\" to \"{}\": {e}",
-                                    command.name(),
-                                    path.display()
-                                ))
-                            })?;
+                        f.write_all(command.atom()).await.map_err(|e| {
+                            WasiStateCreationError::WasiIncludePackageError(format!(
+                                "Unable to save \"{}\" to \"{}\": {e}",
+                                command.name(),
+                                path.display()
+                            ))
+                        })?;
                     }
                 }
 
@@ -937,6 +1049,15 @@ impl WasiEnv {
     /// Given a list of packages, load them from the registry and make them
     /// available.
     pub fn uses<I>(&self, uses: I) -> Result<(), WasiStateCreationError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.tasks().block_on(self.uses_async(uses))
+    }
+
+    /// Like [`WasiEnv::uses()`], but for callers that are already running
+    /// inside an async runtime.
+    pub async fn uses_async<I>(&self, uses: I) -> Result<(), WasiStateCreationError>
     where
         I: IntoIterator<Item = String>,
     {
@@ -946,11 +1067,10 @@ impl WasiEnv {
             let specifier = package_name
                 .parse::<PackageSpecifier>()
                 .map_err(|e| WasiStateCreationError::WasiIncludePackageError(e.to_string()))?;
-            let pkg = rt
-                .task_manager()
-                .block_on(BinaryPackage::from_registry(&specifier, rt))
+            let pkg = BinaryPackage::from_registry(&specifier, rt)
+                .await
                 .map_err(|e| WasiStateCreationError::WasiIncludePackageError(e.to_string()))?;
-            self.use_package(&pkg)?;
+            self.use_package_async(&pkg).await?;
         }
 
         Ok(())
@@ -1005,6 +1125,11 @@ impl WasiEnv {
             Ok(())
         })
         .ok();
+
+        // Hand the turn over to the next thread for good
+        if let Some(scheduler) = self.control_plane.scheduler() {
+            scheduler.release(self.tid());
+        }
     }
 
     /// Cleans up all the open files (if this is the main thread)
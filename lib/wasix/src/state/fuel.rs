@@ -0,0 +1,64 @@
+//! Limits how much work a guest may do before it is stopped.
+//!
+//! Fuel is backed by the metering middleware from `wasmer-middlewares`, so
+//! it only has an effect on modules that were compiled with it.
+
+use wasmer::{AsStoreMut, Instance, RuntimeError};
+
+use crate::{WasiError, WasiFunctionEnv};
+
+#[cfg(feature = "sys")]
+const REMAINING_POINTS: &str = "wasmer_metering_remaining_points";
+
+/// Gives an instance `fuel` points to spend before it traps.
+#[cfg(feature = "sys")]
+pub(crate) fn set_fuel(store: &mut impl AsStoreMut, instance: &Instance, fuel: u64) {
+    if instance.exports.get_global(REMAINING_POINTS).is_err() {
+        tracing::warn!(
+            "a fuel limit was requested but the module was not compiled with metering, ignoring it"
+        );
+        return;
+    }
+    wasmer_middlewares::metering::set_remaining_points(store, instance, fuel);
+}
+
+#[cfg(not(feature = "sys"))]
+pub(crate) fn set_fuel(_store: &mut impl AsStoreMut, _instance: &Instance, _fuel: u64) {
+    tracing::warn!("fuel limits are only supported with the sys feature, ignoring it");
+}
+
+/// Checks whether an instance was stopped because it used up all of its fuel.
+#[cfg(feature = "sys")]
+pub(crate) fn is_out_of_fuel(store: &mut impl AsStoreMut, instance: &Instance) -> bool {
+    use wasmer_middlewares::metering::{get_remaining_points, MeteringPoints};
+
+    instance.exports.get_global(REMAINING_POINTS).is_ok()
+        && get_remaining_points(store, instance) == MeteringPoints::Exhausted
+}
+
+#[cfg(not(feature = "sys"))]
+pub(crate) fn is_out_of_fuel(_store: &mut impl AsStoreMut, _instance: &Instance) -> bool {
+    false
+}
+
+/// Turns the trap raised by an instance that used up all of its fuel into
+/// [`WasiError::OutOfFuel`], leaving every other error untouched.
+pub(crate) fn out_of_fuel_trap(
+    store: &mut impl AsStoreMut,
+    ctx: &WasiFunctionEnv,
+    err: RuntimeError,
+) -> RuntimeError {
+    if err.is::<WasiError>() {
+        return err;
+    }
+    let out_of_fuel = ctx
+        .data(&*store)
+        .try_clone_instance()
+        .map(|instance| is_out_of_fuel(store, &instance))
+        .unwrap_or(false);
+    if out_of_fuel {
+        RuntimeError::user(Box::new(WasiError::OutOfFuel))
+    } else {
+        err
+    }
+}
@@ -59,6 +59,10 @@ impl WasiFunctionEnv {
             WasiThreadError::InstanceCreateFailed(Box::new(err))
         })?;
 
+        if let Some(fuel) = ctx.data(&store).capabilities.fuel {
+            super::set_fuel(&mut store, &instance, fuel);
+        }
+
         init(&instance, &store).map_err(|err| {
             tracing::warn!("failed to init instance - {}", err);
             WasiThreadError::InitFailed(err)
@@ -17,14 +17,17 @@
 
 mod builder;
 mod env;
+mod fuel;
 mod func_env;
 mod handles;
+mod snapshot;
+mod syscall_metrics;
 mod types;
 
 use std::{
     collections::{BTreeMap, HashMap},
     path::Path,
-    sync::Mutex,
+    sync::{Arc, Mutex},
     task::Waker,
     time::Duration,
 };
@@ -32,7 +35,7 @@ use std::{
 #[cfg(feature = "enable-serde")]
 use serde::{Deserialize, Serialize};
 use virtual_fs::{FileOpener, FileSystem, FsError, OpenOptions, VirtualFile};
-use wasmer_wasix_types::wasi::{Errno, Fd as WasiFd, Rights, Snapshot0Clockid};
+use wasmer_wasix_types::wasi::{Errno, Fd as WasiFd, Rights, Signal, Snapshot0Clockid};
 
 pub use self::{
     builder::*,
@@ -43,10 +46,13 @@ pub use self::{
 pub use crate::fs::{InodeGuard, InodeWeakGuard};
 use crate::{
     fs::{fs_error_into_wasi_err, WasiFs, WasiFsRoot, WasiInodes, WasiStateFileGuard},
+    os::task::signal::SignalDisposition,
     syscalls::types::*,
     utils::WasiParkingLot,
 };
+pub(crate) use fuel::{is_out_of_fuel, out_of_fuel_trap, set_fuel};
 pub(crate) use handles::*;
+pub(crate) use syscall_metrics::SyscallMetrics;
 
 /// all the rights enabled
 pub const ALL_RIGHTS: Rights = Rights::all();
@@ -130,11 +136,20 @@ pub(crate) struct WasiState {
     pub inodes: WasiInodes,
     pub futexs: Mutex<WasiFutexState>,
     pub clock_offset: Mutex<HashMap<Snapshot0Clockid, i64>>,
+    /// Name of the program that is running, `args[0]` may be different
+    pub program_name: String,
     pub args: Vec<String>,
-    pub envs: Vec<Vec<u8>>,
+    pub envs: Mutex<Vec<Vec<u8>>>,
     // TODO: should not be here, since this requires active work to resolve.
     // State should only hold active runtime state that can be reproducibly re-created.
     pub preopen: Vec<String>,
+    /// Overrides what happens to signals the program has no handler for.
+    #[cfg_attr(feature = "enable-serde", serde(skip))]
+    pub signal_dispositions: HashMap<Signal, SignalDisposition>,
+    /// Counts the syscalls the program makes, if that was enabled with
+    /// [`WasiEnvBuilder::enable_syscall_metrics()`].
+    #[cfg_attr(feature = "enable-serde", serde(skip))]
+    pub syscall_metrics: Option<Arc<SyscallMetrics>>,
 }
 
 impl WasiState {
@@ -250,9 +265,25 @@ impl WasiState {
             inodes: self.inodes.clone(),
             futexs: Default::default(),
             clock_offset: Mutex::new(self.clock_offset.lock().unwrap().clone()),
+            program_name: self.program_name.clone(),
             args: self.args.clone(),
-            envs: self.envs.clone(),
+            envs: Mutex::new(self.envs.lock().unwrap().clone()),
             preopen: self.preopen.clone(),
+            signal_dispositions: self.signal_dispositions.clone(),
+            syscall_metrics: self.syscall_metrics.clone(),
         }
     }
+
+    /// What happens to `signal` when the program hasn't registered a
+    /// signal handler.
+    pub fn signal_disposition(&self, signal: Signal) -> SignalDisposition {
+        // SIGKILL can't be ignored
+        if signal == Signal::Sigkill {
+            return SignalDisposition::Terminate;
+        }
+        self.signal_dispositions
+            .get(&signal)
+            .cloned()
+            .unwrap_or_else(|| SignalDisposition::default_for(signal))
+    }
 }
@@ -0,0 +1,474 @@
+//! Save and restore of a [`WasiState`] that is not currently running.
+//!
+//! The snapshot captures the arguments, environment, current directory,
+//! preopened directories, the open file descriptors (including their
+//! offsets), the signal dispositions and the full contents of the sandbox
+//! filesystem. Sockets, pipes, signal callbacks and other live resources
+//! are not captured.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Deref,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Mutex, RwLock},
+};
+
+use bytes::Bytes;
+use futures::executor::block_on;
+use serde::{Deserialize, Serialize};
+use virtual_fs::{AsyncReadExt, AsyncWriteExt, FileSystem, FsError, TmpFileSystem};
+use wasmer_wasix_types::wasi::{Fdflags, Rights, Signal, Snapshot0Clockid};
+
+use super::{PreopenedDir, WasiState};
+use crate::{
+    fs::{Fd, Kind, WasiFs, WasiFsRoot, WasiInodes},
+    os::task::signal::SignalDisposition,
+};
+
+/// Version of the format produced by [`WasiState::serialize`].
+///
+/// Bump this whenever [`WasiStateSnapshot`] changes in an incompatible way.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Written in front of the [`WasiStateSnapshot`] so the version can be
+/// checked before the rest of the buffer is decoded.
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WasiStateSnapshot {
+    secret: [u8; 32],
+    program_name: String,
+    args: Vec<String>,
+    envs: Vec<Vec<u8>>,
+    vfs_preopens: Vec<String>,
+    current_dir: String,
+    clock_offset: Vec<(u32, i64)>,
+    preopens: Vec<SnapshotPreopen>,
+    entries: Vec<SnapshotEntry>,
+    fds: Vec<SnapshotFd>,
+    next_fd: u32,
+    signal_dispositions: Vec<(u8, SnapshotDisposition)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotPreopen {
+    path: PathBuf,
+    alias: String,
+    open_flags: u16,
+    priority: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum SnapshotDisposition {
+    Terminate,
+    Ignore,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum SnapshotEntry {
+    Dir { path: PathBuf },
+    File { path: PathBuf, contents: Vec<u8> },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFd {
+    fd: u32,
+    path: PathBuf,
+    name: String,
+    rights: u64,
+    rights_inheriting: u64,
+    flags: u16,
+    open_flags: u16,
+    offset: u64,
+}
+
+impl WasiState {
+    /// Serializes this (non-running) state into a versioned byte buffer that
+    /// can later be turned back into a [`WasiState`] with
+    /// [`WasiState::deserialize`].
+    ///
+    /// Only sandboxed filesystems can be captured, a state that uses a
+    /// backing filesystem will return [`FsError::InvalidInput`].
+    pub fn serialize(&self) -> Result<Bytes, FsError> {
+        let root_fs = match &self.fs.root_fs {
+            WasiFsRoot::Sandbox(fs) => fs.clone(),
+            WasiFsRoot::Backing(_) => return Err(FsError::InvalidInput),
+        };
+
+        let preopen_fds = self.fs.preopen_fds.read().unwrap().clone();
+        let preopen_priorities = self.fs.preopen_priorities.read().unwrap().clone();
+        let fd_map = self.fs.fd_map.read().unwrap();
+
+        let mut preopens = Vec::new();
+        let mut fds = Vec::new();
+        let mut fd_numbers = fd_map.keys().copied().collect::<Vec<_>>();
+        fd_numbers.sort_unstable();
+        for fd_num in fd_numbers {
+            let fd = &fd_map[&fd_num];
+            if fd.is_stdio {
+                continue;
+            }
+            let guard = fd.inode.read();
+            match guard.deref() {
+                Kind::Dir { path, .. } if preopen_fds.contains(&fd_num) => {
+                    // Virtual preopens are recreated from their names
+                    if self.preopen.iter().any(|p| p.as_str() == fd.inode.name) {
+                        continue;
+                    }
+                    preopens.push(SnapshotPreopen {
+                        path: path.clone(),
+                        alias: fd.inode.name.to_string(),
+                        open_flags: fd.open_flags,
+                        priority: preopen_priorities.get(&fd_num).copied().unwrap_or_default(),
+                    });
+                }
+                Kind::File { path, fd: None, .. } => {
+                    fds.push(SnapshotFd {
+                        fd: fd_num,
+                        path: path.clone(),
+                        name: fd.inode.name.to_string(),
+                        rights: fd.rights.bits(),
+                        rights_inheriting: fd.rights_inheriting.bits(),
+                        flags: fd.flags.bits(),
+                        open_flags: fd.open_flags,
+                        offset: fd.offset.load(Ordering::Acquire),
+                    });
+                }
+                _ => {}
+            }
+        }
+        drop(fd_map);
+
+        // Callbacks live in the host, so only the plain dispositions can be
+        // captured
+        let mut signal_dispositions = self
+            .signal_dispositions
+            .iter()
+            .filter_map(|(signal, disposition)| {
+                let disposition = match disposition {
+                    SignalDisposition::Terminate => SnapshotDisposition::Terminate,
+                    SignalDisposition::Ignore => SnapshotDisposition::Ignore,
+                    SignalDisposition::Callback(_) => return None,
+                };
+                Some((*signal as u8, disposition))
+            })
+            .collect::<Vec<_>>();
+        signal_dispositions.sort_unstable_by_key(|(signal, _)| *signal);
+
+        let snapshot = WasiStateSnapshot {
+            secret: self.secret,
+            program_name: self.program_name.clone(),
+            args: self.args.clone(),
+            envs: self.envs.lock().unwrap().clone(),
+            vfs_preopens: self.preopen.clone(),
+            current_dir: self.fs.current_dir.lock().unwrap().clone(),
+            clock_offset: self
+                .clock_offset
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, offset)| (*id as u32, *offset))
+                .collect(),
+            preopens,
+            entries: snapshot_entries(&root_fs)?,
+            fds,
+            next_fd: self.fs.next_fd.load(Ordering::SeqCst),
+            signal_dispositions,
+        };
+
+        let header = SnapshotHeader {
+            version: SNAPSHOT_VERSION,
+        };
+        let mut data = Vec::new();
+        bincode::serialize_into(&mut data, &header).map_err(|_| FsError::InvalidData)?;
+        bincode::serialize_into(&mut data, &snapshot).map_err(|_| FsError::InvalidData)?;
+        Ok(Bytes::from(data))
+    }
+
+    /// Reconstructs a [`WasiState`] from a buffer produced by
+    /// [`WasiState::serialize`].
+    pub fn deserialize(mut data: &[u8]) -> Result<Self, FsError> {
+        let header: SnapshotHeader =
+            bincode::deserialize_from(&mut data).map_err(|_| FsError::InvalidData)?;
+        if header.version != SNAPSHOT_VERSION {
+            tracing::debug!(
+                version = header.version,
+                expected = SNAPSHOT_VERSION,
+                "Unsupported WasiState snapshot version"
+            );
+            return Err(FsError::InvalidData);
+        }
+        let snapshot: WasiStateSnapshot =
+            bincode::deserialize_from(&mut data).map_err(|_| FsError::InvalidData)?;
+
+        let root_fs = TmpFileSystem::new();
+        for entry in &snapshot.entries {
+            match entry {
+                SnapshotEntry::Dir { path } => root_fs.create_dir(path)?,
+                SnapshotEntry::File { path, contents } => {
+                    let mut file = root_fs
+                        .new_open_options()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(path)?;
+                    block_on(file.write_all(contents))?;
+                }
+            }
+        }
+
+        let preopens = snapshot
+            .preopens
+            .into_iter()
+            .map(|p| PreopenedDir {
+                path: p.path,
+                alias: Some(p.alias),
+                read: p.open_flags & Fd::READ != 0,
+                write: p.open_flags & Fd::WRITE != 0,
+                create: p.open_flags & Fd::CREATE != 0,
+                priority: p.priority,
+            })
+            .collect::<Vec<_>>();
+
+        let inodes = WasiInodes::new();
+        let fs = WasiFs::new_with_preopen(
+            &inodes,
+            &preopens,
+            &snapshot.vfs_preopens,
+            WasiFsRoot::Sandbox(Arc::new(root_fs)),
+        )
+        .map_err(|e| {
+            tracing::debug!(error = %e, "Unable to restore the WASI filesystem");
+            FsError::InvalidData
+        })?;
+        fs.set_current_dir(&snapshot.current_dir);
+
+        for entry in snapshot.fds {
+            let handle = fs
+                .root_fs
+                .new_open_options()
+                .read(entry.open_flags & Fd::READ != 0)
+                .write(entry.open_flags & (Fd::WRITE | Fd::APPEND) != 0)
+                .append(entry.open_flags & Fd::APPEND != 0)
+                .open(&entry.path)?;
+            let kind = Kind::File {
+                handle: Some(Arc::new(RwLock::new(handle))),
+                path: entry.path,
+                fd: None,
+            };
+            let inode = fs
+                .create_inode(&inodes, kind, false, entry.name)
+                .map_err(|_| FsError::IOError)?;
+            fs.create_fd_ext(
+                Rights::from_bits_truncate(entry.rights),
+                Rights::from_bits_truncate(entry.rights_inheriting),
+                Fdflags::from_bits_truncate(entry.flags),
+                entry.open_flags,
+                inode,
+                entry.fd,
+            )
+            .map_err(|_| FsError::IOError)?;
+            fs.get_fd(entry.fd)
+                .map_err(|_| FsError::InvalidFd)?
+                .offset
+                .store(entry.offset, Ordering::Release);
+        }
+        fs.next_fd.fetch_max(snapshot.next_fd, Ordering::SeqCst);
+
+        let clock_offset = snapshot
+            .clock_offset
+            .into_iter()
+            .filter_map(|(id, offset)| Snapshot0Clockid::try_from(id).ok().map(|id| (id, offset)))
+            .collect::<HashMap<_, _>>();
+
+        let signal_dispositions = snapshot
+            .signal_dispositions
+            .into_iter()
+            .filter_map(|(signal, disposition)| {
+                let disposition = match disposition {
+                    SnapshotDisposition::Terminate => SignalDisposition::Terminate,
+                    SnapshotDisposition::Ignore => SignalDisposition::Ignore,
+                };
+                Signal::try_from(signal)
+                    .ok()
+                    .map(|signal| (signal, disposition))
+            })
+            .collect::<HashMap<_, _>>();
+
+        Ok(WasiState {
+            secret: snapshot.secret,
+            fs,
+            inodes,
+            futexs: Default::default(),
+            clock_offset: Mutex::new(clock_offset),
+            program_name: snapshot.program_name,
+            args: snapshot.args,
+            envs: Mutex::new(snapshot.envs),
+            preopen: snapshot.vfs_preopens,
+            signal_dispositions,
+            syscall_metrics: None,
+        })
+    }
+}
+
+/// Walks the sandbox filesystem breadth first so that parent directories are
+/// always recorded before their children.
+fn snapshot_entries(fs: &TmpFileSystem) -> Result<Vec<SnapshotEntry>, FsError> {
+    let mut entries = Vec::new();
+    let mut to_visit = VecDeque::new();
+    to_visit.push_back(PathBuf::from("/"));
+
+    while let Some(dir) = to_visit.pop_front() {
+        for entry in fs.read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                entries.push(SnapshotEntry::Dir {
+                    path: entry.path.clone(),
+                });
+                to_visit.push_back(entry.path);
+            } else if metadata.is_file() {
+                let mut file = fs.new_open_options().read(true).open(&entry.path)?;
+                let mut contents = Vec::new();
+                block_on(file.read_to_end(&mut contents))?;
+                entries.push(SnapshotEntry::File {
+                    path: entry.path,
+                    contents,
+                });
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::WasiEnv;
+
+    #[test]
+    fn round_trip_with_files_and_env() {
+        let init = WasiEnv::builder("prog")
+            .with_argv0("renamed")
+            .arg("--verbose")
+            .env("GREETING", "hello")
+            .build_init()
+            .unwrap();
+        let state = init.state;
+
+        state.fs_create_dir("/data").unwrap();
+        let mut file = state
+            .fs_new_open_options()
+            .write(true)
+            .create(true)
+            .open("/data/hello.txt")
+            .unwrap();
+        block_on(file.write_all(b"hello world")).unwrap();
+        drop(file);
+        state.fs.set_current_dir("/data");
+
+        let handle = state
+            .fs_new_open_options()
+            .read(true)
+            .open("/data/hello.txt")
+            .unwrap();
+        let kind = Kind::File {
+            handle: Some(Arc::new(RwLock::new(handle))),
+            path: PathBuf::from("/data/hello.txt"),
+            fd: None,
+        };
+        let inode = state
+            .fs
+            .create_inode(&state.inodes, kind, false, "hello.txt".to_string())
+            .unwrap();
+        let fd = state
+            .fs
+            .create_fd(
+                Rights::all(),
+                Rights::all(),
+                Fdflags::empty(),
+                Fd::READ,
+                inode,
+            )
+            .unwrap();
+        state
+            .fs
+            .get_fd(fd)
+            .unwrap()
+            .offset
+            .store(6, Ordering::Release);
+
+        let data = state.serialize().unwrap();
+        let restored = WasiState::deserialize(&data).unwrap();
+
+        assert_eq!(restored.args, vec!["renamed", "--verbose"]);
+        assert_eq!(restored.program_name, "prog");
+        assert_eq!(
+            *restored.envs.lock().unwrap(),
+            vec![b"GREETING=hello".to_vec()]
+        );
+        assert_eq!(restored.secret, state.secret);
+        assert_eq!(restored.fs.current_dir.lock().unwrap().as_str(), "/data");
+
+        let mut contents = String::new();
+        let mut file = restored
+            .fs_new_open_options()
+            .read(true)
+            .open("/data/hello.txt")
+            .unwrap();
+        block_on(file.read_to_string(&mut contents)).unwrap();
+        assert_eq!(contents, "hello world");
+
+        let restored_fd = restored.fs.get_fd(fd).unwrap();
+        assert_eq!(restored_fd.offset.load(Ordering::Acquire), 6);
+        assert_eq!(restored_fd.open_flags, Fd::READ);
+        assert!(restored.fs.next_fd.load(Ordering::SeqCst) > fd);
+    }
+
+    #[test]
+    fn preopen_priorities_and_signal_dispositions_are_kept() {
+        let root = TmpFileSystem::new();
+        root.create_dir(Path::new("/data")).unwrap();
+        let init = WasiEnv::builder("prog")
+            .sandbox_fs(root)
+            .with_preopen_priority("data", "/data", 3)
+            .unwrap()
+            .with_signal_disposition(Signal::Sigint, SignalDisposition::Ignore)
+            .build_init()
+            .unwrap();
+
+        let data = init.state.serialize().unwrap();
+        let restored = WasiState::deserialize(&data).unwrap();
+
+        let preopen_fds = restored.fs.preopen_fds.read().unwrap().clone();
+        let priorities = restored.fs.preopen_priorities.read().unwrap().clone();
+        assert_eq!(preopen_fds.len(), 1);
+        assert_eq!(priorities[&preopen_fds[0]], 3);
+        assert!(matches!(
+            restored.signal_dispositions.get(&Signal::Sigint),
+            Some(SignalDisposition::Ignore)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        // Whatever follows the header of a newer version can't be decoded
+        // by this one
+        let header = SnapshotHeader {
+            version: SNAPSHOT_VERSION + 1,
+        };
+        let mut data = bincode::serialize(&header).unwrap();
+        data.extend_from_slice(b"a layout from the future");
+
+        assert_eq!(
+            WasiState::deserialize(&data).unwrap_err(),
+            FsError::InvalidData
+        );
+    }
+}
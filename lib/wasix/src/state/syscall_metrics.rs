@@ -0,0 +1,119 @@
+//! Counts how often a guest makes each syscall.
+//!
+//! Counting is opt-in: only when it's enabled are the syscall imports
+//! wrapped, so there is no cost otherwise.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use wasmer::{AsStoreMut, Exports, Extern, Function, FunctionEnv, FunctionEnvMut, Imports};
+
+use crate::WasiEnv;
+
+/// The names of all the syscalls that have been counted so far.
+///
+/// Each name is only leaked once, no matter how many instances are created.
+static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+fn intern(name: &str) -> &'static str {
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.get(name) {
+        return *name;
+    }
+    let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+    names.insert(name);
+    name
+}
+
+/// Cumulative counts of the syscalls made by a program, shared by all of its
+/// threads.
+#[derive(Debug, Default)]
+pub(crate) struct SyscallMetrics {
+    counters: Mutex<HashMap<&'static str, Arc<AtomicU64>>>,
+}
+
+impl SyscallMetrics {
+    fn counter(&self, name: &str) -> Arc<AtomicU64> {
+        self.counters
+            .lock()
+            .unwrap()
+            .entry(intern(name))
+            .or_default()
+            .clone()
+    }
+
+    /// How many times each syscall was made, leaving out the ones that
+    /// weren't made at all.
+    pub(crate) fn counts(&self) -> HashMap<&'static str, u64> {
+        self.counters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, counter)| (*name, counter.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    /// Wraps every syscall in `exports` so calling it bumps its counter.
+    ///
+    /// The same syscall imported from different namespaces (e.g.
+    /// `wasi_snapshot_preview1` and `wasix_32v1`) shares a counter.
+    pub(crate) fn instrument(
+        &self,
+        store: &mut impl AsStoreMut,
+        env: &FunctionEnv<WasiEnv>,
+        exports: Exports,
+    ) -> Exports {
+        exports
+            .into_iter()
+            .map(|(name, export)| {
+                let function = match export {
+                    Extern::Function(function) => function,
+                    other => return (name, other),
+                };
+
+                let counter = self.counter(&name);
+                let ty = function.ty(&*store);
+                let wrapped = Function::new_with_env(
+                    store,
+                    env,
+                    ty,
+                    move |mut ctx: FunctionEnvMut<'_, WasiEnv>, args| {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                        function.call(&mut ctx, args).map(Vec::from)
+                    },
+                );
+                (name, Extern::Function(wrapped))
+            })
+            .collect()
+    }
+
+    /// Like [`SyscallMetrics::instrument()`], but for every namespace in
+    /// `imports`.
+    pub(crate) fn instrument_imports(
+        &self,
+        store: &mut impl AsStoreMut,
+        env: &FunctionEnv<WasiEnv>,
+        imports: Imports,
+    ) -> Imports {
+        let mut namespaces: BTreeMap<String, Vec<(String, Extern)>> = BTreeMap::new();
+        for ((namespace, name), export) in &imports {
+            namespaces
+                .entry(namespace)
+                .or_default()
+                .push((name, export));
+        }
+
+        let mut instrumented = Imports::new();
+        for (namespace, exports) in namespaces {
+            let exports = self.instrument(store, env, exports.into_iter().collect());
+            instrumented.register_namespace(&namespace, exports);
+        }
+        instrumented
+    }
+}
@@ -30,7 +30,7 @@ pub mod legacy;
 pub(crate) use std::{
     borrow::{Borrow, Cow},
     cell::RefCell,
-    collections::{hash_map::Entry, HashMap, HashSet},
+    collections::{hash_map::Entry, HashMap},
     convert::{Infallible, TryInto},
     io::{self, Read, Seek, Write},
     mem::transmute,
@@ -90,6 +90,7 @@ pub(crate) use self::types::{
 use self::{state::WasiInstanceGuardMemory, utils::WasiDummyWaker};
 pub(crate) use crate::os::task::{
     process::{WasiProcessId, WasiProcessWait},
+    scheduler::BlockedTurn,
     thread::{WasiThread, WasiThreadId},
 };
 pub(crate) use crate::{
@@ -306,6 +307,7 @@ where
     {
         ctx: &'a mut FunctionEnvMut<'b, WasiEnv>,
         pinned_work: Pin<Box<Fut>>,
+        turn: &'a BlockedTurn,
     }
     impl<'a, 'b, Fut, T> Future for Poller<'a, 'b, Fut, T>
     where
@@ -325,6 +327,7 @@ where
                 }
                 return Poll::Ready(Ok(Err(Errno::Intr)));
             }
+            self.turn.release();
             Poll::Pending
         }
     }
@@ -332,7 +335,12 @@ where
     // Block on the work
     let mut pinned_work = Box::pin(work);
     let tasks = env.tasks().clone();
-    let poller = Poller { ctx, pinned_work };
+    let turn = BlockedTurn::new(env.control_plane.scheduler(), env.tid());
+    let poller = Poller {
+        ctx,
+        pinned_work,
+        turn: &turn,
+    };
     block_on_with_timeout(&tasks, timeout, poller)
 }
 
@@ -349,6 +357,7 @@ where
     process_signals: bool,
     thread: WasiThread,
     work: &'a mut Pin<Box<Fut>>,
+    turn: &'a BlockedTurn,
 }
 impl<'a, T, Fut> Future for AsyncifyPoller<'a, T, Fut>
 where
@@ -379,6 +388,7 @@ where
                 }
             }
         }
+        self.turn.release();
         Poll::Pending
     }
 }
@@ -422,6 +432,8 @@ where
 
     // Define the work
     let tasks = ctx.data().tasks().clone();
+    let blocked_turn = BlockedTurn::new(ctx.data().control_plane.scheduler(), ctx.data().tid());
+    let turn = &blocked_turn;
     let work = async move {
         let env = ctx.data();
 
@@ -440,6 +452,7 @@ where
                 process_signals,
                 thread: ctx.data().thread.clone(),
                 work: &mut trigger,
+                turn,
             } => {
                 let result = res?;
                 AsyncifyAction::Finish(ctx, result)
@@ -482,6 +495,7 @@ where
     {
         env: &'a WasiEnv,
         pinned_work: Pin<Box<Fut>>,
+        turn: &'a BlockedTurn,
     }
     impl<'a, Fut, T> Future for Poller<'a, Fut, T>
     where
@@ -498,13 +512,19 @@ where
             if let Some(signals) = self.env.thread.pop_signals_or_subscribe(cx.waker()) {
                 return Poll::Ready(Ok(Err(Errno::Intr)));
             }
+            self.turn.release();
             Poll::Pending
         }
     }
 
     // Block on the work
     let mut pinned_work = Box::pin(work);
-    let poller = Poller { env, pinned_work };
+    let turn = BlockedTurn::new(env.control_plane.scheduler(), env.tid());
+    let poller = Poller {
+        env,
+        pinned_work,
+        turn: &turn,
+    };
     block_on_with_timeout(env.tasks(), timeout, poller)
 }
 
@@ -781,11 +801,125 @@ pub(crate) fn write_buffer_array<M: MemorySize>(
     Errno::Success
 }
 
-pub(crate) fn get_current_time_in_nanos() -> Result<Timestamp, Errno> {
-    let now = platform_clock_time_get(Snapshot0Clockid::Monotonic, 1_000_000).unwrap() as u128;
+/// Reads a clock of the guest. The realtime and monotonic clocks come from
+/// the [`Clock`](crate::runtime::Clock) of the runtime, while the CPU time
+/// clocks always come from the platform.
+pub(crate) fn runtime_clock_time_get(
+    clock: &dyn crate::runtime::Clock,
+    clock_id: Snapshot0Clockid,
+    precision: Timestamp,
+) -> Result<i64, Errno> {
+    match clock_id {
+        Snapshot0Clockid::Realtime => Ok(clock.now().as_nanos() as i64),
+        Snapshot0Clockid::Monotonic => Ok(clock.monotonic().as_nanos() as i64),
+        _ => platform_clock_time_get(clock_id, precision),
+    }
+}
+
+/// Reads the realtime clock, including any offset the environment has
+/// applied to it with `clock_time_set`.
+pub(crate) fn get_current_time_in_nanos(env: &WasiEnv) -> Result<Timestamp, Errno> {
+    let mut now = runtime_clock_time_get(
+        env.runtime().clock().as_ref(),
+        Snapshot0Clockid::Realtime,
+        1_000_000,
+    )?;
+    if let Some(offset) = env
+        .state
+        .clock_offset
+        .lock()
+        .unwrap()
+        .get(&Snapshot0Clockid::Realtime)
+    {
+        now += *offset;
+    }
     Ok(now as Timestamp)
 }
 
+/// Works out which timestamps `fd_filestat_set_times` and
+/// `path_filestat_set_times` should change. A timestamp that is `None`
+/// is left alone (`UTIME_OMIT`), while the `*_NOW` flags (`UTIME_NOW`)
+/// use the current time.
+pub(crate) fn resolve_set_times(
+    env: &WasiEnv,
+    st_atim: Timestamp,
+    st_mtim: Timestamp,
+    fst_flags: Fstflags,
+) -> Result<(Option<Timestamp>, Option<Timestamp>), Errno> {
+    if (fst_flags.contains(Fstflags::SET_ATIM) && fst_flags.contains(Fstflags::SET_ATIM_NOW))
+        || (fst_flags.contains(Fstflags::SET_MTIM) && fst_flags.contains(Fstflags::SET_MTIM_NOW))
+    {
+        return Err(Errno::Inval);
+    }
+
+    let atime = if fst_flags.contains(Fstflags::SET_ATIM) {
+        Some(st_atim)
+    } else if fst_flags.contains(Fstflags::SET_ATIM_NOW) {
+        Some(get_current_time_in_nanos(env)?)
+    } else {
+        None
+    };
+    let mtime = if fst_flags.contains(Fstflags::SET_MTIM) {
+        Some(st_mtim)
+    } else if fst_flags.contains(Fstflags::SET_MTIM_NOW) {
+        Some(get_current_time_in_nanos(env)?)
+    } else {
+        None
+    };
+    Ok((atime, mtime))
+}
+
+/// Applies the timestamps to an inode, and to the file behind it so that
+/// the change is visible to the file system.
+pub(crate) fn set_inode_times(
+    state: &WasiState,
+    inode: &InodeGuard,
+    atime: Option<Timestamp>,
+    mtime: Option<Timestamp>,
+) -> Result<(), Errno> {
+    if let Kind::File { handle, path, .. } = inode.read().deref() {
+        match handle {
+            Some(handle) => {
+                let mut handle = handle.write().unwrap();
+                handle
+                    .set_times(atime, mtime)
+                    .map_err(fs_error_into_wasi_err)?;
+            }
+            None => {
+                // Opening the file counts as accessing it, so hold on to
+                // the access time when it isn't meant to change
+                let atime = match atime {
+                    Some(atime) => atime,
+                    None => {
+                        state
+                            .fs
+                            .root_fs
+                            .metadata(path)
+                            .map_err(fs_error_into_wasi_err)?
+                            .accessed
+                    }
+                };
+                let mut file = state
+                    .fs_new_open_options()
+                    .read(true)
+                    .open(path)
+                    .map_err(fs_error_into_wasi_err)?;
+                file.set_times(Some(atime), mtime)
+                    .map_err(fs_error_into_wasi_err)?;
+            }
+        }
+    }
+
+    let mut stat = inode.stat.write().unwrap();
+    if let Some(atime) = atime {
+        stat.st_atim = atime;
+    }
+    if let Some(mtime) = mtime {
+        stat.st_mtim = mtime;
+    }
+    Ok(())
+}
+
 pub(crate) fn get_stack_lower(env: &WasiEnv) -> u64 {
     env.layout.stack_lower
 }
@@ -1236,35 +1370,17 @@ where
 }
 
 // Function to prepare the WASI environment
-pub(crate) fn _prepare_wasi(wasi_env: &mut WasiEnv, args: Option<Vec<String>>) {
+pub(crate) fn _prepare_wasi(wasi_env: &mut WasiEnv, name: &str, args: Option<Vec<String>>) {
     // Swap out the arguments with the new ones
     if let Some(args) = args {
         let mut wasi_state = wasi_env.state.fork();
+        wasi_state.program_name = name.to_string();
         wasi_state.args = args;
         wasi_env.state = Arc::new(wasi_state);
     }
 
-    // Close any files after the STDERR that are not preopened
-    let close_fds = {
-        let preopen_fds = {
-            let preopen_fds = wasi_env.state.fs.preopen_fds.read().unwrap();
-            preopen_fds.iter().copied().collect::<HashSet<_>>()
-        };
-        let mut fd_map = wasi_env.state.fs.fd_map.read().unwrap();
-        fd_map
-            .keys()
-            .filter_map(|a| match *a {
-                a if a <= __WASI_STDERR_FILENO => None,
-                a if preopen_fds.contains(&a) => None,
-                a => Some(a),
-            })
-            .collect::<Vec<_>>()
-    };
-
-    // Now close all these files
-    for fd in close_fds {
-        let _ = wasi_env.state.fs.close_fd(fd);
-    }
+    // Close any files that should not be inherited by the new program
+    wasi_env.state.fs.close_cloexec_fds();
 }
 
 pub(crate) fn conv_spawn_err_to_errno(err: SpawnError) -> Errno {
@@ -21,7 +21,8 @@ pub fn clock_time_get<M: MemorySize>(
     let env = ctx.data();
     let memory = unsafe { env.memory_view(&ctx) };
 
-    let mut t_out = wasi_try!(platform_clock_time_get(clock_id, precision));
+    let clock = env.runtime().clock();
+    let mut t_out = wasi_try!(runtime_clock_time_get(clock.as_ref(), clock_id, precision));
     {
         let guard = env.state.clock_offset.lock().unwrap();
         if let Some(offset) = guard.get(&clock_id) {
@@ -18,7 +18,8 @@ pub fn clock_time_set<M: MemorySize>(
     let memory = unsafe { env.memory_view(&ctx) };
 
     let precision = 1 as Timestamp;
-    let t_now = wasi_try!(platform_clock_time_get(clock_id, precision));
+    let clock = env.runtime().clock();
+    let t_now = wasi_try!(runtime_clock_time_get(clock.as_ref(), clock_id, precision));
     let t_now = t_now;
 
     let t_target = time as i64;
@@ -18,5 +18,6 @@ pub fn environ_get<M: MemorySize>(
     let env = ctx.data();
     let (memory, mut state) = unsafe { env.get_memory_and_wasi_state(&ctx, 0) };
 
-    write_buffer_array(&memory, &state.envs, environ, environ_buf)
+    let envs = state.envs.lock().unwrap();
+    write_buffer_array(&memory, &envs, environ, environ_buf)
 }
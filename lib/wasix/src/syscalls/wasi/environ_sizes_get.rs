@@ -20,9 +20,9 @@ pub fn environ_sizes_get<M: MemorySize>(
     let environ_count = environ_count.deref(&memory);
     let environ_buf_size = environ_buf_size.deref(&memory);
 
-    let env_var_count: M::Offset =
-        wasi_try!(state.envs.len().try_into().map_err(|_| Errno::Overflow));
-    let env_buf_size: usize = state.envs.iter().map(|v| v.len() + 1).sum();
+    let envs = state.envs.lock().unwrap();
+    let env_var_count: M::Offset = wasi_try!(envs.len().try_into().map_err(|_| Errno::Overflow));
+    let env_buf_size: usize = envs.iter().map(|v| v.len() + 1).sum();
     let env_buf_size: M::Offset = wasi_try!(env_buf_size.try_into().map_err(|_| Errno::Overflow));
     wasi_try_mem!(environ_count.write(env_var_count));
     wasi_try_mem!(environ_buf_size.write(env_buf_size));
@@ -1,3 +1,5 @@
+use virtual_fs::{BufferedReadFile, NullFile, Upcastable};
+
 use super::*;
 use crate::syscalls::*;
 
@@ -20,7 +22,221 @@ pub fn fd_advise(
     len: Filesize,
     advice: Advice,
 ) -> Errno {
-    // this is used for our own benefit, so just returning success is a valid
-    // implementation for now
+    let env = ctx.data();
+    let (_, mut state) = unsafe { env.get_memory_and_wasi_state(&ctx, 0) };
+    let fd_entry = wasi_try!(state.fs.get_fd(fd));
+    let inode = fd_entry.inode;
+
+    if !fd_entry.rights.contains(Rights::FD_ADVISE) {
+        return Errno::Access;
+    }
+
+    // The advice is only a hint, so everything other than `Dontneed` and
+    // `Sequential` is safely ignored. For in-memory files `Dontneed` is a
+    // good moment to give back any memory the file is holding on to beyond
+    // its contents.
+    let guard = inode.read();
+    if let Kind::File {
+        handle: Some(handle),
+        ..
+    } = guard.deref()
+    {
+        let mut handle = handle.write().unwrap();
+        match advice {
+            Advice::Dontneed => {
+                wasi_try!(handle.shrink_to_fit().map_err(fs_error_into_wasi_err));
+            }
+            // Reading ahead turns many small reads into a few big ones
+            Advice::Sequential if !(**handle).upcast_any_ref().is::<BufferedReadFile>() => {
+                let inner = std::mem::replace(&mut *handle, Box::<NullFile>::default());
+                *handle = Box::new(BufferedReadFile::new(inner));
+            }
+            _ => {}
+        }
+    }
+
     Errno::Success
 }
+
+#[cfg(all(test, feature = "sys"))]
+mod tests {
+    use std::{
+        io,
+        ops::Deref,
+        path::Path,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+    };
+
+    use virtual_fs::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf, TmpFileSystem, VirtualFile};
+    use wasmer::{Module, Store};
+
+    use crate::{fs::Kind, WasiEnv};
+
+    /// Counts how often the file it wraps is read from.
+    #[derive(Debug)]
+    struct CountingFile {
+        inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl VirtualFile for CountingFile {
+        fn last_accessed(&self) -> u64 {
+            self.inner.last_accessed()
+        }
+
+        fn last_modified(&self) -> u64 {
+            self.inner.last_modified()
+        }
+
+        fn created_time(&self) -> u64 {
+            self.inner.created_time()
+        }
+
+        fn size(&self) -> u64 {
+            self.inner.size()
+        }
+
+        fn set_len(&mut self, new_size: u64) -> virtual_fs::Result<()> {
+            self.inner.set_len(new_size)
+        }
+
+        fn unlink(&mut self) -> virtual_fs::Result<()> {
+            self.inner.unlink()
+        }
+    }
+
+    impl AsyncRead for CountingFile {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for CountingFile {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    impl AsyncSeek for CountingFile {
+        fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+            Pin::new(&mut self.inner).start_seek(position)
+        }
+
+        fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+            Pin::new(&mut self.inner).poll_complete(cx)
+        }
+    }
+
+    /// Reads a 4 KiB file in 16 byte chunks, returning how many bytes were
+    /// read and how many reads that took on the host.
+    fn read_file(advise: bool) -> (i32, usize) {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_advise" (func $fd_advise (param i32 i64 i64 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 200) "data.bin")
+                (func (export "_start"))
+                (func (export "open") (result i32)
+                    (call $path_open (i32.const 3) (i32.const 0) (i32.const 200) (i32.const 8)
+                        (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0) (i32.const 0))
+                    drop
+                    (i32.load (i32.const 0)))
+                ;; Advises sequential access
+                (func (export "advise") (param $fd i32) (result i32)
+                    (call $fd_advise (local.get $fd) (i64.const 0) (i64.const 0) (i32.const 1)))
+                (func (export "read_all") (param $fd i32) (result i32)
+                    (local $total i32)
+                    (loop $again
+                        (i32.store (i32.const 16) (i32.const 1024))
+                        (i32.store (i32.const 20) (i32.const 16))
+                        (call $fd_read (local.get $fd) (i32.const 16) (i32.const 1) (i32.const 8))
+                        drop
+                        (local.set $total (i32.add (local.get $total) (i32.load (i32.const 8))))
+                        (br_if $again (i32.load (i32.const 8))))
+                    (local.get $total)))
+            "#,
+        )
+        .unwrap();
+
+        let fs = TmpFileSystem::new();
+        fs.new_open_options_ext()
+            .insert_ro_file(Path::new("/data.bin"), vec![7u8; 4096].into())
+            .unwrap();
+        let (instance, env) = WasiEnv::builder("reader")
+            .sandbox_fs(fs)
+            .preopen_dir("/")
+            .unwrap()
+            .instantiate(module, &mut store)
+            .unwrap();
+        let call = |store: &mut Store, name: &str, fd: i32| {
+            let func = instance.exports.get_function(name).unwrap();
+            let args: Vec<_> = (name != "open").then_some(fd.into()).into_iter().collect();
+            func.call(store, &args).unwrap()[0].unwrap_i32()
+        };
+
+        let fd = call(&mut store, "open", 0);
+        let reads = Arc::new(AtomicUsize::new(0));
+        {
+            let inode = env.data(&store).state.fs.get_fd(fd as u32).unwrap().inode;
+            let guard = inode.read();
+            let Kind::File {
+                handle: Some(handle),
+                ..
+            } = guard.deref()
+            else {
+                panic!("not a file");
+            };
+            let mut handle = handle.write().unwrap();
+            let inner = std::mem::replace(&mut *handle, Box::<virtual_fs::NullFile>::default());
+            *handle = Box::new(CountingFile {
+                inner,
+                reads: reads.clone(),
+            });
+        }
+        if advise {
+            assert_eq!(call(&mut store, "advise", fd), 0);
+        }
+        let total = call(&mut store, "read_all", fd);
+
+        (total, reads.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn sequential_advice_reads_ahead() {
+        let (total, unadvised_reads) = read_file(false);
+        assert_eq!(total, 4096);
+        let (total, advised_reads) = read_file(true);
+        assert_eq!(total, 4096);
+
+        assert!(unadvised_reads > 256);
+        assert!(advised_reads <= 2);
+    }
+}
@@ -17,6 +17,7 @@ pub fn fd_datasync(mut ctx: FunctionEnvMut<'_, WasiEnv>, fd: WasiFd) -> Result<E
 
     #[allow(clippy::await_holding_lock)]
     Ok(wasi_try_ok!(__asyncify(&mut ctx, None, async move {
-        state.fs.flush(fd).await.map(|_| Errno::Success)
+        state.fs.flush(fd).await?;
+        state.fs.sync_data(fd).map(|_| Errno::Success)
     })?))
 }
@@ -26,31 +26,9 @@ pub fn fd_filestat_set_times(
         return Errno::Access;
     }
 
-    if (fst_flags.contains(Fstflags::SET_ATIM) && fst_flags.contains(Fstflags::SET_ATIM_NOW))
-        || (fst_flags.contains(Fstflags::SET_MTIM) && fst_flags.contains(Fstflags::SET_MTIM_NOW))
-    {
-        return Errno::Inval;
-    }
-
+    let (atime, mtime) = wasi_try!(resolve_set_times(env, st_atim, st_mtim, fst_flags));
     let inode = fd_entry.inode;
-
-    if fst_flags.contains(Fstflags::SET_ATIM) || fst_flags.contains(Fstflags::SET_ATIM_NOW) {
-        let time_to_set = if fst_flags.contains(Fstflags::SET_ATIM) {
-            st_atim
-        } else {
-            wasi_try!(get_current_time_in_nanos())
-        };
-        inode.stat.write().unwrap().st_atim = time_to_set;
-    }
-
-    if fst_flags.contains(Fstflags::SET_MTIM) || fst_flags.contains(Fstflags::SET_MTIM_NOW) {
-        let time_to_set = if fst_flags.contains(Fstflags::SET_MTIM) {
-            st_mtim
-        } else {
-            wasi_try!(get_current_time_in_nanos())
-        };
-        inode.stat.write().unwrap().st_mtim = time_to_set;
-    }
+    wasi_try!(set_inode_times(state, &inode, atime, mtime));
 
     Errno::Success
 }
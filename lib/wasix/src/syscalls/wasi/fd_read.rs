@@ -158,6 +158,21 @@ fn fd_read_internal<M: MemorySize>(
                                 None
                             },
                             async move {
+                                // Positional reads leave the cursor alone, so
+                                // they only need to share the handle
+                                if !should_update_cursor && !is_stdio {
+                                    let iovs_arr =
+                                        iovs.slice(&memory, iovs_len).map_err(mem_error_to_wasi)?;
+                                    let iovs_arr = iovs_arr.access().map_err(mem_error_to_wasi)?;
+                                    let file = handle.read().unwrap();
+                                    if let Some(read) =
+                                        read_iovs_at(&**file, &memory, iovs_arr.as_ref(), offset)
+                                            .await?
+                                    {
+                                        return Ok(read);
+                                    }
+                                }
+
                                 let mut handle = handle.write().unwrap();
                                 if !is_stdio {
                                     handle
@@ -371,3 +386,39 @@ fn fd_read_internal<M: MemorySize>(
 
     Ok(Ok(bytes_read))
 }
+
+/// Reads into the buffers with [`VirtualFile::read_at`], starting at
+/// `offset`.
+///
+/// Returns `None` if the file doesn't support positional reads.
+async fn read_iovs_at<M: MemorySize>(
+    file: &(dyn VirtualFile + Send + Sync),
+    memory: &MemoryView<'_>,
+    iovs: &[__wasi_iovec_t<M>],
+    offset: usize,
+) -> Result<Option<usize>, Errno> {
+    let mut total_read = 0usize;
+    for iovs in iovs.iter() {
+        let mut buf = WasmPtr::<u8, M>::new(iovs.buf)
+            .slice(memory, iovs.buf_len)
+            .map_err(mem_error_to_wasi)?
+            .access()
+            .map_err(mem_error_to_wasi)?;
+        let local_read = match file
+            .read_at((offset + total_read) as u64, buf.as_mut())
+            .await
+        {
+            Ok(s) => s,
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported && total_read == 0 => {
+                return Ok(None);
+            }
+            Err(_) if total_read > 0 => break,
+            Err(err) => return Err(map_io_err(err)),
+        };
+        total_read += local_read;
+        if local_read != buf.len() {
+            break;
+        }
+    }
+    Ok(Some(total_read))
+}
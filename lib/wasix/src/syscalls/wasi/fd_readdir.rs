@@ -33,7 +33,6 @@ pub fn fd_readdir<M: MemorySize>(
     let buf_arr = wasi_try_mem!(buf.slice(&memory, buf_len));
     let bufused_ref = bufused.deref(&memory);
     let working_dir = wasi_try!(state.fs.get_fd(fd));
-    let mut cur_cookie = cookie;
     let mut buf_idx = 0usize;
 
     let entries: Vec<(String, Filetype, u64)> = {
@@ -101,15 +100,24 @@ pub fn fd_readdir<M: MemorySize>(
         }
     };
 
-    for (entry_path_str, wasi_file_type, ino) in entries.iter().skip(cookie as usize) {
-        cur_cookie += 1;
+    // The cookies are positions in the listing, which stay put even if the
+    // directory is changed in between calls
+    let mut listing = working_dir.dir_listing.lock().unwrap();
+    if cookie == 0 {
+        listing.restart(entries);
+    } else {
+        listing.refresh(entries);
+    }
+
+    for (next_cookie, entry) in listing.entries_from(cookie) {
+        let entry_path_str = &entry.name;
         let namlen = entry_path_str.len();
         trace!("returning dirent for {}", entry_path_str);
         let dirent = Dirent {
-            d_next: cur_cookie,
-            d_ino: *ino,
+            d_next: next_cookie,
+            d_ino: entry.ino,
             d_namlen: namlen as u32,
-            d_type: *wasi_file_type,
+            d_type: entry.filetype,
         };
         let dirent_bytes = dirent_to_le_bytes(&dirent);
         let buf_len: u64 = buf_len.into();
@@ -138,3 +146,108 @@ pub fn fd_readdir<M: MemorySize>(
     wasi_try_mem!(bufused_ref.write(buf_idx));
     Errno::Success
 }
+
+#[cfg(all(test, feature = "sys"))]
+mod tests {
+    use std::path::Path;
+
+    use virtual_fs::{FileSystem, TmpFileSystem};
+    use wasmer::{Module, Store};
+
+    use crate::WasiEnv;
+
+    /// Parses the names of the complete entries that `fd_readdir()` wrote,
+    /// along with the cookie to resume from.
+    fn parse(mut buf: &[u8]) -> (Vec<String>, u64) {
+        let mut names = Vec::new();
+        let mut cookie = 0;
+        while buf.len() >= 24 {
+            let d_next = u64::from_le_bytes(buf[0..8].try_into().unwrap());
+            let namlen = u32::from_le_bytes(buf[16..20].try_into().unwrap()) as usize;
+            if buf.len() < 24 + namlen {
+                break;
+            }
+            names.push(String::from_utf8(buf[24..24 + namlen].to_vec()).unwrap());
+            cookie = d_next;
+            buf = &buf[24 + namlen..];
+        }
+        (names, cookie)
+    }
+
+    #[test]
+    fn cookies_survive_changes_to_the_directory() {
+        let mut store = Store::default();
+        let module = Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_readdir" (func $fd_readdir (param i32 i32 i32 i64 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                (data (i32.const 200) "data")
+                (func (export "_start"))
+                ;; Opens the directory and returns its file descriptor
+                (func (export "open_dir") (result i32)
+                    (call $path_open (i32.const 3) (i32.const 0) (i32.const 200) (i32.const 4)
+                        (i32.const 2) (i64.const -1) (i64.const -1) (i32.const 0) (i32.const 0))
+                    drop
+                    (i32.load (i32.const 0)))
+                ;; Lists the directory into memory at offset 1024 and returns
+                ;; how many bytes were used
+                (func (export "readdir") (param $fd i32) (param $cookie i64) (param $len i32) (result i32)
+                    (call $fd_readdir (local.get $fd) (i32.const 1024) (local.get $len) (local.get $cookie) (i32.const 8))
+                    drop
+                    (i32.load (i32.const 8))))
+            "#,
+        )
+        .unwrap();
+
+        let fs = TmpFileSystem::new();
+        fs.create_dir(Path::new("/data")).unwrap();
+        let create = |name: &str| {
+            fs.new_open_options()
+                .write(true)
+                .create(true)
+                .open(Path::new("/data").join(name))
+                .unwrap();
+        };
+        create("a.txt");
+        create("c.txt");
+        create("e.txt");
+
+        let (instance, _env) = WasiEnv::builder("readdir")
+            .sandbox_fs(fs.clone())
+            .instantiate(module, &mut store)
+            .unwrap();
+        let open_dir = instance
+            .exports
+            .get_typed_function::<(), i32>(&store, "open_dir")
+            .unwrap();
+        let readdir = instance
+            .exports
+            .get_typed_function::<(i32, i64, i32), i32>(&store, "readdir")
+            .unwrap();
+        let memory = instance.exports.get_memory("memory").unwrap();
+        let read = |store: &mut Store, fd: i32, cookie: u64, len: i32| {
+            let used = readdir.call(store, fd, cookie as i64, len).unwrap() as usize;
+            let mut buf = vec![0u8; used];
+            memory.view(&*store).read(1024, &mut buf).unwrap();
+            parse(&buf)
+        };
+
+        let fd = open_dir.call(&mut store).unwrap();
+        // Just enough room for ".", ".." and "a.txt"
+        let (mut names, cookie) = read(&mut store, fd, 0, 3 * 24 + 1 + 2 + 5);
+        assert_eq!(names, [".", "..", "a.txt"]);
+
+        // Add a file that sorts before the ones that were listed already,
+        // and remove one that wasn't listed yet
+        create("0.txt");
+        fs.remove_file(Path::new("/data/c.txt")).unwrap();
+
+        let (rest, _) = read(&mut store, fd, cookie, 4096);
+        names.extend(rest);
+        assert_eq!(names, [".", "..", "a.txt", "e.txt", "0.txt"]);
+    }
+}
@@ -24,6 +24,7 @@ pub fn fd_renumber(ctx: FunctionEnvMut<'_, WasiEnv>, from: WasiFd, to: WasiFd) -
         offset: fd_entry.offset.clone(),
         rights: fd_entry.rights_inheriting,
         inode: fd_entry.inode.clone(),
+        dir_listing: fd_entry.dir_listing.clone(),
         ..*fd_entry
     };
     fd_map.insert(to, new_fd_entry);
@@ -2,7 +2,8 @@ use super::*;
 use crate::syscalls::*;
 
 /// ### `fd_sync()`
-/// Synchronize file and metadata to disk (TODO: expand upon what this means in our system)
+/// Synchronize file and metadata to disk
+/// (this flushes the file and then syncs it, see [`VirtualFile::sync`])
 /// Inputs:
 /// - `Fd fd`
 ///     The file descriptor to sync
@@ -37,6 +38,7 @@ pub fn fd_sync(mut ctx: FunctionEnvMut<'_, WasiEnv>, fd: WasiFd) -> Result<Errno
                             #[allow(clippy::await_holding_lock)]
                             let mut handle = handle.write().unwrap();
                             handle.flush().await.map_err(map_io_err)?;
+                            handle.sync().map_err(fs_error_into_wasi_err)?;
                             Ok(handle.size())
                         })?)
                     };
@@ -120,6 +120,18 @@ fn fd_write_internal<M: MemorySize>(
                                 None
                             },
                             async {
+                                // Positional writes leave the cursor alone, so
+                                // they only need to share the handle
+                                if !should_update_cursor && !is_stdio {
+                                    let file = handle.read().unwrap();
+                                    if let Some(written) =
+                                        write_iovs_at(&**file, &memory, iovs_arr.as_ref(), offset)
+                                            .await?
+                                    {
+                                        return Ok(written);
+                                    }
+                                }
+
                                 let mut handle = handle.write().unwrap();
                                 if !is_stdio {
                                     handle
@@ -267,8 +279,15 @@ fn fd_write_internal<M: MemorySize>(
             // pipes and sockets will not do anything with this
             let (mut memory, _, inodes) =
                 unsafe { env.get_memory_and_wasi_state_and_inodes(&ctx, 0) };
+            let mut stat = fd_entry.inode.stat.write().unwrap();
             // Cast is valid because we don't support 128 bit systems...
-            fd_entry.inode.stat.write().unwrap().st_size += bytes_written as u64;
+            if fd_entry.flags.contains(Fdflags::APPEND) {
+                stat.st_size += bytes_written as u64;
+            } else {
+                // Other writes (positional ones in particular) may overwrite
+                // existing data rather than extend the file
+                stat.st_size = stat.st_size.max((offset + bytes_written) as u64);
+            }
         }
         bytes_written
     };
@@ -282,3 +301,35 @@ fn fd_write_internal<M: MemorySize>(
 
     Ok(Errno::Success)
 }
+
+/// Writes the buffers with [`VirtualFile::write_at`], starting at `offset`.
+///
+/// Returns `None` if the file doesn't support positional writes.
+async fn write_iovs_at<M: MemorySize>(
+    file: &(dyn VirtualFile + Send + Sync),
+    memory: &MemoryView<'_>,
+    iovs: &[__wasi_ciovec_t<M>],
+    offset: usize,
+) -> Result<Option<usize>, Errno> {
+    let mut written = 0usize;
+    for iovs in iovs.iter() {
+        let buf = WasmPtr::<u8, M>::new(iovs.buf)
+            .slice(memory, iovs.buf_len)
+            .map_err(mem_error_to_wasi)?
+            .access()
+            .map_err(mem_error_to_wasi)?;
+        let local_written = match file.write_at((offset + written) as u64, buf.as_ref()).await {
+            Ok(s) => s,
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported && written == 0 => {
+                return Ok(None);
+            }
+            Err(_) if written > 0 => break,
+            Err(err) => return Err(map_io_err(err)),
+        };
+        written += local_written;
+        if local_written != buf.len() {
+            break;
+        }
+    }
+    Ok(Some(written))
+}
@@ -60,7 +60,9 @@ pub fn path_create_directory<M: MemorySize>(
     }
 
     let mut cur_dir_inode = working_dir.inode;
-    for comp in &path_vec {
+    for (i, comp) in path_vec.iter().enumerate() {
+        // Only the last component is the directory that gets created
+        let is_last = i + 1 == path_vec.len();
         let processing_cur_dir_inode = cur_dir_inode.clone();
         let mut guard = processing_cur_dir_inode.write();
         match guard.deref_mut() {
@@ -80,6 +82,9 @@ pub fn path_create_directory<M: MemorySize>(
                     _ => (),
                 }
                 if let Some(child) = entries.get(comp) {
+                    if is_last && !matches!(child.read().deref(), Kind::Dir { .. }) {
+                        return Errno::Exist;
+                    }
                     cur_dir_inode = child.clone();
                 } else {
                     let mut adjusted_path = path.clone();
@@ -96,7 +101,7 @@ pub fn path_create_directory<M: MemorySize>(
                         &adjusted_path.to_string_lossy(),
                     ) {
                         if adjusted_path_stat.st_filetype != Filetype::Directory {
-                            return Errno::Notdir;
+                            return if is_last { Errno::Exist } else { Errno::Notdir };
                         }
                     } else {
                         wasi_try!(state.fs_create_dir(&adjusted_path));
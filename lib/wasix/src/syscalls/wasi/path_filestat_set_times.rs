@@ -32,15 +32,10 @@ pub fn path_filestat_set_times<M: MemorySize>(
     let env = ctx.data();
     let (memory, mut state, inodes) = unsafe { env.get_memory_and_wasi_state_and_inodes(&ctx, 0) };
     let fd_entry = wasi_try!(state.fs.get_fd(fd));
-    let fd_inode = fd_entry.inode;
     if !fd_entry.rights.contains(Rights::PATH_FILESTAT_SET_TIMES) {
         return Errno::Access;
     }
-    if (fst_flags.contains(Fstflags::SET_ATIM) && fst_flags.contains(Fstflags::SET_ATIM_NOW))
-        || (fst_flags.contains(Fstflags::SET_MTIM) && fst_flags.contains(Fstflags::SET_MTIM_NOW))
-    {
-        return Errno::Inval;
-    }
+    let (atime, mtime) = wasi_try!(resolve_set_times(env, st_atim, st_mtim, fst_flags));
 
     let mut path_string = unsafe { get_input_str!(&memory, path, path_len) };
     Span::current().record("path", path_string.as_str());
@@ -59,27 +54,7 @@ pub fn path_filestat_set_times<M: MemorySize>(
         &path_string,
         flags & __WASI_LOOKUP_SYMLINK_FOLLOW != 0,
     ));
-    let stat = {
-        let guard = file_inode.read();
-        wasi_try!(state.fs.get_stat_for_kind(guard.deref()))
-    };
-
-    if fst_flags.contains(Fstflags::SET_ATIM) || fst_flags.contains(Fstflags::SET_ATIM_NOW) {
-        let time_to_set = if fst_flags.contains(Fstflags::SET_ATIM) {
-            st_atim
-        } else {
-            wasi_try!(get_current_time_in_nanos())
-        };
-        fd_inode.stat.write().unwrap().st_atim = time_to_set;
-    }
-    if fst_flags.contains(Fstflags::SET_MTIM) || fst_flags.contains(Fstflags::SET_MTIM_NOW) {
-        let time_to_set = if fst_flags.contains(Fstflags::SET_MTIM) {
-            st_mtim
-        } else {
-            wasi_try!(get_current_time_in_nanos())
-        };
-        fd_inode.stat.write().unwrap().st_mtim = time_to_set;
-    }
+    wasi_try!(set_inode_times(state, &file_inode, atime, mtime));
 
     Errno::Success
 }
@@ -46,6 +46,9 @@ pub fn path_link<M: MemorySize>(
     {
         return Errno::Access;
     }
+    if !env.capabilities.filesystem.allow_hard_links {
+        return Errno::Perm;
+    }
 
     // Convert relative paths into absolute paths
     old_path_str = ctx.data().state.fs.relative_path_to_absolute(old_path_str);
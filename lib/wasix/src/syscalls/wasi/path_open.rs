@@ -57,6 +57,7 @@ pub fn path_open<M: MemorySize>(
     // - __WASI_O_DIRECTORY (fail if not dir)
     // - __WASI_O_EXCL (fail if file exists)
     // - __WASI_O_TRUNC (truncate size to 0)
+    // - __WASI_O_TMPFILE (create an unnamed file in the directory)
 
     let working_dir = wasi_try!(state.fs.get_fd(dirfd));
     let working_dir_rights_inheriting = working_dir.rights_inheriting;
@@ -99,16 +100,18 @@ pub fn path_open<M: MemorySize>(
         Ok(_) => {
             let write_permission = adjusted_rights.contains(Rights::FD_WRITE);
 
-            // append, truncate, and create all require the permission to write
-            let (append_permission, truncate_permission, create_permission) = if write_permission {
-                (
-                    fs_flags.contains(Fdflags::APPEND),
-                    o_flags.contains(Oflags::TRUNC),
-                    o_flags.contains(Oflags::CREATE),
-                )
-            } else {
-                (false, false, false)
-            };
+            // append, truncate, create and tmpfile all require the permission to write
+            let (append_permission, truncate_permission, create_permission, tmpfile_permission) =
+                if write_permission {
+                    (
+                        fs_flags.contains(Fdflags::APPEND),
+                        o_flags.contains(Oflags::TRUNC),
+                        o_flags.contains(Oflags::CREATE),
+                        o_flags.contains(Oflags::TMPFILE),
+                    )
+                } else {
+                    (false, false, false, false)
+                };
 
             virtual_fs::OpenOptionsConfig {
                 read: fs_rights_base.contains(Rights::FD_READ),
@@ -117,6 +120,7 @@ pub fn path_open<M: MemorySize>(
                 create: create_permission,
                 append: append_permission,
                 truncate: truncate_permission,
+                tmpfile: tmpfile_permission,
             }
         }
         Err(_) => virtual_fs::OpenOptionsConfig {
@@ -126,6 +130,7 @@ pub fn path_open<M: MemorySize>(
             create_new: o_flags.contains(Oflags::CREATE) && o_flags.contains(Oflags::EXCL),
             create: o_flags.contains(Oflags::CREATE),
             truncate: o_flags.contains(Oflags::TRUNC),
+            tmpfile: o_flags.contains(Oflags::TMPFILE),
         },
     };
 
@@ -138,13 +143,44 @@ pub fn path_open<M: MemorySize>(
         create: true,
         append: true,
         truncate: true,
+        tmpfile: true,
     };
 
     let minimum_rights = target_rights.minimum_rights(&parent_rights);
 
     open_options.options(minimum_rights.clone());
 
-    let inode = if let Ok(inode) = maybe_inode {
+    let inode = if o_flags.contains(Oflags::TMPFILE) {
+        // The path is the directory the unnamed file is created in
+        let dir_path = match wasi_try!(maybe_inode).read().deref() {
+            Kind::Dir { path, .. } => path.clone(),
+            Kind::Root { .. } => return Errno::Notcapable,
+            _ => return Errno::Notdir,
+        };
+        if !minimum_rights.tmpfile {
+            return Errno::Inval;
+        }
+
+        let handle = wasi_try!(open_options
+            .read(minimum_rights.read)
+            .write(minimum_rights.write)
+            .tmpfile(true)
+            .open(&dir_path)
+            .map_err(fs_error_into_wasi_err));
+        if minimum_rights.read {
+            open_flags |= Fd::READ;
+        }
+        open_flags |= Fd::WRITE;
+
+        // The inode isn't added to the entries of the directory, so it
+        // goes away with the last file descriptor
+        let kind = Kind::File {
+            handle: Some(Arc::new(std::sync::RwLock::new(handle))),
+            path: dir_path,
+            fd: None,
+        };
+        wasi_try!(state.fs.create_inode(inodes, kind, false, String::new()))
+    } else if let Ok(inode) = maybe_inode {
         // Happy path, we found the file we're trying to open
         let processing_inode = inode.clone();
         let mut guard = processing_inode.write();
@@ -214,10 +250,15 @@ pub fn path_open<M: MemorySize>(
                     return Errno::Notcapable;
                 }
             }
-            Kind::Dir { .. }
-            | Kind::Socket { .. }
-            | Kind::Pipe { .. }
-            | Kind::EventNotifications { .. } => {}
+            Kind::Dir { .. } => {
+                if o_flags.contains(Oflags::EXCL) {
+                    return Errno::Exist;
+                }
+                if o_flags.contains(Oflags::CREATE) {
+                    return Errno::Isdir;
+                }
+            }
+            Kind::Socket { .. } | Kind::Pipe { .. } | Kind::EventNotifications { .. } => {}
             Kind::Symlink {
                 base_po_dir,
                 path_to_symlink,
@@ -35,6 +35,9 @@ pub fn path_symlink<M: MemorySize>(
     if !base_fd.rights.contains(Rights::PATH_SYMLINK) {
         return Errno::Access;
     }
+    if !env.capabilities.filesystem.allow_symlinks {
+        return Errno::Perm;
+    }
 
     // get the depth of the parent + 1 (UNDER INVESTIGATION HMMMMMMMM THINK FISH ^ THINK FISH)
     let old_path_path = std::path::Path::new(&old_path_str);
@@ -1,9 +1,11 @@
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
-use wasmer_wasix_types::wasi::{SubscriptionClock, Userdata};
+use wasmer_wasix_types::wasi::{Subclockflags, SubscriptionClock, Userdata};
 
 use super::*;
 use crate::{
     fs::{InodeValFilePollGuard, InodeValFilePollGuardJoin},
+    runtime::Clock,
     state::PollEventSet,
     syscalls::*,
     WasiInodes,
@@ -158,6 +160,108 @@ impl Future for PollBatch {
     }
 }
 
+/// A clock subscription along with the point in time (on its own clock)
+/// at which it fires.
+#[derive(Debug, Clone, Copy)]
+struct ClockDeadline {
+    clock_info: SubscriptionClock,
+    userdata: Userdata,
+    deadline: Timestamp,
+}
+
+impl ClockDeadline {
+    fn event(&self) -> EventResult {
+        EventResult {
+            userdata: self.userdata,
+            error: Errno::Success,
+            type_: Eventtype::Clock,
+            inner: EventResultType::Clock(0),
+        }
+    }
+}
+
+/// Reads the current time of a clock, including any offset that the guest
+/// applied through `clock_time_set`.
+fn clock_now(
+    clock: &dyn Clock,
+    offsets: &HashMap<Snapshot0Clockid, i64>,
+    clock_id: Clockid,
+) -> Result<u64, Errno> {
+    let clock_id: Snapshot0Clockid = clock_id.into();
+    let mut now = runtime_clock_time_get(clock, clock_id, 1)?;
+    if let Some(offset) = offsets.get(&clock_id) {
+        now += *offset;
+    }
+    Ok(now.max(0) as u64)
+}
+
+/// Returns the events of all the clocks whose deadline has passed.
+fn expired_clocks(
+    clock: &dyn Clock,
+    offsets: &HashMap<Snapshot0Clockid, i64>,
+    deadlines: &[ClockDeadline],
+) -> Result<Vec<EventResult>, Errno> {
+    let mut evts = Vec::new();
+    for deadline in deadlines {
+        if clock_now(clock, offsets, deadline.clock_info.clock_id)? >= deadline.deadline {
+            evts.push(deadline.event());
+        }
+    }
+    Ok(evts)
+}
+
+/// Waits for either the file descriptors or the timeout to trigger and
+/// returns every event that is ready at that point.
+///
+/// A timer and a file descriptor can become ready at the same time, in
+/// which case both of them are reported rather than whichever one happened
+/// to be polled first.
+async fn poll_fds_or_timeout<B, T>(
+    mut batch: B,
+    timeout: T,
+    clock: Arc<dyn Clock + Send + Sync>,
+    offsets: HashMap<Snapshot0Clockid, i64>,
+    clocks: Vec<ClockDeadline>,
+) -> Result<Vec<EventResult>, Errno>
+where
+    B: Future<Output = Result<Vec<EventResult>, Errno>> + Unpin,
+    T: Future<Output = ()>,
+{
+    let fds_triggered = tokio::select! {
+        biased;
+        res = &mut batch => Some(res?),
+        _ = timeout => None,
+    };
+
+    let mut evts = match fds_triggered {
+        Some(evts) => evts,
+        None => {
+            // Pick up any file descriptors that became ready at the same
+            // time as the timer
+            let mut evts = batch.now_or_never().transpose()?.unwrap_or_default();
+            let expired = expired_clocks(clock.as_ref(), &offsets, &clocks)?;
+            if expired.is_empty() {
+                // The sleep and the guest clock can drift apart slightly, the
+                // sleep is authoritative so report the earliest clock
+                if let Some(clock) = clocks.iter().min_by_key(|c| c.deadline) {
+                    evts.push(clock.event());
+                }
+            } else {
+                evts.extend(expired);
+            }
+            if evts.is_empty() {
+                return Err(Errno::Timedout);
+            }
+            return Ok(evts);
+        }
+    };
+
+    // The file descriptors triggered first, but a clock may have expired
+    // in the meantime
+    evts.extend(expired_clocks(clock.as_ref(), &offsets, &clocks)?);
+    Ok(evts)
+}
+
 /// ### `poll_oneoff()`
 /// Concurrently poll for a set of events
 /// Inputs:
@@ -194,8 +298,10 @@ where
         .iter()
         .filter(|a| a.2.type_ == Eventtype::Clock)
         .count();
-    let mut clock_subs: Vec<(SubscriptionClock, u64)> = Vec::with_capacity(subs.len());
+    let mut clock_subs: Vec<ClockDeadline> = Vec::with_capacity(subs.len());
     let mut time_to_sleep = Duration::MAX;
+    let clock_offsets = state.clock_offset.lock().unwrap().clone();
+    let clock = env.runtime().clock();
 
     // First we extract all the subscriptions into an array so that they
     // can be processed
@@ -246,23 +352,42 @@ where
                     || clock_info.clock_id == Clockid::Monotonic
                 {
                     // Ignore duplicates
-                    if clock_subs
-                        .iter()
-                        .any(|c| c.0.clock_id == clock_info.clock_id && c.1 == s.userdata)
-                    {
+                    if clock_subs.iter().any(|c| {
+                        c.clock_info.clock_id == clock_info.clock_id && c.userdata == s.userdata
+                    }) {
                         continue;
                     }
 
-                    // If the timeout duration is zero then this is an immediate check rather than
-                    // a sleep itself
-                    if clock_info.timeout == 0 {
-                        time_to_sleep = Duration::MAX;
+                    let now = wasi_try_ok!(clock_now(
+                        clock.as_ref(),
+                        &clock_offsets,
+                        clock_info.clock_id
+                    ));
+                    let deadline = if clock_info
+                        .flags
+                        .contains(Subclockflags::SUBSCRIPTION_CLOCK_ABSTIME)
+                    {
+                        clock_info.timeout
+                    } else if clock_info.timeout == 0 {
+                        // If the timeout duration is zero then this subscription
+                        // does not put a limit on the sleep
+                        continue;
                     } else if clock_info.timeout == 1 {
+                        // A timeout of one is an immediate check rather than a sleep itself
                         time_to_sleep = Duration::ZERO;
+                        continue;
                     } else {
-                        time_to_sleep = Duration::from_nanos(clock_info.timeout);
-                        clock_subs.push((clock_info, s.userdata));
-                    }
+                        now.saturating_add(clock_info.timeout)
+                    };
+
+                    // The earliest clock decides how long we sleep for
+                    time_to_sleep =
+                        time_to_sleep.min(Duration::from_nanos(deadline.saturating_sub(now)));
+                    clock_subs.push(ClockDeadline {
+                        clock_info,
+                        userdata: s.userdata,
+                        deadline,
+                    });
                     continue;
                 } else {
                     error!("polling not implemented for these clocks yet");
@@ -349,22 +474,17 @@ where
             Some(time)
         }
     };
-    let tasks = env.tasks().clone();
+    let sleep = timeout.map(|timeout| clock.sleep(env.tasks().as_ref(), timeout));
     let timeout = async move {
-        if let Some(timeout) = timeout {
-            tasks.sleep_now(timeout).await;
+        if let Some(sleep) = sleep {
+            sleep.await;
         } else {
             InfiniteSleep::default().await
         }
     };
 
     // Build the trigger using the timeout
-    let trigger = async move {
-        tokio::select! {
-            res = batch => res,
-            _ = timeout => Err(Errno::Timedout)
-        }
-    };
+    let trigger = poll_fds_or_timeout(batch, timeout, clock, clock_offsets, clock_subs.clone());
 
     // We replace the process events callback with another callback
     // which will interpret the error codes
@@ -386,18 +506,13 @@ where
                         tracing::warn!("triggered_timeout (without any clock subscriptions)",);
                     }
                     let mut evts = Vec::new();
-                    for (clock_info, userdata) in clock_subs {
-                        let evt = Event {
-                            userdata,
-                            error: Errno::Success,
-                            type_: Eventtype::Clock,
-                            u: EventUnion { clock: 0 },
-                        };
+                    for clock in clock_subs {
+                        let evt = clock.event().into_event();
                         Span::current().record(
                             "seen",
                             &format!(
                                 "clock(id={},userdata={})",
-                                clock_info.clock_id as u32, evt.userdata
+                                clock.clock_info.clock_id as u32, evt.userdata
                             ),
                         );
                         evts.push(evt);
@@ -434,3 +549,305 @@ where
     }
     Ok(Errno::Success)
 }
+
+#[cfg(all(test, feature = "sys"))]
+mod tests {
+    use std::{task::Waker, time::Duration};
+
+    use virtual_fs::{AsyncWriteExt, Pipe, VirtualFile};
+
+    use super::*;
+    use crate::runtime::{MockClock, SystemClock};
+
+    /// Resolves once the pipe has data to read, like a `FdRead` subscription
+    struct PipeReadable {
+        pipe: Pipe,
+        userdata: Userdata,
+    }
+
+    impl Future for PipeReadable {
+        type Output = Result<Vec<EventResult>, Errno>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let userdata = self.userdata;
+            match Pin::new(&mut self.pipe).poll_read_ready(cx) {
+                Poll::Ready(Ok(nbytes)) => Poll::Ready(Ok(vec![EventResult {
+                    userdata,
+                    error: Errno::Success,
+                    type_: Eventtype::FdRead,
+                    inner: EventResultType::Fd(EventFdReadwrite {
+                        nbytes: nbytes as u64,
+                        flags: Eventrwflags::empty(),
+                    }),
+                }])),
+                Poll::Ready(Err(_)) => Poll::Ready(Err(Errno::Io)),
+                Poll::Pending => Poll::Pending,
+            }
+        }
+    }
+
+    /// A file that only becomes readable once it's told so, like a cursor
+    /// waiting for the results of a query. Writing relies on the default
+    /// readiness of [`VirtualFile`].
+    #[derive(Debug, Clone, Default)]
+    struct EventuallyReadable {
+        state: Arc<Mutex<(bool, Option<Waker>)>>,
+    }
+
+    impl EventuallyReadable {
+        fn make_readable(&self) {
+            let mut state = self.state.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    impl VirtualFile for EventuallyReadable {
+        fn last_accessed(&self) -> u64 {
+            0
+        }
+        fn last_modified(&self) -> u64 {
+            0
+        }
+        fn created_time(&self) -> u64 {
+            0
+        }
+        fn size(&self) -> u64 {
+            0
+        }
+        fn set_len(&mut self, _new_size: u64) -> virtual_fs::Result<()> {
+            Err(virtual_fs::FsError::PermissionDenied)
+        }
+        fn unlink(&mut self) -> virtual_fs::Result<()> {
+            Ok(())
+        }
+        fn poll_read_ready(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<usize>> {
+            let mut state = self.state.lock().unwrap();
+            if state.0 {
+                Poll::Ready(Ok(5))
+            } else {
+                state.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    impl tokio::io::AsyncRead for EventuallyReadable {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl tokio::io::AsyncWrite for EventuallyReadable {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl tokio::io::AsyncSeek for EventuallyReadable {
+        fn start_seek(self: Pin<&mut Self>, _position: std::io::SeekFrom) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn poll_complete(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<u64>> {
+            Poll::Ready(Ok(0))
+        }
+    }
+
+    #[test]
+    fn guest_waits_for_a_custom_file_to_become_readable() {
+        let mut store = wasmer::Store::default();
+        let module = wasmer::Module::new(
+            &store,
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                ;; Waits for stdin to be readable, the event is written at offset 64
+                (func (export "_start")
+                    (i64.store (i32.const 0) (i64.const 42))
+                    (i32.store8 (i32.const 8) (i32.const 1))
+                    (i32.store (i32.const 16) (i32.const 0))
+                    (call $poll_oneoff (i32.const 0) (i32.const 64) (i32.const 1) (i32.const 128))
+                    drop))
+            "#,
+        )
+        .unwrap();
+
+        let file = EventuallyReadable::default();
+        let (instance, _env) = crate::WasiEnv::builder("poll")
+            .stdin(Box::new(file.clone()))
+            .instantiate(module, &mut store)
+            .unwrap();
+
+        let delay = Duration::from_millis(200);
+        std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            file.make_readable();
+        });
+
+        let started = std::time::Instant::now();
+        let start = instance.exports.get_function("_start").unwrap();
+        start.call(&mut store, &[]).unwrap();
+        assert!(started.elapsed() >= delay);
+
+        let memory = instance.exports.get_memory("memory").unwrap();
+        let view = memory.view(&store);
+        let mut nevents = [0u8; 4];
+        view.read(128, &mut nevents).unwrap();
+        let mut event = [0u8; 32];
+        view.read(64, &mut event).unwrap();
+        assert_eq!(u32::from_le_bytes(nevents), 1);
+        // userdata, error and type
+        assert_eq!(u64::from_le_bytes(event[..8].try_into().unwrap()), 42);
+        assert_eq!(u16::from_le_bytes(event[8..10].try_into().unwrap()), 0);
+        assert_eq!(event[10], Eventtype::FdRead as u8);
+        // how many bytes are available
+        assert_eq!(u64::from_le_bytes(event[16..24].try_into().unwrap()), 5);
+    }
+
+    fn clock_in(offsets: &HashMap<Snapshot0Clockid, i64>, timeout: Duration) -> ClockDeadline {
+        let now = clock_now(&SystemClock, offsets, Clockid::Monotonic).unwrap();
+        ClockDeadline {
+            clock_info: SubscriptionClock {
+                clock_id: Clockid::Monotonic,
+                timeout: timeout.as_nanos() as u64,
+                precision: 0,
+                flags: Subclockflags::empty(),
+            },
+            userdata: 2,
+            deadline: now + timeout.as_nanos() as u64,
+        }
+    }
+
+    fn userdata(evts: &[EventResult]) -> Vec<(Userdata, Eventtype)> {
+        let mut evts: Vec<_> = evts.iter().map(|e| (e.userdata, e.type_)).collect();
+        evts.sort_by_key(|e| e.0);
+        evts
+    }
+
+    #[tokio::test]
+    async fn pipe_readable_just_before_timeout() {
+        let offsets = HashMap::new();
+        let (mut tx, rx) = Pipe::channel();
+        let clocks = vec![clock_in(&offsets, Duration::from_millis(500))];
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            tx.write_all(b"ping").await.unwrap();
+            // keep the pipe open until the poll is done
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        });
+
+        let batch = PipeReadable {
+            pipe: rx,
+            userdata: 1,
+        };
+        let timeout = tokio::time::sleep(Duration::from_millis(500));
+        let evts = poll_fds_or_timeout(batch, timeout, Arc::new(SystemClock), offsets, clocks)
+            .await
+            .unwrap();
+
+        assert_eq!(userdata(&evts), vec![(1, Eventtype::FdRead)]);
+    }
+
+    #[tokio::test]
+    async fn fd_and_timer_ready_together_are_both_reported() {
+        let offsets = HashMap::new();
+        let (mut tx, rx) = Pipe::channel();
+        let clocks = vec![clock_in(&offsets, Duration::ZERO)];
+
+        // The pipe only becomes readable while the timer is firing
+        let timeout = async move {
+            tx.write_all(b"ping").await.unwrap();
+        };
+        let batch = PipeReadable {
+            pipe: rx,
+            userdata: 1,
+        };
+        let evts = poll_fds_or_timeout(batch, timeout, Arc::new(SystemClock), offsets, clocks)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            userdata(&evts),
+            vec![(1, Eventtype::FdRead), (2, Eventtype::Clock)]
+        );
+    }
+
+    #[tokio::test]
+    async fn timer_uses_the_guest_clock_offset() {
+        let (mut tx, rx) = Pipe::channel();
+        tx.write_all(b"ping").await.unwrap();
+
+        // Without an offset the clock is still half an hour away
+        let mut clock = clock_in(&HashMap::new(), Duration::from_secs(30 * 60));
+        clock.clock_info.flags = Subclockflags::SUBSCRIPTION_CLOCK_ABSTIME;
+        clock.clock_info.timeout = clock.deadline;
+
+        // ...but the guest moved its monotonic clock an hour ahead
+        let mut offsets = HashMap::new();
+        offsets.insert(Snapshot0Clockid::Monotonic, 3_600_000_000_000i64);
+
+        let batch = PipeReadable {
+            pipe: rx,
+            userdata: 1,
+        };
+        let timeout = tokio::time::sleep(Duration::from_secs(30 * 60));
+        let evts = poll_fds_or_timeout(batch, timeout, Arc::new(SystemClock), offsets, vec![clock])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            userdata(&evts),
+            vec![(1, Eventtype::FdRead), (2, Eventtype::Clock)]
+        );
+    }
+
+    #[test]
+    fn timers_expire_when_the_runtime_clock_says_so() {
+        let clock = MockClock::default();
+        let offsets = HashMap::new();
+        let deadline = ClockDeadline {
+            clock_info: SubscriptionClock {
+                clock_id: Clockid::Monotonic,
+                timeout: 1_000_000_000,
+                precision: 0,
+                flags: Subclockflags::empty(),
+            },
+            userdata: 2,
+            deadline: clock_now(&clock, &offsets, Clockid::Monotonic).unwrap() + 1_000_000_000,
+        };
+
+        clock.advance(Duration::from_millis(999));
+        assert!(expired_clocks(&clock, &offsets, &[deadline])
+            .unwrap()
+            .is_empty());
+
+        clock.advance(Duration::from_millis(1));
+        let evts = expired_clocks(&clock, &offsets, &[deadline]).unwrap();
+        assert_eq!(userdata(&evts), vec![(2, Eventtype::Clock)]);
+    }
+}
@@ -35,7 +35,8 @@ pub fn proc_raise_interval(
         a => Some(Duration::from_millis(a)),
     };
     let repeat = matches!(repeat, Bool::True);
-    env.process.signal_interval(sig, interval, repeat);
+    let clock = env.runtime().clock();
+    env.process.signal_interval(sig, interval, repeat, clock.as_ref());
 
     wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
 
@@ -117,7 +117,8 @@ pub fn futex_wait<M: MemorySize + 'static>(
         let poller_idx = guard.poller_seed;
 
         // Create the timeout if one exists
-        let timeout = timeout.map(|timeout| env.tasks().sleep_now(timeout));
+        let clock = env.runtime().clock();
+        let timeout = timeout.map(|timeout| clock.sleep(env.tasks().as_ref(), timeout));
 
         // We insert the futex before we check the condition variable to avoid
         // certain race conditions
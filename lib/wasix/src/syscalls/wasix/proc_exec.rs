@@ -89,7 +89,7 @@ pub fn proc_exec<M: MemorySize>(
         std::mem::swap(vfork.env.as_mut(), ctx.data_mut());
         let mut wasi_env = *vfork.env;
         wasi_env.owned_handles.push(vfork.handle);
-        _prepare_wasi(&mut wasi_env, Some(args));
+        _prepare_wasi(&mut wasi_env, &name, Some(args));
 
         // Recrod the stack offsets before we give up ownership of the wasi_env
         let stack_lower = wasi_env.layout.stack_lower;
@@ -185,7 +185,7 @@ pub fn proc_exec<M: MemorySize>(
     else {
         // Prepare the environment
         let mut wasi_env = ctx.data().clone();
-        _prepare_wasi(&mut wasi_env, Some(args));
+        _prepare_wasi(&mut wasi_env, &name, Some(args));
 
         // Get a reference to the runtime
         let bin_factory = ctx.data().bin_factory.clone();
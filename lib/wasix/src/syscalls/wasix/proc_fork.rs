@@ -255,6 +255,7 @@ fn run<M: MemorySize>(
     let err = if ctx.data(&store).thread.is_main() {
         trace!(%pid, %tid, "re-invoking main");
         let start = unsafe { ctx.data(&store).inner() }.start.clone().unwrap();
+        let _timer = ctx.data(&store).process.usage.cpu_timer();
         start.call(&mut store)
     } else {
         trace!(%pid, %tid, "re-invoking thread_spawn");
@@ -262,6 +263,7 @@ fn run<M: MemorySize>(
             .thread_spawn
             .clone()
             .unwrap();
+        let _timer = ctx.data(&store).process.usage.cpu_timer();
         start.call(&mut store, 0, 0)
     };
     if let Err(err) = err {
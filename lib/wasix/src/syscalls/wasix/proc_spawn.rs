@@ -117,10 +117,14 @@ pub fn proc_spawn_internal(
     let child_process = child_env.process.clone();
     if let Some(args) = args {
         let mut child_state = env.state.fork();
+        child_state.program_name = name.clone();
         child_state.args = args;
         child_env.state = Arc::new(child_state);
     }
 
+    // The child only inherits the file descriptors that are not close-on-exec
+    child_env.state.fs.close_cloexec_fds();
+
     // Take ownership of this child
     ctx.data_mut().owned_handles.push(handle);
     let env = ctx.data();
@@ -26,6 +26,12 @@ pub(crate) fn thread_sleep_internal<M: MemorySize + 'static>(
 
     let env = ctx.data();
 
+    if duration == 0 {
+        if let Some(scheduler) = env.control_plane.scheduler() {
+            scheduler.yield_now(env.tid());
+        }
+    }
+
     #[cfg(feature = "sys-thread")]
     if duration == 0 {
         std::thread::yield_now();
@@ -33,9 +39,9 @@ pub(crate) fn thread_sleep_internal<M: MemorySize + 'static>(
 
     if duration > 0 {
         let duration = Duration::from_nanos(duration);
-        let tasks = env.tasks().clone();
+        let sleep = env.runtime().clock().sleep(env.tasks().as_ref(), duration);
         __asyncify_with_deep_sleep::<M, _, _>(ctx, Duration::from_millis(50), async move {
-            tasks.sleep_now(duration).await;
+            sleep.await;
         })?;
     }
     Ok(Errno::Success)
@@ -72,6 +72,9 @@ pub(crate) fn thread_spawn_internal<M: MemorySize>(
     };
     tracing::trace!("spawn with layout {:?}", layout);
 
+    let scheduler = env.control_plane.scheduler();
+    let parent_id = env.tid();
+
     // Create the handle that represents this thread
     let mut thread_handle = match env.process.new_thread() {
         Ok(h) => Arc::new(h),
@@ -128,13 +131,23 @@ pub(crate) fn thread_spawn_internal<M: MemorySize>(
     let run = move |props: TaskWasmRunProperties| {
         execute_module(props.ctx, props.store);
     };
+    // Deterministically scheduled threads take turns, so the new thread
+    // needs to be queued up before it gets a chance to start
+    if let Some(scheduler) = &scheduler {
+        scheduler.spawned(parent_id, thread_id.into());
+    }
     tasks
         .task_wasm(
             TaskWasm::new(Box::new(run), thread_env, thread_module, false)
                 .with_snapshot(&snapshot)
                 .with_memory(spawn_type),
         )
-        .map_err(Into::<Errno>::into)?;
+        .map_err(|err| {
+            if let Some(scheduler) = &scheduler {
+                scheduler.release(thread_id.into());
+            }
+            Into::<Errno>::into(err)
+        })?;
 
     // Success
     Ok(thread_id)
@@ -160,16 +173,20 @@ fn call_module<M: MemorySize>(
             .clone()
             .unwrap();
         let tid = env.data(&store).tid();
-        let call_ret = spawn.call(
-            store,
-            tid.raw().try_into().map_err(|_| Errno::Overflow).unwrap(),
-            start_ptr_offset
-                .try_into()
-                .map_err(|_| Errno::Overflow)
-                .unwrap(),
-        );
+        let call_ret = {
+            let _timer = env.data(&store).process.usage.cpu_timer();
+            spawn.call(
+                store,
+                tid.raw().try_into().map_err(|_| Errno::Overflow).unwrap(),
+                start_ptr_offset
+                    .try_into()
+                    .map_err(|_| Errno::Overflow)
+                    .unwrap(),
+            )
+        };
         let mut ret = Errno::Success;
         if let Err(err) = call_ret {
+            let err = crate::state::out_of_fuel_trap(store, env, err);
             match err.downcast::<WasiError>() {
                 Ok(WasiError::Exit(code)) => {
                     ret = if code.is_success() {
@@ -186,6 +203,10 @@ fn call_module<M: MemorySize>(
                     debug!("failed as wasi version is unknown",);
                     ret = Errno::Noexec;
                 }
+                Ok(WasiError::OutOfFuel) => {
+                    debug!("failed as the thread ran out of fuel");
+                    ret = Errno::Noexec;
+                }
                 Err(err) => {
                     debug!("failed with runtime error: {}", err);
                     ret = Errno::Noexec;
@@ -215,6 +236,11 @@ fn call_module<M: MemorySize>(
         }
     }
 
+    // Wait for our turn when the threads are scheduled deterministically
+    if let Some(scheduler) = ctx.data(&store).control_plane.scheduler() {
+        scheduler.wait_for_turn(ctx.data(&store).tid());
+    }
+
     // Now invoke the module
     let ret = call_module_internal(&ctx, &mut store);
 
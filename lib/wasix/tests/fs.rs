@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use virtual_fs::{AsyncReadExt, FileSystem, TmpFileSystem};
+use wasmer::{Module, Store};
+use wasmer_wasix::{Pipe, WasiEnv};
+
+mod sys {
+    #[tokio::test]
+    async fn test_create_over_the_wrong_kind_of_entry() {
+        super::test_create_over_the_wrong_kind_of_entry().await;
+    }
+
+    #[tokio::test]
+    async fn test_tmpfile() {
+        super::test_tmpfile().await;
+    }
+
+    #[tokio::test]
+    async fn test_positional_io_keeps_the_cursor() {
+        super::test_positional_io_keeps_the_cursor().await;
+    }
+}
+
+/// Runs the program with `fs` as its file system and `/data` preopened as
+/// fd 4, and returns what it wrote to stdout.
+async fn run_in_data_dir(name: &str, wat: &str, fs: &TmpFileSystem) -> String {
+    let mut store = Store::default();
+    let module = Module::new(&store, wat).unwrap();
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+    let builder = WasiEnv::builder(name)
+        .sandbox_fs(fs.clone())
+        .preopen_dir("/data")
+        .unwrap()
+        .stdout(Box::new(stdout_tx));
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout = String::new();
+    stdout_rx.read_to_string(&mut stdout).await.unwrap();
+    stdout
+}
+
+async fn test_create_over_the_wrong_kind_of_entry() {
+    // Each mismatch exits with its own code
+    let wat = r#"
+(module
+    (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "path_create_directory" (func $path_create_directory (param i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 100) "dir")
+    (data (i32.const 120) "file")
+    (data (i32.const 140) "file/sub")
+
+    (func $main (export "_start")
+        ;; O_CREAT | O_EXCL over a directory is EEXIST
+        (if (i32.ne (call $path_open (i32.const 4) (i32.const 0) (i32.const 100) (i32.const 3) (i32.const 5) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 0)) (i32.const 20))
+            (then (call $proc_exit (i32.const 1))))
+        ;; O_CREAT over a directory is EISDIR
+        (if (i32.ne (call $path_open (i32.const 4) (i32.const 0) (i32.const 100) (i32.const 3) (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 0)) (i32.const 31))
+            (then (call $proc_exit (i32.const 2))))
+        ;; mkdir over a file is EEXIST
+        (if (i32.ne (call $path_create_directory (i32.const 4) (i32.const 120) (i32.const 4)) (i32.const 20))
+            (then (call $proc_exit (i32.const 3))))
+        ;; mkdir inside a file is ENOTDIR
+        (if (i32.ne (call $path_create_directory (i32.const 4) (i32.const 140) (i32.const 8)) (i32.const 54))
+            (then (call $proc_exit (i32.const 4))))
+    )
+)
+"#;
+
+    let fs = TmpFileSystem::new();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.create_dir(Path::new("/data/dir")).unwrap();
+    fs.new_open_options()
+        .write(true)
+        .create_new(true)
+        .open("/data/file")
+        .unwrap();
+
+    run_in_data_dir("creator", wat, &fs).await;
+
+    assert!(fs.metadata(Path::new("/data/dir")).unwrap().is_dir());
+    assert!(fs.metadata(Path::new("/data/file")).unwrap().is_file());
+}
+
+async fn test_tmpfile() {
+    let wat = r#"
+(module
+    (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_seek" (func $fd_seek (param i32 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 100) "scratch")
+    (data (i32.const 200) "hello")
+
+    (func $main (export "_start")
+        ;; O_TMPFILE with the rights to read, seek and write
+        (if (call $path_open (i32.const 4) (i32.const 0) (i32.const 100) (i32.const 7) (i32.const 16) (i64.const 70) (i64.const 0) (i32.const 0) (i32.const 0))
+            (then (call $proc_exit (i32.const 1))))
+        (i32.store (i32.const 16) (i32.const 200))
+        (i32.store (i32.const 20) (i32.const 5))
+        (if (call $fd_write (i32.load (i32.const 0)) (i32.const 16) (i32.const 1) (i32.const 32))
+            (then (call $proc_exit (i32.const 2))))
+        (if (call $fd_seek (i32.load (i32.const 0)) (i64.const 0) (i32.const 0) (i32.const 40))
+            (then (call $proc_exit (i32.const 3))))
+        ;; What is read back goes to stdout
+        (i32.store (i32.const 16) (i32.const 300))
+        (i32.store (i32.const 20) (i32.const 16))
+        (if (call $fd_read (i32.load (i32.const 0)) (i32.const 16) (i32.const 1) (i32.const 32))
+            (then (call $proc_exit (i32.const 4))))
+        (i32.store (i32.const 20) (i32.load (i32.const 32)))
+        (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 32)))
+    )
+)
+"#;
+
+    let fs = TmpFileSystem::new();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.create_dir(Path::new("/data/scratch")).unwrap();
+
+    let stdout = run_in_data_dir("tmpfile", wat, &fs).await;
+
+    assert_eq!(stdout, "hello");
+    // The file never showed up in the directory
+    assert_eq!(fs.read_dir(Path::new("/data/scratch")).unwrap().count(), 0);
+}
+
+async fn test_positional_io_keeps_the_cursor() {
+    let wat = r#"
+(module
+    (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_pread" (func $fd_pread (param i32 i32 i32 i64 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_pwrite" (func $fd_pwrite (param i32 i32 i32 i64 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 100) "file.txt")
+    (data (i32.const 200) "ab")
+
+    (func $main (export "_start")
+        (if (call $path_open (i32.const 4) (i32.const 0) (i32.const 100) (i32.const 8) (i32.const 0) (i64.const 70) (i64.const 0) (i32.const 0) (i32.const 0))
+            (then (call $proc_exit (i32.const 1))))
+        (i32.store (i32.const 16) (i32.const 300))
+        (i32.store (i32.const 20) (i32.const 4))
+        (if (call $fd_pread (i32.load (i32.const 0)) (i32.const 16) (i32.const 1) (i64.const 6) (i32.const 32))
+            (then (call $proc_exit (i32.const 2))))
+        (i32.store (i32.const 16) (i32.const 200))
+        (i32.store (i32.const 20) (i32.const 2))
+        (if (call $fd_pwrite (i32.load (i32.const 0)) (i32.const 16) (i32.const 1) (i64.const 2) (i32.const 32))
+            (then (call $proc_exit (i32.const 3))))
+        ;; The cursor is still at the start of the file
+        (i32.store (i32.const 16) (i32.const 304))
+        (i32.store (i32.const 20) (i32.const 4))
+        (if (call $fd_read (i32.load (i32.const 0)) (i32.const 16) (i32.const 1) (i32.const 32))
+            (then (call $proc_exit (i32.const 4))))
+        (i32.store (i32.const 16) (i32.const 300))
+        (i32.store (i32.const 20) (i32.const 8))
+        (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 32)))
+    )
+)
+"#;
+
+    let fs = TmpFileSystem::new();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.write_file(Path::new("/data/file.txt"), b"0123456789")
+        .unwrap();
+
+    let stdout = run_in_data_dir("pread", wat, &fs).await;
+
+    assert_eq!(stdout, "678901ab");
+}
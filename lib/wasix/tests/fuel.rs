@@ -0,0 +1,71 @@
+#![cfg(feature = "sys")]
+
+use std::sync::Arc;
+
+use wasmer::{
+    wasmparser::Operator, CompilerConfig, Cranelift, Engine, EngineBuilder, Module, Store,
+};
+use wasmer_middlewares::Metering;
+use wasmer_wasix::{WasiEnv, WasiError, WasiRuntimeError};
+
+const INFINITE_LOOP: &[u8] = br#"
+(module
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (func $main (export "_start")
+        (loop $forever
+            (br $forever)
+        )
+    )
+)
+"#;
+
+fn metered_engine() -> Engine {
+    let mut compiler = Cranelift::default();
+    compiler.push_middleware(Arc::new(Metering::new(0, |_: &Operator| 1)));
+    EngineBuilder::new(compiler).into()
+}
+
+#[tokio::test]
+async fn infinite_loop_runs_out_of_fuel() {
+    let mut store = Store::new(metered_engine());
+    let module = Module::new(&store, INFINITE_LOOP).unwrap();
+
+    let builder = WasiEnv::builder("command-name").with_fuel(1_000);
+
+    let result = std::thread::spawn(move || builder.run_with_store(module, &mut store))
+        .join()
+        .unwrap();
+
+    assert!(
+        matches!(result, Err(WasiRuntimeError::Wasi(WasiError::OutOfFuel))),
+        "{result:?}"
+    );
+}
+
+#[cfg(feature = "sys-thread")]
+#[tokio::test(flavor = "multi_thread")]
+async fn spawned_processes_run_out_of_fuel() {
+    use wasmer_wasix::{
+        bin_factory::spawn_exec_module, runtime::task_manager::tokio::TokioTaskManager,
+        wasmer_wasix_types::wasi::Errno, PluggableRuntime, Runtime,
+    };
+
+    let engine = metered_engine();
+    let module = Module::new(&engine, INFINITE_LOOP).unwrap();
+    let mut rt = PluggableRuntime::new(Arc::new(TokioTaskManager::default()));
+    rt.set_engine(Some(engine));
+    let runtime: Arc<dyn Runtime + Send + Sync> = Arc::new(rt);
+
+    let env = WasiEnv::builder("command-name")
+        .with_fuel(1_000)
+        .runtime(runtime.clone())
+        .build()
+        .unwrap();
+
+    let mut handle = spawn_exec_module(module, env, &runtime).unwrap();
+    let exit_code = handle.wait_finished().await.unwrap();
+
+    assert_eq!(exit_code, Errno::Noexec.into());
+}
@@ -22,7 +22,7 @@ use webc::Container;
 
 #[cfg(feature = "webc_runner_rt_wasi")]
 mod wasi {
-    use wasmer_wasix::{bin_factory::BinaryPackage, runners::wasi::WasiRunner, WasiError};
+    use wasmer_wasix::{bin_factory::BinaryPackage, runners::wasi::WasiRunner, WasiRuntimeError};
 
     use super::*;
 
@@ -67,11 +67,15 @@ mod wasi {
         });
         let err = handle.join().unwrap().unwrap_err();
 
-        let runtime_error = err.chain().find_map(|e| e.downcast_ref::<WasiError>());
+        let runtime_error = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<WasiRuntimeError>());
         let exit_code = match runtime_error {
-            Some(WasiError::Exit(code)) => *code,
-            Some(other) => panic!("Something else went wrong: {:?}", other),
-            None => panic!("Not a WasiError: {:?}", err),
+            Some(runtime_error) => match runtime_error.as_exit_code() {
+                Some(code) => code,
+                None => panic!("Something else went wrong: {:?}", runtime_error),
+            },
+            None => panic!("Not a WasiRuntimeError: {:?}", err),
         };
         assert_eq!(exit_code.raw(), 42);
     }
@@ -1,6 +1,19 @@
-use virtual_fs::{AsyncReadExt, AsyncWriteExt};
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use virtual_fs::{
+    AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt, FileSystem, ReadBuf,
+    TmpFileSystem, VirtualFile,
+};
 use wasmer::{Module, Store};
-use wasmer_wasix::{Pipe, WasiEnv};
+use wasmer_wasix::{Pipe, WasiEnv, WasiError};
 
 mod sys {
     #[tokio::test]
@@ -13,10 +26,70 @@ mod sys {
         super::test_stdin().await;
     }
 
+    #[tokio::test]
+    async fn test_stdin_file_path() {
+        super::test_stdin_file_path().await;
+    }
+
     #[tokio::test]
     async fn test_env() {
         super::test_env().await;
     }
+
+    #[tokio::test]
+    async fn test_fd_advise() {
+        super::test_fd_advise().await;
+    }
+
+    #[tokio::test]
+    async fn test_fd_sync() {
+        super::test_fd_sync().await;
+    }
+
+    #[tokio::test]
+    async fn test_stdout_prefix() {
+        super::test_stdout_prefix().await;
+    }
+
+    #[tokio::test]
+    async fn test_env_set_after_build() {
+        super::test_env_set_after_build().await;
+    }
+
+    #[tokio::test]
+    async fn test_positional_io() {
+        super::test_positional_io().await;
+    }
+
+    #[tokio::test]
+    async fn test_fd_tell_after_short_read() {
+        super::test_fd_tell_after_short_read().await;
+    }
+
+    #[tokio::test]
+    async fn test_set_times_now() {
+        super::test_set_times_now().await;
+    }
+
+    #[tokio::test]
+    async fn test_proc_self_cmdline() {
+        super::test_proc_self_cmdline().await;
+    }
+
+    // The listener is driven by the test runtime while the program runs on
+    // another thread
+    #[cfg(feature = "host-vnet")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_bridged_listener() {
+        super::test_bridged_listener().await;
+    }
+
+    #[tokio::test]
+    async fn test_stdio_tty() {
+        super::test_stdio_tty(None, "tty").await;
+        super::test_stdio_tty(Some(true), "tty").await;
+        super::test_stdio_tty(Some(false), "notty").await;
+    }
 }
 
 // #[cfg(feature = "js")]
@@ -96,6 +169,58 @@ async fn test_stdout() {
     assert_eq!(stdout_as_str, "hello world");
 }
 
+async fn test_stdout_prefix() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 100) "first\nsec")
+    (data (i32.const 200) "ond\nthird")
+
+    (func $main (export "_start")
+        ;; The lines are split across writes, and the last one is never finished
+        (i32.store (i32.const 0) (i32.const 100))
+        (i32.store (i32.const 4) (i32.const 9))
+        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+        drop
+        (i32.store (i32.const 0) (i32.const 200))
+        (i32.store (i32.const 4) (i32.const 9))
+        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+    let builder = WasiEnv::builder("command-name")
+        .stdout(Box::new(stdout_tx))
+        .with_stdout_prefix("app:");
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout_str = String::new();
+    stdout_rx.read_to_string(&mut stdout_str).await.unwrap();
+    assert_eq!(stdout_str, "app:first\napp:second\napp:third");
+}
+
 async fn test_env() {
     let mut store = Store::default();
     let module = Module::new(&store, include_bytes!("envvar.wasm")).unwrap();
@@ -136,6 +261,49 @@ async fn test_env() {
     assert_eq!(stdout_as_str, "Env vars:\nDOG=X\nTEST2=VALUE2\nTEST=VALUE\nDOG Ok(\"X\")\nDOG_TYPE Err(NotPresent)\nSET VAR Ok(\"HELLO\")\n");
 }
 
+async fn test_env_set_after_build() {
+    let mut store = Store::default();
+    let module = Module::new(&store, include_bytes!("envvar.wasm")).unwrap();
+
+    let (pipe_tx, mut pipe_rx) = Pipe::channel();
+
+    let builder = WasiEnv::builder("command-name")
+        .env("DOG", "X")
+        .stdout(Box::new(pipe_tx));
+
+    let run = move || {
+        let (instance, env) = builder.instantiate(module, &mut store).unwrap();
+
+        let wasi_env = env.data(&store);
+        assert!(wasi_env.set_env_var("BAD=KEY", "value").is_err());
+        wasi_env.set_env_var("DOG", "Y").unwrap();
+        wasi_env.set_env_var("TOKEN", "secret").unwrap();
+
+        let start = instance.exports.get_function("_start").unwrap();
+        if let Err(err) = start.call(&mut store, &[]) {
+            match err.downcast::<WasiError>() {
+                Ok(WasiError::Exit(code)) if code.is_success() => {}
+                other => panic!("the guest failed: {other:?}"),
+            }
+        }
+        env.cleanup(&mut store, None);
+    };
+
+    #[cfg(feature = "js")]
+    {
+        run();
+    }
+
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(run).join().unwrap();
+    }
+
+    let mut stdout_str = String::new();
+    pipe_rx.read_to_string(&mut stdout_str).await.unwrap();
+    assert_eq!(stdout_str, "Env vars:\nDOG=Y\nTOKEN=secret\nDOG Ok(\"Y\")\nDOG_TYPE Err(NotPresent)\nSET VAR Ok(\"HELLO\")\n");
+}
+
 async fn test_stdin() {
     let mut store = Store::default();
     let module = Module::new(&store, include_bytes!("stdin-hello.wasm")).unwrap();
@@ -170,3 +338,691 @@ async fn test_stdin() {
     // pipe.read_to_end(&mut buf).await.unwrap();
     // assert_eq!(buf.len(), 0);
 }
+
+async fn test_stdin_file_path() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    ;; Copies stdin to stdout 16 bytes at a time
+    (func $main (export "_start")
+        (i32.store (i32.const 0) (i32.const 100))
+        (loop $copy
+            (i32.store (i32.const 4) (i32.const 16))
+            (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16))
+            drop
+            (if (i32.load (i32.const 16))
+                (then
+                    (i32.store (i32.const 4) (i32.load (i32.const 16)))
+                    (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+                    drop
+                    (br $copy))))
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let fs = TmpFileSystem::new();
+    fs.create_dir(std::path::Path::new("/input")).unwrap();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open("/input/batch.txt")
+        .unwrap()
+        .write_all(b"first record\nsecond record\n")
+        .await
+        .unwrap();
+
+    // A missing file is reported when building
+    let result = WasiEnv::builder("command-name")
+        .sandbox_fs(fs.clone())
+        .with_stdin_file_path("/input/missing.txt")
+        .build_init();
+    assert!(result.is_err());
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+    let builder = WasiEnv::builder("command-name")
+        .sandbox_fs(fs)
+        .with_stdin_file_path("/input/batch.txt")
+        .stdout(Box::new(stdout_tx));
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout_str = String::new();
+    stdout_rx.read_to_string(&mut stdout_str).await.unwrap();
+    assert_eq!(stdout_str, "first record\nsecond record\n");
+}
+
+async fn test_stdio_tty(is_tty: Option<bool>, expected: &str) {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "fd_fdstat_get" (func $fd_fdstat_get (param i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 64) "tty")
+    (data (i32.const 72) "notty")
+
+    (func $main (export "_start")
+        ;; Read the fdstat of stdout into memory at offset 32
+        (call $fd_fdstat_get (i32.const 1) (i32.const 32))
+        drop
+
+        ;; `isatty` is true when the file type is a character device (2)
+        (if (i32.eq (i32.load8_u (i32.const 32)) (i32.const 2))
+            (then
+                (i32.store (i32.const 0) (i32.const 64))
+                (i32.store (i32.const 4) (i32.const 3)))
+            (else
+                (i32.store (i32.const 0) (i32.const 72))
+                (i32.store (i32.const 4) (i32.const 5))))
+
+        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+
+    let mut builder = WasiEnv::builder("command-name").stdout(Box::new(stdout_tx));
+    if let Some(is_tty) = is_tty {
+        builder = builder.with_stdio_tty(is_tty);
+    }
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout_str = String::new();
+    stdout_rx.read_to_string(&mut stdout_str).await.unwrap();
+    assert_eq!(stdout_str, expected);
+}
+
+async fn test_fd_advise() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "fd_advise" (func $fd_advise (param i32 i64 i64 i32) (result i32)))
+    (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    ;; Stores the errno of an fd_advise call as an ASCII digit
+    (func $advise (param $offset i32) (param $fd i32) (param $advice i32)
+        (i32.store8
+            (local.get $offset)
+            (i32.add
+                (i32.const 48)
+                (call $fd_advise (local.get $fd) (i64.const 0) (i64.const 0) (local.get $advice))))
+    )
+
+    (func $main (export "_start")
+        (call $advise (i32.const 64) (i32.const 1) (i32.const 3)) ;; WILLNEED
+        (call $advise (i32.const 65) (i32.const 1) (i32.const 4)) ;; DONTNEED
+        (call $advise (i32.const 66) (i32.const 42) (i32.const 4)) ;; bad fd
+
+        (i32.store (i32.const 0) (i32.const 64))
+        (i32.store (i32.const 4) (i32.const 3))
+        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+    let builder = WasiEnv::builder("command-name").stdout(Box::new(stdout_tx));
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout_str = String::new();
+    stdout_rx.read_to_string(&mut stdout_str).await.unwrap();
+    // Success, success and EBADF (8)
+    assert_eq!(stdout_str, "008");
+}
+
+/// Forwards everything to a [`Pipe`] while counting the calls to
+/// [`VirtualFile::sync`] and [`VirtualFile::sync_data`].
+#[derive(Debug)]
+struct SyncCountingFile {
+    inner: Pipe,
+    syncs: Arc<AtomicUsize>,
+    data_syncs: Arc<AtomicUsize>,
+}
+
+impl VirtualFile for SyncCountingFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> virtual_fs::Result<()> {
+        self.inner.set_len(new_size)
+    }
+
+    fn unlink(&mut self) -> virtual_fs::Result<()> {
+        self.inner.unlink()
+    }
+
+    fn sync(&mut self) -> virtual_fs::Result<()> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn sync_data(&mut self) -> virtual_fs::Result<()> {
+        self.data_syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_ready(cx)
+    }
+}
+
+impl AsyncRead for SyncCountingFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SyncCountingFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for SyncCountingFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+async fn test_fd_sync() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "fd_sync" (func $fd_sync (param i32) (result i32)))
+    (import "wasi_unstable" "fd_datasync" (func $fd_datasync (param i32) (result i32)))
+    (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (func $main (export "_start")
+        ;; Store the errno of each call as an ASCII digit
+        (i32.store8 (i32.const 64) (i32.add (i32.const 48) (call $fd_sync (i32.const 1))))
+        (i32.store8 (i32.const 65) (i32.add (i32.const 48) (call $fd_datasync (i32.const 1))))
+
+        (i32.store (i32.const 0) (i32.const 64))
+        (i32.store (i32.const 4) (i32.const 2))
+        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+    let syncs = Arc::new(AtomicUsize::new(0));
+    let data_syncs = Arc::new(AtomicUsize::new(0));
+    let stdout = SyncCountingFile {
+        inner: stdout_tx,
+        syncs: syncs.clone(),
+        data_syncs: data_syncs.clone(),
+    };
+    let builder = WasiEnv::builder("command-name").stdout(Box::new(stdout));
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout_str = String::new();
+    stdout_rx.read_to_string(&mut stdout_str).await.unwrap();
+    assert_eq!(stdout_str, "00");
+    assert_eq!(syncs.load(Ordering::SeqCst), 1);
+    assert_eq!(data_syncs.load(Ordering::SeqCst), 1);
+}
+
+async fn test_positional_io() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_pread" (func $fd_pread (param i32 i32 i32 i64 i32) (result i32)))
+    (import "wasi_unstable" "fd_pwrite" (func $fd_pwrite (param i32 i32 i32 i64 i32) (result i32)))
+    (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 300) "data.txt")
+    (data (i32.const 320) "XY")
+
+    ;; Points the iovec at 32 to two bytes at $buf
+    (func $iovec (param $buf i32)
+        (i32.store (i32.const 32) (local.get $buf))
+        (i32.store (i32.const 36) (i32.const 2))
+    )
+
+    (func $main (export "_start")
+        (local $fd i32)
+
+        (call $path_open (i32.const 3) (i32.const 0) (i32.const 300) (i32.const 8) (i32.const 0)
+            (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0) (i32.const 16))
+        drop
+        (local.set $fd (i32.load (i32.const 16)))
+
+        ;; Sequential read, positional read and write, then sequential read again
+        (call $iovec (i32.const 100))
+        (call $fd_read (local.get $fd) (i32.const 32) (i32.const 1) (i32.const 48))
+        drop
+        (call $iovec (i32.const 102))
+        (call $fd_pread (local.get $fd) (i32.const 32) (i32.const 1) (i64.const 6) (i32.const 48))
+        drop
+        (call $iovec (i32.const 320))
+        (call $fd_pwrite (local.get $fd) (i32.const 32) (i32.const 1) (i64.const 0) (i32.const 48))
+        drop
+        (call $iovec (i32.const 104))
+        (call $fd_read (local.get $fd) (i32.const 32) (i32.const 1) (i32.const 48))
+        drop
+
+        (i32.store (i32.const 0) (i32.const 100))
+        (i32.store (i32.const 4) (i32.const 6))
+        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let fs = TmpFileSystem::new();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open("/data.txt")
+        .unwrap()
+        .write_all(b"0123456789")
+        .await
+        .unwrap();
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+    let builder = WasiEnv::builder("command-name")
+        .sandbox_fs(fs.clone())
+        .stdout(Box::new(stdout_tx));
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout_str = String::new();
+    stdout_rx.read_to_string(&mut stdout_str).await.unwrap();
+    // The second sequential read carries on from where the first one stopped
+    assert_eq!(stdout_str, "016723");
+
+    let mut contents = String::new();
+    fs.new_open_options()
+        .read(true)
+        .open("/data.txt")
+        .unwrap()
+        .read_to_string(&mut contents)
+        .await
+        .unwrap();
+    assert_eq!(contents, "XY23456789");
+}
+
+async fn test_fd_tell_after_short_read() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_tell" (func $fd_tell (param i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 300) "data.txt")
+
+    (func $main (export "_start")
+        (local $fd i32)
+
+        (call $path_open (i32.const 3) (i32.const 0) (i32.const 300) (i32.const 8) (i32.const 0)
+            (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0) (i32.const 16))
+        drop
+        (local.set $fd (i32.load (i32.const 16)))
+
+        ;; Ask for 100 bytes twice, noting how many were read and where the
+        ;; cursor is after each read
+        (i32.store (i32.const 32) (i32.const 100))
+        (i32.store (i32.const 36) (i32.const 100))
+        (call $fd_read (local.get $fd) (i32.const 32) (i32.const 1) (i32.const 48))
+        drop
+        (call $fd_tell (local.get $fd) (i32.const 56))
+        drop
+        (call $fd_read (local.get $fd) (i32.const 32) (i32.const 1) (i32.const 64))
+        drop
+        (call $fd_tell (local.get $fd) (i32.const 72))
+        drop
+
+        (i32.store (i32.const 0) (i32.const 48))
+        (i32.store (i32.const 4) (i32.const 32))
+        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let fs = TmpFileSystem::new();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open("/data.txt")
+        .unwrap()
+        .write_all(b"0123456789")
+        .await
+        .unwrap();
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+    let builder = WasiEnv::builder("command-name")
+        .sandbox_fs(fs)
+        .stdout(Box::new(stdout_tx));
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout = Vec::new();
+    stdout_rx.read_to_end(&mut stdout).await.unwrap();
+    let u32_at = |at: usize| u32::from_le_bytes(stdout[at..at + 4].try_into().unwrap());
+    let u64_at = |at: usize| u64::from_le_bytes(stdout[at..at + 8].try_into().unwrap());
+
+    // The cursor only moves by the bytes that were actually delivered
+    assert_eq!(u32_at(0), 10);
+    assert_eq!(u64_at(8), 10);
+    assert_eq!(u32_at(16), 0);
+    assert_eq!(u64_at(24), 10);
+}
+
+async fn test_set_times_now() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "path_filestat_set_times" (func $path_filestat_set_times (param i32 i32 i32 i32 i64 i64 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 300) "data.txt")
+
+    (func $main (export "_start")
+        ;; Only SET_MTIM_NOW, the access time is left alone
+        (call $path_filestat_set_times (i32.const 3) (i32.const 0) (i32.const 300) (i32.const 8)
+            (i64.const 0) (i64.const 0) (i32.const 8))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let fs = TmpFileSystem::new();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open("/data.txt")
+        .unwrap()
+        .set_times(Some(1_000), Some(1_000))
+        .unwrap();
+
+    let builder = WasiEnv::builder("command-name").sandbox_fs(fs.clone());
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let metadata = fs.metadata("/data.txt".as_ref()).unwrap();
+    assert_eq!(metadata.accessed, 1_000);
+    assert!(metadata.modified > 1_000);
+}
+
+async fn test_proc_self_cmdline() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_unstable" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (data (i32.const 300) "proc/self/cmdline")
+
+    (func $main (export "_start")
+        (call $path_open (i32.const 3) (i32.const 0) (i32.const 300) (i32.const 17) (i32.const 0)
+            (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0) (i32.const 16))
+        drop
+
+        ;; Read up to 64 bytes into 100 and echo whatever was read to stdout
+        (i32.store (i32.const 32) (i32.const 100))
+        (i32.store (i32.const 36) (i32.const 64))
+        (call $fd_read (i32.load (i32.const 16)) (i32.const 32) (i32.const 1) (i32.const 48))
+        drop
+
+        (i32.store (i32.const 0) (i32.const 100))
+        (i32.store (i32.const 4) (i32.load (i32.const 48)))
+        (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let (stdout_tx, mut stdout_rx) = Pipe::channel();
+    let builder = WasiEnv::builder("command-name")
+        .arg("hello")
+        .with_proc_fs(true)
+        .stdout(Box::new(stdout_tx));
+
+    #[cfg(feature = "js")]
+    {
+        builder.run_with_store(module, &mut store).unwrap();
+    }
+    #[cfg(not(feature = "js"))]
+    {
+        std::thread::spawn(move || builder.run_with_store(module, &mut store))
+            .join()
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut stdout_str = String::new();
+    stdout_rx.read_to_string(&mut stdout_str).await.unwrap();
+    assert_eq!(stdout_str, "command-name\0hello\0");
+}
+
+#[cfg(feature = "host-vnet")]
+async fn test_bridged_listener() {
+    use std::io::{Read, Write};
+
+    let mut store = Store::default();
+    let module = Module::new(
+        &store,
+        br#"
+(module
+    (import "wasi_snapshot_preview1" "sock_accept" (func $sock_accept (param i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (func $main (export "_start")
+        ;; Accept a connection on the bridged listener
+        (call $sock_accept (i32.const 10) (i32.const 0) (i32.const 16))
+        drop
+
+        ;; Read a single byte into 100 and send it back
+        (i32.store (i32.const 32) (i32.const 100))
+        (i32.store (i32.const 36) (i32.const 1))
+        (call $fd_read (i32.load (i32.const 16)) (i32.const 32) (i32.const 1) (i32.const 48))
+        drop
+        (call $fd_write (i32.load (i32.const 16)) (i32.const 32) (i32.const 1) (i32.const 48))
+        drop
+    )
+)
+"#,
+    )
+    .unwrap();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+
+    let builder = WasiEnv::builder("echo").bridge_listener(
+        Box::new(virtual_net::host::LocalTcpListener::new(listener)),
+        10,
+    );
+    let program = std::thread::spawn(move || builder.run_with_store(module, &mut store));
+
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream.write_all(b"x").unwrap();
+    let mut buf = [0; 1];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"x");
+
+    program.join().unwrap().unwrap();
+}